@@ -0,0 +1,116 @@
+//! Render a MIDI file to a WAV file with the raw synthesizer, outside the browser.
+//!
+//! ```sh
+//! cargo run --example render --no-default-features -- song.mid song.wav [wave]
+//! ```
+//!
+//! The wave is one of the values of the wave select of the player, "instrument" by default, which
+//! plays every channel with the timbre of its program.
+use std::{env, fs, process::ExitCode, time::Instant};
+
+use syntezator::{
+    midi::MIDIFileData,
+    synth::{SynthSettings, raw::MidiSynth, wav},
+    wave::{
+        BellWave, OrganWave, PulseWave, SawtoothWave, SineWave, SquareWave, StringsWave,
+        TriangleWave, Wave, WhiteNoise,
+    },
+};
+
+const SAMPLE_RATE: u32 = 44100;
+
+static NOISE: WhiteNoise = WhiteNoise::new(0x5EED);
+static PULSE_QUARTER: PulseWave = PulseWave::new(0.25);
+static PULSE_EIGHTH: PulseWave = PulseWave::new(0.125);
+
+/// Prints the warnings of the parser and the synthesizer
+struct StderrLogger;
+
+impl log::Log for StderrLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!("{}: {}", record.level(), record.args());
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: StderrLogger = StderrLogger;
+
+/// The wave forced for all notes, if any, by the name of its option in the player
+fn wave(name: &str) -> Result<Option<&'static dyn Wave>, String> {
+    Ok(match name {
+        "instrument" => None,
+        "sine" => Some(&SineWave),
+        "square" => Some(&SquareWave),
+        "sawtooth" => Some(&SawtoothWave),
+        "triangle" => Some(&TriangleWave),
+        "pulse_25" => Some(&PULSE_QUARTER),
+        "pulse_12_5" => Some(&PULSE_EIGHTH),
+        "noise" => Some(&NOISE),
+        "organ" => Some(&OrganWave),
+        "bell" => Some(&BellWave),
+        "strings" => Some(&StringsWave),
+        _ => return Err(format!("unknown wave {name:?}")),
+    })
+}
+
+fn render(input: &str, output: &str, wave_name: &str) -> Result<(), String> {
+    let wave = wave(wave_name)?;
+    let bytes = fs::read(input).map_err(|error| format!("failed to read {input}: {error}"))?;
+    let data = MIDIFileData::try_from(&bytes[..])
+        .map_err(|error| format!("failed to parse {input}: {error}"))?;
+
+    let synth = MidiSynth::new(data, SynthSettings::default());
+    println!(
+        "{input}: {} tracks, {:.2} s",
+        synth.data().num_tracks(),
+        synth.total_duration().as_secs_f64()
+    );
+
+    let started = Instant::now();
+    let (buffer_length, buffers) = synth.create_buffer(SAMPLE_RATE, wave);
+    let channels = synth.mix_down(SAMPLE_RATE, buffer_length, &buffers);
+    println!(
+        "rendered at {SAMPLE_RATE} Hz in {:.2} s",
+        started.elapsed().as_secs_f64()
+    );
+
+    let file = wav::encode(
+        SAMPLE_RATE,
+        buffer_length,
+        &[channels.to_vec()],
+        wav::SampleFormat::Pcm16,
+    );
+    fs::write(output, file).map_err(|error| format!("failed to write {output}: {error}"))?;
+    println!("wrote {output}");
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Warn);
+
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    let (input, output, wave_name) = match &args[..] {
+        [input, output] => (input, output, "instrument"),
+        [input, output, wave_name] => (input, output, wave_name.as_str()),
+        _ => {
+            eprintln!("usage: render <input.mid> <output.wav> [wave]");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match render(input, output, wave_name) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! JavaScript API of the crate, for parsing and rendering files from other pages than the player.
+//!
+//! ```js
+//! import init, { parseMidi } from "./pkg/syntezator.js";
+//!
+//! await init();
+//! const bytes = new Uint8Array(await (await fetch("song.mid")).arrayBuffer());
+//! try {
+//!     const file = parseMidi(bytes);
+//!     console.log(file.trackNames, file.tempoBpm, file.durationSeconds);
+//!
+//!     const [left, right] = file.renderToBuffers(48000, "instrument");
+//!     const context = new AudioContext({ sampleRate: 48000 });
+//!     const buffer = context.createBuffer(2, left.length, 48000);
+//!     buffer.copyToChannel(left, 0);
+//!     buffer.copyToChannel(right, 1);
+//!     const source = new AudioBufferSourceNode(context, { buffer });
+//!     source.connect(context.destination);
+//!     source.start();
+//! } catch (error) {
+//!     // Such as "Not a MIDI file, missing MThd header at byte 0x0"
+//!     console.error(error.message);
+//! }
+//! ```
+use wasm_bindgen::prelude::*;
+use web_sys::js_sys;
+
+use crate::{
+    dom::{WaveKindOption, wave_kind_option},
+    midi::MIDIFileData,
+    player::{UserWaves, select_wave},
+    synth::{SynthSettings, raw::MidiSynth},
+    wave::{HarmonicWave, Wave},
+};
+
+/// A parsed MIDI file, rendered by the raw synthesizer
+#[wasm_bindgen]
+pub struct MidiFile {
+    synth: MidiSynth,
+}
+
+/// Parse the contents of a MIDI or RMID file. Throws an `Error` telling what is wrong with it and
+/// where otherwise.
+#[wasm_bindgen(js_name = parseMidi)]
+pub fn parse_midi(bytes: &[u8]) -> Result<MidiFile, JsError> {
+    Ok(MidiFile::from(MIDIFileData::try_from(bytes)?))
+}
+
+impl From<MIDIFileData> for MidiFile {
+    fn from(data: MIDIFileData) -> Self {
+        Self {
+            synth: MidiSynth::new(data, SynthSettings::default()),
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl MidiFile {
+    /// Same as `parseMidi`
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<MidiFile, JsError> {
+        parse_midi(bytes)
+    }
+
+    #[wasm_bindgen(getter = numTracks)]
+    pub fn num_tracks(&self) -> u16 {
+        self.synth.data().num_tracks()
+    }
+
+    /// Time until the last note ends, without the release of the notes
+    #[wasm_bindgen(getter = durationSeconds)]
+    pub fn duration_seconds(&self) -> f64 {
+        self.synth.total_duration().as_secs_f64()
+    }
+
+    /// Names of the tracks in order, "Track N" for the unnamed ones
+    #[wasm_bindgen(getter = trackNames)]
+    pub fn track_names(&self) -> Vec<String> {
+        self.synth.track_names()
+    }
+
+    /// Tempo the file starts with, in quarter notes per minute
+    #[wasm_bindgen(getter = tempoBpm)]
+    pub fn tempo_bpm(&self) -> f64 {
+        60_000_000.0 / self.synth.data().tempo_map(0).initial_tempo().as_mpqn() as f64
+    }
+
+    /// Render the file into a left and a right channel at `sampleRate`. `waveKind` is one of the
+    /// values of the wave select of the player: "instrument" plays every channel with the timbre of
+    /// its program, the others such as "sine" or "organ" play all notes with that wave. The waves
+    /// the user loads or draws on the player aren't available here.
+    #[wasm_bindgen(js_name = renderToBuffers)]
+    pub fn render_to_buffers(
+        &self,
+        sample_rate: u32,
+        wave_kind: &str,
+    ) -> Result<Vec<js_sys::Float32Array>, JsError> {
+        if sample_rate == 0 {
+            return Err(JsError::new("The sample rate must be positive"));
+        }
+        let wave_kind = wave_kind_option(wave_kind)
+            .filter(|wave_kind| {
+                !matches!(
+                    wave_kind,
+                    WaveKindOption::Custom | WaveKindOption::Harmonics
+                )
+            })
+            .ok_or_else(|| JsError::new(&format!("Unknown wave kind {wave_kind:?}")))?;
+        let user_waves = UserWaves {
+            preset: None,
+            harmonics: HarmonicWave::new(vec![1.0]),
+        };
+
+        Ok(self
+            .render(sample_rate, select_wave(wave_kind, &user_waves))
+            .iter()
+            .map(|channel| js_sys::Float32Array::from(&channel[..]))
+            .collect())
+    }
+}
+
+impl MidiFile {
+    /// Stereo mix of the file, like the one played by the player
+    fn render(&self, sample_rate: u32, wave: Option<&dyn Wave>) -> [Vec<f32>; 2] {
+        let (buffer_length, buffers) = self.synth.create_buffer(sample_rate, wave);
+        self.synth.mix_down(sample_rate, buffer_length, &buffers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        midi::{
+            MIDIFormat, Tempo, TimeDivision,
+            builder::{FileBuilder, TrackBuilder},
+        },
+        wave::SineWave,
+    };
+
+    #[test]
+    fn files_are_described_and_rendered_without_the_page() {
+        let data = FileBuilder::new(MIDIFormat::MultiTracks, TimeDivision::TicksPerBit(96))
+            .track(TrackBuilder::new().set_tempo(Tempo::from_mpqn(600_000)))
+            .track(TrackBuilder::new().note(0, 69, 100, 192))
+            .build();
+        let file = MidiFile::from(data);
+
+        assert_eq!(file.num_tracks(), 2);
+        assert_eq!(file.track_names(), ["Track 1", "Track 2"]);
+        assert_eq!(file.tempo_bpm(), 100.0);
+        assert!((file.duration_seconds() - 1.2).abs() < 1e-6);
+
+        let [left, right] = file.render(8000, Some(&SineWave));
+        assert_eq!(left.len(), right.len());
+        assert!(left.len() >= 9600);
+        assert!(left.iter().any(|sample| sample.abs() > 0.1));
+    }
+}
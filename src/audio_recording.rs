@@ -0,0 +1,177 @@
+//! Recording of the audio played into a compressed file, with a `MediaRecorder`.
+use std::time::Duration;
+
+use wasm_bindgen::prelude::*;
+use web_sys::js_sys;
+
+/// Formats tried in order, the first one supported by the browser being recorded
+const AUDIO_MIME_TYPES: [&str; 5] = [
+    "audio/webm;codecs=opus",
+    "audio/ogg;codecs=opus",
+    "audio/webm",
+    "audio/ogg",
+    "audio/mp4",
+];
+
+/// Length of the chunks of data handed over by the recorder while it records
+const CHUNK_DURATION: Duration = Duration::from_secs(1);
+
+/// First of the formats accepted by `is_supported`, if any
+fn preferred_mime_type(is_supported: impl Fn(&str) -> bool) -> Option<&'static str> {
+    AUDIO_MIME_TYPES
+        .into_iter()
+        .find(|mime_type| is_supported(mime_type))
+}
+
+/// Extension of the files of `mime_type`, whatever its codecs
+pub fn file_extension(mime_type: &str) -> &'static str {
+    match mime_type.split(';').next().unwrap_or_default().trim() {
+        "audio/webm" | "video/webm" => "webm",
+        "audio/ogg" => "ogg",
+        "audio/mp4" | "video/mp4" => "m4a",
+        _ => "audio",
+    }
+}
+
+/// Records everything played through an audio node, from when it's started until it's stopped.
+pub struct AudioRecorder {
+    source: web_sys::AudioNode,
+    destination: web_sys::MediaStreamAudioDestinationNode,
+    recorder: web_sys::MediaRecorder,
+    /// Blobs of recorded data, in order
+    chunks: js_sys::Array,
+    on_data_closure: Closure<dyn FnMut(web_sys::BlobEvent)>,
+    on_error_closure: Closure<dyn FnMut(web_sys::Event)>,
+    /// Context time at which the recording started
+    start: Duration,
+}
+
+impl AudioRecorder {
+    /// Start recording the output of `source`, in the first format the browser supports, or in its
+    /// default one if it supports none of them.
+    pub fn start(
+        ctx: &web_sys::AudioContext,
+        source: &web_sys::AudioNode,
+    ) -> Result<Self, JsValue> {
+        let destination = web_sys::MediaStreamAudioDestinationNode::new(ctx)?;
+        let stream = destination.stream();
+        let recorder = match preferred_mime_type(web_sys::MediaRecorder::is_type_supported) {
+            Some(mime_type) => {
+                let options = web_sys::MediaRecorderOptions::new();
+                options.set_mime_type(mime_type);
+                web_sys::MediaRecorder::new_with_media_stream_and_media_recorder_options(
+                    &stream, &options,
+                )?
+            }
+            None => web_sys::MediaRecorder::new_with_media_stream(&stream)?,
+        };
+
+        let chunks = js_sys::Array::new();
+        let chunks_c = chunks.clone();
+        let on_data_closure = Closure::wrap(Box::new(move |event: web_sys::BlobEvent| {
+            if let Some(data) = event.data().filter(|data| data.size() > 0.0) {
+                chunks_c.push(&data);
+            }
+        }) as Box<dyn FnMut(_)>);
+        recorder.set_ondataavailable(Some(on_data_closure.as_ref().unchecked_ref()));
+
+        let on_error_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            log::error!("failed to record audio: {:?}", event);
+        }) as Box<dyn FnMut(_)>);
+        recorder.set_onerror(Some(on_error_closure.as_ref().unchecked_ref()));
+
+        source.connect_with_audio_node(&destination)?;
+        recorder.start_with_time_slice(CHUNK_DURATION.as_millis() as i32)?;
+
+        Ok(Self {
+            source: source.clone(),
+            destination,
+            recorder,
+            chunks,
+            on_data_closure,
+            on_error_closure,
+            start: Duration::from_secs_f64(ctx.current_time()),
+        })
+    }
+
+    /// Length of the recording at the context time `now`. The context clock stands still while it
+    /// is suspended, which is when the recorder is paused.
+    pub fn duration(&self, now: Duration) -> Duration {
+        now.saturating_sub(self.start)
+    }
+
+    /// Stop recording while nothing can be heard, such as while the context is suspended
+    pub fn pause(&self) -> Result<(), JsValue> {
+        if self.recorder.state() == web_sys::RecordingState::Recording {
+            self.recorder.pause()?;
+        }
+        Ok(())
+    }
+
+    pub fn resume(&self) -> Result<(), JsValue> {
+        if self.recorder.state() == web_sys::RecordingState::Paused {
+            self.recorder.resume()?;
+        }
+        Ok(())
+    }
+
+    /// Stop recording and pass the recorded file, with its MIME type, to `recorded_cb` once the
+    /// recorder has handed over its last data.
+    pub fn stop<F: FnOnce(web_sys::Blob, String) + 'static>(
+        self,
+        recorded_cb: F,
+    ) -> Result<(), JsValue> {
+        let Self {
+            source,
+            destination,
+            recorder,
+            chunks,
+            on_data_closure,
+            on_error_closure,
+            ..
+        } = self;
+        source.disconnect_with_audio_node(&destination)?;
+
+        let mime_type = recorder.mime_type();
+        let on_stop_closure = Closure::once_into_js(move || {
+            // The recorder calls them until it stops
+            drop((on_data_closure, on_error_closure));
+
+            let options = web_sys::BlobPropertyBag::new();
+            options.set_type(&mime_type);
+            match web_sys::Blob::new_with_blob_sequence_and_options(&chunks, &options) {
+                Ok(blob) => recorded_cb(blob, mime_type),
+                Err(error) => log::error!("failed to assemble the recorded audio: {:?}", error),
+            }
+        });
+        recorder.set_onstop(Some(on_stop_closure.unchecked_ref()));
+        recorder.stop()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsupported_formats_fall_back_to_the_next_ones() {
+        assert_eq!(
+            preferred_mime_type(|_| true),
+            Some("audio/webm;codecs=opus")
+        );
+        assert_eq!(
+            preferred_mime_type(|mime_type| mime_type.starts_with("audio/ogg")),
+            Some("audio/ogg;codecs=opus")
+        );
+        assert_eq!(
+            preferred_mime_type(|mime_type| mime_type == "audio/mp4"),
+            Some("audio/mp4")
+        );
+        assert_eq!(preferred_mime_type(|_| false), None);
+
+        assert_eq!(file_extension("audio/webm;codecs=opus"), "webm");
+        assert_eq!(file_extension("audio/ogg; codecs=opus"), "ogg");
+        assert_eq!(file_extension("audio/mp4"), "m4a");
+        assert_eq!(file_extension(""), "audio");
+    }
+}
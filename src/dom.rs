@@ -1,10 +1,25 @@
 //! Handles to DOM elements in the HTML, and helper functions for interacting with JS.
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+    time::Duration,
+};
 
 use wasm_bindgen::prelude::*;
-use web_sys::{Document, FileReader, js_sys::Uint8Array};
+use web_sys::{
+    Document, FileReader,
+    js_sys::{self, Uint8Array},
+};
 
-use crate::midi;
+use crate::{
+    effects::{self, DelaySettings, DelayTime, EQ_BANDS, EqGains, FilterSettings, NoteValue},
+    loop_region::{LoopPoint, LoopPoints, LoopRegion},
+    lyrics::LyricsTimeline,
+    midi::{self, BarBeat, gm, stats::Statistics},
+    synth::{ChannelFilter, Marker, TrackFilter, TrackInfo, Unison, live, metronome::CountIn},
+    wave::{self, CustomWave},
+};
 
 #[allow(dead_code)]
 pub struct MidiInput {
@@ -14,8 +29,16 @@ pub struct MidiInput {
 }
 
 impl MidiInput {
-    pub fn new<F: FnMut(midi::MIDIFileData) + 'static, E: FnMut(midi::MIDIFileError) + 'static>(
+    /// `files_cb` is fired with the files the user selects, which are only read when passed to
+    /// [`MidiInput::read_file`]. Files that are read or fetched are passed to `midi_cb` once
+    /// parsed, or to `error_cb` if they fail to parse.
+    pub fn new<
+        S: FnMut(Vec<web_sys::File>) + 'static,
+        F: FnMut(midi::MIDIFileData) + 'static,
+        E: FnMut(midi::MIDIFileError) + 'static,
+    >(
         document: &Document,
+        mut files_cb: S,
         midi_cb: F,
         error_cb: E,
     ) -> Self {
@@ -25,68 +48,2496 @@ impl MidiInput {
             .dyn_into::<web_sys::HtmlInputElement>()
             .expect("failed to cast midi input to HtmlInputElement");
 
-        let midi_cb = Rc::new(RefCell::new(midi_cb));
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            let Some(file_list) = input.files() else {
+                return;
+            };
+            let files = (0..file_list.length())
+                .filter_map(|index| file_list.item(index))
+                .collect::<Vec<_>>();
+            if !files.is_empty() {
+                files_cb(files);
+            }
+        }) as Box<dyn FnMut(_)>);
+
+        element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+
+        Self {
+            element,
+            midi_cb: Rc::new(RefCell::new(midi_cb)),
+            error_cb: Rc::new(RefCell::new(error_cb)),
+        }
+    }
+
+    /// Read and parse a file selected by the user
+    pub fn read_file(&self, file: &web_sys::File) {
+        let reader = FileReader::new().expect("failed to create file reader");
+        let midi_cb = self.midi_cb.clone();
+        let error_cb = self.error_cb.clone();
+
+        let on_load_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let reader: web_sys::FileReader = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for load");
+
+            let array_buffer = reader.result().expect("failed to get result");
+            parse_midi(
+                &Uint8Array::new(&array_buffer).to_vec(),
+                &midi_cb,
+                &error_cb,
+            );
+        }) as Box<dyn FnMut(_)>);
+
+        reader.set_onload(Some(on_load_closure.as_ref().unchecked_ref()));
+        reader
+            .read_as_array_buffer(file)
+            .expect("cannot read as array buffer");
+
+        on_load_closure.forget();
+    }
+
+    /// Parse a MIDI file held in memory, such as a recording, and pass it to the same callbacks as
+    /// uploaded files
+    pub fn load_bytes(&self, bytes: &[u8]) {
+        parse_midi(bytes, &self.midi_cb, &self.error_cb);
+    }
+
+    /// Fetch the MIDI file at `url` and pass it to the same callbacks as uploaded files. Failing to
+    /// fetch it, such as when the server refuses a cross-origin request, is logged as an error.
+    pub fn load_url(&self, url: &str) {
+        let window = web_sys::window().expect("no global `window` exists");
+        let url = url.to_owned();
+        let midi_cb = self.midi_cb.clone();
+        let error_cb = self.error_cb.clone();
+
+        let on_failed = fetch_failed(&url);
+        let url_c = url.clone();
+        let on_response = Closure::wrap(Box::new(move |response: JsValue| {
+            let response: web_sys::Response = response.unchecked_into();
+            if !response.ok() {
+                log::error!(
+                    "failed to fetch {url_c}: {} {}",
+                    response.status(),
+                    response.status_text()
+                );
+                return;
+            }
+            let array_buffer = match response.array_buffer() {
+                Ok(array_buffer) => array_buffer,
+                Err(error) => {
+                    log::error!("failed to read {url_c}: {:?}", error);
+                    return;
+                }
+            };
+
+            let midi_cb = midi_cb.clone();
+            let error_cb = error_cb.clone();
+            let on_read = Closure::wrap(Box::new(move |array_buffer: JsValue| {
+                parse_midi(
+                    &Uint8Array::new(&array_buffer).to_vec(),
+                    &midi_cb,
+                    &error_cb,
+                );
+            }) as Box<dyn FnMut(JsValue)>);
+            let on_failed = fetch_failed(&url_c);
+            let _ = array_buffer.then2(&on_read, &on_failed);
+
+            on_read.forget();
+            on_failed.forget();
+        }) as Box<dyn FnMut(JsValue)>);
+        let _ = window.fetch_with_str(&url).then2(&on_response, &on_failed);
+
+        on_response.forget();
+        on_failed.forget();
+    }
+}
+
+/// Parse the contents of a MIDI file, passing it to `midi_cb` or the error to `error_cb`
+fn parse_midi(
+    bytes: &[u8],
+    midi_cb: &RefCell<dyn FnMut(midi::MIDIFileData)>,
+    error_cb: &RefCell<dyn FnMut(midi::MIDIFileError)>,
+) {
+    match midi::MIDIFileData::try_from(bytes) {
+        Ok(data) => (midi_cb.borrow_mut())(data),
+        Err(error) => (error_cb.borrow_mut())(error),
+    }
+}
+
+/// Log a rejected fetch of `url`. Browsers reject cross-origin requests refused by the server
+/// the same way as network failures, without telling them apart.
+fn fetch_failed(url: &str) -> Closure<dyn FnMut(JsValue)> {
+    let url = url.to_owned();
+    Closure::wrap(Box::new(move |error: JsValue| {
+        log::error!("failed to fetch {url}: {:?}", error);
+    }) as Box<dyn FnMut(JsValue)>)
+}
+
+/// Address of a MIDI file to load, and the button loading it
+pub struct MidiUrlInput {
+    element: web_sys::HtmlInputElement,
+    button: web_sys::HtmlButtonElement,
+}
+
+impl MidiUrlInput {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("midi-url")
+            .expect("midi-url input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast midi-url to HtmlInputElement");
+        let button = document
+            .get_element_by_id("midi-url-load")
+            .expect("midi-url-load button not found")
+            .dyn_into::<web_sys::HtmlButtonElement>()
+            .expect("failed to cast midi-url-load to HtmlButtonElement");
+
+        Self { element, button }
+    }
+
+    pub fn set_url(&self, url: &str) {
+        self.element.set_value(url);
+    }
+
+    /// Register a callback fired with the address entered when the user clicks the load button.
+    pub fn on_load<F: FnMut(String) + 'static>(&self, mut url_cb: F) {
+        let element = self.element.clone();
+        let on_click_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            let url = element.value();
+            if !url.trim().is_empty() {
+                url_cb(url.trim().to_owned());
+            }
+        }) as Box<dyn FnMut(_)>);
+
+        self.button
+            .add_event_listener_with_callback("click", on_click_closure.as_ref().unchecked_ref())
+            .expect("failed to set click event handler");
+        on_click_closure.forget();
+    }
+}
+
+/// Value of the `name` parameter of the query string of the page, if any
+pub fn query_parameter(name: &str) -> Option<String> {
+    let search = web_sys::window()?.location().search().ok()?;
+    web_sys::UrlSearchParams::new_with_str(&search)
+        .ok()?
+        .get(name)
+}
+
+/// File input for wavetable presets, see [`wave::custom_wave_from_json`]
+#[allow(dead_code)]
+pub struct WavePresetInput {
+    element: web_sys::HtmlInputElement,
+}
+
+impl WavePresetInput {
+    pub fn new<F: FnMut(CustomWave) + 'static, E: FnMut(wave::WaveParseError) + 'static>(
+        document: &Document,
+        wave_cb: F,
+        error_cb: E,
+    ) -> Self {
+        let element = document
+            .get_element_by_id("wave-preset")
+            .expect("wave-preset input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast wave-preset to HtmlInputElement");
+
+        let wave_cb = Rc::new(RefCell::new(wave_cb));
         let error_cb = Rc::new(RefCell::new(error_cb));
-        let midi_cb_c = midi_cb.clone();
-        let error_cb_c = error_cb.clone();
 
-        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
-            let input: web_sys::HtmlInputElement = event
-                .target()
-                .unwrap()
-                .dyn_into()
-                .expect("cannot get correct target for change");
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            if let Some(file) = input.files().and_then(|f| f.item(0)) {
+                let reader = FileReader::new().expect("failed to create file reader");
+                let wave_cb = wave_cb.clone();
+                let error_cb = error_cb.clone();
+
+                let on_load_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+                    let reader: web_sys::FileReader = event
+                        .target()
+                        .unwrap()
+                        .dyn_into()
+                        .expect("cannot get correct target for load");
+
+                    let json = reader
+                        .result()
+                        .expect("failed to get result")
+                        .as_string()
+                        .unwrap_or_default();
+
+                    match wave::custom_wave_from_json(&json) {
+                        Ok(wave) => (wave_cb.borrow_mut())(wave),
+                        Err(error) => (error_cb.borrow_mut())(error),
+                    }
+                }) as Box<dyn FnMut(_)>);
+
+                reader.set_onload(Some(on_load_closure.as_ref().unchecked_ref()));
+                reader.read_as_text(&file).expect("cannot read as text");
+
+                on_load_closure.forget();
+            }
+        }) as Box<dyn FnMut(_)>);
+
+        element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+
+        Self { element }
+    }
+}
+
+type PositionCallback = Rc<RefCell<Option<Box<dyn FnMut(Duration)>>>>;
+
+/// Play/pause switch and the position scrubber of the current playback.
+pub struct PlaybackControls {
+    document: Document,
+    play_pause: web_sys::HtmlInputElement,
+    position: web_sys::HtmlInputElement,
+    /// Ticks along the scrubber for the markers of the loaded file
+    markers: web_sys::Element,
+    /// Points of the A-B loop along the scrubber, and the region between them
+    loop_points: web_sys::Element,
+    /// Fired when the user moves the scrubber or clicks a marker
+    position_cb: PositionCallback,
+    position_label: web_sys::Element,
+    /// Show the position in bars and beats instead of minutes and seconds
+    bars: web_sys::HtmlInputElement,
+    /// Set while the user drags the scrubber, so that position updates don't fight with them
+    scrubbing: Rc<Cell<bool>>,
+    download: web_sys::HtmlButtonElement,
+    record_audio: web_sys::HtmlInputElement,
+    /// Length of the audio recording, hidden when not recording
+    recording_time: web_sys::HtmlElement,
+}
+
+impl PlaybackControls {
+    pub fn new(document: &Document) -> Self {
+        let play_pause = document
+            .get_element_by_id("play-pause")
+            .expect("play-pause input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast play-pause to HtmlInputElement");
+
+        let position = document
+            .get_element_by_id("position")
+            .expect("position input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast position to HtmlInputElement");
+
+        let position_label = document
+            .get_element_by_id("position-label")
+            .expect("position-label element not found");
+
+        let markers = document
+            .get_element_by_id("position-markers")
+            .expect("position-markers element not found");
+
+        let loop_points = document
+            .get_element_by_id("position-loop")
+            .expect("position-loop element not found");
+
+        let bars = document
+            .get_element_by_id("position-bars")
+            .expect("position-bars input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast position-bars to HtmlInputElement");
+
+        let download = document
+            .get_element_by_id("download-wav")
+            .expect("download-wav button element not found")
+            .dyn_into::<web_sys::HtmlButtonElement>()
+            .expect("failed to cast download-wav to HtmlButtonElement");
+
+        let record_audio = document
+            .get_element_by_id("record-audio")
+            .expect("record-audio input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast record-audio to HtmlInputElement");
+
+        let recording_time = document
+            .get_element_by_id("recording-time")
+            .expect("recording-time element not found")
+            .dyn_into::<web_sys::HtmlElement>()
+            .expect("failed to cast recording-time to HtmlElement");
+
+        let scrubbing = Rc::new(Cell::new(false));
+        let scrubbing_c = scrubbing.clone();
+        let on_input_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            scrubbing_c.set(true);
+        }) as Box<dyn FnMut(_)>);
+
+        position
+            .add_event_listener_with_callback("input", on_input_closure.as_ref().unchecked_ref())
+            .expect("failed to set input event handler");
+        on_input_closure.forget();
+
+        Self {
+            document: document.clone(),
+            play_pause,
+            position,
+            markers,
+            loop_points,
+            position_cb: Rc::new(RefCell::new(None)),
+            position_label,
+            bars,
+            scrubbing,
+            download,
+            record_audio,
+            recording_time,
+        }
+    }
+
+    /// Register a callback fired when the user toggles playback, with `true` meaning "play".
+    pub fn on_play_pause<F: FnMut(bool) + 'static>(&self, mut play_pause_cb: F) {
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            play_pause_cb(input.checked());
+        }) as Box<dyn FnMut(_)>);
+
+        self.play_pause
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+
+    /// Register a callback fired when the user moves the scrubber to a new position, or clicks a
+    /// marker.
+    pub fn on_position_change<F: FnMut(Duration) + 'static>(&self, position_cb: F) {
+        *self.position_cb.borrow_mut() = Some(Box::new(position_cb));
+
+        let position_cb = self.position_cb.clone();
+        let scrubbing = self.scrubbing.clone();
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            scrubbing.set(false);
+
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            if let Some(position_cb) = position_cb.borrow_mut().as_mut() {
+                position_cb(Duration::from_secs_f64(input.value_as_number().max(0.0)));
+            }
+        }) as Box<dyn FnMut(_)>);
+
+        self.position
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+
+    /// Register a callback fired when the user asks to download the rendered audio.
+    pub fn on_download<F: FnMut() + 'static>(&self, mut download_cb: F) {
+        let on_click_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            download_cb();
+        }) as Box<dyn FnMut(_)>);
+
+        self.download
+            .add_event_listener_with_callback("click", on_click_closure.as_ref().unchecked_ref())
+            .expect("failed to set click event handler");
+        on_click_closure.forget();
+    }
+
+    /// Register a callback fired when the user starts or stops recording the audio played.
+    pub fn on_record_audio<F: FnMut(bool) + 'static>(&self, mut record_cb: F) {
+        let record_audio = self.record_audio.clone();
+        add_listener(&self.record_audio, "change", move |_event| {
+            record_cb(record_audio.checked());
+        });
+    }
+
+    pub fn set_playing(&self, playing: bool) {
+        self.play_pause.set_checked(playing);
+    }
+
+    /// Turn the audio recording switch off, such as when the recording couldn't start
+    pub fn set_recording_audio(&self, recording: bool) {
+        self.record_audio.set_checked(recording);
+    }
+
+    /// Show how long the audio has been recorded for, or hide it when not recording
+    pub fn set_recording_time(&self, recording_time: Option<Duration>) {
+        self.recording_time.set_hidden(recording_time.is_none());
+        if let Some(recording_time) = recording_time {
+            self.recording_time
+                .set_text_content(Some(&format_duration(recording_time)));
+        }
+    }
+
+    pub fn set_enabled(&self, enabled: bool) {
+        self.play_pause.set_disabled(!enabled);
+        self.position.set_disabled(!enabled);
+    }
+
+    /// Only the raw synthesizer renders audio which can be downloaded
+    pub fn set_download_enabled(&self, enabled: bool) {
+        self.download.set_disabled(!enabled);
+    }
+
+    pub fn set_duration(&self, duration: Duration) {
+        self.position.set_max(&duration.as_secs_f64().to_string());
+    }
+
+    /// Replace the ticks along the scrubber with the given markers of a file lasting `duration`.
+    /// Hovering a tick shows its label, and clicking it seeks to it.
+    pub fn set_markers(&self, markers: &[Marker], duration: Duration) -> Result<(), JsValue> {
+        self.markers.set_inner_html("");
+        if duration.is_zero() {
+            return Ok(());
+        }
+
+        for marker in markers {
+            let tick = self.document.create_element("div")?;
+            tick.set_class_name("marker");
+            tick.set_attribute(
+                "style",
+                &format!(
+                    "left: {}%",
+                    (marker.time.as_secs_f64() / duration.as_secs_f64()).min(1.0) * 100.0
+                ),
+            )?;
+            tick.set_attribute(
+                "title",
+                &format!("{} ({})", marker.label, format_duration(marker.time)),
+            )?;
+
+            let time = marker.time;
+            let position_cb = self.position_cb.clone();
+            let on_click_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+                if let Some(position_cb) = position_cb.borrow_mut().as_mut() {
+                    position_cb(time);
+                }
+            }) as Box<dyn FnMut(_)>);
+
+            tick.add_event_listener_with_callback(
+                "click",
+                on_click_closure.as_ref().unchecked_ref(),
+            )?;
+            on_click_closure.forget();
+
+            self.markers.append_child(&tick)?;
+        }
+
+        Ok(())
+    }
+
+    /// Replace the points of the A-B loop along the scrubber of a file lasting `duration`, and shade
+    /// the `region` looped between them
+    pub fn set_loop_points(
+        &self,
+        loop_points: &LoopPoints,
+        region: Option<LoopRegion>,
+        duration: Duration,
+    ) -> Result<(), JsValue> {
+        self.loop_points.set_inner_html("");
+        if duration.is_zero() {
+            return Ok(());
+        }
+
+        let percent =
+            |time: Duration| (time.as_secs_f64() / duration.as_secs_f64()).min(1.0) * 100.0;
+        if let Some(region) = region {
+            let shade = self.document.create_element("div")?;
+            shade.set_class_name("loop-region");
+            shade.set_attribute(
+                "style",
+                &format!(
+                    "left: {}%; width: {}%",
+                    percent(region.start),
+                    percent(region.end) - percent(region.start)
+                ),
+            )?;
+            self.loop_points.append_child(&shade)?;
+        }
+
+        for (label, time) in [("A", loop_points.a), ("B", loop_points.b)] {
+            let Some(time) = time else {
+                continue;
+            };
+            let point = self.document.create_element("span")?;
+            point.set_class_name("loop-point");
+            point.set_text_content(Some(label));
+            point.set_attribute("style", &format!("left: {}%", percent(time)))?;
+            self.loop_points.append_child(&point)?;
+        }
+
+        Ok(())
+    }
+
+    /// Show the time left before the file starts, counting down from the count-in, with the
+    /// position at the start
+    pub fn set_count_in(&self, remaining: Duration) {
+        if !self.scrubbing.get() {
+            self.position.set_value_as_number(0.0);
+        }
+
+        let label = format!(
+            "-{}",
+            format_duration(Duration::from_secs(remaining.as_secs_f64().ceil() as u64))
+        );
+        self.position_label.set_text_content(Some(&label));
+    }
+
+    /// Show the position, in bars and beats if the user chose so and `bar_beat` is known
+    pub fn set_position(&self, position: Duration, bar_beat: Option<BarBeat>) {
+        if !self.scrubbing.get() {
+            self.position.set_value_as_number(position.as_secs_f64());
+        }
+
+        let label = match bar_beat {
+            Some(bar_beat) if self.bars.checked() => {
+                format!("bar {}, beat {}", bar_beat.bar, bar_beat.beat)
+            }
+            _ => format_duration(position),
+        };
+        self.position_label.set_text_content(Some(&label));
+    }
+}
+
+/// Slider for the master volume, independent of the loaded file.
+pub struct VolumeControl {
+    element: web_sys::HtmlInputElement,
+}
+
+impl VolumeControl {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("volume")
+            .expect("volume input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast volume to HtmlInputElement");
+
+        Self { element }
+    }
+
+    /// Volume currently selected, from 0 (silent) to 1
+    pub fn volume(&self) -> f32 {
+        self.element.value_as_number().clamp(0.0, 1.0) as f32
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        self.element
+            .set_value_as_number(volume.clamp(0.0, 1.0) as f64);
+    }
+
+    /// Register a callback fired while the user moves the slider.
+    pub fn on_volume_change<F: FnMut(f32) + 'static>(&self, mut volume_cb: F) {
+        let on_input_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for input");
+
+            volume_cb(input.value_as_number().clamp(0.0, 1.0) as f32);
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("input", on_input_closure.as_ref().unchecked_ref())
+            .expect("failed to set input event handler");
+        on_input_closure.forget();
+    }
+}
+
+/// Slider for the level of the reverberation mixed into the output.
+pub struct ReverbControl {
+    element: web_sys::HtmlInputElement,
+}
+
+impl ReverbControl {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("reverb")
+            .expect("reverb input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast reverb to HtmlInputElement");
+
+        Self { element }
+    }
+
+    /// Reverb amount currently selected, from 0 (none) to 1
+    pub fn amount(&self) -> f32 {
+        self.element.value_as_number().clamp(0.0, 1.0) as f32
+    }
+
+    pub fn set_amount(&self, amount: f32) {
+        self.element
+            .set_value_as_number(amount.clamp(0.0, 1.0) as f64);
+    }
+
+    /// Register a callback fired while the user moves the slider.
+    pub fn on_change<F: FnMut(f32) + 'static>(&self, mut amount_cb: F) {
+        let element = self.element.clone();
+        add_listener(&self.element, "input", move |_event| {
+            amount_cb(element.value_as_number().clamp(0.0, 1.0) as f32);
+        });
+    }
+}
+
+/// Gain sliders of the bands of the equalizer, `eq-band-0` being the lowest one.
+#[derive(Clone)]
+pub struct EqualizerControls {
+    bands: Vec<web_sys::HtmlInputElement>,
+}
+
+impl EqualizerControls {
+    pub fn new(document: &Document) -> Self {
+        let bands = (0..EQ_BANDS.len())
+            .map(|index| {
+                document
+                    .get_element_by_id(&format!("eq-band-{index}"))
+                    .expect("equalizer band input element not found")
+                    .dyn_into::<web_sys::HtmlInputElement>()
+                    .expect("failed to cast equalizer band to HtmlInputElement")
+            })
+            .collect();
+
+        Self { bands }
+    }
+
+    /// Gains currently selected, in dB
+    pub fn gains(&self) -> EqGains {
+        let mut gains = EqGains::default();
+        for (gain, band) in gains.iter_mut().zip(&self.bands) {
+            *gain = band
+                .value_as_number()
+                .clamp(-effects::MAX_EQ_GAIN as f64, effects::MAX_EQ_GAIN as f64)
+                as f32;
+        }
+        gains
+    }
+
+    /// Register a callback fired while the user moves any of the sliders.
+    pub fn on_change<F: FnMut(EqGains) + 'static>(&self, gains_cb: F) {
+        let gains_cb = Rc::new(RefCell::new(gains_cb));
+        for band in &self.bands {
+            let controls = self.clone();
+            let gains_cb = gains_cb.clone();
+            add_listener(band, "input", move |_event| {
+                (gains_cb.borrow_mut())(controls.gains());
+            });
+        }
+    }
+}
+
+/// Time, feedback and mix of the delay. The time is either synced to a note value, or set in
+/// milliseconds when the sync select is on "free".
+#[derive(Clone)]
+pub struct DelayControls {
+    sync: web_sys::HtmlSelectElement,
+    time: web_sys::HtmlInputElement,
+    feedback: web_sys::HtmlInputElement,
+    mix: web_sys::HtmlInputElement,
+}
+
+impl DelayControls {
+    pub fn new(document: &Document) -> Self {
+        let sync = document
+            .get_element_by_id("delay-sync")
+            .expect("delay-sync select element not found")
+            .dyn_into::<web_sys::HtmlSelectElement>()
+            .expect("failed to cast delay-sync to HtmlSelectElement");
+        let slider = |id: &str| {
+            document
+                .get_element_by_id(id)
+                .expect("delay slider not found")
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .expect("failed to cast delay slider to HtmlInputElement")
+        };
+
+        let controls = Self {
+            sync,
+            time: slider("delay-time"),
+            feedback: slider("delay-feedback"),
+            mix: slider("delay-mix"),
+        };
+        controls.show_sync();
+        controls
+    }
+
+    /// Settings currently selected
+    pub fn settings(&self) -> DelaySettings {
+        let time = match delay_note_value(&self.sync.value()) {
+            Some(note_value) => DelayTime::Synced(note_value),
+            None => DelayTime::Free(Duration::from_secs_f64(
+                self.time.value_as_number().max(0.0) / 1000.0,
+            )),
+        };
+
+        DelaySettings {
+            time,
+            feedback: self.feedback.value_as_number().clamp(0.0, 1.0) as f32,
+            mix: self.mix.value_as_number().clamp(0.0, 1.0) as f32,
+        }
+    }
+
+    /// Register a callback fired while the user changes any of the settings.
+    pub fn on_change<F: FnMut(DelaySettings) + 'static>(&self, settings_cb: F) {
+        let settings_cb = Rc::new(RefCell::new(settings_cb));
+        for (element, event_type) in [
+            (self.sync.unchecked_ref::<web_sys::EventTarget>(), "change"),
+            (&self.time, "input"),
+            (&self.feedback, "input"),
+            (&self.mix, "input"),
+        ] {
+            let controls = self.clone();
+            let settings_cb = settings_cb.clone();
+            add_listener(element, event_type, move |_event| {
+                controls.show_sync();
+                (settings_cb.borrow_mut())(controls.settings());
+            });
+        }
+    }
+
+    /// Milliseconds only apply to free delays
+    fn show_sync(&self) {
+        self.time
+            .set_disabled(delay_note_value(&self.sync.value()).is_some());
+    }
+}
+
+/// Cutoff and resonance sliders of the low-pass filter, and the switch of the filter envelope of
+/// the notes.
+#[derive(Clone)]
+pub struct EffectsControls {
+    /// From 0 to 1, along which the cutoff rises exponentially
+    cutoff: web_sys::HtmlInputElement,
+    resonance: web_sys::HtmlInputElement,
+    envelope: web_sys::HtmlInputElement,
+    compressor: web_sys::HtmlInputElement,
+}
+
+impl EffectsControls {
+    pub fn new(document: &Document) -> Self {
+        let input = |id: &str| {
+            document
+                .get_element_by_id(id)
+                .expect("filter input element not found")
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .expect("failed to cast filter input to HtmlInputElement")
+        };
+
+        Self {
+            cutoff: input("filter-cutoff"),
+            resonance: input("filter-resonance"),
+            envelope: input("filter-envelope"),
+            compressor: input("compressor"),
+        }
+    }
+
+    /// Filter settings currently selected
+    pub fn filter(&self) -> FilterSettings {
+        FilterSettings {
+            cutoff: effects::cutoff_frequency(self.cutoff.value_as_number() as f32),
+            q: self.resonance.value_as_number() as f32,
+            envelope: self.envelope.checked(),
+        }
+    }
+
+    /// Register a callback fired while the user moves the filter sliders.
+    pub fn on_filter_input<F: FnMut(FilterSettings) + 'static>(&self, filter_cb: F) {
+        let filter_cb = Rc::new(RefCell::new(filter_cb));
+        for element in [&self.cutoff, &self.resonance] {
+            let controls = self.clone();
+            let filter_cb = filter_cb.clone();
+            add_listener(element, "input", move |_event| {
+                (filter_cb.borrow_mut())(controls.filter());
+            });
+        }
+    }
+
+    /// Register a callback fired once the user is done changing the filter, having released a
+    /// slider or flipped the envelope switch.
+    pub fn on_filter_change<F: FnMut(FilterSettings) + 'static>(&self, filter_cb: F) {
+        let filter_cb = Rc::new(RefCell::new(filter_cb));
+        for element in [&self.cutoff, &self.resonance, &self.envelope] {
+            let controls = self.clone();
+            let filter_cb = filter_cb.clone();
+            add_listener(element, "change", move |_event| {
+                (filter_cb.borrow_mut())(controls.filter());
+            });
+        }
+    }
+
+    /// Whether the compressor is switched on
+    pub fn compressor(&self) -> bool {
+        self.compressor.checked()
+    }
+
+    /// Register a callback fired when the user flips the compressor switch.
+    pub fn on_compressor_change<F: FnMut(bool) + 'static>(&self, mut compressor_cb: F) {
+        let compressor = self.compressor.clone();
+        add_listener(&self.compressor, "change", move |_event| {
+            compressor_cb(compressor.checked());
+        });
+    }
+}
+
+/// Oscillators playing each note of the Web Audio synthesizer, and their detune in cents.
+#[derive(Clone)]
+pub struct UnisonControls {
+    unison: web_sys::HtmlSelectElement,
+    spread: web_sys::HtmlInputElement,
+}
+
+impl UnisonControls {
+    pub fn new(document: &Document) -> Self {
+        let unison = document
+            .get_element_by_id("unison")
+            .expect("unison select element not found")
+            .dyn_into::<web_sys::HtmlSelectElement>()
+            .expect("failed to cast unison to HtmlSelectElement");
+        let spread = document
+            .get_element_by_id("unison-spread")
+            .expect("unison-spread input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast unison-spread to HtmlInputElement");
+
+        let controls = Self { unison, spread };
+        controls.show_unison();
+        controls
+    }
+
+    pub fn unison(&self) -> Unison {
+        match self.unison.value().as_str() {
+            "2" => Unison::Two,
+            "3" => Unison::Three,
+            _ => Unison::Off,
+        }
+    }
+
+    /// Detune of the outermost oscillators, in cents
+    pub fn spread(&self) -> f32 {
+        self.spread.value_as_number().max(0.0) as f32
+    }
+
+    /// Register a callback fired once the user picks a unison or releases the spread slider, with
+    /// both of them.
+    pub fn on_change<F: FnMut(Unison, f32) + 'static>(&self, unison_cb: F) {
+        let unison_cb = Rc::new(RefCell::new(unison_cb));
+        for element in [
+            self.unison.unchecked_ref::<web_sys::EventTarget>(),
+            &self.spread,
+        ] {
+            let controls = self.clone();
+            let unison_cb = unison_cb.clone();
+            add_listener(element, "change", move |_event| {
+                controls.show_unison();
+                (unison_cb.borrow_mut())(controls.unison(), controls.spread());
+            });
+        }
+    }
+
+    /// The spread only applies to several oscillators
+    fn show_unison(&self) {
+        self.spread.set_disabled(self.unison() == Unison::Off);
+    }
+}
+
+/// Note value of an option value of the delay sync select, or `None` for free delays
+fn delay_note_value(value: &str) -> Option<NoteValue> {
+    match value {
+        "1/2" => Some(NoteValue::Half),
+        "1/4" => Some(NoteValue::Quarter),
+        "1/8d" => Some(NoteValue::DottedEighth),
+        "1/8" => Some(NoteValue::Eighth),
+        "1/16" => Some(NoteValue::Sixteenth),
+        _ => None,
+    }
+}
+
+/// Select of the FFT size of the analyser, trading how often the plots change for how finely the
+/// spectrum is split.
+pub struct FftSizeSelect {
+    element: web_sys::HtmlSelectElement,
+}
+
+impl FftSizeSelect {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("fft-size")
+            .expect("fft-size select element not found")
+            .dyn_into::<web_sys::HtmlSelectElement>()
+            .expect("failed to cast fft-size to HtmlSelectElement");
+
+        Self { element }
+    }
+
+    pub fn fft_size(&self) -> u32 {
+        fft_size(&self.element)
+    }
+
+    /// Select `fft_size`, or the default size if it isn't one of the options
+    pub fn set_fft_size(&self, fft_size: u32) {
+        self.element.set_value(&fft_size.to_string());
+        if self.element.selected_index() < 0 {
+            self.element
+                .set_value(&crate::plotter::DEFAULT_FFT_SIZE.to_string());
+        }
+    }
+
+    /// Register a callback fired when the user selects another size.
+    pub fn on_change<F: FnMut(u32) + 'static>(&self, mut fft_size_cb: F) {
+        let element = self.element.clone();
+        let on_change_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            fft_size_cb(fft_size(&element));
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+}
+
+fn fft_size(select: &web_sys::HtmlSelectElement) -> u32 {
+    select.value().parse().expect("unknown fft size selected")
+}
+
+/// Slider for the playback speed, from half to twice the speed of the file.
+pub struct RateControl {
+    element: web_sys::HtmlInputElement,
+}
+
+impl RateControl {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("playback-rate")
+            .expect("playback-rate input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast playback-rate to HtmlInputElement");
+
+        Self { element }
+    }
+
+    pub fn rate(&self) -> f32 {
+        self.element.value_as_number().clamp(0.5, 2.0) as f32
+    }
+
+    /// Register a callback fired when the user releases the slider at a new rate.
+    pub fn on_rate_change<F: FnMut(f32) + 'static>(&self, mut rate_cb: F) {
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            rate_cb(input.value_as_number().clamp(0.5, 2.0) as f32);
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+}
+
+/// Number of semitones by which all notes but the drums are transposed.
+pub struct TransposeControl {
+    element: web_sys::HtmlInputElement,
+}
+
+impl TransposeControl {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("transpose")
+            .expect("transpose input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast transpose to HtmlInputElement");
+
+        Self { element }
+    }
+
+    pub fn semitones(&self) -> i8 {
+        semitones(&self.element)
+    }
+
+    /// Register a callback fired when the user enters a new transposition.
+    pub fn on_change<F: FnMut(i8) + 'static>(&self, mut transpose_cb: F) {
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            transpose_cb(semitones(&input));
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+}
+
+/// Value of a transpose input, which is empty (NaN) while the user is typing
+fn semitones(input: &web_sys::HtmlInputElement) -> i8 {
+    let value = input.value_as_number();
+    if value.is_nan() {
+        0
+    } else {
+        value.round().clamp(-12.0, 12.0) as i8
+    }
+}
+
+/// Switch of the metronome clicking along with the playback, and its volume slider.
+#[derive(Clone)]
+pub struct MetronomeControls {
+    toggle: web_sys::HtmlInputElement,
+    volume: web_sys::HtmlInputElement,
+    count_in: web_sys::HtmlSelectElement,
+}
+
+impl MetronomeControls {
+    pub fn new(document: &Document) -> Self {
+        let input = |id: &str| {
+            document
+                .get_element_by_id(id)
+                .expect("metronome input element not found")
+                .dyn_into::<web_sys::HtmlInputElement>()
+                .expect("failed to cast metronome input to HtmlInputElement")
+        };
+
+        let count_in = document
+            .get_element_by_id("count-in")
+            .expect("count-in select element not found")
+            .dyn_into::<web_sys::HtmlSelectElement>()
+            .expect("failed to cast count-in to HtmlSelectElement");
+
+        let controls = Self {
+            toggle: input("metronome"),
+            volume: input("metronome-volume"),
+            count_in,
+        };
+        controls.show_toggle();
+        controls
+    }
+
+    /// Volume of the clicks from 0 to 1, or `None` when the metronome is off
+    pub fn volume(&self) -> Option<f32> {
+        self.toggle.checked().then(|| self.click_volume())
+    }
+
+    /// Volume of the clicks from 0 to 1, whether the metronome is on or not
+    pub fn click_volume(&self) -> f32 {
+        self.volume.value_as_number().clamp(0.0, 1.0) as f32
+    }
+
+    pub fn count_in(&self) -> CountIn {
+        match self.count_in.value().as_str() {
+            "1" => CountIn::OneBar,
+            "2" => CountIn::TwoBars,
+            _ => CountIn::Off,
+        }
+    }
+
+    /// Register a callback fired once the user flips the switch or releases the volume slider,
+    /// with the volume of the clicks.
+    pub fn on_change<F: FnMut(Option<f32>) + 'static>(&self, volume_cb: F) {
+        let volume_cb = Rc::new(RefCell::new(volume_cb));
+        for element in [&self.toggle, &self.volume] {
+            let controls = self.clone();
+            let volume_cb = volume_cb.clone();
+            add_listener(element, "change", move |_event| {
+                controls.show_toggle();
+                (volume_cb.borrow_mut())(controls.volume());
+            });
+        }
+    }
+
+    /// Register a callback fired once the user picks a count-in or releases the volume slider,
+    /// with the count-in and the volume of its clicks.
+    pub fn on_count_in_change<F: FnMut(CountIn, f32) + 'static>(&self, count_in_cb: F) {
+        let count_in_cb = Rc::new(RefCell::new(count_in_cb));
+        for element in [
+            self.count_in.unchecked_ref::<web_sys::EventTarget>(),
+            &self.volume,
+        ] {
+            let controls = self.clone();
+            let count_in_cb = count_in_cb.clone();
+            add_listener(element, "change", move |_event| {
+                controls.show_toggle();
+                (count_in_cb.borrow_mut())(controls.count_in(), controls.click_volume());
+            });
+        }
+    }
+
+    /// The volume only applies while the metronome or the count-in is on
+    fn show_toggle(&self) {
+        self.volume
+            .set_disabled(!self.toggle.checked() && self.count_in() == CountIn::Off);
+    }
+}
+
+/// Buttons setting the points of the A-B loop at the current position, and clearing them.
+pub struct LoopRegionControls {
+    a_button: web_sys::HtmlButtonElement,
+    b_button: web_sys::HtmlButtonElement,
+    clear_button: web_sys::HtmlButtonElement,
+}
+
+impl LoopRegionControls {
+    pub fn new(document: &Document) -> Self {
+        let button = |id: &str| {
+            document
+                .get_element_by_id(id)
+                .expect("loop button not found")
+                .dyn_into::<web_sys::HtmlButtonElement>()
+                .expect("failed to cast loop button to HtmlButtonElement")
+        };
+
+        Self {
+            a_button: button("loop-a"),
+            b_button: button("loop-b"),
+            clear_button: button("loop-clear"),
+        }
+    }
+
+    /// Register a callback fired with the point the user sets.
+    pub fn on_set<F: FnMut(LoopPoint) + 'static>(&self, set_cb: F) {
+        let set_cb = Rc::new(RefCell::new(set_cb));
+        for (button, point) in [
+            (&self.a_button, LoopPoint::A),
+            (&self.b_button, LoopPoint::B),
+        ] {
+            let set_cb = set_cb.clone();
+            on_click(button, move || (set_cb.borrow_mut())(point));
+        }
+    }
+
+    /// Register a callback fired when the user clears the loop.
+    pub fn on_clear<F: FnMut() + 'static>(&self, clear_cb: F) {
+        on_click(&self.clear_button, clear_cb);
+    }
+}
+
+/// Switch restarting the playback from the start once it ends.
+pub struct LoopToggle {
+    element: web_sys::HtmlInputElement,
+}
+
+impl LoopToggle {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("loop")
+            .expect("loop input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast loop to HtmlInputElement");
+
+        Self { element }
+    }
+
+    pub fn is_looping(&self) -> bool {
+        self.element.checked()
+    }
+
+    /// Register a callback fired when the user toggles looping.
+    pub fn on_change<F: FnMut(bool) + 'static>(&self, mut looping_cb: F) {
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            looping_cb(input.checked());
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+}
+
+/// Switch spacing the spectrum plot by octaves rather than by hertz.
+pub struct LogFrequencyToggle {
+    element: web_sys::HtmlInputElement,
+}
+
+impl LogFrequencyToggle {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("log-frequency")
+            .expect("log-frequency input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast log-frequency to HtmlInputElement");
+
+        Self { element }
+    }
+
+    pub fn is_log(&self) -> bool {
+        self.element.checked()
+    }
+
+    /// Register a callback fired when the user toggles the axis.
+    pub fn on_change<F: FnMut(bool) + 'static>(&self, mut log_cb: F) {
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            log_cb(input.checked());
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+}
+
+pub struct PeakHoldToggle {
+    element: web_sys::HtmlInputElement,
+}
+
+impl PeakHoldToggle {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("peak-hold")
+            .expect("peak-hold input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast peak-hold to HtmlInputElement");
+
+        Self { element }
+    }
+
+    pub fn is_holding(&self) -> bool {
+        self.element.checked()
+    }
+
+    /// Register a callback fired when the user toggles the peak hold.
+    pub fn on_change<F: FnMut(bool) + 'static>(&self, mut peak_hold_cb: F) {
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            peak_hold_cb(input.checked());
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+}
+
+pub struct AveragingToggle {
+    element: web_sys::HtmlInputElement,
+}
+
+impl AveragingToggle {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("averaging")
+            .expect("averaging input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast averaging to HtmlInputElement");
+
+        Self { element }
+    }
+
+    pub fn is_averaging(&self) -> bool {
+        self.element.checked()
+    }
+
+    /// Register a callback fired when the user toggles the averaging.
+    pub fn on_change<F: FnMut(bool) + 'static>(&self, mut averaging_cb: F) {
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            averaging_cb(input.checked());
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+}
+
+/// Switches filtering the parts of the loaded file, updating a `T` filter when toggled.
+pub struct FilterList<T> {
+    document: Document,
+    element: web_sys::Element,
+    filter: Rc<RefCell<T>>,
+    change_cb: Rc<RefCell<dyn FnMut(T)>>,
+}
+
+/// Mute and solo switches for every channel of the loaded file.
+pub type ChannelList = FilterList<ChannelFilter>;
+
+/// Enable switches for every track of the loaded file.
+pub type TrackList = FilterList<TrackFilter>;
+
+impl<T: Clone + Default + 'static> FilterList<T> {
+    /// `change_cb` is fired with the new filter whenever the user toggles a switch.
+    pub fn new<F: FnMut(T) + 'static>(document: &Document, element_id: &str, change_cb: F) -> Self {
+        let element = document
+            .get_element_by_id(element_id)
+            .expect("filter list element not found");
+
+        Self {
+            document: document.clone(),
+            element,
+            filter: Rc::new(RefCell::new(T::default())),
+            change_cb: Rc::new(RefCell::new(change_cb)),
+        }
+    }
+
+    /// Remove all switches and reset the filter
+    fn clear(&self) {
+        self.element.set_inner_html("");
+        *self.filter.borrow_mut() = T::default();
+    }
+
+    /// Append a labeled input of the given type, which updates the filter when changed
+    fn add_switch<F: Fn(&mut T, bool) + 'static>(
+        &self,
+        input_type: &str,
+        text: &str,
+        checked: bool,
+        update: F,
+    ) -> Result<(), JsValue> {
+        let label = self.document.create_element("label")?;
+        let input = self
+            .document
+            .create_element("input")?
+            .dyn_into::<web_sys::HtmlInputElement>()?;
+        input.set_type(input_type);
+        if input_type == "radio" {
+            // Radios of a list are exclusive with each other
+            input.set_name(&self.element.id());
+        }
+        input.set_checked(checked);
+
+        let filter = self.filter.clone();
+        let change_cb = self.change_cb.clone();
+        let on_change_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let input: web_sys::HtmlInputElement = event
+                .target()
+                .unwrap()
+                .dyn_into()
+                .expect("cannot get correct target for change");
+
+            update(&mut filter.borrow_mut(), input.checked());
+            let filter = filter.borrow().clone();
+            (change_cb.borrow_mut())(filter);
+        }) as Box<dyn FnMut(_)>);
+
+        input.add_event_listener_with_callback(
+            "change",
+            on_change_closure.as_ref().unchecked_ref(),
+        )?;
+        on_change_closure.forget();
+
+        label.append_child(&input)?;
+        label.append_with_str_1(text)?;
+        self.element.append_child(&label)?;
+        Ok(())
+    }
+}
+
+impl ChannelList {
+    /// Replace the switches with ones for the given channels, muted as in `channel_filter` and
+    /// none soloed.
+    pub fn set_channels(
+        &self,
+        channels: &[u8],
+        channel_filter: &ChannelFilter,
+    ) -> Result<(), JsValue> {
+        self.clear();
+        *self.filter.borrow_mut() = channel_filter.clone();
+
+        self.add_switch("radio", "No solo", true, |channel_filter, checked| {
+            if checked {
+                channel_filter.set_solo(None);
+            }
+        })?;
+
+        for &channel in channels {
+            self.add_switch(
+                "checkbox",
+                &format!("Channel {}", channel + 1),
+                !channel_filter.is_muted(channel),
+                move |channel_filter, checked| channel_filter.set_muted(channel, !checked),
+            )?;
+            self.add_switch("radio", "Solo", false, move |channel_filter, checked| {
+                if checked {
+                    channel_filter.set_solo(Some(channel));
+                }
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+impl TrackList {
+    /// Replace the switches with ones for the tracks with the given names, all enabled.
+    pub fn set_tracks(&self, track_names: &[String]) -> Result<(), JsValue> {
+        self.clear();
+
+        for (track_index, name) in track_names.iter().enumerate() {
+            self.add_switch("checkbox", name, true, move |track_filter, checked| {
+                track_filter.set_enabled(track_index, checked)
+            })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Number of sliders of the [`HarmonicEditor`]
+const HARMONICS: usize = 16;
+
+/// Time without slider changes after which the [`HarmonicEditor`] fires, so that the waves aren't
+/// rebuilt for every step of a drag
+const HARMONICS_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// A vertical slider for the amplitude of every harmonic, the first of which is the fundamental.
+#[allow(dead_code)]
+pub struct HarmonicEditor {
+    sliders: Rc<Vec<web_sys::HtmlInputElement>>,
+}
+
+impl HarmonicEditor {
+    /// `change_cb` is fired with the amplitudes once the user stops moving the sliders.
+    pub fn new<F: FnMut(Vec<f32>) + 'static>(
+        document: &Document,
+        mut change_cb: F,
+    ) -> Result<Self, JsValue> {
+        let element = document
+            .get_element_by_id("harmonics")
+            .expect("harmonics element not found");
+
+        let mut sliders = vec![];
+        for harmonic in 0..HARMONICS {
+            let slider = document
+                .create_element("input")?
+                .dyn_into::<web_sys::HtmlInputElement>()?;
+            slider.set_type("range");
+            slider.set_min("0");
+            slider.set_max("1");
+            slider.set_step("0.01");
+            slider.set_value(if harmonic == 0 { "1" } else { "0" });
+            slider.set_title(&format!("Harmonic {}", harmonic + 1));
+
+            element.append_child(&slider)?;
+            sliders.push(slider);
+        }
+        let sliders = Rc::new(sliders);
+
+        let sliders_c = sliders.clone();
+        let fire_closure = Closure::wrap(Box::new(move || {
+            let amplitudes = sliders_c
+                .iter()
+                .map(|slider| slider.value_as_number() as f32)
+                .collect();
+            change_cb(amplitudes);
+        }) as Box<dyn FnMut()>);
+
+        // Every change restarts the timeout, so it only fires once the sliders settle
+        let timeout = Cell::new(None);
+        let on_input_closure = Closure::wrap(Box::new(move |_: web_sys::Event| {
+            let window = web_sys::window().expect("no global `window` exists");
+            if let Some(handle) = timeout.take() {
+                window.clear_timeout_with_handle(handle);
+            }
+
+            let handle = window
+                .set_timeout_with_callback_and_timeout_and_arguments_0(
+                    fire_closure.as_ref().unchecked_ref(),
+                    HARMONICS_DEBOUNCE.as_millis() as i32,
+                )
+                .expect("failed to set timeout");
+            timeout.set(Some(handle));
+        }) as Box<dyn FnMut(_)>);
+
+        for slider in sliders.iter() {
+            slider.add_event_listener_with_callback(
+                "input",
+                on_input_closure.as_ref().unchecked_ref(),
+            )?;
+        }
+        on_input_closure.forget();
+
+        Ok(Self { sliders })
+    }
+}
+
+/// Description of the tracks of the loaded file, and its copyright notice
+pub struct TrackInfoPanel {
+    document: Document,
+    element: web_sys::Element,
+}
+
+impl TrackInfoPanel {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("track-info")
+            .expect("track-info element not found");
+
+        Self {
+            document: document.clone(),
+            element,
+        }
+    }
+
+    /// Replace the panel contents with the given tracks. Texts come from the file, so they're
+    /// set as text content and never parsed as HTML.
+    pub fn set_tracks(
+        &self,
+        track_infos: &[TrackInfo],
+        copyright: Option<&str>,
+    ) -> Result<(), JsValue> {
+        self.element.set_inner_html("");
+
+        if let Some(copyright) = copyright {
+            let paragraph = self.document.create_element("p")?;
+            paragraph.set_text_content(Some(copyright));
+            self.element.append_child(&paragraph)?;
+        }
+
+        let list = self.document.create_element("ul")?;
+        for (track_index, track_info) in track_infos.iter().enumerate() {
+            let mut text = match &track_info.name {
+                Some(name) => name.clone(),
+                None => format!("Track {}", track_index + 1),
+            };
+            if let Some(instrument) = &track_info.instrument {
+                text += &format!(" ({instrument})");
+            }
+
+            let channel_labels = track_info.channel_labels();
+            if !channel_labels.is_empty() {
+                text += &format!(", {}", channel_labels.join(", "));
+            }
+            text += &format!(
+                ", {} notes, {}",
+                track_info.note_count,
+                format_duration(track_info.duration)
+            );
+
+            let item = self.document.create_element("li")?;
+            item.set_text_content(Some(&text));
+            list.append_child(&item)?;
+        }
+
+        self.element.append_child(&list)?;
+        Ok(())
+    }
+}
+
+/// Lyrics of the loaded file, one line of syllables per row, with the one being sung highlighted
+/// Files of the playlist, with the current one highlighted and buttons to move through them
+pub struct PlaylistView {
+    document: Document,
+    element: web_sys::Element,
+    previous_button: web_sys::HtmlButtonElement,
+    next_button: web_sys::HtmlButtonElement,
+}
+
+impl PlaylistView {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("playlist")
+            .expect("playlist element not found");
+        let button = |id: &str| {
+            document
+                .get_element_by_id(id)
+                .expect("playlist button not found")
+                .dyn_into::<web_sys::HtmlButtonElement>()
+                .expect("failed to cast playlist button to HtmlButtonElement")
+        };
+
+        Self {
+            document: document.clone(),
+            element,
+            previous_button: button("playlist-previous"),
+            next_button: button("playlist-next"),
+        }
+    }
+
+    /// Register a callback fired with the index of an entry the user clicks on.
+    pub fn on_select<F: FnMut(usize) + 'static>(&self, mut select_cb: F) {
+        // Listened to on the list, since the entries are replaced with it
+        let on_click_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
+            let index = event
+                .target()
+                .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+                .and_then(|target| target.closest("li").ok().flatten())
+                .and_then(|entry| entry.get_attribute("data-index"))
+                .and_then(|index| index.parse().ok());
+
+            if let Some(index) = index {
+                select_cb(index);
+            }
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("click", on_click_closure.as_ref().unchecked_ref())
+            .expect("failed to set click event handler");
+        on_click_closure.forget();
+    }
+
+    /// Replace the list with entries named `names`, highlighting the `current` one
+    pub fn set_entries(&self, names: &[String], current: Option<usize>) -> Result<(), JsValue> {
+        self.element.set_inner_html("");
+        for (index, name) in names.iter().enumerate() {
+            let entry = self.document.create_element("li")?;
+            entry.set_text_content(Some(name));
+            entry.set_attribute("data-index", &index.to_string())?;
+            if current == Some(index) {
+                entry.set_class_name("current");
+            }
+            self.element.append_child(&entry)?;
+        }
+
+        // Moving through a single file is pointless
+        self.previous_button.set_disabled(names.len() < 2);
+        self.next_button.set_disabled(names.len() < 2);
+        Ok(())
+    }
+
+    /// Register a callback fired when the user clicks the previous button.
+    pub fn on_previous<F: FnMut() + 'static>(&self, previous_cb: F) {
+        on_click(&self.previous_button, previous_cb);
+    }
+
+    /// Register a callback fired when the user clicks the next button.
+    pub fn on_next<F: FnMut() + 'static>(&self, next_cb: F) {
+        on_click(&self.next_button, next_cb);
+    }
+}
+
+fn on_click<F: FnMut() + 'static>(button: &web_sys::HtmlButtonElement, mut click_cb: F) {
+    let on_click_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+        click_cb();
+    }) as Box<dyn FnMut(_)>);
+
+    button
+        .add_event_listener_with_callback("click", on_click_closure.as_ref().unchecked_ref())
+        .expect("failed to set click event handler");
+    on_click_closure.forget();
+}
+
+/// Lowest and highest notes of the on-screen piano keyboard, three octaves from C3
+const KEYBOARD_RANGE: (u8, u8) = (48, 84);
+
+/// Piano keyboard played by pressing its keys on the screen, or the keys of the computer keyboard
+/// mapped by [`live::computer_key_note`].
+pub struct PianoKeyboard {
+    element: web_sys::Element,
+}
 
-            if let Some(file) = input.files().and_then(|f| f.item(0)) {
-                let reader = FileReader::new().expect("failed to create file reader");
-                let midi_cb_c = midi_cb_c.clone();
-                let error_cb_c = error_cb_c.clone();
+impl PianoKeyboard {
+    /// Fill the keyboard container with keys
+    pub fn new(document: &Document) -> Result<Self, JsValue> {
+        let element = document
+            .get_element_by_id("keyboard")
+            .expect("keyboard element not found");
 
-                let on_load_closure = Closure::wrap(Box::new(move |event: web_sys::Event| {
-                    let reader: web_sys::FileReader = event
-                        .target()
-                        .unwrap()
-                        .dyn_into()
-                        .expect("cannot get correct target for load");
+        for note in KEYBOARD_RANGE.0..=KEYBOARD_RANGE.1 {
+            let key = document.create_element("div")?;
+            key.set_class_name(key_class(note, false));
+            key.set_attribute("data-note", &note.to_string())?;
+            element.append_child(&key)?;
+        }
+
+        Ok(Self { element })
+    }
 
-                    let array_buffer = reader.result().expect("failed to get result");
-                    let buffer = Uint8Array::new(&array_buffer).to_vec();
+    /// Register callbacks fired when a key is pressed and released. A key held down is pressed
+    /// once, however many times the computer keyboard repeats it. Keys are released when the page
+    /// loses focus, since their release would go unnoticed.
+    pub fn on_play<P: FnMut(u8) + 'static, R: FnMut(u8) + 'static>(
+        &self,
+        press_cb: P,
+        release_cb: R,
+    ) {
+        let held_keys = Rc::new(RefCell::new(HeldKeys {
+            element: self.element.clone(),
+            keys: vec![],
+            pointers: HashMap::new(),
+            press_cb,
+            release_cb,
+        }));
 
-                    match midi::MIDIFileData::try_from(&buffer[..]) {
-                        Ok(data) => (midi_cb_c.borrow_mut())(data),
-                        Err(error) => (error_cb_c.borrow_mut())(error),
-                    }
+        let held_keys_c = held_keys.clone();
+        add_listener(&self.element, "pointerdown", move |event| {
+            let event = event.unchecked_into::<web_sys::PointerEvent>();
+            if let Some((key, note)) = event_key(&event) {
+                event.prevent_default();
+                // Touches capture the pointer, which would keep sliding to other keys unnoticed
+                let _ = key.release_pointer_capture(event.pointer_id());
+                held_keys_c
+                    .borrow_mut()
+                    .press_pointer(event.pointer_id(), note);
+            }
+        });
+
+        // Sliding to another key while pressed plays it instead
+        let held_keys_c = held_keys.clone();
+        add_listener(&self.element, "pointerover", move |event| {
+            let event = event.unchecked_into::<web_sys::PointerEvent>();
+            let mut held_keys = held_keys_c.borrow_mut();
+            if held_keys.pointers.contains_key(&event.pointer_id())
+                && let Some((_, note)) = event_key(&event)
+            {
+                held_keys.press_pointer(event.pointer_id(), note);
+            }
+        });
+
+        // Pointers may be lifted away from the keyboard
+        let window = web_sys::window().expect("no global `window` exists");
+        for event_type in ["pointerup", "pointercancel"] {
+            let held_keys_c = held_keys.clone();
+            add_listener(&window, event_type, move |event| {
+                let event = event.unchecked_into::<web_sys::PointerEvent>();
+                held_keys_c.borrow_mut().release_pointer(event.pointer_id());
+            });
+        }
+
+        let held_keys_c = held_keys.clone();
+        add_listener(&window, "keydown", move |event| {
+            let event = event.unchecked_into::<web_sys::KeyboardEvent>();
+            if let Some(note) = computer_key_note(&event) {
+                event.prevent_default();
+                if !event.repeat() {
+                    held_keys_c.borrow_mut().press(note);
+                }
+            }
+        });
+
+        let held_keys_c = held_keys.clone();
+        add_listener(&window, "keyup", move |event| {
+            let event = event.unchecked_into::<web_sys::KeyboardEvent>();
+            if let Some(note) = computer_key_note(&event) {
+                held_keys_c.borrow_mut().release(note);
+            }
+        });
+
+        add_listener(&window, "blur", move |_event| {
+            held_keys.borrow_mut().release_all();
+        });
+    }
+}
+
+/// Keys held down on a [`PianoKeyboard`], and the callbacks playing them
+struct HeldKeys<P, R> {
+    element: web_sys::Element,
+    keys: Vec<u8>,
+    /// Key held by each pointer pressing the keyboard
+    pointers: HashMap<i32, u8>,
+    press_cb: P,
+    release_cb: R,
+}
+
+impl<P: FnMut(u8), R: FnMut(u8)> HeldKeys<P, R> {
+    fn press(&mut self, note: u8) {
+        if !self.keys.contains(&note) {
+            self.keys.push(note);
+            self.highlight(note, true);
+            (self.press_cb)(note);
+        }
+    }
+
+    fn release(&mut self, note: u8) {
+        if let Some(index) = self.keys.iter().position(|&key| key == note) {
+            self.keys.remove(index);
+            self.highlight(note, false);
+            (self.release_cb)(note);
+        }
+    }
+
+    fn press_pointer(&mut self, pointer_id: i32, note: u8) {
+        match self.pointers.insert(pointer_id, note) {
+            Some(previous) if previous == note => return,
+            Some(previous) => self.release(previous),
+            None => {}
+        }
+        self.press(note);
+    }
+
+    fn release_pointer(&mut self, pointer_id: i32) {
+        if let Some(note) = self.pointers.remove(&pointer_id) {
+            self.release(note);
+        }
+    }
+
+    fn release_all(&mut self) {
+        self.pointers.clear();
+        for note in self.keys.clone() {
+            self.release(note);
+        }
+    }
+
+    fn highlight(&self, note: u8, pressed: bool) {
+        if let Ok(Some(key)) = self
+            .element
+            .query_selector(&format!("[data-note=\"{note}\"]"))
+        {
+            key.set_class_name(key_class(note, pressed));
+        }
+    }
+}
+
+fn key_class(note: u8, pressed: bool) -> &'static str {
+    let black = matches!(note % 12, 1 | 3 | 6 | 8 | 10);
+    match (black, pressed) {
+        (false, false) => "key white",
+        (false, true) => "key white pressed",
+        (true, false) => "key black",
+        (true, true) => "key black pressed",
+    }
+}
+
+/// Key of the keyboard under a pointer, and its note
+fn event_key(event: &web_sys::PointerEvent) -> Option<(web_sys::Element, u8)> {
+    let key = event
+        .target()?
+        .dyn_into::<web_sys::Element>()
+        .ok()?
+        .closest("[data-note]")
+        .ok()??;
+    let note = key.get_attribute("data-note")?.parse().ok()?;
+    Some((key, note))
+}
+
+/// Note of a key of the computer keyboard, unless it is typed into a field or used in a shortcut
+fn computer_key_note(event: &web_sys::KeyboardEvent) -> Option<u8> {
+    let typing = event
+        .target()
+        .and_then(|target| target.dyn_into::<web_sys::Element>().ok())
+        .is_some_and(|target| {
+            matches!(target.tag_name().as_str(), "INPUT" | "SELECT" | "TEXTAREA")
+        });
+    if typing || event.ctrl_key() || event.alt_key() || event.meta_key() {
+        return None;
+    }
+
+    live::computer_key_note(&event.code())
+}
+
+fn add_listener<F: FnMut(web_sys::Event) + 'static>(
+    target: &web_sys::EventTarget,
+    event_type: &str,
+    listener: F,
+) {
+    let closure = Closure::wrap(Box::new(listener) as Box<dyn FnMut(_)>);
+    target
+        .add_event_listener_with_callback(event_type, closure.as_ref().unchecked_ref())
+        .expect("failed to set event handler");
+    closure.forget();
+}
+
+/// Switch recording the notes played live, and buttons for the last recording.
+pub struct RecordingControls {
+    toggle: web_sys::HtmlInputElement,
+    play_button: web_sys::HtmlButtonElement,
+    download_button: web_sys::HtmlButtonElement,
+}
+
+impl RecordingControls {
+    pub fn new(document: &Document) -> Self {
+        let toggle = document
+            .get_element_by_id("record")
+            .expect("record input element not found")
+            .dyn_into::<web_sys::HtmlInputElement>()
+            .expect("failed to cast record to HtmlInputElement");
+        let button = |id: &str| {
+            document
+                .get_element_by_id(id)
+                .expect("recording button not found")
+                .dyn_into::<web_sys::HtmlButtonElement>()
+                .expect("failed to cast recording button to HtmlButtonElement")
+        };
+
+        Self {
+            toggle,
+            play_button: button("recording-play"),
+            download_button: button("recording-download"),
+        }
+    }
+
+    /// Register a callback fired when the user starts or stops recording.
+    pub fn on_record<F: FnMut(bool) + 'static>(&self, mut record_cb: F) {
+        let toggle = self.toggle.clone();
+        add_listener(&self.toggle, "change", move |_event| {
+            record_cb(toggle.checked());
+        });
+    }
+
+    /// Register a callback fired when the user clicks the button playing the last recording.
+    pub fn on_play<F: FnMut() + 'static>(&self, play_cb: F) {
+        on_click(&self.play_button, play_cb);
+    }
+
+    /// Register a callback fired when the user clicks the button downloading the last recording.
+    pub fn on_download<F: FnMut() + 'static>(&self, download_cb: F) {
+        on_click(&self.download_button, download_cb);
+    }
+
+    /// Enable the buttons once there is a recording
+    pub fn set_recorded(&self, recorded: bool) {
+        self.play_button.set_disabled(!recorded);
+        self.download_button.set_disabled(!recorded);
+    }
+}
+
+/// Messages releasing the notes of a device which is no longer listened to: the sustain pedal
+/// lifted, then All Notes Off
+const DEVICE_RELEASE_MESSAGES: [[u8; 3]; 2] = [[0xB0, 64, 0], [0xB0, 123, 0]];
+
+/// Select of the MIDI devices connected to the computer, listed once the user allows the page to
+/// access them.
+pub struct MidiDeviceSelect {
+    element: web_sys::HtmlSelectElement,
+    connect_button: web_sys::HtmlButtonElement,
+}
+
+impl MidiDeviceSelect {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("midi-device")
+            .expect("midi-device select element not found")
+            .dyn_into::<web_sys::HtmlSelectElement>()
+            .expect("failed to cast midi-device to HtmlSelectElement");
+        let connect_button = document
+            .get_element_by_id("midi-connect")
+            .expect("midi-connect button not found")
+            .dyn_into::<web_sys::HtmlButtonElement>()
+            .expect("failed to cast midi-connect to HtmlButtonElement");
+
+        Self {
+            element,
+            connect_button,
+        }
+    }
+
+    /// Request access to the MIDI devices when the user clicks the connect button, then pass the
+    /// channel events sent by the selected device to `event_cb`. Devices plugged in or out while
+    /// the page is open are added to or removed from the select. The notes of a device are
+    /// released when another one is selected or it is unplugged.
+    pub fn on_event<F: FnMut(midi::ChannelEvent) + 'static>(&self, event_cb: F) {
+        let event_cb = Rc::new(RefCell::new(event_cb));
+        let event_cb_c = event_cb.clone();
+        let on_message = Closure::wrap(Box::new(move |event: web_sys::MidiMessageEvent| {
+            // Messages other than channel events, such as the clock, are ignored
+            if let Some(event) = event
+                .data()
+                .ok()
+                .and_then(|message| midi::ChannelEvent::from_message(&message))
+            {
+                (event_cb_c.borrow_mut())(event);
+            }
+        }) as Box<dyn FnMut(_)>);
+
+        let devices = Rc::new(RefCell::new(MidiDevices {
+            element: self.element.clone(),
+            access: None,
+            input: None,
+            event_cb,
+            on_message,
+        }));
+
+        let devices_c = devices.clone();
+        add_listener(&self.element, "change", move |_event| {
+            let mut devices = devices_c.borrow_mut();
+            let id = devices.element.value();
+            devices.listen(&id);
+        });
+
+        let element = self.element.clone();
+        let connect_button = self.connect_button.clone();
+        on_click(&self.connect_button, move || {
+            let navigator = web_sys::window()
+                .expect("no global `window` exists")
+                .navigator();
+            let request = match navigator.request_midi_access() {
+                Ok(request) => request,
+                Err(error) => {
+                    log::warn!("this browser doesn't support MIDI devices: {:?}", error);
+                    return;
+                }
+            };
+
+            let devices = devices.clone();
+            let element = element.clone();
+            let connect_button = connect_button.clone();
+            let on_access = Closure::wrap(Box::new(move |access: JsValue| {
+                let access = access.unchecked_into::<web_sys::MidiAccess>();
+                let devices_c = devices.clone();
+                let on_state_change = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+                    devices_c.borrow_mut().refresh();
                 }) as Box<dyn FnMut(_)>);
+                access.set_onstatechange(Some(on_state_change.as_ref().unchecked_ref()));
+                on_state_change.forget();
 
-                reader.set_onload(Some(on_load_closure.as_ref().unchecked_ref()));
-                reader
-                    .read_as_array_buffer(&file)
-                    .expect("cannot read as array buffer");
+                devices.borrow_mut().access = Some(access);
+                devices.borrow_mut().refresh();
+                element.set_disabled(false);
+                connect_button.set_hidden(true);
+            }) as Box<dyn FnMut(JsValue)>);
+            let on_denied = Closure::wrap(Box::new(move |error: JsValue| {
+                log::warn!("access to MIDI devices was denied: {:?}", error);
+            }) as Box<dyn FnMut(JsValue)>);
+            let _ = request.then2(&on_access, &on_denied);
 
-                on_load_closure.forget();
+            on_access.forget();
+            on_denied.forget();
+        });
+    }
+}
+
+/// MIDI devices listed by a [`MidiDeviceSelect`], and the one listened to
+struct MidiDevices<F> {
+    element: web_sys::HtmlSelectElement,
+    access: Option<web_sys::MidiAccess>,
+    input: Option<web_sys::MidiInput>,
+    event_cb: Rc<RefCell<F>>,
+    /// Set as the message handler of the selected device
+    on_message: Closure<dyn FnMut(web_sys::MidiMessageEvent)>,
+}
+
+impl<F: FnMut(midi::ChannelEvent)> MidiDevices<F> {
+    /// List the connected devices, keeping the selected one if it is still there
+    fn refresh(&mut self) {
+        let Some(access) = &self.access else {
+            return;
+        };
+
+        let selected = self.element.value();
+        // Only the option for no device is kept
+        self.element.set_length(1);
+        for input in access.inputs().values().into_iter().flatten() {
+            let input = input.unchecked_into::<web_sys::MidiInput>();
+            let name = input.name().unwrap_or_else(|| input.id());
+            if let Ok(option) =
+                web_sys::HtmlOptionElement::new_with_text_and_value(&name, &input.id())
+            {
+                let _ = self.element.append_child(&option);
+            }
+        }
+
+        self.element.set_value(&selected);
+        if self.element.value() != selected {
+            log::warn!("the selected MIDI device was disconnected");
+            self.element.set_value("");
+            self.listen("");
+        }
+    }
+
+    /// Pass on the messages of the device with the given `id`, or of none if it isn't connected
+    fn listen(&mut self, id: &str) {
+        if let Some(input) = self.input.take() {
+            input.set_onmidimessage(None);
+            let _ = input.close();
+            for message in DEVICE_RELEASE_MESSAGES {
+                if let Some(event) = midi::ChannelEvent::from_message(&message) {
+                    (self.event_cb.borrow_mut())(event);
+                }
             }
+        }
+
+        self.input = self
+            .access
+            .as_ref()
+            .and_then(|access| access.inputs().get(id));
+        if let Some(input) = &self.input {
+            input.set_onmidimessage(Some(self.on_message.as_ref().unchecked_ref()));
+        }
+    }
+}
+
+pub struct LyricsDisplay {
+    document: Document,
+    element: web_sys::Element,
+    fragments: RefCell<Vec<web_sys::Element>>,
+    current: Cell<Option<usize>>,
+}
+
+impl LyricsDisplay {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("lyrics")
+            .expect("lyrics element not found");
+
+        Self {
+            document: document.clone(),
+            element,
+            fragments: RefCell::new(vec![]),
+            current: Cell::new(None),
+        }
+    }
+
+    /// Replace the shown lyrics, with none highlighted
+    pub fn set_lyrics(&self, lyrics: &LyricsTimeline) -> Result<(), JsValue> {
+        self.element.set_inner_html("");
+        self.current.set(None);
+        self.fragments.borrow_mut().clear();
+        if lyrics.is_empty() {
+            return Ok(());
+        }
+
+        let mut fragments = vec![];
+        let mut line = self.document.create_element("div")?;
+        for (_, text) in lyrics.fragments() {
+            let text = match text.strip_prefix('\n') {
+                Some(text) => {
+                    if line.has_child_nodes() {
+                        self.element.append_child(&line)?;
+                        line = self.document.create_element("div")?;
+                    }
+                    text
+                }
+                None => text,
+            };
+
+            let fragment = self.document.create_element("span")?;
+            fragment.set_text_content(Some(text));
+            line.append_child(&fragment)?;
+            fragments.push(fragment);
+        }
+        self.element.append_child(&line)?;
+
+        *self.fragments.borrow_mut() = fragments;
+        Ok(())
+    }
+
+    /// Highlight the fragment with the given index, as returned by [`LyricsTimeline::current`]
+    pub fn highlight(&self, current: Option<usize>) -> Result<(), JsValue> {
+        let previous = self.current.replace(current);
+        if previous == current {
+            return Ok(());
+        }
+
+        let fragments = self.fragments.borrow();
+        if let Some(fragment) = previous.and_then(|index| fragments.get(index)) {
+            fragment.set_class_name("");
+        }
+        if let Some(fragment) = current.and_then(|index| fragments.get(index)) {
+            fragment.set_class_name("current");
+        }
+        Ok(())
+    }
+}
+
+/// Format as `m:ss`
+/// Progress of the raw synthesizer rendering the loaded file, hidden when it isn't rendering
+pub struct RenderProgress {
+    element: web_sys::HtmlProgressElement,
+}
+
+impl RenderProgress {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("render-progress")
+            .expect("render-progress element not found")
+            .dyn_into::<web_sys::HtmlProgressElement>()
+            .expect("failed to cast render-progress to HtmlProgressElement");
+
+        Self { element }
+    }
+
+    /// Show the part of the file rendered so far, from 0 to 1
+    pub fn show(&self, progress: f64) {
+        self.element.set_value(progress);
+        self.element.set_hidden(false);
+    }
+
+    pub fn hide(&self) {
+        self.element.set_hidden(true);
+    }
+}
+
+/// Entries kept by the status panel, older ones are removed
+const MAX_STATUS_ENTRIES: u32 = 50;
+
+/// Severity of a status panel entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatusLevel {
+    Error,
+    Warning,
+    Info,
+}
+
+impl StatusLevel {
+    /// CSS class of the entries
+    fn class(self) -> &'static str {
+        match self {
+            StatusLevel::Error => "error",
+            StatusLevel::Warning => "warning",
+            StatusLevel::Info => "info",
+        }
+    }
+}
+
+/// Timestamped messages for the user, newest last, shown without blocking the page
+#[derive(Clone)]
+pub struct StatusPanel {
+    document: Document,
+    /// Hidden while there are no entries
+    element: web_sys::HtmlElement,
+    entries: web_sys::Element,
+}
+
+impl StatusPanel {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("status")
+            .expect("status element not found")
+            .dyn_into::<web_sys::HtmlElement>()
+            .expect("failed to cast status to HtmlElement");
+        let entries = document
+            .get_element_by_id("status-entries")
+            .expect("status-entries element not found");
+        let clear_button = document
+            .get_element_by_id("status-clear")
+            .expect("status-clear button not found");
+
+        let panel = Self {
+            document: document.clone(),
+            element,
+            entries,
+        };
+
+        let panel_c = panel.clone();
+        let on_click_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            panel_c.clear();
         }) as Box<dyn FnMut(_)>);
+        clear_button
+            .add_event_listener_with_callback("click", on_click_closure.as_ref().unchecked_ref())
+            .expect("failed to set click event handler");
+        on_click_closure.forget();
 
-        element
-            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
-            .expect("failed to set change event handler");
-        on_change_closure.forget();
+        panel
+    }
+
+    pub fn error(&self, message: &str) {
+        self.push(StatusLevel::Error, message);
+    }
+
+    pub fn warning(&self, message: &str) {
+        self.push(StatusLevel::Warning, message);
+    }
+
+    pub fn info(&self, message: &str) {
+        self.push(StatusLevel::Info, message);
+    }
+
+    /// Append an entry, removing the oldest ones past [`MAX_STATUS_ENTRIES`]
+    fn push(&self, level: StatusLevel, message: &str) {
+        if let Err(error) = self.append(level, message) {
+            // Not logged, which would show it on the panel again
+            web_sys::console::error_2(&"failed to show status:".into(), &error);
+        }
+    }
+
+    fn append(&self, level: StatusLevel, message: &str) -> Result<(), JsValue> {
+        let entry = self.document.create_element("p")?;
+        entry.set_class_name(&format!("status {}", level.class()));
+        let time = self.document.create_element("time")?;
+        time.set_text_content(Some(&String::from(
+            js_sys::Date::new_0().to_locale_time_string("default"),
+        )));
+        entry.append_child(&time)?;
+        entry.append_with_str_1(message)?;
+        self.entries.append_child(&entry)?;
+
+        while self.entries.child_element_count() > MAX_STATUS_ENTRIES {
+            if let Some(oldest) = self.entries.first_element_child() {
+                oldest.remove();
+            }
+        }
+        self.element.set_hidden(false);
+        self.entries.set_scroll_top(self.entries.scroll_height());
+        Ok(())
+    }
+
+    pub fn clear(&self) {
+        self.entries.set_inner_html("");
+        self.element.set_hidden(true);
+    }
+}
+
+pub struct StatisticsTable {
+    document: Document,
+    element: web_sys::Element,
+}
+
+impl StatisticsTable {
+    pub fn new(document: &Document) -> Self {
+        let element = document
+            .get_element_by_id("statistics")
+            .expect("statistics element not found");
 
         Self {
+            document: document.clone(),
             element,
-            midi_cb,
-            error_cb,
         }
     }
+
+    /// Replace the table with a summary of the loaded file
+    pub fn set_statistics(&self, statistics: &Statistics) -> Result<(), JsValue> {
+        self.element.set_inner_html("");
+
+        let pitch_range = match statistics.pitch_range {
+            Some((lowest, highest)) => format!("{lowest} to {highest}"),
+            None => "none".to_owned(),
+        };
+        let channels = statistics
+            .channel_note_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(channel, count)| format!("{}: {count}", channel + 1))
+            .collect::<Vec<_>>();
+        // Numbered 1-based, as in the General MIDI program list
+        let programs = statistics
+            .programs
+            .iter()
+            .map(|program| format!("{} {}", program + 1, gm::program_name(*program)))
+            .collect::<Vec<_>>();
+        let families = statistics
+            .families
+            .iter()
+            .map(|family| family.name())
+            .collect::<Vec<_>>();
+        let drums = statistics
+            .percussion_keys
+            .iter()
+            .map(|key| gm::percussion_name(*key).map_or_else(|| key.to_string(), str::to_owned))
+            .collect::<Vec<_>>();
+        let (slowest, fastest) = statistics.tempo_range;
+
+        let rows = [
+            ("Notes", statistics.note_count.to_string()),
+            ("Pitch range", pitch_range),
+            ("Max polyphony", statistics.max_polyphony.to_string()),
+            ("Notes per channel", channels.join(", ")),
+            ("Programs", programs.join(", ")),
+            ("Families", families.join(", ")),
+            ("Drums", drums.join(", ")),
+            ("Duration", format_duration(statistics.duration)),
+            ("Tempo", format!("{slowest:.0} to {fastest:.0} BPM")),
+        ];
+
+        let table = self.document.create_element("table")?;
+        for (label, value) in rows {
+            let row = self.document.create_element("tr")?;
+            let header = self.document.create_element("th")?;
+            header.set_text_content(Some(label));
+            let cell = self.document.create_element("td")?;
+            cell.set_text_content(Some(&value));
+            row.append_child(&header)?;
+            row.append_child(&cell)?;
+            table.append_child(&row)?;
+        }
+
+        self.element.append_child(&table)?;
+        Ok(())
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+/// Let the user save `bytes` as a file, through a temporary object URL.
+pub fn download_bytes(
+    document: &Document,
+    bytes: &[u8],
+    file_name: &str,
+    mime_type: &str,
+) -> Result<(), JsValue> {
+    let parts = js_sys::Array::of1(&Uint8Array::from(bytes));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options)?;
+    download_blob(document, &blob, file_name)
+}
+
+/// Let the user save `blob` as a file, through a temporary object URL.
+pub fn download_blob(
+    document: &Document,
+    blob: &web_sys::Blob,
+    file_name: &str,
+) -> Result<(), JsValue> {
+    let url = web_sys::Url::create_object_url_with_blob(blob)?;
+
+    let anchor = document
+        .create_element("a")?
+        .dyn_into::<web_sys::HtmlAnchorElement>()?;
+    anchor.set_href(&url);
+    anchor.set_download(file_name);
+    anchor.click();
+
+    web_sys::Url::revoke_object_url(&url)
+}
+
+/// Canvas of the page with the given id
+pub fn canvas(document: &Document, id: &str) -> web_sys::HtmlCanvasElement {
+    document
+        .get_element_by_id(id)
+        .unwrap_or_else(|| panic!("{id} canvas element not found"))
+        .dyn_into::<web_sys::HtmlCanvasElement>()
+        .unwrap_or_else(|_| panic!("failed to cast {id} to HtmlCanvasElement"))
+}
+
+pub fn request_animation_frame(f: &Closure<dyn FnMut()>) {
+    web_sys::window()
+        .expect("no global `window` exists")
+        .request_animation_frame(f.as_ref().unchecked_ref())
+        .expect("failed to request animation frame");
 }
 
 pub struct SynthKind {
     element: web_sys::HtmlSelectElement,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SynthKindOption {
     Raw,
     WebAudio,
+    /// The Web Audio synthesizer, rendered ahead of the playback
+    Offline,
 }
 
 impl SynthKind {
@@ -100,13 +2551,47 @@ impl SynthKind {
         Self { element }
     }
 
+    /// Register a callback fired when the user selects another synthesizer.
+    pub fn on_change<F: FnMut(SynthKindOption) + 'static>(&self, mut synth_kind_cb: F) {
+        let element = self.element.clone();
+        let on_change_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            synth_kind_cb(selected_synth_kind(&element));
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+
     pub fn get_selected(&self) -> SynthKindOption {
-        let value = self.element.value();
-        match value.as_str() {
-            "raw" => SynthKindOption::Raw,
-            "web_audio" => SynthKindOption::WebAudio,
-            _ => panic!("unknown synth kind selected"),
-        }
+        selected_synth_kind(&self.element)
+    }
+
+    pub fn select(&self, synth_kind: SynthKindOption) {
+        self.element.set_value(synth_kind_value(synth_kind));
+    }
+}
+
+fn selected_synth_kind(select: &web_sys::HtmlSelectElement) -> SynthKindOption {
+    synth_kind_option(&select.value()).expect("unknown synth kind selected")
+}
+
+/// Synthesizer of an option value of the synth kind select
+pub fn synth_kind_option(value: &str) -> Option<SynthKindOption> {
+    match value {
+        "raw" => Some(SynthKindOption::Raw),
+        "web_audio" => Some(SynthKindOption::WebAudio),
+        "offline" => Some(SynthKindOption::Offline),
+        _ => None,
+    }
+}
+
+pub fn synth_kind_value(synth_kind: SynthKindOption) -> &'static str {
+    match synth_kind {
+        SynthKindOption::Raw => "raw",
+        SynthKindOption::WebAudio => "web_audio",
+        SynthKindOption::Offline => "offline",
     }
 }
 
@@ -114,11 +2599,26 @@ pub struct WaveKind {
     element: web_sys::HtmlSelectElement,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum WaveKindOption {
+    /// Each channel is played with the timbre of its program
+    Instrument,
     Sine,
     Square,
     Sawtooth,
     Triangle,
+    /// Pulse wave with a 25% duty cycle
+    PulseQuarter,
+    /// Pulse wave with a 12.5% duty cycle
+    PulseEighth,
+    Noise,
+    Organ,
+    Bell,
+    Strings,
+    /// The wavetable preset loaded by the user
+    Custom,
+    /// The harmonics drawn by the user
+    Harmonics,
 }
 
 impl WaveKind {
@@ -133,13 +2633,76 @@ impl WaveKind {
     }
 
     pub fn get_selected(&self) -> WaveKindOption {
-        let value = self.element.value();
-        match value.as_str() {
-            "sine" => WaveKindOption::Sine,
-            "square" => WaveKindOption::Square,
-            "sawtooth" => WaveKindOption::Sawtooth,
-            "triangle" => WaveKindOption::Triangle,
-            _ => panic!("unknown wave kind selected"),
+        selected_wave_kind(&self.element)
+    }
+
+    /// Register a callback fired when the user selects another wave.
+    pub fn on_change<F: FnMut(WaveKindOption) + 'static>(&self, mut wave_kind_cb: F) {
+        let element = self.element.clone();
+        let on_change_closure = Closure::wrap(Box::new(move |_event: web_sys::Event| {
+            wave_kind_cb(selected_wave_kind(&element));
+        }) as Box<dyn FnMut(_)>);
+
+        self.element
+            .add_event_listener_with_callback("change", on_change_closure.as_ref().unchecked_ref())
+            .expect("failed to set change event handler");
+        on_change_closure.forget();
+    }
+
+    /// Enable and select the option of `wave_kind`, such as the one of a just loaded preset
+    pub fn select(&self, wave_kind: WaveKindOption) {
+        let value = wave_kind_value(wave_kind);
+        if let Some(option) = self
+            .element
+            .query_selector(&format!("option[value={value}]"))
+            .ok()
+            .flatten()
+            .and_then(|option| option.dyn_into::<web_sys::HtmlOptionElement>().ok())
+        {
+            option.set_disabled(false);
         }
+        self.element.set_value(value);
+    }
+}
+
+fn selected_wave_kind(select: &web_sys::HtmlSelectElement) -> WaveKindOption {
+    wave_kind_option(&select.value()).expect("unknown wave kind selected")
+}
+
+/// Wave of an option value of the wave kind select
+pub fn wave_kind_option(value: &str) -> Option<WaveKindOption> {
+    match value {
+        "instrument" => Some(WaveKindOption::Instrument),
+        "sine" => Some(WaveKindOption::Sine),
+        "square" => Some(WaveKindOption::Square),
+        "sawtooth" => Some(WaveKindOption::Sawtooth),
+        "triangle" => Some(WaveKindOption::Triangle),
+        "pulse_25" => Some(WaveKindOption::PulseQuarter),
+        "pulse_12_5" => Some(WaveKindOption::PulseEighth),
+        "noise" => Some(WaveKindOption::Noise),
+        "organ" => Some(WaveKindOption::Organ),
+        "bell" => Some(WaveKindOption::Bell),
+        "strings" => Some(WaveKindOption::Strings),
+        "custom" => Some(WaveKindOption::Custom),
+        "harmonics" => Some(WaveKindOption::Harmonics),
+        _ => None,
+    }
+}
+
+pub fn wave_kind_value(wave_kind: WaveKindOption) -> &'static str {
+    match wave_kind {
+        WaveKindOption::Instrument => "instrument",
+        WaveKindOption::Sine => "sine",
+        WaveKindOption::Square => "square",
+        WaveKindOption::Sawtooth => "sawtooth",
+        WaveKindOption::Triangle => "triangle",
+        WaveKindOption::PulseQuarter => "pulse_25",
+        WaveKindOption::PulseEighth => "pulse_12_5",
+        WaveKindOption::Noise => "noise",
+        WaveKindOption::Organ => "organ",
+        WaveKindOption::Bell => "bell",
+        WaveKindOption::Strings => "strings",
+        WaveKindOption::Custom => "custom",
+        WaveKindOption::Harmonics => "harmonics",
     }
 }
@@ -0,0 +1,517 @@
+//! Signal processing helpers which don't depend on the synthesizers.
+use std::{collections::VecDeque, f32::consts::TAU, f64::consts::TAU as TAU_F64, time::Duration};
+
+/// In-place radix-2 fast Fourier transform of the complex signal split into `real` and `imag`.
+///
+/// The inverse transform is not normalized, so transforming forth and back scales the signal by
+/// its length. Both slices must be of the same length, which must be a power of two.
+pub fn fft(real: &mut [f32], imag: &mut [f32], inverse: bool) {
+    let n = real.len();
+    assert_eq!(n, imag.len());
+    assert!(n.is_power_of_two());
+
+    // Bit reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            real.swap(i, j);
+            imag.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * TAU / len as f32;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let (w_imag, w_real) = (angle * k as f32).sin_cos();
+                let (a, b) = (start + k, start + k + len / 2);
+
+                let t_real = real[b] * w_real - imag[b] * w_imag;
+                let t_imag = real[b] * w_imag + imag[b] * w_real;
+                real[b] = real[a] - t_real;
+                imag[b] = imag[a] - t_imag;
+                real[a] += t_real;
+                imag[a] += t_imag;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Coefficients of a second order filter, divided by `a0` so it doesn't appear
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BiquadCoefficients {
+    pub b0: f64,
+    pub b1: f64,
+    pub b2: f64,
+    pub a1: f64,
+    pub a2: f64,
+}
+
+impl BiquadCoefficients {
+    /// Filter multiplying every sample by `gain`
+    const fn gain(gain: f64) -> Self {
+        Self {
+            b0: gain,
+            b1: 0.0,
+            b2: 0.0,
+            a1: 0.0,
+            a2: 0.0,
+        }
+    }
+
+    fn normalized([b0, b1, b2]: [f64; 3], [a0, a1, a2]: [f64; 3]) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+        }
+    }
+
+    /// Low-pass filter of the Audio EQ Cookbook, as computed by a Web Audio `BiquadFilterNode`:
+    /// the resonance `q` is in dB, and cutoffs from the Nyquist frequency up pass everything.
+    pub fn lowpass(sample_rate: f64, cutoff: f64, q: f64) -> Self {
+        let frequency = cutoff / (sample_rate / 2.0);
+        if frequency >= 1.0 {
+            return Self::gain(1.0);
+        }
+        if frequency <= 0.0 {
+            return Self::gain(0.0);
+        }
+
+        let (sin, cos) = (TAU_F64 / 2.0 * frequency).sin_cos();
+        let alpha = sin / (2.0 * 10f64.powf(q / 20.0));
+        Self::normalized(
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha],
+        )
+    }
+
+    /// Low shelf of the Web Audio `BiquadFilterNode`, with a slope of 1: frequencies below the
+    /// corner `frequency` are boosted by `gain` dB, and the corner itself by half of it.
+    pub fn lowshelf(sample_rate: f64, frequency: f64, gain: f64) -> Self {
+        let a = 10f64.powf(gain / 40.0);
+        let frequency = frequency / (sample_rate / 2.0);
+        if frequency >= 1.0 {
+            return Self::gain(a * a);
+        }
+        if frequency <= 0.0 {
+            return Self::gain(1.0);
+        }
+
+        let (sin, cos) = (TAU_F64 / 2.0 * frequency).sin_cos();
+        let alpha = sin / 2.0 * 2f64.sqrt();
+        let root = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + root),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - root),
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + root,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - root,
+            ],
+        )
+    }
+
+    /// High shelf of the Web Audio `BiquadFilterNode`, with a slope of 1: frequencies above the
+    /// corner `frequency` are boosted by `gain` dB, and the corner itself by half of it.
+    pub fn highshelf(sample_rate: f64, frequency: f64, gain: f64) -> Self {
+        let a = 10f64.powf(gain / 40.0);
+        let frequency = frequency / (sample_rate / 2.0);
+        if frequency >= 1.0 {
+            return Self::gain(1.0);
+        }
+        if frequency <= 0.0 {
+            return Self::gain(a * a);
+        }
+
+        let (sin, cos) = (TAU_F64 / 2.0 * frequency).sin_cos();
+        let alpha = sin / 2.0 * 2f64.sqrt();
+        let root = 2.0 * a.sqrt() * alpha;
+        Self::normalized(
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + root),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - root),
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + root,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - root,
+            ],
+        )
+    }
+
+    /// Peaking filter of the Web Audio `BiquadFilterNode`, boosting `frequency` by `gain` dB over
+    /// a bandwidth set by `q`, which unlike the one of low-pass filters is not in dB
+    pub fn peaking(sample_rate: f64, frequency: f64, q: f64, gain: f64) -> Self {
+        let a = 10f64.powf(gain / 40.0);
+        let frequency = frequency / (sample_rate / 2.0);
+        if frequency <= 0.0 || frequency >= 1.0 {
+            return Self::gain(1.0);
+        }
+        if q <= 0.0 {
+            return Self::gain(a * a);
+        }
+
+        let (sin, cos) = (TAU_F64 / 2.0 * frequency).sin_cos();
+        let alpha = sin / (2.0 * q);
+        Self::normalized(
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a],
+        )
+    }
+}
+
+/// Second order filter in direct form II transposed, keeping its state across blocks of samples
+#[derive(Debug, Clone)]
+pub struct Biquad {
+    coefficients: BiquadCoefficients,
+    z1: f64,
+    z2: f64,
+}
+
+impl Biquad {
+    pub fn new(coefficients: BiquadCoefficients) -> Self {
+        Self {
+            coefficients,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// Filter `samples` in place, following on from the previous ones
+    pub fn process(&mut self, samples: &mut [f32]) {
+        let BiquadCoefficients { b0, b1, b2, a1, a2 } = self.coefficients;
+        for sample in samples {
+            let input = *sample as f64;
+            let output = b0 * input + self.z1;
+            self.z1 = b1 * input - a1 * output + self.z2;
+            self.z2 = b2 * input - a2 * output;
+            *sample = output as f32;
+        }
+    }
+}
+
+/// Settings of [`limit`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LimiterSettings {
+    /// Level no sample goes over, as an amplitude
+    pub threshold: f32,
+    /// Time over which the gain is lowered ahead of a peak
+    pub lookahead: Duration,
+    /// Time constant of the gain rising back once a peak is over
+    pub release: Duration,
+}
+
+impl Default for LimiterSettings {
+    fn default() -> Self {
+        Self {
+            // -1 dBFS
+            threshold: 0.891,
+            lookahead: Duration::from_millis(5),
+            release: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Lower the level of `channels` wherever any of them peaks over the threshold, so no sample
+/// does. All channels get the same gain, which keeps the stereo image.
+pub fn limit(channels: &mut [Vec<f32>], sample_rate: f32, settings: LimiterSettings) {
+    let length = channels.iter().map(Vec::len).max().unwrap_or(0);
+    let peaks = (0..length)
+        .map(|index| {
+            channels
+                .iter()
+                .filter_map(|channel| channel.get(index))
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        })
+        .collect::<Vec<_>>();
+
+    let gain = limiter_gain(&peaks, sample_rate, settings);
+    for channel in channels {
+        for (sample, gain) in channel.iter_mut().zip(&gain) {
+            *sample *= gain;
+        }
+    }
+}
+
+/// Gain of every sample making its `peaks` fit under the threshold.
+///
+/// The gain needed by each sample is held for the lookahead before it, then averaged over the
+/// lookahead. The average only includes gains held over the sample, which are no higher than
+/// the one it needs, and it ramps down by at most a lookahead fraction per sample. Afterwards,
+/// the gain rises back no faster than the release.
+fn limiter_gain(peaks: &[f32], sample_rate: f32, settings: LimiterSettings) -> Vec<f32> {
+    let lookahead = ((settings.lookahead.as_secs_f32() * sample_rate).round() as usize).max(1);
+    let needed = peaks
+        .iter()
+        .map(|&peak| {
+            if peak > settings.threshold {
+                settings.threshold / peak
+            } else {
+                1.0
+            }
+        })
+        .collect::<Vec<_>>();
+
+    // Lowest gain needed from each sample to the end of the lookahead after it, with the indices
+    // of the candidates for it in a deque whose gains increase
+    let mut held = vec![1.0; needed.len()];
+    let mut window = VecDeque::new();
+    for index in (0..needed.len()).rev() {
+        while window
+            .back()
+            .is_some_and(|&last| needed[last] >= needed[index])
+        {
+            window.pop_back();
+        }
+        window.push_back(index);
+        if window
+            .front()
+            .is_some_and(|&first| first >= index + lookahead)
+        {
+            window.pop_front();
+        }
+        held[index] = needed[window[0]];
+    }
+
+    // Averaged with the gains of the lookahead before, counting the start as unity gain
+    let release = 1.0 - (-1.0 / (settings.release.as_secs_f32() * sample_rate).max(1.0)).exp();
+    let mut sum = lookahead as f64;
+    let mut gain = 1.0f32;
+    held.iter()
+        .enumerate()
+        .map(|(index, &held_gain)| {
+            sum += held_gain as f64;
+            sum -= match index.checked_sub(lookahead) {
+                Some(dropped) => held[dropped] as f64,
+                None => 1.0,
+            };
+            let average = (sum / lookahead as f64) as f32;
+
+            gain = average.min(gain + (1.0 - gain) * release);
+            gain
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_coefficients(actual: BiquadCoefficients, expected: [f64; 5]) {
+        let BiquadCoefficients { b0, b1, b2, a1, a2 } = actual;
+        for (value, expected) in [b0, b1, b2, a1, a2].into_iter().zip(expected) {
+            assert!(
+                (value - expected).abs() < 1e-6,
+                "{actual:?} != {expected:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn lowpass_coefficients_follow_the_cookbook() {
+        // Butterworth, at a Q of 1/sqrt(2)
+        assert_coefficients(
+            BiquadCoefficients::lowpass(48000.0, 1000.0, -10.0 * 2f64.log10()),
+            [0.0039161, 0.0078323, 0.0039161, -1.8153411, 0.8310056],
+        );
+        assert_coefficients(
+            BiquadCoefficients::lowpass(48000.0, 1000.0, 0.0),
+            [0.0040155, 0.0080310, 0.0040155, -1.8614084, 0.8774705],
+        );
+        assert_coefficients(
+            BiquadCoefficients::lowpass(44100.0, 5000.0, 6.0),
+            [0.1044818, 0.2089635, 0.1044818, -1.3005855, 0.7185126],
+        );
+
+        // Cutoffs out of range pass everything or nothing
+        assert_coefficients(
+            BiquadCoefficients::lowpass(44100.0, 30000.0, 6.0),
+            [1.0, 0.0, 0.0, 0.0, 0.0],
+        );
+        assert_coefficients(
+            BiquadCoefficients::lowpass(44100.0, 0.0, 6.0),
+            [0.0, 0.0, 0.0, 0.0, 0.0],
+        );
+    }
+
+    #[test]
+    fn shelf_and_peaking_coefficients_follow_the_cookbook() {
+        assert_coefficients(
+            BiquadCoefficients::lowshelf(48000.0, 250.0, 6.0),
+            [1.0080629, -1.9606939, 0.9541148, -1.9610640, 0.9618076],
+        );
+        assert_coefficients(
+            BiquadCoefficients::highshelf(48000.0, 3200.0, -6.0),
+            [0.5543029, -0.7303273, 0.2755963, -1.5067447, 0.6063165],
+        );
+        assert_coefficients(
+            BiquadCoefficients::peaking(44100.0, 1000.0, 1.0, 12.0),
+            [1.1024303, -1.9117108, 0.8288492, -1.9117108, 0.9312795],
+        );
+
+        // No gain leaves the audio unchanged
+        for coefficients in [
+            BiquadCoefficients::lowshelf(48000.0, 250.0, 0.0),
+            BiquadCoefficients::highshelf(48000.0, 3200.0, 0.0),
+            BiquadCoefficients::peaking(48000.0, 1000.0, 1.0, 0.0),
+        ] {
+            let BiquadCoefficients { b0, b1, b2, a1, a2 } = coefficients;
+            assert!((b0 - 1.0).abs() < 1e-9);
+            assert!((b1 - a1).abs() < 1e-9 && (b2 - a2).abs() < 1e-9);
+        }
+
+        // Shelves out of range boost everything or nothing, by a gain of 6 dB or about 2
+        assert_coefficients(
+            BiquadCoefficients::lowshelf(44100.0, 30000.0, 20.0 * 2f64.log10()),
+            [2.0, 0.0, 0.0, 0.0, 0.0],
+        );
+        assert_coefficients(
+            BiquadCoefficients::highshelf(44100.0, 30000.0, 6.0),
+            [1.0, 0.0, 0.0, 0.0, 0.0],
+        );
+        assert_coefficients(
+            BiquadCoefficients::peaking(44100.0, 0.0, 1.0, 6.0),
+            [1.0, 0.0, 0.0, 0.0, 0.0],
+        );
+    }
+
+    #[test]
+    fn limited_spikes_stay_under_the_threshold() {
+        let sample_rate = 48000.0;
+        let settings = LimiterSettings {
+            threshold: 0.5,
+            ..LimiterSettings::default()
+        };
+        // A sine at the threshold, with a spike 6 dB above it from 0.5 s to 0.6 s
+        let signal = (0..48000)
+            .map(|i| {
+                let level = if (24000..28800).contains(&i) {
+                    1.0
+                } else {
+                    0.5
+                };
+                level * (TAU * 440.0 * i as f32 / sample_rate).sin()
+            })
+            .collect::<Vec<_>>();
+        let mut channels = vec![
+            signal.clone(),
+            signal.iter().map(|sample| -sample).collect(),
+        ];
+        limit(&mut channels, sample_rate, settings);
+
+        for channel in &channels {
+            let peak = channel
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+            assert!(peak <= 0.5 + 1e-6, "{peak}");
+        }
+        // Both channels get the same gain
+        assert!(channels[0].iter().zip(&channels[1]).all(|(l, r)| *l == -r));
+        // The gain is lowered just ahead of the spike, and left alone long before it
+        assert_eq!(channels[0][..20000], signal[..20000]);
+        assert!(
+            channels[0][24000..28800]
+                .iter()
+                .any(|sample| sample.abs() > 0.49)
+        );
+
+        let peaks = signal.iter().map(|sample| sample.abs()).collect::<Vec<_>>();
+        let gain = limiter_gain(&peaks, sample_rate, settings);
+        assert!(gain.iter().all(|&gain| (0.5..=1.0).contains(&gain)));
+        // The gain only moves by small steps: down over the 240 samples of the lookahead, and up
+        // with the release
+        let lookahead_step = 0.5 / 240.0;
+        assert!(
+            gain.windows(2)
+                .all(|pair| (pair[1] - pair[0]).abs() <= lookahead_step + 1e-6)
+        );
+        // and it comes back up after the spike
+        assert!(gain[47999] > 0.99);
+    }
+
+    #[test]
+    fn quiet_signals_are_left_alone() {
+        let mut channels = vec![vec![0.1, -0.8, 0.891, 0.0]];
+        limit(&mut channels, 44100.0, LimiterSettings::default());
+        assert_eq!(channels, vec![vec![0.1, -0.8, 0.891, 0.0]]);
+
+        let mut channels: Vec<Vec<f32>> = vec![vec![], vec![]];
+        limit(&mut channels, 44100.0, LimiterSettings::default());
+    }
+
+    #[test]
+    fn lowpass_filters_pass_low_frequencies_only() {
+        let sample_rate = 48000.0;
+        let coefficients = BiquadCoefficients::lowpass(sample_rate, 1000.0, 0.0);
+        let BiquadCoefficients { b0, b1, b2, a1, a2 } = coefficients;
+        // Unit gain at DC, and none at the Nyquist frequency
+        assert!(((b0 + b1 + b2) / (1.0 + a1 + a2) - 1.0).abs() < 1e-9);
+        assert!((b0 - b1 + b2).abs() < 1e-9);
+
+        let level = |frequency: f64| {
+            let mut samples = (0..4800)
+                .map(|i| (TAU_F64 * frequency * i as f64 / sample_rate).sin() as f32)
+                .collect::<Vec<_>>();
+            // Filtered in blocks, which must not change the result
+            let mut filter = Biquad::new(coefficients);
+            for block in samples.chunks_mut(100) {
+                filter.process(block);
+            }
+            samples[2400..]
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(sample.abs()))
+        };
+        assert!((level(100.0) - 1.0).abs() < 0.02);
+        // The resonance of 0 dB leaves the cutoff about as loud
+        assert!((level(1000.0) - 1.0).abs() < 0.05);
+        // 12 dB per octave above the cutoff
+        assert!(level(8000.0) < 0.02);
+    }
+
+    #[test]
+    fn fft_matches_the_discrete_transform() {
+        const N: usize = 16;
+        let signal = (0..N)
+            .map(|i| ((i * 7) % 5) as f32 - 2.0)
+            .collect::<Vec<_>>();
+
+        let mut real = signal.clone();
+        let mut imag = vec![0.0; N];
+        fft(&mut real, &mut imag, false);
+
+        for k in 0..N {
+            let (expected_real, expected_imag) =
+                signal
+                    .iter()
+                    .enumerate()
+                    .fold((0.0, 0.0), |(re, im), (i, &x)| {
+                        let angle = -TAU * (k * i) as f32 / N as f32;
+                        (re + x * angle.cos(), im + x * angle.sin())
+                    });
+            assert!((real[k] - expected_real).abs() < 1e-4);
+            assert!((imag[k] - expected_imag).abs() < 1e-4);
+        }
+
+        fft(&mut real, &mut imag, true);
+        for (value, expected) in real.iter().zip(&signal) {
+            assert!((value / N as f32 - expected).abs() < 1e-5);
+        }
+    }
+}
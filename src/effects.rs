@@ -0,0 +1,779 @@
+//! Effects applied to the output of all synthesizers.
+use std::{f32::consts::LN_10, time::Duration};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    dsp::BiquadCoefficients,
+    midi::{Tempo, TempoMap},
+    wave::{Wave, WhiteNoise},
+};
+
+/// Seeds of the noise of the left and right channels of impulse responses. Distinct seeds keep
+/// the channels uncorrelated, which makes the reverberation sound wide.
+const IMPULSE_SEEDS: [u64; 2] = [0x5EED_0001, 0x5EED_0002];
+
+/// Time over which changes of levels are ramped, to avoid zipper noise
+const LEVEL_RAMP: Duration = Duration::from_millis(30);
+
+/// Time over which changes of the delay time are ramped. The echoes bend in pitch meanwhile,
+/// rather than clicking.
+const DELAY_TIME_RAMP: Duration = Duration::from_millis(100);
+
+/// Longest delay of a [`Delay`]
+pub const MAX_DELAY_TIME: Duration = Duration::from_secs(2);
+
+/// Feedback above which the echoes would no longer fade out
+const MAX_FEEDBACK: f32 = 0.95;
+
+/// Lowest cutoff of the low-pass filter, in Hz
+pub const MIN_CUTOFF: f32 = 20.0;
+/// Highest cutoff of the low-pass filter, in Hz. It is past the Nyquist frequency of the usual
+/// sample rates, where the filter lets everything through.
+pub const MAX_CUTOFF: f32 = 24_000.0;
+
+/// Settings of the compressor at the end of the chain. They only squash the loudest passages, and
+/// the makeup gain Web Audio applies brings up the quiet ones.
+const COMPRESSOR_THRESHOLD: f32 = -18.0;
+const COMPRESSOR_KNEE: f32 = 12.0;
+const COMPRESSOR_RATIO: f32 = 8.0;
+const COMPRESSOR_ATTACK: Duration = Duration::from_millis(5);
+const COMPRESSOR_RELEASE: Duration = Duration::from_millis(250);
+
+/// Cutoff at `position` along a slider from 0 to 1, which moves by the same interval for the
+/// same distance, as the ear hears it
+pub fn cutoff_frequency(position: f32) -> f32 {
+    MIN_CUTOFF * (MAX_CUTOFF / MIN_CUTOFF).powf(position.clamp(0.0, 1.0))
+}
+
+/// Settings of the low-pass filter, as chosen by the user
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FilterSettings {
+    /// In Hz
+    pub cutoff: f32,
+    /// Resonance at the cutoff, in dB as the Q of a Web Audio low-pass filter
+    pub q: f32,
+    /// Each note of the Web Audio synthesizer also sweeps a filter down to the cutoff
+    pub envelope: bool,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        Self {
+            cutoff: MAX_CUTOFF,
+            q: 0.0,
+            envelope: false,
+        }
+    }
+}
+
+/// Highest boost, and cut, of a band of the equalizer, in dB
+pub const MAX_EQ_GAIN: f32 = 12.0;
+
+/// Q of the peaking bands of the equalizer. Their bandwidth of about an octave and a half blends
+/// them with their neighbours.
+const EQ_PEAKING_Q: f32 = 1.0;
+
+/// Ratio between the corners of the shelves of the equalizer and the frequencies of their bands.
+/// Shelves only give half their gain at their corner, so it is moved away from the band, which
+/// lies on the full gain instead.
+const EQ_SHELF_SPREAD: f32 = 2.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EqBandKind {
+    LowShelf,
+    Peaking,
+    HighShelf,
+}
+
+/// Band of the equalizer, boosting or cutting the frequencies around its own
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EqBand {
+    kind: EqBandKind,
+    /// In Hz
+    frequency: f32,
+}
+
+/// Bands of the equalizer, from the lowest
+pub const EQ_BANDS: [EqBand; 5] = [
+    EqBand {
+        kind: EqBandKind::LowShelf,
+        frequency: 100.0,
+    },
+    EqBand {
+        kind: EqBandKind::Peaking,
+        frequency: 400.0,
+    },
+    EqBand {
+        kind: EqBandKind::Peaking,
+        frequency: 1000.0,
+    },
+    EqBand {
+        kind: EqBandKind::Peaking,
+        frequency: 3000.0,
+    },
+    EqBand {
+        kind: EqBandKind::HighShelf,
+        frequency: 8000.0,
+    },
+];
+
+/// Gain of each of the [`EQ_BANDS`], in dB
+pub type EqGains = [f32; EQ_BANDS.len()];
+
+impl EqBand {
+    /// Frequency of the filter of the band, which is the corner of shelves
+    fn filter_frequency(&self) -> f32 {
+        match self.kind {
+            EqBandKind::LowShelf => self.frequency * EQ_SHELF_SPREAD,
+            EqBandKind::Peaking => self.frequency,
+            EqBandKind::HighShelf => self.frequency / EQ_SHELF_SPREAD,
+        }
+    }
+
+    fn filter_type(&self) -> web_sys::BiquadFilterType {
+        match self.kind {
+            EqBandKind::LowShelf => web_sys::BiquadFilterType::Lowshelf,
+            EqBandKind::Peaking => web_sys::BiquadFilterType::Peaking,
+            EqBandKind::HighShelf => web_sys::BiquadFilterType::Highshelf,
+        }
+    }
+
+    /// Coefficients of the filter of the band at `gain` dB, the same as the Web Audio one
+    pub fn coefficients(&self, sample_rate: f64, gain: f32) -> BiquadCoefficients {
+        let frequency = self.filter_frequency() as f64;
+        let gain = gain.clamp(-MAX_EQ_GAIN, MAX_EQ_GAIN) as f64;
+        match self.kind {
+            EqBandKind::LowShelf => BiquadCoefficients::lowshelf(sample_rate, frequency, gain),
+            EqBandKind::Peaking => {
+                BiquadCoefficients::peaking(sample_rate, frequency, EQ_PEAKING_Q as f64, gain)
+            }
+            EqBandKind::HighShelf => BiquadCoefficients::highshelf(sample_rate, frequency, gain),
+        }
+    }
+}
+
+/// Move `param` to `value` over `ramp`, from wherever it is now
+fn ramp_to(
+    param: &web_sys::AudioParam,
+    ctx: &web_sys::BaseAudioContext,
+    value: f32,
+    ramp: Duration,
+) -> Result<(), JsValue> {
+    let now = ctx.current_time();
+    param.cancel_scheduled_values(now)?;
+    param.set_value_at_time(param.value(), now)?;
+    param.linear_ramp_to_value_at_time(value, now + ramp.as_secs_f64())?;
+    Ok(())
+}
+
+/// Shape of the reverberation of a [`Reverb`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReverbSettings {
+    /// Length of the impulse response, after which the reverberation is cut off
+    pub length: Duration,
+    /// Time over which the reverberation fades by 60 dB
+    pub decay_time: Duration,
+}
+
+impl Default for ReverbSettings {
+    fn default() -> Self {
+        Self {
+            length: Duration::from_millis(2500),
+            decay_time: Duration::from_secs(2),
+        }
+    }
+}
+
+/// Stereo impulse response of a room, as noise fading out exponentially. Each channel is
+/// normalized to unit energy, so the reverberation is about as loud whatever its length.
+pub fn impulse_response(settings: ReverbSettings, sample_rate: u32) -> [Vec<f32>; 2] {
+    let samples = (settings.length.as_secs_f64() * sample_rate as f64).round() as usize;
+    // The amplitude falls by 60 dB, to a thousandth, over the decay time
+    let decay_rate = 3.0 * LN_10 / settings.decay_time.as_secs_f32().max(f32::EPSILON);
+
+    IMPULSE_SEEDS.map(|seed| {
+        let noise = WhiteNoise::new(seed);
+        let mut channel = (0..samples)
+            .map(|index| {
+                let time = index as f32 / sample_rate as f32;
+                noise.value(1.0, index as f32) * (-decay_rate * time).exp()
+            })
+            .collect::<Vec<_>>();
+
+        let energy = channel
+            .iter()
+            .map(|sample| sample * sample)
+            .sum::<f32>()
+            .sqrt();
+        if energy > 0.0 {
+            channel.iter_mut().for_each(|sample| *sample /= energy);
+        }
+        channel
+    })
+}
+
+/// Reverberation mixed into the audio passing through it. The dry signal always passes at full
+/// level, and the amount of reverberation sent along with it is set with [`Reverb::set_amount`].
+pub struct Reverb {
+    input: web_sys::GainNode,
+    /// Level of the reverberation
+    wet: web_sys::GainNode,
+    output: web_sys::GainNode,
+}
+
+impl Reverb {
+    /// Reverb of the given shape, with no reverberation until the amount is set
+    pub fn new(ctx: &web_sys::BaseAudioContext, settings: ReverbSettings) -> Result<Self, JsValue> {
+        let input = ctx.create_gain()?;
+        let dry = ctx.create_gain()?;
+        let wet = ctx.create_gain()?;
+        let output = ctx.create_gain()?;
+        wet.gain().set_value(0.0);
+
+        let convolver = ctx.create_convolver()?;
+        // The impulse response is normalized already
+        convolver.set_normalize(false);
+        let sample_rate = ctx.sample_rate();
+        let channels = impulse_response(settings, sample_rate as u32);
+        let buffer = ctx.create_buffer(
+            channels.len() as u32,
+            channels[0].len().max(1) as u32,
+            sample_rate,
+        )?;
+        for (channel, samples) in channels.iter().enumerate() {
+            buffer.copy_to_channel(samples, channel as i32)?;
+        }
+        convolver.set_buffer(Some(&buffer));
+
+        input.connect_with_audio_node(&dry)?;
+        dry.connect_with_audio_node(&output)?;
+        input.connect_with_audio_node(&convolver)?;
+        convolver.connect_with_audio_node(&wet)?;
+        wet.connect_with_audio_node(&output)?;
+
+        Ok(Self { input, wet, output })
+    }
+
+    /// Node the audio to reverberate is connected to
+    pub fn input(&self) -> &web_sys::AudioNode {
+        &self.input
+    }
+
+    /// Node playing the audio along with its reverberation
+    pub fn output(&self) -> &web_sys::AudioNode {
+        &self.output
+    }
+
+    /// Set the level of the reverberation, from 0 (none) to 1
+    pub fn set_amount(&self, ctx: &web_sys::BaseAudioContext, amount: f32) -> Result<(), JsValue> {
+        ramp_to(&self.wet.gain(), ctx, amount.clamp(0.0, 1.0), LEVEL_RAMP)
+    }
+}
+
+/// Length of a note, which a delay can be synced to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NoteValue {
+    Half,
+    Quarter,
+    DottedEighth,
+    Eighth,
+    Sixteenth,
+}
+
+impl NoteValue {
+    /// Length in quarter notes
+    pub fn beats(self) -> f64 {
+        match self {
+            NoteValue::Half => 2.0,
+            NoteValue::Quarter => 1.0,
+            NoteValue::DottedEighth => 0.75,
+            NoteValue::Eighth => 0.5,
+            NoteValue::Sixteenth => 0.25,
+        }
+    }
+}
+
+/// Time between the echoes of a [`Delay`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelayTime {
+    Free(Duration),
+    /// Follows the tempo of the loaded file
+    Synced(NoteValue),
+}
+
+impl DelayTime {
+    /// Delay at the time `position` of a file timed by `tempo_map`, or at the default tempo
+    /// when no file is loaded, up to [`MAX_DELAY_TIME`]
+    pub fn duration(self, tempo_map: Option<&TempoMap>, position: Duration) -> Duration {
+        let duration = match (self, tempo_map) {
+            (DelayTime::Free(duration), _) => duration,
+            (DelayTime::Synced(note_value), Some(tempo_map)) => tempo_map.beats_duration_at(
+                position.saturating_sub(tempo_map.start_offset()),
+                note_value.beats(),
+            ),
+            (DelayTime::Synced(note_value), None) => {
+                Duration::from_micros(Tempo::default().as_mpqn() as u64).mul_f64(note_value.beats())
+            }
+        };
+        duration.min(MAX_DELAY_TIME)
+    }
+}
+
+/// Settings of a [`Delay`], as chosen by the user
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DelaySettings {
+    pub time: DelayTime,
+    /// Level of each echo relative to the previous one, from 0 to 1
+    pub feedback: f32,
+    /// Level of the echoes, from 0 (none) to 1
+    pub mix: f32,
+}
+
+/// Echoes of the audio passing through it, fading out as they are fed back into the delay. The
+/// dry signal always passes at full level.
+pub struct Delay {
+    input: web_sys::GainNode,
+    delay: web_sys::DelayNode,
+    feedback: web_sys::GainNode,
+    /// Level of the echoes
+    wet: web_sys::GainNode,
+    output: web_sys::GainNode,
+    /// Delay time last set
+    time: Duration,
+}
+
+impl Delay {
+    /// Delay with no echoes until the mix is set
+    pub fn new(ctx: &web_sys::AudioContext) -> Result<Self, JsValue> {
+        let input = ctx.create_gain()?;
+        let delay = ctx.create_delay_with_max_delay_time(MAX_DELAY_TIME.as_secs_f64())?;
+        let feedback = ctx.create_gain()?;
+        let wet = ctx.create_gain()?;
+        let output = ctx.create_gain()?;
+        feedback.gain().set_value(0.0);
+        wet.gain().set_value(0.0);
+
+        input.connect_with_audio_node(&output)?;
+        input.connect_with_audio_node(&delay)?;
+        delay.connect_with_audio_node(&feedback)?;
+        feedback.connect_with_audio_node(&delay)?;
+        delay.connect_with_audio_node(&wet)?;
+        wet.connect_with_audio_node(&output)?;
+
+        Ok(Self {
+            input,
+            time: Duration::from_secs_f32(delay.delay_time().value()),
+            delay,
+            feedback,
+            wet,
+            output,
+        })
+    }
+
+    /// Change the time between echoes, unless it's about the same
+    pub fn set_time(
+        &mut self,
+        ctx: &web_sys::BaseAudioContext,
+        time: Duration,
+    ) -> Result<(), JsValue> {
+        let time = time.min(MAX_DELAY_TIME);
+        if time.abs_diff(self.time) < Duration::from_millis(1) {
+            return Ok(());
+        }
+
+        self.time = time;
+        ramp_to(
+            &self.delay.delay_time(),
+            ctx,
+            time.as_secs_f32(),
+            DELAY_TIME_RAMP,
+        )
+    }
+
+    pub fn set_feedback(
+        &self,
+        ctx: &web_sys::BaseAudioContext,
+        feedback: f32,
+    ) -> Result<(), JsValue> {
+        ramp_to(
+            &self.feedback.gain(),
+            ctx,
+            feedback.clamp(0.0, MAX_FEEDBACK),
+            LEVEL_RAMP,
+        )
+    }
+
+    pub fn set_mix(&self, ctx: &web_sys::BaseAudioContext, mix: f32) -> Result<(), JsValue> {
+        ramp_to(&self.wet.gain(), ctx, mix.clamp(0.0, 1.0), LEVEL_RAMP)
+    }
+}
+
+/// Effects the output of the synthesizers passes through: a low-pass filter, a delay, then a
+/// reverb
+pub struct EffectsChain {
+    filter: web_sys::BiquadFilterNode,
+    /// A filter for each of the [`EQ_BANDS`], in order
+    equalizer: Vec<web_sys::BiquadFilterNode>,
+    delay: Delay,
+    reverb: Reverb,
+    compressor: web_sys::DynamicsCompressorNode,
+    output: web_sys::GainNode,
+}
+
+impl EffectsChain {
+    /// Effects leaving the audio unchanged until they are set
+    pub fn new(ctx: &web_sys::AudioContext) -> Result<Self, JsValue> {
+        let filter = ctx.create_biquad_filter()?;
+        let delay = Delay::new(ctx)?;
+        let reverb = Reverb::new(ctx, ReverbSettings::default())?;
+        let FilterSettings { cutoff, q, .. } = FilterSettings::default();
+        filter.set_type(web_sys::BiquadFilterType::Lowpass);
+        filter.frequency().set_value(cutoff);
+        filter.q().set_value(q);
+
+        let equalizer = EQ_BANDS
+            .iter()
+            .map(|band| {
+                let node = ctx.create_biquad_filter()?;
+                node.set_type(band.filter_type());
+                node.frequency().set_value(band.filter_frequency());
+                node.q().set_value(EQ_PEAKING_Q);
+                node.gain().set_value(0.0);
+                Ok(node)
+            })
+            .collect::<Result<Vec<_>, JsValue>>()?;
+        let mut previous: &web_sys::AudioNode = &filter;
+        for node in &equalizer {
+            previous.connect_with_audio_node(node)?;
+            previous = node;
+        }
+        previous.connect_with_audio_node(&delay.input)?;
+        delay.output.connect_with_audio_node(reverb.input())?;
+
+        let compressor = ctx.create_dynamics_compressor()?;
+        compressor.threshold().set_value(COMPRESSOR_THRESHOLD);
+        compressor.knee().set_value(COMPRESSOR_KNEE);
+        compressor.ratio().set_value(COMPRESSOR_RATIO);
+        compressor
+            .attack()
+            .set_value(COMPRESSOR_ATTACK.as_secs_f32());
+        compressor
+            .release()
+            .set_value(COMPRESSOR_RELEASE.as_secs_f32());
+        let output = ctx.create_gain()?;
+        compressor.connect_with_audio_node(&output)?;
+        reverb.output().connect_with_audio_node(&output)?;
+
+        Ok(Self {
+            filter,
+            equalizer,
+            delay,
+            reverb,
+            compressor,
+            output,
+        })
+    }
+
+    /// Node the audio to process is connected to
+    pub fn input(&self) -> &web_sys::AudioNode {
+        &self.filter
+    }
+
+    /// Node playing the processed audio
+    pub fn output(&self) -> &web_sys::AudioNode {
+        &self.output
+    }
+
+    /// Pass the processed audio through the compressor, or straight to the output
+    pub fn set_compressor(&self, enabled: bool) -> Result<(), JsValue> {
+        let reverb_output = self.reverb.output();
+        reverb_output.disconnect()?;
+        if enabled {
+            reverb_output.connect_with_audio_node(&self.compressor)?;
+        } else {
+            reverb_output.connect_with_audio_node(&self.output)?;
+        }
+        Ok(())
+    }
+
+    /// Set the cutoff and the resonance of the low-pass filter
+    pub fn set_filter(
+        &self,
+        ctx: &web_sys::BaseAudioContext,
+        settings: FilterSettings,
+    ) -> Result<(), JsValue> {
+        ramp_to(&self.filter.frequency(), ctx, settings.cutoff, LEVEL_RAMP)?;
+        ramp_to(&self.filter.q(), ctx, settings.q, LEVEL_RAMP)
+    }
+
+    /// Set the gain of each band of the equalizer
+    pub fn set_equalizer(
+        &self,
+        ctx: &web_sys::BaseAudioContext,
+        gains: EqGains,
+    ) -> Result<(), JsValue> {
+        for (node, gain) in self.equalizer.iter().zip(gains) {
+            ramp_to(
+                &node.gain(),
+                ctx,
+                gain.clamp(-MAX_EQ_GAIN, MAX_EQ_GAIN),
+                LEVEL_RAMP,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Set the level of the reverberation, from 0 (none) to 1
+    pub fn set_reverb(&self, ctx: &web_sys::BaseAudioContext, amount: f32) -> Result<(), JsValue> {
+        self.reverb.set_amount(ctx, amount)
+    }
+
+    pub fn set_delay_time(
+        &mut self,
+        ctx: &web_sys::BaseAudioContext,
+        time: Duration,
+    ) -> Result<(), JsValue> {
+        self.delay.set_time(ctx, time)
+    }
+
+    /// Set the feedback and the mix of the delay. Its time depends on the position in the loaded
+    /// file when it is synced, so it is set on its own with [`EffectsChain::set_delay_time`].
+    pub fn set_delay_levels(
+        &self,
+        ctx: &web_sys::BaseAudioContext,
+        settings: DelaySettings,
+    ) -> Result<(), JsValue> {
+        self.delay.set_feedback(ctx, settings.feedback)?;
+        self.delay.set_mix(ctx, settings.mix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rms(samples: &[f32]) -> f32 {
+        (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+    }
+
+    #[test]
+    fn impulse_responses_are_normalized_decaying_noise() {
+        let sample_rate = 8000;
+        let settings = ReverbSettings {
+            length: Duration::from_secs(2),
+            decay_time: Duration::from_secs(1),
+        };
+        let channels = impulse_response(settings, sample_rate);
+        assert_eq!(channels[0].len(), 16000);
+        assert_eq!(channels[1].len(), 16000);
+
+        for channel in &channels {
+            let energy = channel.iter().map(|sample| sample * sample).sum::<f32>();
+            assert!((energy - 1.0).abs() < 1e-3, "{energy}");
+
+            // 60 dB quieter after the decay time, and over 100 dB near the end
+            let start = rms(&channel[..400]);
+            let decayed = rms(&channel[8000..8400]);
+            let ratio = decayed / start;
+            assert!((5e-4..2e-3).contains(&ratio), "{ratio}");
+            let ratio = rms(&channel[15600..]) / start;
+            assert!(ratio < 1e-5, "{ratio}");
+        }
+
+        // The channels are uncorrelated
+        let [left, right] = &channels;
+        let correlation = left.iter().zip(right).map(|(l, r)| l * r).sum::<f32>();
+        assert!(correlation.abs() < 0.05, "{correlation}");
+        assert_ne!(left, right);
+
+        // Generated the same every time
+        assert_eq!(impulse_response(settings, sample_rate), channels);
+    }
+
+    #[test]
+    fn synced_delays_follow_the_tempo_at_the_position() {
+        use crate::midi::{
+            MIDIFileData, MIDIFormat, TimeDivision,
+            builder::{FileBuilder, TrackBuilder},
+        };
+
+        let ms = Duration::from_millis;
+        let free = DelayTime::Free(ms(350));
+        assert_eq!(free.duration(None, Duration::ZERO), ms(350));
+        assert_eq!(
+            DelayTime::Free(ms(5000)).duration(None, Duration::ZERO),
+            MAX_DELAY_TIME
+        );
+
+        // Without a file, at 120 BPM
+        let synced = |note_value| DelayTime::Synced(note_value).duration(None, Duration::ZERO);
+        assert_eq!(synced(NoteValue::Half), ms(1000));
+        assert_eq!(synced(NoteValue::Quarter), ms(500));
+        assert_eq!(synced(NoteValue::DottedEighth), ms(375));
+        assert_eq!(synced(NoteValue::Eighth), ms(250));
+        assert_eq!(synced(NoteValue::Sixteenth), ms(125));
+
+        // 60 BPM, then 30 BPM from the second beat
+        let data: MIDIFileData = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(
+            TrackBuilder::new()
+                .set_tempo(Tempo::from_bpm(60))
+                .at(96)
+                .set_tempo(Tempo::from_bpm(30)),
+        )
+        .build();
+        let tempo_map = data.tempo_map(0);
+        let synced = |note_value, position| {
+            DelayTime::Synced(note_value).duration(Some(tempo_map), position)
+        };
+        assert_eq!(synced(NoteValue::Eighth, Duration::ZERO), ms(500));
+        assert_eq!(synced(NoteValue::DottedEighth, Duration::ZERO), ms(750));
+        assert_eq!(synced(NoteValue::Eighth, ms(2000)), ms(1000));
+        // Longer than the longest delay
+        assert_eq!(synced(NoteValue::Half, ms(2000)), MAX_DELAY_TIME);
+        assert_eq!(free.duration(Some(tempo_map), ms(2000)), ms(350));
+    }
+
+    #[test]
+    fn cutoff_sliders_move_by_intervals() {
+        assert_eq!(cutoff_frequency(0.0), MIN_CUTOFF);
+        assert!((cutoff_frequency(1.0) - MAX_CUTOFF).abs() < 0.1);
+        assert_eq!(cutoff_frequency(-1.0), MIN_CUTOFF);
+        assert!((cutoff_frequency(2.0) - MAX_CUTOFF).abs() < 0.1);
+
+        // The same distance is the same interval
+        let interval =
+            |position: f32| cutoff_frequency(position + 0.1) / cutoff_frequency(position);
+        assert!((interval(0.1) - interval(0.7)).abs() < 1e-3);
+        // Halfway is the geometric mean
+        let middle = cutoff_frequency(0.5);
+        assert!((middle - (MIN_CUTOFF * MAX_CUTOFF).sqrt()).abs() < 0.1);
+    }
+
+    #[test]
+    fn equalizer_bands_reach_their_gain_at_their_frequency() {
+        use std::f32::consts::{SQRT_2, TAU};
+
+        use crate::dsp::Biquad;
+
+        for sample_rate in [44100.0, 48000.0] {
+            // Level in dB of a sine at `frequency` through `band` at `gain`
+            let level = |band: &EqBand, gain: f32, frequency: f32| {
+                let mut samples = (0..sample_rate as usize)
+                    .map(|i| (TAU * frequency * i as f32 / sample_rate).sin())
+                    .collect::<Vec<_>>();
+                Biquad::new(band.coefficients(sample_rate as f64, gain)).process(&mut samples);
+                // Relative to the RMS of the sine, as sampled peaks fall short of high frequencies
+                20.0 * (rms(&samples[samples.len() / 2..]) * SQRT_2).log10()
+            };
+
+            for band in &EQ_BANDS {
+                for gain in [-MAX_EQ_GAIN, -6.0, 0.0, 3.0, MAX_EQ_GAIN] {
+                    let level = level(band, gain, band.frequency);
+                    assert!(
+                        (level - gain).abs() < 0.5,
+                        "{band:?} at {gain} dB: {level} dB"
+                    );
+                }
+                // Gains are kept within range
+                let level = level(band, 2.0 * MAX_EQ_GAIN, band.frequency);
+                assert!((level - MAX_EQ_GAIN).abs() < 0.5);
+            }
+
+            // Bands leave the frequencies far from theirs alone
+            assert!(level(&EQ_BANDS[0], MAX_EQ_GAIN, 8000.0).abs() < 0.5);
+            assert!(level(&EQ_BANDS[2], MAX_EQ_GAIN, 100.0).abs() < 0.5);
+            assert!(level(&EQ_BANDS[4], MAX_EQ_GAIN, 100.0).abs() < 0.5);
+        }
+    }
+
+    #[test]
+    fn empty_impulse_responses_are_silent() {
+        let settings = ReverbSettings {
+            length: Duration::ZERO,
+            decay_time: Duration::ZERO,
+        };
+        assert_eq!(impulse_response(settings, 44100), [vec![], vec![]]);
+
+        let settings = ReverbSettings {
+            length: Duration::from_millis(10),
+            decay_time: Duration::ZERO,
+        };
+        let channels = impulse_response(settings, 44100);
+        assert_eq!(channels[0].len(), 441);
+        assert!(channels[0].iter().all(|sample| sample.is_finite()));
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    const SAMPLE_RATE: f32 = 22050.0;
+
+    /// Left and right channels of a click played through a reverb with `amount` of
+    /// reverberation, over a second
+    async fn reverberated_click(amount: f32) -> [Vec<f32>; 2] {
+        let ctx =
+            web_sys::OfflineAudioContext::new_with_number_of_channels_and_length_and_sample_rate(
+                2,
+                SAMPLE_RATE as u32,
+                SAMPLE_RATE,
+            )
+            .unwrap();
+        let settings = ReverbSettings {
+            length: Duration::from_millis(500),
+            decay_time: Duration::from_millis(250),
+        };
+        let reverb = Reverb::new(&ctx, settings).unwrap();
+        reverb.wet.gain().set_value(amount);
+        reverb
+            .output()
+            .connect_with_audio_node(&ctx.destination())
+            .unwrap();
+
+        let click = ctx.create_buffer(1, 1, SAMPLE_RATE).unwrap();
+        click.copy_to_channel(&[1.0], 0).unwrap();
+        let source = ctx.create_buffer_source().unwrap();
+        source.set_buffer(Some(&click));
+        source.connect_with_audio_node(reverb.input()).unwrap();
+        source.start().unwrap();
+
+        let rendered = JsFuture::from(ctx.start_rendering().unwrap())
+            .await
+            .unwrap();
+        let rendered = web_sys::AudioBuffer::from(rendered);
+        [0, 1].map(|channel| rendered.get_channel_data(channel).unwrap())
+    }
+
+    #[wasm_bindgen_test]
+    async fn dry_audio_passes_through_the_reverb() {
+        for channel in reverberated_click(0.0).await {
+            assert!((channel[0] - 1.0).abs() < 1e-6);
+            assert!(channel[1..].iter().all(|sample| sample.abs() < 1e-6));
+        }
+    }
+
+    #[wasm_bindgen_test]
+    async fn the_wet_gain_sends_the_impulse_response() {
+        let [left, right] = reverberated_click(1.0).await;
+        for channel in [&left, &right] {
+            // The impulse response has unit energy, and is mixed with the click
+            let tail_energy = channel[1..]
+                .iter()
+                .map(|sample| sample * sample)
+                .sum::<f32>();
+            assert!((0.5..1.5).contains(&tail_energy), "{tail_energy}");
+            // It is cut off after its length
+            assert!(channel[12000..].iter().all(|sample| sample.abs() < 1e-6));
+        }
+        assert_ne!(left[1..100], right[1..100]);
+    }
+}
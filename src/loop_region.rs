@@ -0,0 +1,130 @@
+//! A-B loop region set by the user on the scrubber, for practicing a passage.
+use std::time::Duration;
+
+/// Shorter regions are lengthened, so that the playback isn't restarted faster than the passage
+/// can be heard
+pub const MIN_LOOP_LENGTH: Duration = Duration::from_secs(1);
+
+/// End of the loop region set by the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopPoint {
+    A,
+    B,
+}
+
+/// Points set by the user, in either order. The playback only loops once both are set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LoopPoints {
+    pub a: Option<Duration>,
+    pub b: Option<Duration>,
+}
+
+impl LoopPoints {
+    pub fn set(&mut self, point: LoopPoint, position: Duration) {
+        match point {
+            LoopPoint::A => self.a = Some(position),
+            LoopPoint::B => self.b = Some(position),
+        }
+    }
+
+    /// Move both points along with the events of the file, such as when its playback rate changes
+    pub fn scale(&mut self, factor: f64) {
+        self.a = self.a.map(|a| a.mul_f64(factor));
+        self.b = self.b.map(|b| b.mul_f64(factor));
+    }
+
+    /// Region between both points within a file lasting `duration`, whichever comes first. Regions
+    /// shorter than `MIN_LOOP_LENGTH` are lengthened past their end, or before their start when
+    /// the file ends first.
+    pub fn region(&self, duration: Duration) -> Option<LoopRegion> {
+        let (a, b) = (self.a?.min(duration), self.b?.min(duration));
+        let end = a.max(b).max(a.min(b) + MIN_LOOP_LENGTH).min(duration);
+        let start = a.min(b).min(end.saturating_sub(MIN_LOOP_LENGTH));
+        (start < end).then_some(LoopRegion { start, end })
+    }
+}
+
+/// Part of the file played over and over
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopRegion {
+    pub start: Duration,
+    pub end: Duration,
+}
+
+impl LoopRegion {
+    /// The playback, started from `from`, reached the end of the region at `position`, so it loops
+    /// back to the start. Playback started past the end, such as after seeking there, plays on.
+    pub fn passed_end(&self, from: Duration, position: Duration) -> bool {
+        from < self.end && position >= self.end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(a: u64, b: u64) -> LoopPoints {
+        LoopPoints {
+            a: Some(Duration::from_millis(a)),
+            b: Some(Duration::from_millis(b)),
+        }
+    }
+
+    fn region(start: u64, end: u64) -> Option<LoopRegion> {
+        Some(LoopRegion {
+            start: Duration::from_millis(start),
+            end: Duration::from_millis(end),
+        })
+    }
+
+    #[test]
+    fn region_spans_both_points_in_either_order() {
+        let duration = Duration::from_secs(60);
+        assert_eq!(points(2000, 5000).region(duration), region(2000, 5000));
+        assert_eq!(points(5000, 2000).region(duration), region(2000, 5000));
+        // Points past the end of the file are at its end
+        assert_eq!(points(2000, 90_000).region(duration), region(2000, 60_000));
+
+        let mut a_only = LoopPoints::default();
+        a_only.set(LoopPoint::A, Duration::from_secs(2));
+        assert_eq!(a_only.region(duration), None);
+        assert_eq!(LoopPoints::default().region(duration), None);
+    }
+
+    #[test]
+    fn short_regions_last_at_least_a_second() {
+        let duration = Duration::from_secs(60);
+        assert_eq!(points(2000, 2300).region(duration), region(2000, 3000));
+        assert_eq!(points(2000, 2000).region(duration), region(2000, 3000));
+        // Lengthened before the start at the end of the file
+        assert_eq!(
+            points(59_800, 59_500).region(duration),
+            region(59_000, 60_000)
+        );
+        // Files shorter than a second loop whole
+        assert_eq!(
+            points(100, 200).region(Duration::from_millis(500)),
+            region(0, 500)
+        );
+        assert_eq!(points(0, 0).region(Duration::ZERO), None);
+    }
+
+    #[test]
+    fn playback_loops_when_it_passes_the_end() {
+        let region = region(2000, 5000).unwrap();
+        let ms = Duration::from_millis;
+        assert!(!region.passed_end(ms(2000), ms(4999)));
+        assert!(region.passed_end(ms(2000), ms(5000)));
+        // Started before the region
+        assert!(region.passed_end(ms(0), ms(5100)));
+        // Seeked past the end
+        assert!(!region.passed_end(ms(6000), ms(7000)));
+    }
+
+    #[test]
+    fn points_scale_with_the_playback_rate() {
+        let mut loop_points = points(2000, 5000);
+        loop_points.scale(0.5);
+        assert_eq!(loop_points, points(1000, 2500));
+    }
+}
@@ -0,0 +1,141 @@
+//! Timed lyrics of a MIDI file, for karaoke display.
+use std::time::Duration;
+
+use crate::midi::{self, MIDIEventKind, MIDIFileData, MetaEvent};
+
+/// Lyric fragments, usually syllables, at the absolute time they are sung.
+///
+/// A fragment starting with `'\n'` begins a new line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LyricsTimeline {
+    fragments: Vec<(Duration, String)>,
+}
+
+impl LyricsTimeline {
+    /// Collect the lyrics meta events of all tracks. Files without any are read as karaoke (.kar)
+    /// files, whose lyrics are text events, except for the ones starting with `@` which hold the
+    /// title and other information.
+    ///
+    /// Both `/` and `\` at the start of a fragment, as well as line feeds and carriage returns
+    /// anywhere in it, mark line breaks.
+    pub fn new(data: &MIDIFileData) -> Self {
+        let has_lyrics = data.tracks().iter().any(|track| {
+            track
+                .events()
+                .iter()
+                .any(|event| matches!(event.kind(), MIDIEventKind::Meta(MetaEvent::Lyrics { .. })))
+        });
+
+        let mut fragments = vec![];
+        for track_index in 0..data.tracks().len() {
+            let mut line_break = false;
+
+            for (time, _, event) in data.walk_track(track_index) {
+                let text = match event.kind() {
+                    MIDIEventKind::Meta(MetaEvent::Lyrics { text }) if has_lyrics => text,
+                    MIDIEventKind::Meta(MetaEvent::TextEvent { text })
+                        if !has_lyrics && !text.starts_with(b"@") =>
+                    {
+                        text
+                    }
+                    _ => continue,
+                };
+
+                let mut text = midi::decode_text(text)
+                    .replace("\r\n", "\n")
+                    .replace('\r', "\n");
+                if let Some(rest) = text.strip_prefix(['/', '\\']) {
+                    text = format!("\n{rest}");
+                }
+
+                // A break at the end of a fragment belongs to the start of the next one
+                let breaks_before = line_break || text.starts_with('\n');
+                let breaks_after = text.ends_with('\n');
+                let text = text.trim_matches('\n');
+                if text.is_empty() {
+                    line_break = breaks_before || breaks_after;
+                    continue;
+                }
+                line_break = breaks_after;
+
+                let text = if breaks_before {
+                    format!("\n{text}")
+                } else {
+                    text.to_owned()
+                };
+                fragments.push((time, text));
+            }
+        }
+
+        // Stable, so fragments at the same time keep their order
+        fragments.sort_by_key(|&(time, _)| time);
+        Self { fragments }
+    }
+
+    pub fn fragments(&self) -> &[(Duration, String)] {
+        &self.fragments
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Index of the fragment being sung at `position`, which is the last one started
+    pub fn current(&self, position: Duration) -> Option<usize> {
+        self.fragments
+            .partition_point(|&(time, _)| time <= position)
+            .checked_sub(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(lyrics: &LyricsTimeline) -> Vec<&str> {
+        lyrics
+            .fragments()
+            .iter()
+            .map(|(_, text)| text.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn lyrics_are_timed_through_tempo_changes() {
+        let midi_bytes = include_bytes!("assets/lyrics.mid");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let lyrics = LyricsTimeline::new(&data);
+
+        assert_eq!(
+            texts(&lyrics),
+            ["Twin", "kle ", "twin", "kle", "\nlit", "tle ", "star"]
+        );
+
+        // 120 BPM for the first beat, 60 BPM afterwards
+        let times = lyrics
+            .fragments()
+            .iter()
+            .map(|&(time, _)| time.as_millis())
+            .collect::<Vec<_>>();
+        assert_eq!(times, [0, 250, 500, 1000, 2000, 3000, 4000]);
+
+        assert_eq!(lyrics.current(Duration::ZERO), Some(0));
+        assert_eq!(lyrics.current(Duration::from_millis(1500)), Some(3));
+        assert_eq!(lyrics.current(Duration::from_secs(10)), Some(6));
+    }
+
+    #[test]
+    fn karaoke_text_events_are_lyrics() {
+        let midi_bytes = include_bytes!("assets/karaoke.kar");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let lyrics = LyricsTimeline::new(&data);
+
+        // The @ information events are left out, and / and \ break lines
+        assert_eq!(
+            texts(&lyrics),
+            ["Hel", "lo ", "world", "\nSec", "ond ", "line", "\nEnd"]
+        );
+        assert_eq!(lyrics.fragments()[3].0, Duration::from_millis(1500));
+        assert_eq!(lyrics.current(Duration::from_millis(100)), None);
+    }
+}
@@ -1,11 +1,30 @@
-use std::{num::NonZeroU8, time::Duration};
+use std::{fmt, num::NonZeroU8, time::Duration};
+
+pub mod builder;
+pub mod gm;
+pub mod notes;
+pub mod recording;
+pub mod rpn;
+pub mod stats;
+pub mod transform;
 
 const MIDI_HEADER_CHUNK: &[u8] = b"MThd";
 const MIDI_TRACK_CHUNK: &[u8] = b"MTrk";
+const RIFF_CHUNK: &[u8] = b"RIFF";
+const RIFF_MIDI_FORM: &[u8] = b"RMID";
+const RIFF_DATA_CHUNK: &[u8] = b"data";
+
+/// General MIDI channel 10, which plays drums instead of pitched notes
+const PERCUSSION_CHANNEL: u8 = 9;
+
+/// Longest delta time a file can hold, in the 28 bits of a variable-length quantity
+const MAX_DELTA_TIME: u32 = 0x0FFF_FFFF;
 
 struct BigEndianReader<'a> {
     buffer: &'a [u8],
     pointer: usize,
+    /// Offset of the buffer within the whole file
+    base: usize,
 }
 
 impl<'a> BigEndianReader<'a> {
@@ -13,9 +32,15 @@ impl<'a> BigEndianReader<'a> {
         Self {
             buffer,
             pointer: 0usize,
+            base: 0usize,
         }
     }
 
+    /// Offset of the next byte within the whole file
+    fn offset(&self) -> usize {
+        self.base + self.pointer
+    }
+
     fn left_bytes(&self) -> usize {
         self.buffer.len() - self.pointer
     }
@@ -49,6 +74,23 @@ impl<'a> BigEndianReader<'a> {
         })
     }
 
+    /// Check that a length read from the file does not run past the end of the buffer, before
+    /// anything gets allocated for it
+    fn check_length(&self, length: u32) -> Result<usize, MIDIFileErrorKind> {
+        if length as usize <= self.left_bytes() {
+            Ok(length as usize)
+        } else {
+            Err(MIDIFileErrorKind::LengthOutOfBounds(length))
+        }
+    }
+
+    /// RIFF containers store their chunk sizes in little endian
+    fn read_u32_le(&mut self) -> Option<u32> {
+        self.read_n_bytes(std::mem::size_of::<u32>(), |bytes| {
+            u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+        })
+    }
+
     fn peek(&self) -> Option<u8> {
         if self.pointer < self.buffer.len() {
             self.buffer.get(self.pointer).copied()
@@ -62,23 +104,82 @@ impl<'a> BigEndianReader<'a> {
         for _ in 0..4 {
             let byte = self.read_u8()?;
             if byte & 0x80 == 0 {
-                value = value + (byte as u32);
-                break;
+                return Some(value + (byte as u32));
             } else {
                 value = (value + ((byte & 0x7Fu8) as u32)) << 7;
             }
         }
 
-        Some(value)
+        // Quantities are at most four bytes long, so the last one may not continue
+        None
     }
 
     fn read_range(&mut self, n: usize) -> Option<&[u8]> {
         self.read_n_bytes(n, |bytes| bytes)
     }
+
+    /// Read the next `n` bytes as a reader of their own, which still reports file offsets
+    fn read_sub_reader(&mut self, n: usize) -> Option<BigEndianReader<'a>> {
+        let base = self.offset();
+        self.read_n_bytes(n, |buffer| BigEndianReader {
+            buffer,
+            pointer: 0usize,
+            base,
+        })
+    }
+}
+
+/// Counterpart of [`BigEndianReader`], used to serialize files
+#[derive(Default)]
+struct BigEndianWriter {
+    buffer: Vec<u8>,
+}
+
+impl BigEndianWriter {
+    fn write_u8(&mut self, value: u8) {
+        self.buffer.push(value);
+    }
+
+    fn write_u16(&mut self, value: u16) {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.buffer.extend_from_slice(&value.to_be_bytes());
+    }
+
+    /// Variable-length quantities hold at most 28 bits, higher ones are dropped
+    fn write_var_length(&mut self, value: u32) {
+        let value = value & 0x0FFF_FFFF;
+        let mut shift = 21;
+        while shift > 0 && value >> shift == 0 {
+            shift -= 7;
+        }
+
+        while shift > 0 {
+            self.write_u8(((value >> shift) & 0x7F) as u8 | 0x80);
+            shift -= 7;
+        }
+        self.write_u8((value & 0x7F) as u8);
+    }
+
+    fn write_range(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Write data preceded by its length
+    fn write_var_length_range(&mut self, bytes: &[u8]) {
+        self.write_var_length(bytes.len() as u32);
+        self.write_range(bytes);
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.buffer
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub enum MIDIFileError {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MIDIFileErrorKind {
     HeaderMismatch,
     InvalidHeader,
     HeaderSizeMismatch,
@@ -91,10 +192,112 @@ pub enum MIDIFileError {
     InvalidTrackEventType(u8),
     UnsupportedEvent,
     InvalidMetaEvent,
-    UnexpectedMetaLength(u8, u32),
+    /// Meta event type, its length and the length the type requires
+    UnexpectedMetaLength(u8, u32, u32),
+    InvalidSysExEvent,
+    InvalidRiffContainer,
+    /// A chunk or event length larger than what is left of the data containing it
+    LengthOutOfBounds(u32),
+    /// A tempo of zero microseconds per quarter note, which would play every note at once
+    ZeroTempo,
+}
+
+impl fmt::Display for MIDIFileErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MIDIFileErrorKind::HeaderMismatch => write!(f, "Not a MIDI file, missing MThd header"),
+            MIDIFileErrorKind::InvalidHeader => write!(f, "Truncated MIDI header"),
+            MIDIFileErrorKind::HeaderSizeMismatch => write!(f, "Unexpected MIDI header size"),
+            MIDIFileErrorKind::InvalidTrackCount => write!(f, "Missing track count"),
+            MIDIFileErrorKind::InvalidTimeDivision => write!(f, "Missing time division"),
+            MIDIFileErrorKind::UnsupportedType => write!(f, "Unsupported MIDI file format"),
+            MIDIFileErrorKind::InvalidSMPTEValue => write!(f, "Invalid SMPTE frame rate"),
+            MIDIFileErrorKind::InvalidTrackChunk => write!(f, "Invalid or truncated MTrk chunk"),
+            MIDIFileErrorKind::InvalidEvent => write!(f, "Invalid or truncated event"),
+            MIDIFileErrorKind::InvalidTrackEventType(event_type) => {
+                write!(f, "Invalid event type 0x{event_type:X}")
+            }
+            MIDIFileErrorKind::UnsupportedEvent => {
+                write!(
+                    f,
+                    "System common and real-time events are not allowed in files"
+                )
+            }
+            MIDIFileErrorKind::InvalidMetaEvent => write!(f, "Invalid or truncated meta event"),
+            MIDIFileErrorKind::UnexpectedMetaLength(event_type, length, expected) => write!(
+                f,
+                "Invalid {} meta event length ({length}, expected {expected})",
+                meta_event_name(*event_type)
+            ),
+            MIDIFileErrorKind::InvalidSysExEvent => write!(f, "Invalid or truncated SysEx event"),
+            MIDIFileErrorKind::InvalidRiffContainer => {
+                write!(
+                    f,
+                    "Invalid RIFF container, expected an RMID form with a data chunk"
+                )
+            }
+            MIDIFileErrorKind::LengthOutOfBounds(length) => {
+                write!(f, "Length {length} runs past the end of the data")
+            }
+            MIDIFileErrorKind::ZeroTempo => write!(f, "Invalid tempo of 0 microseconds per beat"),
+        }
+    }
 }
 
+/// Name of a meta event type as given in the specification, for error messages
+fn meta_event_name(event_type: u8) -> String {
+    match event_type {
+        0x00 => "Sequence Number".to_owned(),
+        0x01 => "Text".to_owned(),
+        0x02 => "Copyright Notice".to_owned(),
+        0x03 => "Sequence/Track Name".to_owned(),
+        0x04 => "Instrument Name".to_owned(),
+        0x05 => "Lyric".to_owned(),
+        0x06 => "Marker".to_owned(),
+        0x07 => "Cue Point".to_owned(),
+        0x20 => "MIDI Channel Prefix".to_owned(),
+        0x2F => "End of Track".to_owned(),
+        0x51 => "Set Tempo".to_owned(),
+        0x54 => "SMPTE Offset".to_owned(),
+        0x58 => "Time Signature".to_owned(),
+        0x59 => "Key Signature".to_owned(),
+        0x7F => "Sequencer-Specific".to_owned(),
+        _ => format!("0x{event_type:02X}"),
+    }
+}
+
+/// A parsing error, along with the offset in the file of the chunk, header field or event
+/// it occurred in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MIDIFileError {
+    kind: MIDIFileErrorKind,
+    offset: usize,
+}
+
+impl MIDIFileError {
+    fn new(kind: MIDIFileErrorKind, offset: usize) -> Self {
+        Self { kind, offset }
+    }
+
+    pub fn kind(&self) -> MIDIFileErrorKind {
+        self.kind
+    }
+
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl fmt::Display for MIDIFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at byte 0x{:X}", self.kind, self.offset)
+    }
+}
+
+impl std::error::Error for MIDIFileError {}
+
 #[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SMPTE {
     _24,
     _25,
@@ -102,6 +305,24 @@ pub enum SMPTE {
     _30,
 }
 
+impl SMPTE {
+    pub fn frames_per_second(&self) -> f64 {
+        match self {
+            SMPTE::_24 => 24.0,
+            SMPTE::_25 => 25.0,
+            SMPTE::_29_97 => 29.97,
+            SMPTE::_30 => 30.0,
+        }
+    }
+}
+
+/// Durations past the range of `Duration::from_nanos`, over five centuries, only come from
+/// corrupt files and are clamped to it
+fn saturating_nanos(nanos: u128) -> Duration {
+    Duration::from_nanos(nanos.min(u64::MAX as u128) as u64)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimeDivision {
     TicksPerBit(u16),
     FramesPerSecond(SMPTE, u16),
@@ -114,20 +335,44 @@ impl TimeDivision {
                 Duration::from_micros(tempo.as_mpqn() as u64) / (*ticks as u32)
             }
             TimeDivision::FramesPerSecond(smpte, ticks) => {
-                let fps = match smpte {
-                    SMPTE::_24 => 24.0,
-                    SMPTE::_25 => 25.0,
-                    SMPTE::_29_97 => 29.97,
-                    SMPTE::_30 => 30.0,
-                };
+                let fps = smpte.frames_per_second() as f32;
 
                 Duration::from_secs(1) / (fps * (*ticks as f32)) as u32
             }
         }
     }
+
+    /// Duration of `ticks` ticks at a constant tempo, without accumulating the rounding of `tick_duration`.
+    /// Rounded up to the nanosecond, so converting back with `duration_ticks` yields the same tick count.
+    pub fn ticks_duration(&self, tempo: Tempo, ticks: u64) -> Duration {
+        match self {
+            TimeDivision::TicksPerBit(ticks_per_beat) => saturating_nanos(
+                (tempo.as_mpqn() as u128 * 1000 * ticks as u128)
+                    .div_ceil((*ticks_per_beat as u128).max(1)),
+            ),
+            TimeDivision::FramesPerSecond(..) => {
+                saturating_nanos(self.tick_duration(tempo).as_nanos() * ticks as u128)
+            }
+        }
+    }
+
+    /// Number of whole ticks that fit in `duration` at a constant tempo. Inverse of `ticks_duration`.
+    /// Counts beyond `u64` saturate.
+    pub fn duration_ticks(&self, tempo: Tempo, duration: Duration) -> u64 {
+        let ticks = match self {
+            TimeDivision::TicksPerBit(ticks_per_beat) => {
+                duration.as_nanos() * *ticks_per_beat as u128
+                    / (tempo.as_mpqn() as u128 * 1000).max(1)
+            }
+            TimeDivision::FramesPerSecond(..) => {
+                duration.as_nanos() / self.tick_duration(tempo).as_nanos().max(1)
+            }
+        };
+        ticks.min(u64::MAX as u128) as u64
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ChannelEventKind {
     NoteOff {
         note: u8,
@@ -163,7 +408,154 @@ pub enum ChannelEventKind {
     },
 }
 
-#[derive(Debug)]
+impl ChannelEventKind {
+    /// Controller changed by the event, if it is a controller change
+    pub fn controller(&self) -> Option<Controller> {
+        match *self {
+            ChannelEventKind::Controller {
+                controller_number,
+                controller_value,
+            } => Some(Controller::new(controller_number, controller_value)),
+            _ => None,
+        }
+    }
+
+    /// Position of the pitch bend wheel, if the event is a pitch bend
+    pub fn pitch_bend(&self) -> Option<PitchBend> {
+        match *self {
+            ChannelEventKind::PitchBend { lsb, msb } => Some(PitchBend::from_bytes(lsb, msb)),
+            _ => None,
+        }
+    }
+}
+
+/// Position of the pitch bend wheel, combined from the two 7-bit halves of a pitch bend event
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PitchBend(i16);
+
+impl PitchBend {
+    pub fn from_bytes(lsb: u8, msb: u8) -> Self {
+        let value = (((msb as u16 & 0x7F) << 7) | (lsb as u16 & 0x7F)) as i16;
+        Self(value - 8192)
+    }
+
+    /// Signed position of the wheel, from -8192 all the way down to 8191 all the way up, with 0
+    /// in the center
+    pub fn value(&self) -> i16 {
+        self.0
+    }
+
+    /// Pitch offset in semitones, for a bend range in semitones
+    pub fn semitones(&self, range: f32) -> f32 {
+        self.0 as f32 / 8192.0 * range
+    }
+}
+
+/// Meaning of a controller number, for the controllers interpreted by the synthesizer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ControllerKind {
+    /// Modulation wheel (CC1)
+    Modulation,
+    /// Most significant half of the value of the selected parameter (CC6)
+    DataEntry,
+    /// Channel volume (CC7)
+    Volume,
+    /// Channel pan (CC10)
+    Pan,
+    /// Channel expression (CC11)
+    Expression,
+    /// Least significant half of the value of the selected parameter (CC38)
+    DataEntryLsb,
+    /// Sustain pedal (CC64)
+    Sustain,
+    /// Least significant half of the selected non-registered parameter number (CC98)
+    NrpnLsb,
+    /// Most significant half of the selected non-registered parameter number (CC99)
+    NrpnMsb,
+    /// Least significant half of the selected registered parameter number (CC100)
+    RpnLsb,
+    /// Most significant half of the selected registered parameter number (CC101)
+    RpnMsb,
+    /// All Sound Off (CC120), which silences a channel immediately
+    AllSoundOff,
+    /// All Notes Off (CC123), which releases every note of a channel
+    AllNotesOff,
+    Other(u8),
+}
+
+impl ControllerKind {
+    pub fn number(&self) -> u8 {
+        match *self {
+            ControllerKind::Modulation => 1,
+            ControllerKind::DataEntry => 6,
+            ControllerKind::Volume => 7,
+            ControllerKind::Pan => 10,
+            ControllerKind::Expression => 11,
+            ControllerKind::DataEntryLsb => 38,
+            ControllerKind::Sustain => 64,
+            ControllerKind::NrpnLsb => 98,
+            ControllerKind::NrpnMsb => 99,
+            ControllerKind::RpnLsb => 100,
+            ControllerKind::RpnMsb => 101,
+            ControllerKind::AllSoundOff => 120,
+            ControllerKind::AllNotesOff => 123,
+            ControllerKind::Other(number) => number,
+        }
+    }
+}
+
+impl From<u8> for ControllerKind {
+    fn from(number: u8) -> Self {
+        match number {
+            1 => ControllerKind::Modulation,
+            6 => ControllerKind::DataEntry,
+            7 => ControllerKind::Volume,
+            10 => ControllerKind::Pan,
+            11 => ControllerKind::Expression,
+            38 => ControllerKind::DataEntryLsb,
+            64 => ControllerKind::Sustain,
+            98 => ControllerKind::NrpnLsb,
+            99 => ControllerKind::NrpnMsb,
+            100 => ControllerKind::RpnLsb,
+            101 => ControllerKind::RpnMsb,
+            120 => ControllerKind::AllSoundOff,
+            123 => ControllerKind::AllNotesOff,
+            number => ControllerKind::Other(number),
+        }
+    }
+}
+
+/// Value set on a controller by a controller change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Controller {
+    number: u8,
+    value: u8,
+}
+
+impl Controller {
+    pub fn new(number: u8, value: u8) -> Self {
+        Self { number, value }
+    }
+
+    pub fn kind(&self) -> ControllerKind {
+        ControllerKind::from(self.number)
+    }
+
+    pub fn number(&self) -> u8 {
+        self.number
+    }
+
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+
+    /// State of a switch controller, such as the sustain pedal, which is on from 64 upwards
+    pub fn is_on(&self) -> bool {
+        self.value >= 64
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ChannelEvent {
     channel: u8,
     kind: ChannelEventKind,
@@ -173,8 +565,8 @@ impl ChannelEvent {
     fn from_track_event(
         event_type: u8,
         channel: u8,
-        mut read_param: impl FnMut() -> Result<u8, MIDIFileError>,
-    ) -> Result<Self, MIDIFileError> {
+        mut read_param: impl FnMut() -> Result<u8, MIDIFileErrorKind>,
+    ) -> Result<Self, MIDIFileErrorKind> {
         Ok(Self {
             channel,
             kind: match event_type {
@@ -211,11 +603,33 @@ impl ChannelEvent {
                     msb: read_param()?,
                 },
 
-                _ => return Err(MIDIFileError::InvalidTrackEventType(event_type)),
+                _ => return Err(MIDIFileErrorKind::InvalidTrackEventType(event_type)),
             },
         })
     }
 
+    pub fn new(channel: u8, kind: ChannelEventKind) -> Self {
+        Self { channel, kind }
+    }
+
+    /// Event of a message sent by a MIDI device, such as `[0x90, 60, 100]`. System messages, such
+    /// as the clock, and truncated messages are not channel events.
+    pub fn from_message(message: &[u8]) -> Option<Self> {
+        let (&status, params) = message.split_first()?;
+        if !(0x80..0xF0).contains(&status) {
+            return None;
+        }
+
+        let mut params = params.iter().copied();
+        Self::from_track_event(status >> 4, status & 0x0F, || {
+            params
+                .next()
+                .filter(|param| *param < 0x80)
+                .ok_or(MIDIFileErrorKind::InvalidEvent)
+        })
+        .ok()
+    }
+
     pub fn channel(&self) -> u8 {
         self.channel
     }
@@ -223,28 +637,402 @@ impl ChannelEvent {
     pub fn kind(&self) -> &ChannelEventKind {
         &self.kind
     }
+
+    /// Status byte of the event, holding its type and channel
+    fn status(&self) -> u8 {
+        let event_type = match self.kind {
+            ChannelEventKind::NoteOff { .. } => 0x8,
+            ChannelEventKind::NoteOn { .. } => 0x9,
+            ChannelEventKind::NoteAftertouch { .. } => 0xA,
+            ChannelEventKind::Controller { .. } => 0xB,
+            ChannelEventKind::ProgramChange { .. } => 0xC,
+            ChannelEventKind::ChannelAftertouch { .. } => 0xD,
+            ChannelEventKind::PitchBend { .. } => 0xE,
+        };
+
+        (event_type << 4) | (self.channel & 0x0F)
+    }
+
+    fn write_params(&self, writer: &mut BigEndianWriter) {
+        match self.kind {
+            ChannelEventKind::NoteOff { note, velocity }
+            | ChannelEventKind::NoteOn { note, velocity } => {
+                writer.write_u8(note);
+                writer.write_u8(velocity);
+            }
+            ChannelEventKind::NoteAftertouch { note, aftertouch } => {
+                writer.write_u8(note);
+                writer.write_u8(aftertouch);
+            }
+            ChannelEventKind::Controller {
+                controller_number,
+                controller_value,
+            } => {
+                writer.write_u8(controller_number);
+                writer.write_u8(controller_value);
+            }
+            ChannelEventKind::ProgramChange { program_number } => {
+                writer.write_u8(program_number);
+            }
+            ChannelEventKind::ChannelAftertouch { aftertouch } => {
+                writer.write_u8(aftertouch);
+            }
+            ChannelEventKind::PitchBend { lsb, msb } => {
+                writer.write_u8(lsb);
+                writer.write_u8(msb);
+            }
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Tempo {
     /// Microseconds per quarter note
     mpqn: u32,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Scale {
     Major,
     Minor,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Key {
     Flats(NonZeroU8),
     C,
     Sharps(NonZeroU8),
 }
 
-#[derive(Debug)]
+/// Text of a meta event. The MIDI standard doesn't specify an encoding, so UTF-8 is tried first,
+/// falling back to Latin-1, which legacy files commonly use and which can decode any byte.
+pub fn decode_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_owned(),
+        Err(_) => bytes.iter().map(|&byte| byte as char).collect(),
+    }
+}
+
+/// Meter of a piece, like 3/4 or 6/8
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    numerator: u8,
+    /// Power of two of the denominator
+    denominator_power: u8,
+}
+
+impl Default for TimeSignature {
+    /// 4/4, which MIDI files without a time signature are assumed to be in
+    fn default() -> Self {
+        Self::new(4, 2)
+    }
+}
+
+impl TimeSignature {
+    /// The denominator is given as a power of two, like in the file, so 6/8 is `new(6, 3)`
+    pub fn new(numerator: u8, denominator_power: u8) -> Self {
+        Self {
+            numerator,
+            denominator_power,
+        }
+    }
+
+    pub fn numerator(&self) -> u8 {
+        self.numerator
+    }
+
+    pub fn denominator(&self) -> u32 {
+        1 << self.denominator_power.min(31)
+    }
+
+    /// Ticks per beat, where a beat is a note of the denominator's length. At least 1.
+    pub fn beat_ticks(&self, ticks_per_quarter: u16) -> u64 {
+        (ticks_per_quarter as u64 * 4 / self.denominator() as u64).max(1)
+    }
+
+    /// Ticks per bar. At least 1.
+    pub fn bar_ticks(&self, ticks_per_quarter: u16) -> u64 {
+        self.beat_ticks(ticks_per_quarter) * self.numerator.max(1) as u64
+    }
+}
+
+/// Most bars or beats listed by [`BarBeat::bar_starts`] and [`BarBeat::beat_starts`]. Only
+/// corrupt files reach it, such as ones running for billions of ticks.
+pub const MAX_BAR_BEATS: usize = 1 << 20;
+
+/// Musical position of a tick, counted from bar 1, beat 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarBeat {
+    pub bar: u32,
+    pub beat: u32,
+    /// Ticks since the start of the beat
+    pub tick: u64,
+}
+
+impl BarBeat {
+    /// Position of the absolute `tick`, given the time signature changes sorted by tick, as
+    /// returned by [`MIDIFileData::time_signatures`]. The file is in 4/4 until the first change.
+    ///
+    /// A change always starts a new bar, even if the previous one wasn't complete. `None` for
+    /// SMPTE time divisions, which don't count beats.
+    pub fn from_tick(
+        tick: u64,
+        time_division: TimeDivision,
+        time_signatures: &[(u64, TimeSignature)],
+    ) -> Option<Self> {
+        let TimeDivision::TicksPerBit(ticks_per_quarter) = time_division else {
+            return None;
+        };
+
+        let mut bars = 0u64;
+        let mut segment_start = 0u64;
+        let mut time_signature = TimeSignature::default();
+        for &(change_tick, change) in time_signatures {
+            if change_tick > tick {
+                break;
+            }
+
+            bars +=
+                (change_tick - segment_start).div_ceil(time_signature.bar_ticks(ticks_per_quarter));
+            segment_start = change_tick;
+            time_signature = change;
+        }
+
+        let bar_ticks = time_signature.bar_ticks(ticks_per_quarter);
+        let beat_ticks = time_signature.beat_ticks(ticks_per_quarter);
+        let in_segment = tick - segment_start;
+        let in_bar = in_segment % bar_ticks;
+
+        Some(Self {
+            bar: (bars + in_segment / bar_ticks + 1) as u32,
+            beat: (in_bar / beat_ticks + 1) as u32,
+            tick: in_bar % beat_ticks,
+        })
+    }
+
+    /// Absolute ticks at which the bars before `end_tick` start, counted like
+    /// [`Self::from_tick`] counts them. Empty for SMPTE time divisions.
+    pub fn bar_starts(
+        end_tick: u64,
+        time_division: TimeDivision,
+        time_signatures: &[(u64, TimeSignature)],
+    ) -> Vec<u64> {
+        let TimeDivision::TicksPerBit(ticks_per_quarter) = time_division else {
+            return vec![];
+        };
+
+        let mut bar_starts = vec![];
+        let mut tick = 0u64;
+        let mut next_change = 0;
+        let mut time_signature = TimeSignature::default();
+        while tick < end_tick && bar_starts.len() < MAX_BAR_BEATS {
+            while let Some(&(change_tick, change)) = time_signatures.get(next_change)
+                && change_tick <= tick
+            {
+                time_signature = change;
+                next_change += 1;
+            }
+            bar_starts.push(tick);
+
+            // A change before the end of the bar starts a new one
+            let bar_end = tick + time_signature.bar_ticks(ticks_per_quarter);
+            tick = match time_signatures.get(next_change) {
+                Some(&(change_tick, _)) if change_tick < bar_end => change_tick,
+                _ => bar_end,
+            };
+        }
+
+        bar_starts
+    }
+
+    /// Absolute ticks at which the beats before `end_tick` start, each with whether it is the
+    /// first beat of its bar. Bars are counted like [`Self::bar_starts`] counts them, so a bar cut
+    /// short by a change has fewer beats. Empty for SMPTE time divisions.
+    pub fn beat_starts(
+        end_tick: u64,
+        time_division: TimeDivision,
+        time_signatures: &[(u64, TimeSignature)],
+    ) -> Vec<(u64, bool)> {
+        let TimeDivision::TicksPerBit(ticks_per_quarter) = time_division else {
+            return vec![];
+        };
+
+        let bar_starts = Self::bar_starts(end_tick, time_division, time_signatures);
+        let mut beat_starts = vec![];
+        let mut next_change = 0;
+        let mut time_signature = TimeSignature::default();
+        for (index, &bar_start) in bar_starts.iter().enumerate() {
+            let bar_end = bar_starts.get(index + 1).copied().unwrap_or(end_tick);
+            while let Some(&(change_tick, change)) = time_signatures.get(next_change)
+                && change_tick <= bar_start
+            {
+                time_signature = change;
+                next_change += 1;
+            }
+            let beat_ticks = time_signature.beat_ticks(ticks_per_quarter);
+
+            beat_starts.extend(
+                (bar_start..bar_end)
+                    .step_by(beat_ticks as usize)
+                    .enumerate()
+                    .map(|(beat, tick)| (tick, beat == 0))
+                    .take(MAX_BAR_BEATS - beat_starts.len()),
+            );
+            if beat_starts.len() == MAX_BAR_BEATS {
+                break;
+            }
+        }
+
+        beat_starts
+    }
+}
+
+/// Letters of the notes, with the pitch class of their natural note
+const LETTERS: [(char, u8); 7] = [
+    ('C', 0),
+    ('D', 2),
+    ('E', 4),
+    ('F', 5),
+    ('G', 7),
+    ('A', 9),
+    ('B', 11),
+];
+
+/// Order in which sharps are added to key signatures, as indices into [`LETTERS`]. Flats are
+/// added in the reverse order.
+const SHARPS_ORDER: [usize; 7] = [3, 0, 4, 1, 5, 2, 6];
+
+/// Names of the keys from 7 flats to 7 sharps
+const MAJOR_NAMES: [&str; 15] = [
+    "C♭ major",
+    "G♭ major",
+    "D♭ major",
+    "A♭ major",
+    "E♭ major",
+    "B♭ major",
+    "F major",
+    "C major",
+    "G major",
+    "D major",
+    "A major",
+    "E major",
+    "B major",
+    "F♯ major",
+    "C♯ major",
+];
+const MINOR_NAMES: [&str; 15] = [
+    "A♭ minor",
+    "E♭ minor",
+    "B♭ minor",
+    "F minor",
+    "C minor",
+    "G minor",
+    "D minor",
+    "A minor",
+    "E minor",
+    "B minor",
+    "F♯ minor",
+    "C♯ minor",
+    "G♯ minor",
+    "D♯ minor",
+    "A♯ minor",
+];
+
+/// Key and scale of a piece, which decide how its notes are spelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeySignature {
+    key: Key,
+    scale: Scale,
+}
+
+impl Default for KeySignature {
+    /// C major, which MIDI files without a key signature are assumed to be in
+    fn default() -> Self {
+        Self::new(Key::C, Scale::Major)
+    }
+}
+
+impl KeySignature {
+    pub fn new(key: Key, scale: Scale) -> Self {
+        Self { key, scale }
+    }
+
+    pub fn key(&self) -> Key {
+        self.key
+    }
+
+    pub fn scale(&self) -> Scale {
+        self.scale
+    }
+
+    /// Sharps as a positive count, flats as a negative one
+    fn fifths(&self) -> i8 {
+        match self.key {
+            Key::Flats(flats) => -(flats.get() as i8),
+            Key::C => 0,
+            Key::Sharps(sharps) => sharps.get() as i8,
+        }
+    }
+
+    /// Name of the key, like "E♭ major" or "F♯ minor"
+    pub fn name(&self) -> &'static str {
+        let index = (self.fifths() + 7) as usize;
+        match self.scale {
+            Scale::Major => MAJOR_NAMES[index],
+            Scale::Minor => MINOR_NAMES[index],
+        }
+    }
+
+    /// Name of the note with octave, like "C♯4" for note 61 in A major or "D♭4" in A♭ major.
+    ///
+    /// Notes of the scale are spelled as the key signature alters them, so G♭ major has a C♭
+    /// and C♯ major an E♯. Other notes are natural if possible, and otherwise sharp in sharp
+    /// keys and C, and flat in flat keys.
+    pub fn spell_note(&self, note: u8) -> String {
+        let fifths = self.fifths();
+        let pitch_class = note % 12;
+
+        // Alteration of every letter by the key signature, in semitones
+        let mut alterations = [0i8; 7];
+        for &letter in &SHARPS_ORDER[..fifths.max(0) as usize] {
+            alterations[letter] = 1;
+        }
+        for &letter in SHARPS_ORDER.iter().rev().take((-fifths).max(0) as usize) {
+            alterations[letter] = -1;
+        }
+
+        let altered = |(letter, alteration): (usize, i8)| {
+            (LETTERS[letter].1 as i8 + alteration).rem_euclid(12) as u8 == pitch_class
+        };
+        let chromatic_alteration = if fifths < 0 { -1 } else { 1 };
+
+        let (letter, alteration) = (0..7)
+            .map(|letter| (letter, alterations[letter]))
+            .find(|&spelling| altered(spelling))
+            .or_else(|| {
+                (0..7)
+                    .map(|letter| (letter, 0))
+                    .find(|&spelling| altered(spelling))
+            })
+            .or_else(|| {
+                (0..7)
+                    .map(|letter| (letter, chromatic_alteration))
+                    .find(|&spelling| altered(spelling))
+            })
+            .expect("every pitch class has a spelling");
+
+        let accidental = match alteration {
+            -1 => "♭",
+            1 => "♯",
+            _ => "",
+        };
+        // The octave is the one of the letter, so C♭4 is just below C4
+        let octave = (note as i16 - alteration as i16).div_euclid(12) - 1;
+        format!("{}{}{}", LETTERS[letter].0, accidental, octave)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MetaEvent {
     SequenceNumber {
         msb: u8,
@@ -299,6 +1087,7 @@ pub enum MetaEvent {
 
     TimeSignature {
         number: u8,
+        /// Power of two of the denominator, as stored in the file
         denom: u8,
         metro: u8,
         _32nds: u8,
@@ -320,29 +1109,31 @@ pub enum MetaEvent {
 }
 
 impl MetaEvent {
-    fn from_track_event(event_reader: &mut BigEndianReader) -> Result<Self, MIDIFileError> {
+    fn from_track_event(event_reader: &mut BigEndianReader) -> Result<Self, MIDIFileErrorKind> {
         let event_type = event_reader
             .read_u8()
-            .ok_or(MIDIFileError::InvalidMetaEvent)?;
+            .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
         let event_length = event_reader
             .read_var_length()
-            .ok_or(MIDIFileError::InvalidMetaEvent)?;
+            .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
+        event_reader.check_length(event_length)?;
 
         match event_type {
             0x00 => {
                 if event_length != 2 {
-                    return Err(MIDIFileError::UnexpectedMetaLength(
+                    return Err(MIDIFileErrorKind::UnexpectedMetaLength(
                         event_type,
                         event_length,
+                        2,
                     ));
                 }
 
                 let msb = event_reader
                     .read_u8()
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
                 let lsb = event_reader
                     .read_u8()
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
 
                 Ok(MetaEvent::SequenceNumber { msb, lsb })
             }
@@ -351,7 +1142,7 @@ impl MetaEvent {
                 text: Vec::from(
                     event_reader
                         .read_range(event_length as usize)
-                        .ok_or(MIDIFileError::InvalidMetaEvent)?,
+                        .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?,
                 ),
             }),
 
@@ -359,7 +1150,7 @@ impl MetaEvent {
                 text: Vec::from(
                     event_reader
                         .read_range(event_length as usize)
-                        .ok_or(MIDIFileError::InvalidMetaEvent)?,
+                        .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?,
                 ),
             }),
 
@@ -367,7 +1158,7 @@ impl MetaEvent {
                 text: Vec::from(
                     event_reader
                         .read_range(event_length as usize)
-                        .ok_or(MIDIFileError::InvalidMetaEvent)?,
+                        .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?,
                 ),
             }),
 
@@ -375,7 +1166,7 @@ impl MetaEvent {
                 text: Vec::from(
                     event_reader
                         .read_range(event_length as usize)
-                        .ok_or(MIDIFileError::InvalidMetaEvent)?,
+                        .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?,
                 ),
             }),
 
@@ -383,7 +1174,7 @@ impl MetaEvent {
                 text: Vec::from(
                     event_reader
                         .read_range(event_length as usize)
-                        .ok_or(MIDIFileError::InvalidMetaEvent)?,
+                        .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?,
                 ),
             }),
 
@@ -391,7 +1182,7 @@ impl MetaEvent {
                 text: Vec::from(
                     event_reader
                         .read_range(event_length as usize)
-                        .ok_or(MIDIFileError::InvalidMetaEvent)?,
+                        .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?,
                 ),
             }),
 
@@ -399,30 +1190,32 @@ impl MetaEvent {
                 text: Vec::from(
                     event_reader
                         .read_range(event_length as usize)
-                        .ok_or(MIDIFileError::InvalidMetaEvent)?,
+                        .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?,
                 ),
             }),
 
             0x20 => {
                 if event_length != 1 {
-                    return Err(MIDIFileError::UnexpectedMetaLength(
+                    return Err(MIDIFileErrorKind::UnexpectedMetaLength(
                         event_type,
                         event_length,
+                        1,
                     ));
                 }
 
                 let channel = event_reader
                     .read_u8()
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
 
                 Ok(MetaEvent::ChannelPrefix { channel })
             }
 
             0x2F => {
                 if event_length != 0 {
-                    return Err(MIDIFileError::UnexpectedMetaLength(
+                    return Err(MIDIFileErrorKind::UnexpectedMetaLength(
                         event_type,
                         event_length,
+                        0,
                     ));
                 }
 
@@ -431,17 +1224,21 @@ impl MetaEvent {
 
             0x51 => {
                 if event_length != 3 {
-                    return Err(MIDIFileError::UnexpectedMetaLength(
+                    return Err(MIDIFileErrorKind::UnexpectedMetaLength(
                         event_type,
                         event_length,
+                        3,
                     ));
                 }
 
                 let bytes = event_reader
                     .read_range(3)
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
 
                 let mpqn = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | (bytes[2] as u32);
+                if mpqn == 0 {
+                    return Err(MIDIFileErrorKind::ZeroTempo);
+                }
 
                 Ok(MetaEvent::SetTempo {
                     tempo: Tempo::from_mpqn(mpqn),
@@ -450,15 +1247,16 @@ impl MetaEvent {
 
             0x54 => {
                 if event_length != 5 {
-                    return Err(MIDIFileError::UnexpectedMetaLength(
+                    return Err(MIDIFileErrorKind::UnexpectedMetaLength(
                         event_type,
                         event_length,
+                        5,
                     ));
                 }
 
                 let bytes = event_reader
                     .read_range(5)
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
 
                 Ok(MetaEvent::SMPTEOffset {
                     hour: bytes[0],
@@ -469,17 +1267,39 @@ impl MetaEvent {
                 })
             }
 
+            0x58 => {
+                if event_length != 4 {
+                    return Err(MIDIFileErrorKind::UnexpectedMetaLength(
+                        event_type,
+                        event_length,
+                        4,
+                    ));
+                }
+
+                let bytes = event_reader
+                    .read_range(4)
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
+
+                Ok(MetaEvent::TimeSignature {
+                    number: bytes[0],
+                    denom: bytes[1],
+                    metro: bytes[2],
+                    _32nds: bytes[3],
+                })
+            }
+
             0x59 => {
                 if event_length != 2 {
-                    return Err(MIDIFileError::UnexpectedMetaLength(
+                    return Err(MIDIFileErrorKind::UnexpectedMetaLength(
                         event_type,
                         event_length,
+                        2,
                     ));
                 }
 
                 let bytes = event_reader
                     .read_range(2)
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
 
                 Ok(MetaEvent::KeySignature {
                     key: match bytes[0] {
@@ -489,14 +1309,14 @@ impl MetaEvent {
                             Key::Flats(NonZeroU8::new(0xFF - flats_byte + 1).unwrap())
                         }
                         _ => {
-                            return Err(MIDIFileError::InvalidMetaEvent);
+                            return Err(MIDIFileErrorKind::InvalidMetaEvent);
                         }
                     },
                     scale: match bytes[1] {
                         0 => Scale::Major,
                         1 => Scale::Minor,
                         _ => {
-                            return Err(MIDIFileError::InvalidMetaEvent);
+                            return Err(MIDIFileErrorKind::InvalidMetaEvent);
                         }
                     },
                 })
@@ -505,7 +1325,7 @@ impl MetaEvent {
             0x7F => {
                 let bytes = event_reader
                     .read_range(event_length as usize)
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
 
                 Ok(MetaEvent::SequencerSpecific {
                     data: Vec::from(bytes),
@@ -515,7 +1335,7 @@ impl MetaEvent {
             _ => {
                 let bytes = event_reader
                     .read_range(event_length as usize)
-                    .ok_or(MIDIFileError::InvalidMetaEvent)?;
+                    .ok_or(MIDIFileErrorKind::InvalidMetaEvent)?;
 
                 Ok(MetaEvent::UnknownEvent {
                     event_type,
@@ -526,13 +1346,103 @@ impl MetaEvent {
     }
 }
 
-#[derive(Debug)]
+impl MetaEvent {
+    /// Write the event, from the type byte following `0xFF`
+    fn write(&self, writer: &mut BigEndianWriter) {
+        let (event_type, data) = match self {
+            MetaEvent::SequenceNumber { msb, lsb } => (0x00, vec![*msb, *lsb]),
+            MetaEvent::TextEvent { text } => (0x01, text.clone()),
+            MetaEvent::CopyrightNotice { text } => (0x02, text.clone()),
+            MetaEvent::SequenceTrackName { text } => (0x03, text.clone()),
+            MetaEvent::InstrumentName { text } => (0x04, text.clone()),
+            MetaEvent::Lyrics { text } => (0x05, text.clone()),
+            MetaEvent::Marker { text } => (0x06, text.clone()),
+            MetaEvent::CuePoint { text } => (0x07, text.clone()),
+            MetaEvent::ChannelPrefix { channel } => (0x20, vec![*channel]),
+            MetaEvent::EndOfTrack => (0x2F, vec![]),
+            MetaEvent::SetTempo { tempo } => (0x51, tempo.as_mpqn().to_be_bytes()[1..].to_vec()),
+            MetaEvent::SMPTEOffset {
+                hour,
+                min,
+                sec,
+                fs,
+                sub_fr,
+            } => (0x54, vec![*hour, *min, *sec, *fs, *sub_fr]),
+            MetaEvent::TimeSignature {
+                number,
+                denom,
+                metro,
+                _32nds,
+            } => (0x58, vec![*number, *denom, *metro, *_32nds]),
+            MetaEvent::KeySignature { key, scale } => {
+                // Flats are stored as a negative count
+                let key = match key {
+                    Key::Flats(flats) => (flats.get() as i8).wrapping_neg() as u8,
+                    Key::C => 0,
+                    Key::Sharps(sharps) => sharps.get(),
+                };
+                let scale = match scale {
+                    Scale::Major => 0,
+                    Scale::Minor => 1,
+                };
+                (0x59, vec![key, scale])
+            }
+            MetaEvent::UnknownEvent { event_type, data } => (*event_type, data.clone()),
+            MetaEvent::SequencerSpecific { data } => (0x7F, data.clone()),
+        };
+
+        writer.write_u8(event_type);
+        writer.write_var_length_range(&data);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SysExEvent {
+    /// A complete message or the first packet of a divided message (`0xF0`)
+    Normal { data: Vec<u8> },
+
+    /// A continuation packet of a divided message or an escape sequence (`0xF7`)
+    Escape { data: Vec<u8> },
+}
+
+impl SysExEvent {
+    fn from_track_event(
+        type_byte: u8,
+        event_reader: &mut BigEndianReader,
+    ) -> Result<Self, MIDIFileErrorKind> {
+        let event_length = event_reader
+            .read_var_length()
+            .ok_or(MIDIFileErrorKind::InvalidSysExEvent)?;
+        event_reader.check_length(event_length)?;
+        let data = Vec::from(
+            event_reader
+                .read_range(event_length as usize)
+                .ok_or(MIDIFileErrorKind::InvalidSysExEvent)?,
+        );
+
+        match type_byte {
+            0xF0 => Ok(SysExEvent::Normal { data }),
+            0xF7 => Ok(SysExEvent::Escape { data }),
+            _ => Err(MIDIFileErrorKind::InvalidTrackEventType(type_byte)),
+        }
+    }
+
+    /// Raw payload, as stored in the file (including the trailing `0xF7` if present)
+    pub fn data(&self) -> &[u8] {
+        match self {
+            SysExEvent::Normal { data } | SysExEvent::Escape { data } => data,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum MIDIEventKind {
     Channel(ChannelEvent),
     Meta(MetaEvent),
+    SysEx(SysExEvent),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MIDIEvent {
     delta_time: u32,
     kind: MIDIEventKind,
@@ -576,86 +1486,328 @@ impl Default for Tempo {
     }
 }
 
-pub struct MIDITrack {
-    events: Vec<MIDIEvent>,
+/// Convert the fields of an SMPTE offset event into a duration. The top bits of the hour byte
+/// hold the frame rate, and fractional frames are in hundredths.
+fn smpte_offset(hour: u8, min: u8, sec: u8, frames: u8, fractional_frames: u8) -> Duration {
+    let smpte = match (hour >> 5) & 0x3 {
+        0 => SMPTE::_24,
+        1 => SMPTE::_25,
+        2 => SMPTE::_29_97,
+        _ => SMPTE::_30,
+    };
+    let hours = (hour & 0x1F) as u64;
+
+    Duration::from_secs(hours * 3600 + min as u64 * 60 + sec as u64)
+        + Duration::from_secs_f64(
+            (frames as f64 + fractional_frames as f64 / 100.0) / smpte.frames_per_second(),
+        )
 }
 
-impl MIDITrack {
-    pub fn events(&self) -> &[MIDIEvent] {
-        &self.events
-    }
-
-    fn new(reader: &mut BigEndianReader) -> Result<MIDITrack, MIDIFileError> {
-        if reader.read_range(4) != Some(MIDI_TRACK_CHUNK) {
-            return Err(MIDIFileError::InvalidTrackChunk);
-        }
+/// Tempo changes at absolute tick positions, used to convert ticks into real time.
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    time_division: TimeDivision,
+    /// Sorted by tick
+    changes: Vec<(u64, Tempo)>,
+    /// Speed of the playback relative to the tempo of the file. Durations are divided by it.
+    rate: f64,
+    /// Time before the first tick, from the SMPTE offset event of the track
+    start_offset: Duration,
+}
 
-        let chunk_size = reader.read_u32().ok_or(MIDIFileError::InvalidTrackChunk)?;
-        let track_buffer = reader
-            .read_range(chunk_size as usize)
-            .ok_or(MIDIFileError::InvalidTrackChunk)?;
+impl TempoMap {
+    fn from_track(time_division: TimeDivision, track: &MIDITrack) -> Self {
+        let mut changes = vec![];
+        let mut start_offset = None;
+        let mut tick = 0u64;
 
-        let mut track_reader = BigEndianReader::new(track_buffer);
-        let mut events = vec![];
+        for event in track.events() {
+            tick += event.delta_time() as u64;
 
-        let mut running_status: Option<(u8, u8)> = None;
+            match event.kind() {
+                MIDIEventKind::Meta(MetaEvent::SetTempo { tempo }) => {
+                    changes.push((tick, *tempo));
+                }
+                MIDIEventKind::Meta(MetaEvent::SMPTEOffset {
+                    hour,
+                    min,
+                    sec,
+                    fs,
+                    sub_fr,
+                }) => {
+                    start_offset
+                        .get_or_insert_with(|| smpte_offset(*hour, *min, *sec, *fs, *sub_fr));
+                }
+                _ => {}
+            }
+        }
 
-        loop {
-            let delta_time = track_reader
-                .read_var_length()
-                .ok_or(MIDIFileError::InvalidEvent)?;
-
-            let type_byte = track_reader.read_u8().ok_or(MIDIFileError::InvalidEvent)?;
-
-            let kind = match type_byte {
-                0x00..=0x7F => {
-                    // channel event with a running status
-                    let (event_type, channel) =
-                        running_status.ok_or(MIDIFileError::InvalidEvent)?;
-                    let mut param1 = Some(type_byte);
-
-                    let event = ChannelEvent::from_track_event(event_type, channel, || {
-                        if let Some(v) = param1.take() {
-                            Ok(v)
-                        } else {
-                            track_reader.read_u8().ok_or(MIDIFileError::InvalidEvent)
-                        }
-                    })?;
+        Self {
+            time_division,
+            changes,
+            rate: 1.0,
+            start_offset: start_offset.unwrap_or_default(),
+        }
+    }
 
-                    MIDIEventKind::Channel(event)
-                }
-                0x80..=0xEF => {
-                    // channel event
-                    let event_type = (0xf0u8 & type_byte) >> 4;
-                    let channel = 0x0fu8 & type_byte;
+    /// Time at which the track starts, from its SMPTE offset event
+    pub fn start_offset(&self) -> Duration {
+        self.scaled(self.start_offset)
+    }
 
-                    let event = ChannelEvent::from_track_event(event_type, channel, || {
-                        track_reader.read_u8().ok_or(MIDIFileError::InvalidEvent)
-                    })?;
+    /// Time taken to play `duration` of the file at the playback rate. Times too long for a
+    /// [`Duration`] saturate to [`Duration::MAX`].
+    fn scaled(&self, duration: Duration) -> Duration {
+        Duration::try_from_secs_f64(duration.as_secs_f64() / self.rate).unwrap_or(Duration::MAX)
+    }
 
-                    running_status = Some((event_type, channel));
-                    MIDIEventKind::Channel(event)
-                }
-                0xF0..=0xF7 => {
-                    // sysex event
-                    return Err(MIDIFileError::UnsupportedEvent);
-                }
-                0xF8..=0xFE => {
-                    // real-time event
-                    return Err(MIDIFileError::UnsupportedEvent);
-                }
-                0xFF => {
-                    // meta event
-                    let event = MetaEvent::from_track_event(&mut track_reader)?;
+    /// Time of the file played in `duration` at the playback rate, saturating like
+    /// [`Self::scaled`]
+    fn unscaled(&self, duration: Duration) -> Duration {
+        Duration::try_from_secs_f64(duration.as_secs_f64() * self.rate).unwrap_or(Duration::MAX)
+    }
 
-                    running_status = None;
-                    MIDIEventKind::Meta(event)
-                }
-            };
+    /// Absolute time of the given tick, counted from the beginning of the track.
+    pub fn tick_to_duration(&self, tick: u64) -> Duration {
+        let mut duration = Duration::ZERO;
+        let mut last_tick = 0u64;
+        let mut tempo = Tempo::default();
 
-            let is_end_of_track = matches!(kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack));
+        for &(change_tick, change_tempo) in &self.changes {
+            if change_tick >= tick {
+                break;
+            }
+
+            duration = duration.saturating_add(
+                self.time_division
+                    .ticks_duration(tempo, change_tick - last_tick),
+            );
+            last_tick = change_tick;
+            tempo = change_tempo;
+        }
 
-            events.push(MIDIEvent { delta_time, kind });
+        self.scaled(
+            duration.saturating_add(self.time_division.ticks_duration(tempo, tick - last_tick)),
+        )
+    }
+
+    /// The last tick that starts at or before the given time, counted from the beginning of the track.
+    pub fn duration_to_tick(&self, duration: Duration) -> u64 {
+        let duration = self.unscaled(duration);
+        let mut elapsed = Duration::ZERO;
+        let mut last_tick = 0u64;
+        let mut tempo = Tempo::default();
+
+        for &(change_tick, change_tempo) in &self.changes {
+            let segment = self
+                .time_division
+                .ticks_duration(tempo, change_tick - last_tick);
+            if elapsed.saturating_add(segment) > duration {
+                break;
+            }
+
+            elapsed += segment;
+            last_tick = change_tick;
+            tempo = change_tempo;
+        }
+
+        last_tick.saturating_add(self.time_division.duration_ticks(tempo, duration - elapsed))
+    }
+
+    /// Tempo the track starts with, before any change
+    pub fn initial_tempo(&self) -> Tempo {
+        self.changes
+            .first()
+            .filter(|&&(tick, _)| tick == 0)
+            .map_or_else(Tempo::default, |&(_, tempo)| tempo)
+    }
+
+    /// Duration of `ticks` ticks at the tempo the track starts with, ignoring any later change
+    pub fn initial_ticks_duration(&self, ticks: u64) -> Duration {
+        self.scaled(
+            self.time_division
+                .ticks_duration(self.initial_tempo(), ticks),
+        )
+    }
+
+    /// Duration of `beats` quarter notes from the given time, following the tempo changes within
+    /// them. Files timed in SMPTE frames have no beats, so theirs last as long as at the default
+    /// tempo.
+    pub fn beats_duration_at(&self, duration: Duration, beats: f64) -> Duration {
+        match self.time_division {
+            TimeDivision::TicksPerBit(ticks_per_beat) => {
+                let start = self.duration_to_tick(duration);
+                let ticks = (beats * ticks_per_beat as f64).round() as u64;
+                self.tick_to_duration(start + ticks) - self.tick_to_duration(start)
+            }
+            TimeDivision::FramesPerSecond(..) => {
+                self.scaled(Duration::from_micros(Tempo::default().as_mpqn() as u64).mul_f64(beats))
+            }
+        }
+    }
+
+    /// Tempo changes with their absolute tick positions
+    pub fn changes(&self) -> &[(u64, Tempo)] {
+        &self.changes
+    }
+}
+
+/// Iterator over the events of a track, yielding each with its absolute time, counted from the
+/// start of the file, and its absolute tick. Times follow the tempo map of the track, and the walk
+/// ends with the end of track event.
+///
+/// Ticks only move forward, so the tempo map is walked along with the events instead of being
+/// searched for every one of them. For a view of all tracks at once, walk the single track of
+/// [`MIDIFileData::merge_tracks`].
+#[derive(Clone)]
+pub struct EventWalker<'a> {
+    events: &'a [MIDIEvent],
+    tempo_map: &'a TempoMap,
+    /// Time of the first tick of the track
+    track_start: Duration,
+    position: WalkPosition,
+}
+
+/// Where a walk over the events of a track stopped, so it can be resumed later without keeping
+/// the file borrowed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WalkPosition {
+    /// Index of the next event to yield
+    next_event: usize,
+    /// Tick of the last event yielded
+    tick: u64,
+    /// Index of the first tempo change after the current tempo segment
+    next_change: usize,
+    /// Tick and time, before the playback rate is applied, of the start of the current tempo
+    /// segment, with its tempo
+    segment: (u64, Duration, Tempo),
+    ended: bool,
+}
+
+impl<'a> EventWalker<'a> {
+    fn new(data: &'a MIDIFileData, track_index: usize, position: WalkPosition) -> Self {
+        Self {
+            events: &data.tracks[track_index].events,
+            tempo_map: data.tempo_map(track_index),
+            track_start: data.track_start(track_index),
+            position,
+        }
+    }
+
+    /// Absolute tick of the last event yielded, or of the end of the track once the walk is over
+    pub fn tick(&self) -> u64 {
+        self.position.tick
+    }
+
+    /// Absolute time of the last event yielded, or of the end of the track once the walk is over
+    pub fn time(&self) -> Duration {
+        let (segment_tick, segment_time, tempo) = self.position.segment;
+        let ticks_duration = self
+            .tempo_map
+            .time_division
+            .ticks_duration(tempo, self.position.tick - segment_tick);
+
+        self.track_start.saturating_add(
+            self.tempo_map
+                .scaled(segment_time.saturating_add(ticks_duration)),
+        )
+    }
+
+    /// Where the walk is, to resume it with [`MIDIFileData::resume_walk`]
+    pub fn position(&self) -> WalkPosition {
+        self.position
+    }
+
+    /// Move into the tempo segment of the current tick. Changes at the tick itself only apply
+    /// to the ticks after it, as in [`TempoMap::tick_to_duration`].
+    fn advance_tempo(&mut self) {
+        let position = &mut self.position;
+        while let Some(&(change_tick, change_tempo)) =
+            self.tempo_map.changes.get(position.next_change)
+        {
+            if change_tick >= position.tick {
+                break;
+            }
+
+            let (segment_tick, segment_time, tempo) = position.segment;
+            let segment_duration = self
+                .tempo_map
+                .time_division
+                .ticks_duration(tempo, change_tick - segment_tick);
+            position.segment = (
+                change_tick,
+                segment_time.saturating_add(segment_duration),
+                change_tempo,
+            );
+            position.next_change += 1;
+        }
+    }
+}
+
+impl<'a> Iterator for EventWalker<'a> {
+    type Item = (Duration, u64, &'a MIDIEvent);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.position.ended {
+            return None;
+        }
+
+        let event = self.events.get(self.position.next_event)?;
+        self.position.next_event += 1;
+        self.position.tick += event.delta_time as u64;
+        self.advance_tempo();
+        self.position.ended = matches!(event.kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack));
+
+        Some((self.time(), self.position.tick, event))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MIDITrack {
+    events: Vec<MIDIEvent>,
+}
+
+impl MIDITrack {
+    pub fn events(&self) -> &[MIDIEvent] {
+        &self.events
+    }
+
+    /// Tick of the end of track event, where the track stops
+    pub fn end_tick(&self) -> u64 {
+        let mut tick = 0u64;
+        for event in &self.events {
+            tick += event.delta_time() as u64;
+            if matches!(event.kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack)) {
+                break;
+            }
+        }
+        tick
+    }
+
+    fn new(reader: &mut BigEndianReader) -> Result<MIDITrack, MIDIFileError> {
+        let chunk_offset = reader.offset();
+        let chunk_error = MIDIFileError::new(MIDIFileErrorKind::InvalidTrackChunk, chunk_offset);
+        if reader.read_range(4) != Some(MIDI_TRACK_CHUNK) {
+            return Err(chunk_error);
+        }
+
+        let chunk_size = reader.read_u32().ok_or(chunk_error)?;
+        let chunk_size = reader
+            .check_length(chunk_size)
+            .map_err(|kind| MIDIFileError::new(kind, chunk_offset))?;
+        let mut track_reader = reader.read_sub_reader(chunk_size).ok_or(chunk_error)?;
+
+        let mut events = vec![];
+        let mut running_status: Option<(u8, u8)> = None;
+
+        loop {
+            let event_offset = track_reader.offset();
+            let event = Self::read_event(&mut track_reader, &mut running_status)
+                .map_err(|kind| MIDIFileError::new(kind, event_offset))?;
+
+            let is_end_of_track = matches!(event.kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack));
+
+            events.push(event);
 
             if is_end_of_track {
                 break;
@@ -664,8 +1816,81 @@ impl MIDITrack {
 
         Ok(Self { events })
     }
+
+    fn read_event(
+        track_reader: &mut BigEndianReader,
+        running_status: &mut Option<(u8, u8)>,
+    ) -> Result<MIDIEvent, MIDIFileErrorKind> {
+        let delta_time = track_reader
+            .read_var_length()
+            .ok_or(MIDIFileErrorKind::InvalidEvent)?;
+
+        let type_byte = track_reader
+            .read_u8()
+            .ok_or(MIDIFileErrorKind::InvalidEvent)?;
+
+        let kind = match type_byte {
+            0x00..=0x7F => {
+                // channel event with a running status
+                let (event_type, channel) =
+                    running_status.ok_or(MIDIFileErrorKind::InvalidEvent)?;
+                let mut param1 = Some(type_byte);
+
+                let event = ChannelEvent::from_track_event(event_type, channel, || {
+                    if let Some(v) = param1.take() {
+                        Ok(v)
+                    } else {
+                        track_reader
+                            .read_u8()
+                            .ok_or(MIDIFileErrorKind::InvalidEvent)
+                    }
+                })?;
+
+                MIDIEventKind::Channel(event)
+            }
+            0x80..=0xEF => {
+                // channel event
+                let event_type = (0xf0u8 & type_byte) >> 4;
+                let channel = 0x0fu8 & type_byte;
+
+                let event = ChannelEvent::from_track_event(event_type, channel, || {
+                    track_reader
+                        .read_u8()
+                        .ok_or(MIDIFileErrorKind::InvalidEvent)
+                })?;
+
+                *running_status = Some((event_type, channel));
+                MIDIEventKind::Channel(event)
+            }
+            0xF0 | 0xF7 => {
+                // sysex event
+                let event = SysExEvent::from_track_event(type_byte, track_reader)?;
+
+                *running_status = None;
+                MIDIEventKind::SysEx(event)
+            }
+            0xF1..=0xF6 => {
+                // system common event, not allowed in files
+                return Err(MIDIFileErrorKind::UnsupportedEvent);
+            }
+            0xF8..=0xFE => {
+                // real-time event
+                return Err(MIDIFileErrorKind::UnsupportedEvent);
+            }
+            0xFF => {
+                // meta event, running status is kept across meta events since plenty of
+                // encoders rely on it, even though the spec says it should be cancelled
+                let event = MetaEvent::from_track_event(track_reader)?;
+
+                MIDIEventKind::Meta(event)
+            }
+        };
+
+        Ok(MIDIEvent { delta_time, kind })
+    }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MIDIFormat {
     SingleMultiChannelTrack,
     MultiTracks,
@@ -674,12 +1899,18 @@ pub enum MIDIFormat {
 
 impl MIDIFormat {
     fn new(reader: &mut BigEndianReader) -> Result<MIDIFormat, MIDIFileError> {
-        let value = reader.read_u16().ok_or(MIDIFileError::InvalidHeader)?;
+        let offset = reader.offset();
+        let value = reader
+            .read_u16()
+            .ok_or(MIDIFileError::new(MIDIFileErrorKind::InvalidHeader, offset))?;
         match value {
             0 => Ok(MIDIFormat::SingleMultiChannelTrack),
             1 => Ok(MIDIFormat::MultiTracks),
             2 => Ok(MIDIFormat::MultiIndependentTracks),
-            _ => Err(MIDIFileError::UnsupportedType),
+            _ => Err(MIDIFileError::new(
+                MIDIFileErrorKind::UnsupportedType,
+                offset,
+            )),
         }
     }
 }
@@ -689,9 +1920,39 @@ pub struct MIDIFileData {
     format: MIDIFormat,
     tracks: Vec<MIDITrack>,
     time_division: TimeDivision,
+    /// One map per track in format 2, a single shared map otherwise
+    tempo_maps: Vec<TempoMap>,
 }
 
 impl MIDIFileData {
+    fn new(format: MIDIFormat, time_division: TimeDivision, tracks: Vec<MIDITrack>) -> Self {
+        let tempo_maps = match format {
+            MIDIFormat::SingleMultiChannelTrack | MIDIFormat::MultiTracks => {
+                vec![match tracks.first() {
+                    Some(track) => TempoMap::from_track(time_division, track),
+                    None => TempoMap {
+                        time_division,
+                        changes: vec![],
+                        rate: 1.0,
+                        start_offset: Duration::ZERO,
+                    },
+                }]
+            }
+            MIDIFormat::MultiIndependentTracks => tracks
+                .iter()
+                .map(|track| TempoMap::from_track(time_division, track))
+                .collect(),
+        };
+
+        Self {
+            num_tracks: tracks.len() as u16,
+            format,
+            tracks,
+            time_division,
+            tempo_maps,
+        }
+    }
+
     pub fn num_tracks(&self) -> u16 {
         self.num_tracks
     }
@@ -704,26 +1965,258 @@ impl MIDIFileData {
         &self.time_division
     }
 
-    fn parse_time_division(value: u16) -> Result<TimeDivision, MIDIFileError> {
+    /// Find the standard MIDI file wrapped in a RIFF (`.rmi`) container, skipping the other
+    /// chunks such as `DISP` or `LIST`
+    fn read_riff_data<'a>(
+        reader: &mut BigEndianReader<'a>,
+    ) -> Result<BigEndianReader<'a>, MIDIFileError> {
+        let error = |offset| MIDIFileError::new(MIDIFileErrorKind::InvalidRiffContainer, offset);
+
+        reader.read_range(4).ok_or(error(0))?;
+        // Some writers get the size of the whole container wrong, so it is capped to the file
+        let riff_size = reader.read_u32_le().ok_or(error(4))? as usize;
+        let mut riff_reader = reader
+            .read_sub_reader(riff_size.min(reader.left_bytes()))
+            .ok_or(error(8))?;
+
+        if riff_reader.read_range(4) != Some(RIFF_MIDI_FORM) {
+            return Err(error(8));
+        }
+
+        loop {
+            let chunk_offset = riff_reader.offset();
+            let is_data = riff_reader.read_range(4).ok_or(error(chunk_offset))? == RIFF_DATA_CHUNK;
+            let chunk_size = riff_reader.read_u32_le().ok_or(error(chunk_offset))?;
+            let chunk_size = riff_reader
+                .check_length(chunk_size)
+                .map_err(|kind| MIDIFileError::new(kind, chunk_offset))?;
+            let chunk_reader = riff_reader
+                .read_sub_reader(chunk_size)
+                .ok_or(error(chunk_offset))?;
+
+            if is_data {
+                return Ok(chunk_reader);
+            }
+
+            // Chunks are padded to an even size
+            if chunk_size % 2 == 1 {
+                riff_reader.read_u8();
+            }
+        }
+    }
+
+    fn parse_time_division(value: u16) -> Result<TimeDivision, MIDIFileErrorKind> {
+        // zero ticks per beat or frame would make every tick infinitely long
         if value & 0x8000u16 == 0 {
-            Ok(TimeDivision::TicksPerBit(value & 0x7FFFu16))
+            match value & 0x7FFFu16 {
+                0 => Err(MIDIFileErrorKind::InvalidTimeDivision),
+                ticks => Ok(TimeDivision::TicksPerBit(ticks)),
+            }
         } else {
-            let smpte_value = match (value & 0x7F00u16) >> 8 {
-                24 => SMPTE::_24,
-                25 => SMPTE::_25,
-                29 => SMPTE::_29_97,
-                30 => SMPTE::_30,
-                _ => return Err(MIDIFileError::InvalidSMPTEValue),
+            // the frame rate is stored negated, in two's complement
+            let smpte_value = match (value >> 8) as u8 as i8 {
+                -24 => SMPTE::_24,
+                -25 => SMPTE::_25,
+                -29 => SMPTE::_29_97,
+                -30 => SMPTE::_30,
+                _ => return Err(MIDIFileErrorKind::InvalidSMPTEValue),
             };
 
-            let clock_ticks = value & 0x00FFu16;
-            Ok(TimeDivision::FramesPerSecond(smpte_value, clock_ticks))
+            match value & 0x00FFu16 {
+                0 => Err(MIDIFileErrorKind::InvalidTimeDivision),
+                clock_ticks => Ok(TimeDivision::FramesPerSecond(smpte_value, clock_ticks)),
+            }
         }
     }
 
     pub fn format(&self) -> &MIDIFormat {
         &self.format
     }
+
+    /// The tempo map used to time the events of the given track.
+    ///
+    /// In formats 0 and 1 tempo changes live in the first track and apply to every track,
+    /// while format 2 tracks are independent sequences, each with its own tempo changes.
+    pub fn tempo_map(&self, track_index: usize) -> &TempoMap {
+        if self.is_sequential() {
+            &self.tempo_maps[track_index]
+        } else {
+            &self.tempo_maps[0]
+        }
+    }
+
+    /// Play the file faster (`rate` above 1) or slower, scaling the tempo maps of all tracks.
+    pub fn set_playback_rate(&mut self, rate: f64) {
+        for tempo_map in &mut self.tempo_maps {
+            tempo_map.rate = rate;
+        }
+    }
+
+    /// The first key signature of the file, in the order of the tracks. Files without one are
+    /// assumed to be in C major.
+    pub fn key_signature(&self) -> KeySignature {
+        self.tracks
+            .iter()
+            .flat_map(|track| track.events())
+            .find_map(|event| match event.kind() {
+                MIDIEventKind::Meta(MetaEvent::KeySignature { key, scale }) => {
+                    Some(KeySignature::new(*key, *scale))
+                }
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// Time signature changes of all tracks at their absolute ticks, sorted by tick. Of changes at
+    /// the same tick, the one of the last track is kept.
+    pub fn time_signatures(&self) -> Vec<(u64, TimeSignature)> {
+        let mut time_signatures = vec![];
+        for track in &self.tracks {
+            let mut tick = 0u64;
+            for event in track.events() {
+                tick += event.delta_time() as u64;
+
+                if let MIDIEventKind::Meta(MetaEvent::TimeSignature { number, denom, .. }) =
+                    event.kind()
+                {
+                    time_signatures.push((tick, TimeSignature::new(*number, *denom)));
+                }
+            }
+        }
+
+        // Stable, so the last change at a tick stays last
+        time_signatures.sort_by_key(|&(tick, _)| tick);
+        time_signatures.reverse();
+        time_signatures.dedup_by_key(|&mut (tick, _)| tick);
+        time_signatures.reverse();
+        time_signatures
+    }
+
+    /// Walk the events of the given track at their absolute time and tick
+    pub fn walk_track(&self, track_index: usize) -> EventWalker<'_> {
+        EventWalker::new(self, track_index, WalkPosition::default())
+    }
+
+    /// Continue a walk over the events of the given track from where it stopped
+    pub fn resume_walk(&self, track_index: usize, position: WalkPosition) -> EventWalker<'_> {
+        EventWalker::new(self, track_index, position)
+    }
+
+    /// Time at which the given track starts, counted from the start of the file. Format 2 tracks
+    /// play one after another, and any track may be delayed by an SMPTE offset.
+    pub fn track_start(&self, track_index: usize) -> Duration {
+        let previous_tracks = if self.is_sequential() {
+            (0..track_index)
+                .map(|index| self.track_span(index))
+                .fold(Duration::ZERO, Duration::saturating_add)
+        } else {
+            Duration::ZERO
+        };
+
+        previous_tracks.saturating_add(self.tempo_map(track_index).start_offset())
+    }
+
+    /// Time it takes to play the whole file
+    pub fn duration(&self) -> Duration {
+        let spans = (0..self.tracks.len()).map(|index| self.track_span(index));
+        if self.is_sequential() {
+            spans.fold(Duration::ZERO, Duration::saturating_add)
+        } else {
+            spans.max().unwrap_or_default()
+        }
+    }
+
+    /// Duration of a track including its SMPTE offset
+    fn track_span(&self, track_index: usize) -> Duration {
+        let tempo_map = self.tempo_map(track_index);
+        tempo_map
+            .start_offset()
+            .saturating_add(tempo_map.tick_to_duration(self.tracks[track_index].end_tick()))
+    }
+
+    /// Whether tracks are played one after another rather than simultaneously.
+    pub fn is_sequential(&self) -> bool {
+        matches!(self.format, MIDIFormat::MultiIndependentTracks)
+    }
+}
+
+impl MIDIFileData {
+    /// Serialize the file as a standard MIDI file. Every channel event gets its own status byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.write(false)
+    }
+
+    /// Serialize the file as a standard MIDI file, leaving out the status bytes of channel
+    /// events which repeat the one before them
+    pub fn to_bytes_with_running_status(&self) -> Vec<u8> {
+        self.write(true)
+    }
+
+    fn write(&self, running_status: bool) -> Vec<u8> {
+        let mut writer = BigEndianWriter::default();
+
+        writer.write_range(MIDI_HEADER_CHUNK);
+        writer.write_u32(6);
+        writer.write_u16(match self.format {
+            MIDIFormat::SingleMultiChannelTrack => 0,
+            MIDIFormat::MultiTracks => 1,
+            MIDIFormat::MultiIndependentTracks => 2,
+        });
+        writer.write_u16(self.tracks.len() as u16);
+        writer.write_u16(match self.time_division {
+            TimeDivision::TicksPerBit(ticks) => ticks & 0x7FFF,
+            TimeDivision::FramesPerSecond(smpte, ticks) => {
+                // the frame rate is stored negated, in two's complement
+                let rate: i8 = match smpte {
+                    SMPTE::_24 => -24,
+                    SMPTE::_25 => -25,
+                    SMPTE::_29_97 => -29,
+                    SMPTE::_30 => -30,
+                };
+                (rate as u8 as u16) << 8 | (ticks & 0x00FF)
+            }
+        });
+
+        for track in &self.tracks {
+            let mut track_writer = BigEndianWriter::default();
+            let mut last_status = None;
+            for event in &track.events {
+                track_writer.write_var_length(event.delta_time);
+                match &event.kind {
+                    MIDIEventKind::Channel(channel_event) => {
+                        let status = channel_event.status();
+                        if !running_status || last_status != Some(status) {
+                            track_writer.write_u8(status);
+                        }
+                        last_status = Some(status);
+                        channel_event.write_params(&mut track_writer);
+                    }
+                    MIDIEventKind::Meta(meta_event) => {
+                        // The parser keeps the running status across meta events, but the
+                        // specification cancels it, so it is restarted for other readers
+                        last_status = None;
+                        track_writer.write_u8(0xFF);
+                        meta_event.write(&mut track_writer);
+                    }
+                    MIDIEventKind::SysEx(sysex_event) => {
+                        last_status = None;
+                        track_writer.write_u8(match sysex_event {
+                            SysExEvent::Normal { .. } => 0xF0,
+                            SysExEvent::Escape { .. } => 0xF7,
+                        });
+                        track_writer.write_var_length_range(sysex_event.data());
+                    }
+                }
+            }
+
+            let track_bytes = track_writer.into_bytes();
+            writer.write_range(MIDI_TRACK_CHUNK);
+            writer.write_u32(track_bytes.len() as u32);
+            writer.write_range(&track_bytes);
+        }
+
+        writer.into_bytes()
+    }
 }
 
 impl TryFrom<&[u8]> for MIDIFileData {
@@ -731,34 +2224,47 @@ impl TryFrom<&[u8]> for MIDIFileData {
 
     fn try_from(buffer: &[u8]) -> Result<Self, MIDIFileError> {
         let mut reader = BigEndianReader::new(buffer);
+        if buffer.starts_with(RIFF_CHUNK) {
+            reader = Self::read_riff_data(&mut reader)?;
+        }
+
+        let offset = reader.offset();
         if reader.read_range(4) != Some(MIDI_HEADER_CHUNK) {
-            return Err(MIDIFileError::HeaderMismatch);
+            return Err(MIDIFileError::new(
+                MIDIFileErrorKind::HeaderMismatch,
+                offset,
+            ));
         }
 
+        let offset = reader.offset();
         if reader.read_u32() != Some(6u32) {
-            return Err(MIDIFileError::HeaderSizeMismatch);
+            return Err(MIDIFileError::new(
+                MIDIFileErrorKind::HeaderSizeMismatch,
+                offset,
+            ));
         }
 
         let format = MIDIFormat::new(&mut reader)?;
 
-        let num_tracks = reader.read_u16().ok_or(MIDIFileError::InvalidTrackCount)?;
-        let time_division = Self::parse_time_division(
-            reader
-                .read_u16()
-                .ok_or(MIDIFileError::InvalidTimeDivision)?,
-        )?;
+        let offset = reader.offset();
+        let num_tracks = reader.read_u16().ok_or(MIDIFileError::new(
+            MIDIFileErrorKind::InvalidTrackCount,
+            offset,
+        ))?;
+
+        let offset = reader.offset();
+        let time_division = reader
+            .read_u16()
+            .ok_or(MIDIFileErrorKind::InvalidTimeDivision)
+            .and_then(Self::parse_time_division)
+            .map_err(|kind| MIDIFileError::new(kind, offset))?;
 
         let mut tracks = vec![];
         for _ in 0..num_tracks {
             tracks.push(MIDITrack::new(&mut reader)?);
         }
 
-        Ok(Self {
-            tracks,
-            format,
-            num_tracks,
-            time_division,
-        })
+        Ok(Self::new(format, time_division, tracks))
     }
 }
 
@@ -766,6 +2272,28 @@ impl TryFrom<&[u8]> for MIDIFileData {
 mod tests {
     use super::*;
 
+    #[test]
+    fn var_length_encoding_matches_the_specification() {
+        let tests = [
+            (0x00, vec![0x00]),
+            (0x7F, vec![0x7F]),
+            (0x80, vec![0x81, 0x00]),
+            (0x3FFF, vec![0xFF, 0x7F]),
+            (0x4000, vec![0x81, 0x80, 0x00]),
+            (0x1FFFFF, vec![0xFF, 0xFF, 0x7F]),
+            (0x200000, vec![0x81, 0x80, 0x80, 0x00]),
+            (0x0FFFFFFF, vec![0xFF, 0xFF, 0xFF, 0x7F]),
+        ];
+
+        for (value, expected) in tests {
+            let mut writer = BigEndianWriter::default();
+            writer.write_var_length(value);
+            let bytes = writer.into_bytes();
+            assert_eq!(bytes, expected, "encoding of 0x{value:X}");
+            assert_eq!(BigEndianReader::new(&bytes).read_var_length(), Some(value));
+        }
+    }
+
     #[test]
     fn test_read_var_len() {
         let tests = vec![
@@ -860,4 +2388,916 @@ mod tests {
             MIDIEventKind::Meta(MetaEvent::EndOfTrack)
         ))
     }
+
+    #[test]
+    fn test_midi_format_1() {
+        let midi_bytes = include_bytes!("./assets/format1.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        assert!(matches!(midi.format(), MIDIFormat::MultiTracks));
+        assert_eq!(midi.tracks().len(), 2);
+
+        let tempo_map = midi.tempo_map(1);
+        assert_eq!(tempo_map.tick_to_duration(0), Duration::ZERO);
+        assert_eq!(tempo_map.tick_to_duration(192), Duration::from_secs(2));
+        assert_eq!(tempo_map.tick_to_duration(384), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn playback_rate_scales_tempo_maps() {
+        let midi_bytes = include_bytes!("./assets/format1.mid");
+        let mut midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        midi.set_playback_rate(2.0);
+        let tempo_map = midi.tempo_map(1);
+        assert_eq!(tempo_map.tick_to_duration(192), Duration::from_secs(1));
+        assert_eq!(tempo_map.tick_to_duration(384), Duration::from_millis(1500));
+        assert_eq!(tempo_map.duration_to_tick(Duration::from_secs(1)), 192);
+
+        midi.set_playback_rate(0.5);
+        let tempo_map = midi.tempo_map(1);
+        assert_eq!(tempo_map.tick_to_duration(384), Duration::from_secs(6));
+        assert_eq!(tempo_map.duration_to_tick(Duration::from_secs(4)), 192);
+    }
+
+    #[test]
+    fn playback_rates_saturate_durations() {
+        let midi_bytes = include_bytes!("./assets/format1.mid");
+        let mut midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        midi.set_playback_rate(2.0);
+        assert_eq!(midi.tempo_map(1).duration_to_tick(Duration::MAX), u64::MAX);
+        let cropped = transform::crop(&midi, Duration::ZERO, Duration::MAX);
+        assert_eq!(cropped.tracks()[1].end_tick(), midi.tracks()[1].end_tick());
+
+        midi.set_playback_rate(1e-30);
+        assert_eq!(midi.tempo_map(1).tick_to_duration(u64::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn test_midi_format_2() {
+        let midi_bytes = include_bytes!("./assets/format2.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        assert!(matches!(midi.format(), MIDIFormat::MultiIndependentTracks));
+        assert!(midi.is_sequential());
+        assert_eq!(midi.tracks().len(), 2);
+
+        // the first track sets 60 BPM, the second one keeps the default 120 BPM
+        assert_eq!(
+            midi.tempo_map(0).tick_to_duration(96),
+            Duration::from_secs(1)
+        );
+        assert_eq!(
+            midi.tempo_map(1).tick_to_duration(96),
+            Duration::from_millis(500)
+        );
+    }
+
+    #[test]
+    fn test_midi_running_status() {
+        let midi_bytes = include_bytes!("./assets/running_status.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        let events = midi.tracks()[0].events();
+        assert_eq!(events.len(), 10);
+
+        let notes = events
+            .iter()
+            .filter_map(|event| match event.kind() {
+                MIDIEventKind::Channel(ChannelEvent {
+                    channel: 0,
+                    kind: ChannelEventKind::NoteOn { note, velocity },
+                }) => Some((*note, *velocity)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // the running status survives the marker meta event in the middle of the track
+        assert_eq!(
+            notes,
+            vec![(60, 100), (60, 0), (64, 100), (64, 0), (67, 100), (67, 0)]
+        );
+
+        // a new status byte replaces the running status
+        assert!(matches!(
+            events[8].kind(),
+            MIDIEventKind::Channel(ChannelEvent {
+                kind: ChannelEventKind::Controller {
+                    controller_number: 64,
+                    controller_value: 0
+                },
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_midi_sysex() {
+        let midi_bytes = include_bytes!("./assets/sysex.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        let events = midi.tracks()[0].events();
+        assert_eq!(events.len(), 8);
+
+        // GM Reset
+        assert!(matches!(
+            events[0].kind(),
+            MIDIEventKind::SysEx(SysExEvent::Normal { data }) if data == &[0x7E, 0x7F, 0x09, 0x01, 0xF7]
+        ));
+        assert!(matches!(
+            events[4].kind(),
+            MIDIEventKind::SysEx(SysExEvent::Escape { data }) if data == &[0x01, 0x02]
+        ));
+        assert!(matches!(
+            events[5].kind(),
+            MIDIEventKind::Channel(ChannelEvent {
+                kind: ChannelEventKind::NoteOn { note: 64, .. },
+                ..
+            })
+        ));
+        assert!(matches!(
+            events.last().unwrap().kind(),
+            MIDIEventKind::Meta(MetaEvent::EndOfTrack)
+        ));
+    }
+
+    #[test]
+    fn test_midi_meta_delta_time() {
+        let midi_bytes = include_bytes!("./assets/tempo_change.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        let events = midi.tracks()[0].events();
+        assert!(matches!(
+            events[2].kind(),
+            MIDIEventKind::Meta(MetaEvent::SetTempo { .. })
+        ));
+        assert_eq!(events[2].delta_time(), 288);
+        assert!(matches!(
+            events[5].kind(),
+            MIDIEventKind::Meta(MetaEvent::EndOfTrack)
+        ));
+        assert_eq!(events[5].delta_time(), 96);
+    }
+
+    #[test]
+    fn test_tempo_map() {
+        let midi_bytes = include_bytes!("./assets/tempo_map.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let tempo_map = midi.tempo_map(0);
+
+        assert_eq!(tempo_map.changes().len(), 4);
+
+        let mut tick = 0u64;
+        let note_times = midi.tracks()[0]
+            .events()
+            .iter()
+            .filter_map(|event| {
+                tick += event.delta_time() as u64;
+                match event.kind() {
+                    MIDIEventKind::Channel(ChannelEvent {
+                        kind: ChannelEventKind::NoteOn { .. },
+                        ..
+                    }) => Some(tempo_map.tick_to_duration(tick)),
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        // a quarter note at 120, 60, 240 and 30 BPM, then one more note
+        assert_eq!(
+            note_times,
+            vec![
+                Duration::ZERO,
+                Duration::from_millis(500),
+                Duration::from_millis(1500),
+                Duration::from_millis(1750),
+                Duration::from_millis(3750),
+            ]
+        );
+
+        for tick in [0, 1, 95, 96, 97, 150, 192, 250, 288, 300, 384, 1000] {
+            assert_eq!(
+                tempo_map.duration_to_tick(tempo_map.tick_to_duration(tick)),
+                tick
+            );
+        }
+
+        assert_eq!(tempo_map.duration_to_tick(Duration::from_millis(1000)), 144);
+        assert_eq!(tempo_map.duration_to_tick(Duration::from_millis(1749)), 287);
+    }
+
+    #[test]
+    fn beats_last_as_long_as_the_tempo_they_are_played_at() {
+        let midi_bytes = include_bytes!("./assets/tempo_map.mid");
+        let mut midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let ms = Duration::from_millis;
+
+        // a quarter note at 120, 60, 240 and 30 BPM
+        let tempo_map = midi.tempo_map(0);
+        assert_eq!(tempo_map.beats_duration_at(Duration::ZERO, 1.0), ms(500));
+        assert_eq!(tempo_map.beats_duration_at(Duration::ZERO, 0.5), ms(250));
+        assert_eq!(tempo_map.beats_duration_at(ms(600), 0.25), ms(250));
+        assert_eq!(tempo_map.beats_duration_at(ms(1500), 0.5), ms(125));
+        // across tempo changes
+        assert_eq!(tempo_map.beats_duration_at(Duration::ZERO, 2.0), ms(1500));
+        assert_eq!(tempo_map.beats_duration_at(ms(1500), 2.0), ms(2250));
+        // after the last change
+        assert_eq!(tempo_map.beats_duration_at(ms(10_000), 1.0), ms(2000));
+
+        midi.set_playback_rate(2.0);
+        let tempo_map = midi.tempo_map(0);
+        assert_eq!(tempo_map.beats_duration_at(Duration::ZERO, 1.0), ms(250));
+        assert_eq!(tempo_map.beats_duration_at(ms(250), 1.0), ms(500));
+
+        let tempo_map = TempoMap {
+            time_division: TimeDivision::FramesPerSecond(SMPTE::_25, 40),
+            changes: vec![],
+            rate: 2.0,
+            start_offset: Duration::ZERO,
+        };
+        assert_eq!(tempo_map.beats_duration_at(ms(1000), 1.0), ms(250));
+    }
+
+    fn key(fifths: i8) -> Key {
+        match fifths {
+            0 => Key::C,
+            1.. => Key::Sharps(NonZeroU8::new(fifths as u8).unwrap()),
+            _ => Key::Flats(NonZeroU8::new(fifths.unsigned_abs()).unwrap()),
+        }
+    }
+
+    #[test]
+    fn key_signatures_spell_their_scales() {
+        let major = [
+            "C♭", "G♭", "D♭", "A♭", "E♭", "B♭", "F", "C", "G", "D", "A", "E", "B", "F♯", "C♯",
+        ];
+        let minor = [
+            "A♭", "E♭", "B♭", "F", "C", "G", "D", "A", "E", "B", "F♯", "C♯", "G♯", "D♯", "A♯",
+        ];
+
+        for fifths in -7..=7i8 {
+            for (scale, tonics, tonic_offset, steps) in [
+                (Scale::Major, major, 0, [0, 2, 4, 5, 7, 9, 11]),
+                (Scale::Minor, minor, 9, [0, 2, 3, 5, 7, 8, 10]),
+            ] {
+                let signature = KeySignature::new(key(fifths), scale);
+                let tonic = tonics[(fifths + 7) as usize];
+                let scale_name = match scale {
+                    Scale::Major => "major",
+                    Scale::Minor => "minor",
+                };
+                assert_eq!(signature.name(), format!("{tonic} {scale_name}"));
+
+                // Each letter appears once in the scale, starting with the tonic
+                let tonic_note = 60 + (fifths as i16 * 7 + tonic_offset).rem_euclid(12) as u8;
+                let letters = steps
+                    .iter()
+                    .map(|step| {
+                        signature
+                            .spell_note(tonic_note + step)
+                            .trim_end_matches(|c: char| c.is_ascii_digit())
+                            .to_string()
+                    })
+                    .collect::<Vec<_>>();
+                assert_eq!(letters[0], tonic, "{}", signature.name());
+
+                let mut first_letters = letters
+                    .iter()
+                    .map(|letter| letter.chars().next().unwrap())
+                    .collect::<Vec<_>>();
+                first_letters.sort();
+                first_letters.dedup();
+                assert_eq!(
+                    first_letters.len(),
+                    7,
+                    "{}: {:?}",
+                    signature.name(),
+                    letters
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn enharmonic_spellings() {
+        let a_flat_major = KeySignature::new(key(-4), Scale::Major);
+        let a_major = KeySignature::new(key(3), Scale::Major);
+        assert_eq!(a_flat_major.spell_note(61), "D♭4");
+        assert_eq!(a_major.spell_note(61), "C♯4");
+
+        // Notes outside of the scale
+        assert_eq!(a_major.spell_note(63), "D♯4");
+        assert_eq!(a_flat_major.spell_note(66), "G♭4");
+        assert_eq!(KeySignature::default().spell_note(70), "A♯4");
+
+        // The octave follows the letter
+        assert_eq!(
+            KeySignature::new(key(-6), Scale::Major).spell_note(59),
+            "C♭4"
+        );
+        assert_eq!(
+            KeySignature::new(key(7), Scale::Major).spell_note(60),
+            "B♯3"
+        );
+        assert_eq!(
+            KeySignature::new(key(6), Scale::Major).spell_note(65),
+            "E♯4"
+        );
+    }
+
+    #[test]
+    fn first_key_signature_is_used() {
+        let midi_bytes = include_bytes!("./assets/key_signature.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        assert_eq!(midi.key_signature().name(), "E♭ major");
+        assert_eq!(midi.key_signature().spell_note(63), "E♭4");
+
+        let midi_bytes = include_bytes!("./assets/format1.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        assert_eq!(midi.key_signature(), KeySignature::default());
+    }
+
+    #[test]
+    fn time_signature_changes() {
+        let midi_bytes = include_bytes!("./assets/meter_change.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        let time_signatures = midi.time_signatures();
+        assert_eq!(
+            time_signatures,
+            [
+                (0, TimeSignature::new(4, 2)),
+                (768, TimeSignature::new(3, 2))
+            ]
+        );
+
+        let bar_beat =
+            |tick| BarBeat::from_tick(tick, *midi.time_division(), &time_signatures).unwrap();
+        let position = |bar, beat, tick| BarBeat { bar, beat, tick };
+        assert_eq!(bar_beat(0), position(1, 1, 0));
+        assert_eq!(bar_beat(767), position(2, 4, 95));
+        assert_eq!(bar_beat(768), position(3, 1, 0));
+        assert_eq!(bar_beat(768 + 288), position(4, 1, 0));
+        assert_eq!(bar_beat(768 + 288 + 106), position(4, 2, 10));
+    }
+
+    #[test]
+    fn bars_default_to_four_four() {
+        let time_division = TimeDivision::TicksPerBit(96);
+        assert_eq!(
+            BarBeat::from_tick(2 * 384 + 2 * 96 + 5, time_division, &[]),
+            Some(BarBeat {
+                bar: 3,
+                beat: 3,
+                tick: 5
+            })
+        );
+
+        // A change in the middle of a bar starts a new one
+        let six_eight = [(96, TimeSignature::new(6, 3))];
+        assert_eq!(
+            BarBeat::from_tick(96 + 6 * 48 + 50, time_division, &six_eight),
+            Some(BarBeat {
+                bar: 3,
+                beat: 2,
+                tick: 2
+            })
+        );
+
+        assert_eq!(
+            BarBeat::from_tick(0, TimeDivision::FramesPerSecond(SMPTE::_25, 40), &[]),
+            None
+        );
+    }
+
+    #[test]
+    fn bars_start_at_time_signature_changes() {
+        let time_division = TimeDivision::TicksPerBit(96);
+        let changes = [
+            (96, TimeSignature::new(6, 3)),
+            (96 + 288, TimeSignature::new(3, 2)),
+        ];
+
+        // The first bar is cut short by the change to 6/8
+        let bar_starts = BarBeat::bar_starts(96 + 288 + 2 * 288 + 1, time_division, &changes);
+        assert_eq!(bar_starts, [0, 96, 384, 672, 960]);
+        for (bar, tick) in bar_starts.into_iter().enumerate() {
+            let bar_beat = BarBeat::from_tick(tick, time_division, &changes).unwrap();
+            assert_eq!(
+                (bar_beat.bar, bar_beat.beat, bar_beat.tick),
+                (bar as u32 + 1, 1, 0)
+            );
+        }
+
+        assert_eq!(BarBeat::bar_starts(384, time_division, &[]), [0]);
+        assert_eq!(
+            BarBeat::bar_starts(384, TimeDivision::FramesPerSecond(SMPTE::_25, 40), &[]),
+            []
+        );
+    }
+
+    #[test]
+    fn beats_follow_the_meter_of_their_bar() {
+        let midi_bytes = include_bytes!("./assets/meter_change.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let time_signatures = midi.time_signatures();
+
+        // Two bars of 4/4, then bars of 3/4, with the last one cut short by the end
+        let beat_starts =
+            BarBeat::beat_starts(768 + 2 * 288, *midi.time_division(), &time_signatures);
+        let downbeats = beat_starts
+            .iter()
+            .filter(|(_, downbeat)| *downbeat)
+            .map(|(tick, _)| *tick)
+            .collect::<Vec<_>>();
+        assert_eq!(downbeats, [0, 384, 768, 1056]);
+        assert_eq!(beat_starts.len(), 8 + 6);
+        for (tick, downbeat) in beat_starts {
+            let bar_beat =
+                BarBeat::from_tick(tick, *midi.time_division(), &time_signatures).unwrap();
+            assert_eq!(bar_beat.tick, 0);
+            assert_eq!(bar_beat.beat == 1, downbeat);
+        }
+
+        // 6/8 counts eighths, and the bar cut short by the change only has its first beat
+        let time_division = TimeDivision::TicksPerBit(96);
+        let changes = [(48, TimeSignature::new(6, 3))];
+        assert_eq!(
+            BarBeat::beat_starts(48 + 3 * 48, time_division, &changes),
+            [(0, true), (48, true), (96, false), (144, false)]
+        );
+        assert_eq!(
+            BarBeat::beat_starts(384, TimeDivision::FramesPerSecond(SMPTE::_25, 40), &[]),
+            []
+        );
+    }
+
+    #[test]
+    fn text_falls_back_to_latin_1() {
+        assert_eq!(decode_text("Ünïcode ♪".as_bytes()), "Ünïcode ♪");
+        assert_eq!(decode_text(&[0x43, 0x61, 0x66, 0xE9]), "Café");
+    }
+
+    #[test]
+    fn errors_carry_the_offset_of_the_failing_event() {
+        let mut buffer = vec![];
+        buffer.extend_from_slice(b"MThd");
+        buffer.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        buffer.extend_from_slice(b"MTrk");
+        buffer.extend_from_slice(&[0, 0, 0, 16]);
+        // note on, then a set tempo with one byte too many
+        buffer.extend_from_slice(&[0x00, 0x90, 60, 100]);
+        buffer.extend_from_slice(&[0x00, 0xFF, 0x51, 0x04, 0x07, 0xA1, 0x20, 0x00]);
+        buffer.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let error = MIDIFileData::try_from(&buffer[..]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            MIDIFileErrorKind::UnexpectedMetaLength(0x51, 4, 3)
+        );
+        assert_eq!(error.offset(), 26);
+        assert_eq!(
+            error.to_string(),
+            "Invalid Set Tempo meta event length (4, expected 3) at byte 0x1A"
+        );
+    }
+
+    #[test]
+    fn header_errors_point_at_the_header_field() {
+        let error = MIDIFileData::try_from(&b"OggS"[..]).err().unwrap();
+        assert_eq!(error.kind(), MIDIFileErrorKind::HeaderMismatch);
+        assert_eq!(error.offset(), 0);
+
+        let mut buffer = vec![];
+        buffer.extend_from_slice(b"MThd");
+        buffer.extend_from_slice(&[0, 0, 0, 6, 0, 3, 0, 1, 0, 96]);
+        let error = MIDIFileData::try_from(&buffer[..]).err().unwrap();
+        assert_eq!(error.kind(), MIDIFileErrorKind::UnsupportedType);
+        assert_eq!(error.offset(), 8);
+    }
+
+    #[test]
+    fn riff_wrapped_files_parse_like_plain_ones() {
+        let midi_bytes = include_bytes!("./assets/test.mid");
+        let rmi_bytes = include_bytes!("./assets/test.rmi");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let rmi = MIDIFileData::try_from(&rmi_bytes[..]).unwrap();
+
+        let event_counts = |data: &MIDIFileData| {
+            data.tracks()
+                .iter()
+                .map(|track| track.events().len())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(rmi.tracks().len(), midi.tracks().len());
+        assert_eq!(event_counts(&rmi), event_counts(&midi));
+    }
+
+    #[test]
+    fn riff_without_data_chunk_is_rejected() {
+        let mut buffer = vec![];
+        buffer.extend_from_slice(b"RIFF");
+        buffer.extend_from_slice(&12u32.to_le_bytes());
+        buffer.extend_from_slice(b"RMIDDISP");
+        buffer.extend_from_slice(&0u32.to_le_bytes());
+
+        let error = MIDIFileData::try_from(&buffer[..]).err().unwrap();
+        assert_eq!(error.kind(), MIDIFileErrorKind::InvalidRiffContainer);
+        assert_eq!(error.offset(), 20);
+    }
+
+    fn single_track_file(track: &[u8]) -> Vec<u8> {
+        let mut buffer = vec![];
+        buffer.extend_from_slice(b"MThd");
+        buffer.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        buffer.extend_from_slice(b"MTrk");
+        buffer.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        buffer.extend_from_slice(track);
+        buffer
+    }
+
+    #[test]
+    fn oversized_lengths_are_rejected() {
+        // a text event claiming 200 MB
+        let buffer = single_track_file(&[0x00, 0xFF, 0x01, 0xDF, 0xAF, 0x84, 0x00, b'a']);
+        let error = MIDIFileData::try_from(&buffer[..]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            MIDIFileErrorKind::LengthOutOfBounds(200_000_000)
+        );
+        assert_eq!(error.offset(), 22);
+
+        let buffer = single_track_file(&[0x00, 0xF0, 0x7F, 0x7E, 0xF7]);
+        let error = MIDIFileData::try_from(&buffer[..]).err().unwrap();
+        assert_eq!(error.kind(), MIDIFileErrorKind::LengthOutOfBounds(0x7F));
+
+        let mut buffer = single_track_file(&[0x00, 0xFF, 0x2F, 0x00]);
+        buffer[18..22].copy_from_slice(&0x0FFF_FFFFu32.to_be_bytes());
+        let error = MIDIFileData::try_from(&buffer[..]).err().unwrap();
+        assert_eq!(
+            error.kind(),
+            MIDIFileErrorKind::LengthOutOfBounds(0x0FFF_FFFF)
+        );
+        assert_eq!(error.offset(), 14);
+    }
+
+    #[test]
+    fn random_and_corrupted_buffers_never_panic() {
+        // xorshift, so failures are reproducible
+        let mut state = 0x2545_F491_4F6C_DD1Du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let midi_bytes = include_bytes!("./assets/test.mid");
+        for _ in 0..2000 {
+            let length = (next() % 64) as usize;
+            let noise = (0..length).map(|_| next() as u8).collect::<Vec<_>>();
+
+            let _ = MIDIFileData::try_from(&noise[..]);
+            let _ = MIDIFileData::try_from(&single_track_file(&noise)[..]);
+
+            let mut corrupted = midi_bytes.to_vec();
+            for _ in 0..4 {
+                let index = (next() as usize) % corrupted.len();
+                corrupted[index] = next() as u8;
+            }
+            let _ = MIDIFileData::try_from(&corrupted[..]);
+        }
+    }
+
+    /// Variable length quantity of the 28 low bits of `value`
+    fn var_length(value: u32) -> Vec<u8> {
+        let value = value & 0x0FFF_FFFF;
+        let mut bytes = vec![(value & 0x7F) as u8];
+        let mut rest = value >> 7;
+        while rest > 0 {
+            bytes.insert(0, (rest & 0x7F) as u8 | 0x80);
+            rest >>= 7;
+        }
+        bytes
+    }
+
+    #[test]
+    fn generated_files_up_to_64_kb_never_panic() {
+        let mut state = 0x9E37_79B9_7F4A_7C15u64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let midi_bytes = include_bytes!("./assets/test.mid");
+        for _ in 0..50 {
+            // Valid chunks of events taking extreme values, as random bytes rarely get past the
+            // header
+            let mut buffer = b"MThd\0\0\0\x06".to_vec();
+            let divisions = [1, 96, 0x7FFF, 0xE250, 0xE701, next() as u16];
+            let num_tracks = 1 + next() as u16 % 4;
+            buffer.extend_from_slice(&(next() as u16 % 3).to_be_bytes());
+            buffer.extend_from_slice(&num_tracks.to_be_bytes());
+            buffer.extend_from_slice(&divisions[next() as usize % divisions.len()].to_be_bytes());
+            for _ in 0..num_tracks {
+                let mut track = vec![];
+                for _ in 0..next() % 2000 {
+                    let deltas = [0, 1, 0x0FFF_FFFF, next() as u32];
+                    track.extend(var_length(deltas[next() as usize % deltas.len()]));
+                    let [a, b, c, d, ..] = next().to_le_bytes();
+                    match next() % 64 {
+                        0..20 => track.extend([0x90 | a & 0x0F, b & 0x7F, c & 0x7F]),
+                        20..40 => track.extend([0x80 | a & 0x0F, b & 0x7F, c & 0x7F]),
+                        40..50 => {
+                            let tempos = [1, 0xFF_FFFF, next() as u32 & 0xFF_FFFF];
+                            let tempo = tempos[next() as usize % tempos.len()];
+                            track.extend([0xFF, 0x51, 0x03]);
+                            track.extend(&tempo.to_be_bytes()[1..]);
+                        }
+                        50..56 => track.extend([0xFF, 0x58, 0x04, a, b, 24, 8]),
+                        56..63 => track.extend([0xFF, 0x54, 0x05, a, b, c, d, a]),
+                        _ => track.extend([0xB0 | a & 0x0F, b & 0x7F, c & 0x7F]),
+                    }
+                }
+                track.extend([0x00, 0xFF, 0x2F, 0x00]);
+                buffer.extend_from_slice(b"MTrk");
+                buffer.extend_from_slice(&(track.len() as u32).to_be_bytes());
+                buffer.extend(track);
+            }
+            buffer.truncate(0x10000);
+
+            let length = (next() % 0x10000) as usize;
+            let noise = (0..length).map(|_| next() as u8).collect::<Vec<_>>();
+
+            let mut corrupted = midi_bytes.to_vec();
+            corrupted.truncate((next() as usize) % (corrupted.len() + 1));
+            corrupted.extend(&noise[..noise.len() % 256]);
+
+            for bytes in [buffer, noise, corrupted] {
+                let Ok(mut data) = MIDIFileData::try_from(&bytes[..]) else {
+                    continue;
+                };
+                data.set_playback_rate(4.0);
+                let end_tick = data.tempo_map(0).duration_to_tick(data.duration());
+                let _ =
+                    BarBeat::beat_starts(end_tick, *data.time_division(), &data.time_signatures());
+                let _ = notes::extract_notes(&data);
+                assert!(MIDIFileData::try_from(&data.to_bytes()[..]).is_ok());
+            }
+        }
+    }
+
+    #[test]
+    fn zero_tempos_are_rejected() {
+        let buffer = single_track_file(&[0x00, 0xFF, 0x51, 0x03, 0x00, 0x00, 0x00]);
+        let error = MIDIFileData::try_from(&buffer[..]).err().unwrap();
+        assert_eq!(error.kind(), MIDIFileErrorKind::ZeroTempo);
+    }
+
+    #[test]
+    fn durations_of_extreme_tempos_and_deltas_do_not_overflow() {
+        // The slowest tempo at one tick per beat, then notes a quarter billion ticks apart, which
+        // overflowed the nanoseconds of the track duration
+        let mut track = vec![0x00, 0xFF, 0x51, 0x03, 0xFF, 0xFF, 0xFF];
+        for _ in 0..8 {
+            track.extend(var_length(0x0FFF_FFFF));
+            track.extend([0x90, 60, 100]);
+        }
+        track.extend([0x00, 0xFF, 0x2F, 0x00]);
+        let mut buffer = single_track_file(&track);
+        buffer[12..14].copy_from_slice(&1u16.to_be_bytes());
+
+        let data = MIDIFileData::try_from(&buffer[..]).unwrap();
+        assert_eq!(data.tracks()[0].end_tick(), 8 * 0x0FFF_FFFF);
+        assert!(data.duration() > Duration::from_secs(500 * 365 * 24 * 60 * 60));
+
+        let division = TimeDivision::FramesPerSecond(SMPTE::_30, 1);
+        assert_eq!(
+            division.ticks_duration(Tempo::default(), u64::MAX),
+            Duration::from_nanos(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn bars_and_beats_are_bounded() {
+        // A tempo of a microsecond per beat fits billions of beats in a file lasting seconds
+        let time_division = TimeDivision::TicksPerBit(1);
+        assert_eq!(
+            BarBeat::bar_starts(u64::MAX, time_division, &[]).len(),
+            MAX_BAR_BEATS
+        );
+        let beat_starts = BarBeat::beat_starts(u64::MAX, time_division, &[]);
+        assert_eq!(beat_starts.len(), MAX_BAR_BEATS);
+        assert_eq!(beat_starts[4], (4, true));
+    }
+
+    #[test]
+    fn smpte_divisions_are_negative_frame_rates() {
+        let parse = |rate: i8, ticks: u8| {
+            let mut buffer = vec![];
+            buffer.extend_from_slice(b"MThd");
+            buffer.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 0, rate as u8, ticks]);
+            MIDIFileData::try_from(&buffer[..]).map(|data| *data.time_division())
+        };
+
+        assert!(matches!(
+            parse(-24, 4),
+            Ok(TimeDivision::FramesPerSecond(SMPTE::_24, 4))
+        ));
+        assert!(matches!(
+            parse(-25, 40),
+            Ok(TimeDivision::FramesPerSecond(SMPTE::_25, 40))
+        ));
+        assert!(matches!(
+            parse(-29, 80),
+            Ok(TimeDivision::FramesPerSecond(SMPTE::_29_97, 80))
+        ));
+        assert!(matches!(
+            parse(-30, 100),
+            Ok(TimeDivision::FramesPerSecond(SMPTE::_30, 100))
+        ));
+        assert!(parse(-23, 4).is_err());
+    }
+
+    #[test]
+    fn smpte_offset_uses_the_rate_of_the_hour_byte() {
+        // 25 fps, 1 hour, 2 seconds and 12.5 frames
+        let offset = smpte_offset(0x21, 0, 2, 12, 50);
+        assert_eq!(offset, Duration::from_millis(3_602_500));
+
+        let midi_bytes = include_bytes!("./assets/smpte_offset.mid");
+        let mut midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        assert_eq!(midi.tempo_map(0).start_offset(), Duration::from_secs(2));
+
+        midi.set_playback_rate(2.0);
+        assert_eq!(midi.tempo_map(0).start_offset(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn serialized_files_parse_back_event_for_event() {
+        let files: [&[u8]; 8] = [
+            include_bytes!("./assets/test.mid"),
+            include_bytes!("./assets/format2.mid"),
+            include_bytes!("./assets/running_status.mid"),
+            include_bytes!("./assets/sysex.mid"),
+            include_bytes!("./assets/key_signature.mid"),
+            include_bytes!("./assets/meter_change.mid"),
+            include_bytes!("./assets/smpte_offset.mid"),
+            include_bytes!("./assets/karaoke.kar"),
+        ];
+
+        for midi_bytes in files {
+            let data = MIDIFileData::try_from(midi_bytes).unwrap();
+            for bytes in [data.to_bytes(), data.to_bytes_with_running_status()] {
+                let parsed = MIDIFileData::try_from(&bytes[..]).unwrap();
+                assert_eq!(parsed.format(), data.format());
+                assert_eq!(parsed.time_division(), data.time_division());
+                assert_eq!(parsed.tracks(), data.tracks());
+            }
+        }
+    }
+
+    #[test]
+    fn running_status_leaves_out_repeated_status_bytes() {
+        let midi_bytes = include_bytes!("./assets/running_status.mid");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+
+        let channel_events = data.tracks()[0]
+            .events()
+            .iter()
+            .filter(|event| matches!(event.kind(), MIDIEventKind::Channel(_)))
+            .count();
+        assert!(channel_events > 1);
+        assert!(data.to_bytes_with_running_status().len() < data.to_bytes().len());
+
+        // a six byte header chunk, followed by the track chunk
+        let bytes = data.to_bytes_with_running_status();
+        assert_eq!(&bytes[..8], b"MThd\0\0\0\x06");
+        assert_eq!(&bytes[14..18], b"MTrk");
+    }
+
+    #[test]
+    fn walked_events_are_timed_like_the_tempo_map() {
+        let assets: [&[u8]; 5] = [
+            include_bytes!("./assets/test.mid"),
+            include_bytes!("./assets/tempo_map.mid"),
+            include_bytes!("./assets/format2.mid"),
+            include_bytes!("./assets/smpte_offset.mid"),
+            include_bytes!("./assets/tempo_change.mid"),
+        ];
+
+        for midi_bytes in assets {
+            let mut data = MIDIFileData::try_from(midi_bytes).unwrap();
+            data.set_playback_rate(1.5);
+
+            for track_index in 0..data.tracks().len() {
+                let tempo_map = data.tempo_map(track_index);
+                let track_start = data.track_start(track_index);
+                let mut walker = data.walk_track(track_index);
+                let mut walked = 0;
+
+                for (time, tick, _) in walker.by_ref() {
+                    assert_eq!(time, track_start + tempo_map.tick_to_duration(tick));
+                    walked += 1;
+                }
+
+                let track = &data.tracks()[track_index];
+                assert_eq!(walked, track.events().len());
+                assert_eq!(walker.tick(), track.end_tick());
+                assert!(walker.next().is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn device_messages_are_channel_events() {
+        assert_eq!(
+            ChannelEvent::from_message(&[0x93, 60, 100]),
+            Some(ChannelEvent {
+                channel: 3,
+                kind: ChannelEventKind::NoteOn {
+                    note: 60,
+                    velocity: 100
+                }
+            })
+        );
+        assert_eq!(
+            ChannelEvent::from_message(&[0xB0, 64, 127]).map(|event| event.kind),
+            Some(ChannelEventKind::Controller {
+                controller_number: 64,
+                controller_value: 127
+            })
+        );
+        assert_eq!(
+            ChannelEvent::from_message(&[0xEF, 0x00, 0x40]).map(|event| event.kind),
+            Some(ChannelEventKind::PitchBend {
+                lsb: 0x00,
+                msb: 0x40
+            })
+        );
+
+        // the clock, a truncated note and a data byte out of range
+        assert_eq!(ChannelEvent::from_message(&[0xF8]), None);
+        assert_eq!(ChannelEvent::from_message(&[0x80, 60]), None);
+        assert_eq!(ChannelEvent::from_message(&[0x90, 60, 0x80]), None);
+        assert_eq!(ChannelEvent::from_message(&[60, 100]), None);
+        assert_eq!(ChannelEvent::from_message(&[]), None);
+    }
+
+    #[test]
+    fn pitch_bends_combine_both_halves() {
+        let bend = |lsb, msb| {
+            ChannelEventKind::PitchBend { lsb, msb }
+                .pitch_bend()
+                .unwrap()
+        };
+        assert_eq!(bend(0x00, 0x40).value(), 0);
+        assert_eq!(bend(0x00, 0x00).value(), -8192);
+        assert_eq!(bend(0x7F, 0x7F).value(), 8191);
+        assert_eq!(bend(0x01, 0x40).value(), 1);
+        assert_eq!(bend(0x7F, 0x3F).value(), -1);
+
+        assert_eq!(bend(0x00, 0x40).semitones(2.0), 0.0);
+        assert_eq!(bend(0x00, 0x00).semitones(2.0), -2.0);
+        assert!((bend(0x7F, 0x7F).semitones(2.0) - 2.0).abs() < 0.001);
+        assert_eq!(bend(0x00, 0x60).semitones(12.0), 6.0);
+
+        let note_on = ChannelEventKind::NoteOn {
+            note: 60,
+            velocity: 100,
+        };
+        assert_eq!(note_on.pitch_bend(), None);
+        assert_eq!(note_on.controller(), None);
+    }
+
+    #[test]
+    fn controller_kinds_round_trip_their_numbers() {
+        for number in 0..=127 {
+            assert_eq!(ControllerKind::from(number).number(), number);
+        }
+        assert_eq!(ControllerKind::from(64), ControllerKind::Sustain);
+        assert_eq!(ControllerKind::from(7), ControllerKind::Volume);
+        assert_eq!(ControllerKind::from(2), ControllerKind::Other(2));
+
+        let controller = ChannelEventKind::Controller {
+            controller_number: 10,
+            controller_value: 32,
+        }
+        .controller()
+        .unwrap();
+        assert_eq!(controller.kind(), ControllerKind::Pan);
+        assert_eq!(controller.value(), 32);
+    }
+
+    #[test]
+    fn switch_controllers_are_on_from_64() {
+        assert!(!Controller::new(64, 0).is_on());
+        assert!(!Controller::new(64, 63).is_on());
+        assert!(Controller::new(64, 64).is_on());
+        assert!(Controller::new(64, 127).is_on());
+    }
 }
@@ -0,0 +1,255 @@
+//! Construction of MIDI files in code, for tests and generated content.
+//!
+//! Events are placed at absolute ticks through a cursor, and turned into delta times when the
+//! track is built:
+//!
+//! ```ignore
+//! let track = TrackBuilder::new()
+//!     .set_tempo(Tempo::from_bpm(120))
+//!     .note(0, 60, 100, 96)
+//!     .delta(96)
+//!     .note(0, 64, 100, 96);
+//! let data = FileBuilder::new(MIDIFormat::SingleMultiChannelTrack, TimeDivision::TicksPerBit(96))
+//!     .track(track)
+//!     .build();
+//! ```
+use crate::midi::{
+    ChannelEvent, ChannelEventKind, MIDIEvent, MIDIEventKind, MIDIFileData, MIDIFormat, MIDITrack,
+    MetaEvent, SysExEvent, Tempo, TimeDivision,
+};
+
+#[derive(Debug, Default)]
+pub struct TrackBuilder {
+    /// Events with their absolute tick, in the order they were added
+    events: Vec<(u64, MIDIEventKind)>,
+    /// Tick at which the next event is placed
+    cursor: u64,
+}
+
+impl TrackBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the cursor to an absolute tick
+    pub fn at(mut self, tick: u64) -> Self {
+        self.cursor = tick;
+        self
+    }
+
+    /// Move the cursor forward
+    pub fn delta(mut self, ticks: u64) -> Self {
+        self.cursor += ticks;
+        self
+    }
+
+    /// Add an event at the cursor
+    pub fn event(mut self, kind: MIDIEventKind) -> Self {
+        self.events.push((self.cursor, kind));
+        self
+    }
+
+    pub fn channel_event(self, channel: u8, kind: ChannelEventKind) -> Self {
+        self.event(MIDIEventKind::Channel(ChannelEvent { channel, kind }))
+    }
+
+    pub fn note_on(self, channel: u8, note: u8, velocity: u8) -> Self {
+        self.channel_event(channel, ChannelEventKind::NoteOn { note, velocity })
+    }
+
+    pub fn note_off(self, channel: u8, note: u8, velocity: u8) -> Self {
+        self.channel_event(channel, ChannelEventKind::NoteOff { note, velocity })
+    }
+
+    /// Add a note on at the cursor and its note off `length` ticks later, leaving the cursor
+    /// where it was
+    pub fn note(self, channel: u8, note: u8, velocity: u8, length: u64) -> Self {
+        let cursor = self.cursor;
+        self.note_on(channel, note, velocity)
+            .delta(length)
+            .note_off(channel, note, 0)
+            .at(cursor)
+    }
+
+    pub fn controller(self, channel: u8, controller_number: u8, controller_value: u8) -> Self {
+        self.channel_event(
+            channel,
+            ChannelEventKind::Controller {
+                controller_number,
+                controller_value,
+            },
+        )
+    }
+
+    pub fn program_change(self, channel: u8, program_number: u8) -> Self {
+        self.channel_event(channel, ChannelEventKind::ProgramChange { program_number })
+    }
+
+    pub fn set_tempo(self, tempo: Tempo) -> Self {
+        self.meta(MetaEvent::SetTempo { tempo })
+    }
+
+    pub fn meta(self, event: MetaEvent) -> Self {
+        self.event(MIDIEventKind::Meta(event))
+    }
+
+    pub fn sysex(self, event: SysExEvent) -> Self {
+        self.event(MIDIEventKind::SysEx(event))
+    }
+
+    /// Sort the events by tick and end the track after the last one, or at the cursor if it is
+    /// further. End of track events added by hand are replaced by that one.
+    pub fn build(self) -> MIDITrack {
+        let mut events = self
+            .events
+            .into_iter()
+            .filter(|(_, kind)| !matches!(kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack)))
+            .collect::<Vec<_>>();
+        // Stable, so events at the same tick keep the order they were added in
+        events.sort_by_key(|(tick, _)| *tick);
+
+        let end_tick = events
+            .last()
+            .map_or(self.cursor, |(tick, _)| self.cursor.max(*tick));
+        events.push((end_tick, MIDIEventKind::Meta(MetaEvent::EndOfTrack)));
+
+        let mut last_tick = 0u64;
+        let events = events
+            .into_iter()
+            .map(|(tick, kind)| {
+                let delta_time = (tick - last_tick) as u32;
+                last_tick = tick;
+                MIDIEvent { delta_time, kind }
+            })
+            .collect();
+
+        MIDITrack { events }
+    }
+}
+
+pub struct FileBuilder {
+    format: MIDIFormat,
+    time_division: TimeDivision,
+    tracks: Vec<MIDITrack>,
+}
+
+impl FileBuilder {
+    pub fn new(format: MIDIFormat, time_division: TimeDivision) -> Self {
+        Self {
+            format,
+            time_division,
+            tracks: vec![],
+        }
+    }
+
+    pub fn track(mut self, track: TrackBuilder) -> Self {
+        self.tracks.push(track.build());
+        self
+    }
+
+    pub fn build(self) -> MIDIFileData {
+        MIDIFileData::new(self.format, self.time_division, self.tracks)
+    }
+
+    /// Serialize the file, as it would be stored on disk
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.build().to_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU8;
+
+    use super::*;
+    use crate::midi::{Key, Scale};
+
+    fn assert_round_trip(data: &MIDIFileData) {
+        let parsed = MIDIFileData::try_from(&data.to_bytes()[..]).unwrap();
+        assert_eq!(parsed.format(), data.format());
+        assert_eq!(parsed.time_division(), data.time_division());
+        assert_eq!(parsed.tracks(), data.tracks());
+    }
+
+    #[test]
+    fn events_get_delta_times_from_absolute_ticks() {
+        let track = TrackBuilder::new()
+            .set_tempo(Tempo::from_bpm(120))
+            .note(0, 60, 100, 96)
+            .delta(48)
+            .note(0, 64, 90, 96)
+            .build();
+
+        let deltas = track
+            .events()
+            .iter()
+            .map(|event| event.delta_time())
+            .collect::<Vec<_>>();
+        // tempo, C on, E on, C off, E off, end of track
+        assert_eq!(deltas, [0, 0, 48, 48, 48, 0]);
+        assert!(matches!(
+            track.events()[3].kind(),
+            MIDIEventKind::Channel(ChannelEvent {
+                channel: 0,
+                kind: ChannelEventKind::NoteOff { note: 60, .. }
+            })
+        ));
+        assert_eq!(track.end_tick(), 144);
+    }
+
+    #[test]
+    fn trailing_cursor_delays_the_end_of_track() {
+        let track = TrackBuilder::new().note(0, 60, 100, 96).delta(192).build();
+        assert_eq!(track.end_tick(), 192);
+
+        let track = TrackBuilder::new()
+            .meta(MetaEvent::EndOfTrack)
+            .note(0, 60, 100, 96)
+            .build();
+        assert_eq!(track.events().len(), 3);
+        assert_eq!(track.end_tick(), 96);
+    }
+
+    #[test]
+    fn built_files_round_trip_through_the_parser() {
+        let tempo_track = TrackBuilder::new()
+            .meta(MetaEvent::SequenceTrackName {
+                text: b"Tempo".to_vec(),
+            })
+            .meta(MetaEvent::TimeSignature {
+                number: 6,
+                denom: 3,
+                metro: 24,
+                _32nds: 8,
+            })
+            .meta(MetaEvent::KeySignature {
+                key: Key::Flats(NonZeroU8::new(3).unwrap()),
+                scale: Scale::Minor,
+            })
+            .set_tempo(Tempo::from_bpm(90))
+            .at(20_000)
+            .set_tempo(Tempo::from_mpqn(0x0FFFFF));
+        let melody = TrackBuilder::new()
+            .program_change(1, 40)
+            .controller(1, 7, 100)
+            .note(1, 72, 127, 0x3FFF)
+            .channel_event(1, ChannelEventKind::PitchBend { lsb: 0, msb: 0x40 })
+            .sysex(SysExEvent::Normal {
+                data: vec![0x7E, 0x7F, 0x09, 0x01, 0xF7],
+            });
+
+        let data = FileBuilder::new(MIDIFormat::MultiTracks, TimeDivision::TicksPerBit(480))
+            .track(tempo_track)
+            .track(melody)
+            .build();
+        assert_round_trip(&data);
+
+        let data = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::FramesPerSecond(crate::midi::SMPTE::_29_97, 80),
+        )
+        .track(TrackBuilder::new().note(9, 36, 100, 10))
+        .build();
+        assert_round_trip(&data);
+    }
+}
@@ -0,0 +1,355 @@
+//! Names of the General MIDI instruments, as selected by program changes, and of the drums of the
+//! percussion channel.
+use std::fmt;
+
+/// Names of the melodic programs, indexed by program number
+const PROGRAM_NAMES: [&str; 128] = [
+    // Piano
+    "Acoustic Grand Piano",
+    "Bright Acoustic Piano",
+    "Electric Grand Piano",
+    "Honky-tonk Piano",
+    "Electric Piano 1",
+    "Electric Piano 2",
+    "Harpsichord",
+    "Clavi",
+    // Chromatic percussion
+    "Celesta",
+    "Glockenspiel",
+    "Music Box",
+    "Vibraphone",
+    "Marimba",
+    "Xylophone",
+    "Tubular Bells",
+    "Dulcimer",
+    // Organ
+    "Drawbar Organ",
+    "Percussive Organ",
+    "Rock Organ",
+    "Church Organ",
+    "Reed Organ",
+    "Accordion",
+    "Harmonica",
+    "Tango Accordion",
+    // Guitar
+    "Acoustic Guitar (nylon)",
+    "Acoustic Guitar (steel)",
+    "Electric Guitar (jazz)",
+    "Electric Guitar (clean)",
+    "Electric Guitar (muted)",
+    "Overdriven Guitar",
+    "Distortion Guitar",
+    "Guitar Harmonics",
+    // Bass
+    "Acoustic Bass",
+    "Electric Bass (finger)",
+    "Electric Bass (pick)",
+    "Fretless Bass",
+    "Slap Bass 1",
+    "Slap Bass 2",
+    "Synth Bass 1",
+    "Synth Bass 2",
+    // Strings
+    "Violin",
+    "Viola",
+    "Cello",
+    "Contrabass",
+    "Tremolo Strings",
+    "Pizzicato Strings",
+    "Orchestral Harp",
+    "Timpani",
+    // Ensemble
+    "String Ensemble 1",
+    "String Ensemble 2",
+    "Synth Strings 1",
+    "Synth Strings 2",
+    "Choir Aahs",
+    "Voice Oohs",
+    "Synth Voice",
+    "Orchestra Hit",
+    // Brass
+    "Trumpet",
+    "Trombone",
+    "Tuba",
+    "Muted Trumpet",
+    "French Horn",
+    "Brass Section",
+    "Synth Brass 1",
+    "Synth Brass 2",
+    // Reed
+    "Soprano Sax",
+    "Alto Sax",
+    "Tenor Sax",
+    "Baritone Sax",
+    "Oboe",
+    "English Horn",
+    "Bassoon",
+    "Clarinet",
+    // Pipe
+    "Piccolo",
+    "Flute",
+    "Recorder",
+    "Pan Flute",
+    "Blown Bottle",
+    "Shakuhachi",
+    "Whistle",
+    "Ocarina",
+    // Synth lead
+    "Lead 1 (square)",
+    "Lead 2 (sawtooth)",
+    "Lead 3 (calliope)",
+    "Lead 4 (chiff)",
+    "Lead 5 (charang)",
+    "Lead 6 (voice)",
+    "Lead 7 (fifths)",
+    "Lead 8 (bass + lead)",
+    // Synth pad
+    "Pad 1 (new age)",
+    "Pad 2 (warm)",
+    "Pad 3 (polysynth)",
+    "Pad 4 (choir)",
+    "Pad 5 (bowed)",
+    "Pad 6 (metallic)",
+    "Pad 7 (halo)",
+    "Pad 8 (sweep)",
+    // Synth effects
+    "FX 1 (rain)",
+    "FX 2 (soundtrack)",
+    "FX 3 (crystal)",
+    "FX 4 (atmosphere)",
+    "FX 5 (brightness)",
+    "FX 6 (goblins)",
+    "FX 7 (echoes)",
+    "FX 8 (sci-fi)",
+    // Ethnic
+    "Sitar",
+    "Banjo",
+    "Shamisen",
+    "Koto",
+    "Kalimba",
+    "Bag pipe",
+    "Fiddle",
+    "Shanai",
+    // Percussive
+    "Tinkle Bell",
+    "Agogo",
+    "Steel Drums",
+    "Woodblock",
+    "Taiko Drum",
+    "Melodic Tom",
+    "Synth Drum",
+    "Reverse Cymbal",
+    // Sound effects
+    "Guitar Fret Noise",
+    "Breath Noise",
+    "Seashore",
+    "Bird Tweet",
+    "Telephone Ring",
+    "Helicopter",
+    "Applause",
+    "Gunshot",
+];
+
+/// Key of the first drum of the percussion map
+const FIRST_PERCUSSION_NOTE: u8 = 35;
+
+/// Names of the drums of the percussion channel, indexed by key from [`FIRST_PERCUSSION_NOTE`]
+const PERCUSSION_NAMES: [&str; 47] = [
+    "Acoustic Bass Drum",
+    "Bass Drum 1",
+    "Side Stick",
+    "Acoustic Snare",
+    "Hand Clap",
+    "Electric Snare",
+    "Low Floor Tom",
+    "Closed Hi-Hat",
+    "High Floor Tom",
+    "Pedal Hi-Hat",
+    "Low Tom",
+    "Open Hi-Hat",
+    "Low-Mid Tom",
+    "Hi-Mid Tom",
+    "Crash Cymbal 1",
+    "High Tom",
+    "Ride Cymbal 1",
+    "Chinese Cymbal",
+    "Ride Bell",
+    "Tambourine",
+    "Splash Cymbal",
+    "Cowbell",
+    "Crash Cymbal 2",
+    "Vibraslap",
+    "Ride Cymbal 2",
+    "Hi Bongo",
+    "Low Bongo",
+    "Mute Hi Conga",
+    "Open Hi Conga",
+    "Low Conga",
+    "High Timbale",
+    "Low Timbale",
+    "High Agogo",
+    "Low Agogo",
+    "Cabasa",
+    "Maracas",
+    "Short Whistle",
+    "Long Whistle",
+    "Short Guiro",
+    "Long Guiro",
+    "Claves",
+    "Hi Wood Block",
+    "Low Wood Block",
+    "Mute Cuica",
+    "Open Cuica",
+    "Mute Triangle",
+    "Open Triangle",
+];
+
+/// Group of eight consecutive General MIDI programs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Family {
+    Piano,
+    ChromaticPercussion,
+    Organ,
+    Guitar,
+    Bass,
+    Strings,
+    Ensemble,
+    Brass,
+    Reed,
+    Pipe,
+    SynthLead,
+    SynthPad,
+    SynthEffects,
+    Ethnic,
+    Percussive,
+    SoundEffects,
+}
+
+impl Family {
+    pub const ALL: [Family; 16] = [
+        Family::Piano,
+        Family::ChromaticPercussion,
+        Family::Organ,
+        Family::Guitar,
+        Family::Bass,
+        Family::Strings,
+        Family::Ensemble,
+        Family::Brass,
+        Family::Reed,
+        Family::Pipe,
+        Family::SynthLead,
+        Family::SynthPad,
+        Family::SynthEffects,
+        Family::Ethnic,
+        Family::Percussive,
+        Family::SoundEffects,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Family::Piano => "Piano",
+            Family::ChromaticPercussion => "Chromatic Percussion",
+            Family::Organ => "Organ",
+            Family::Guitar => "Guitar",
+            Family::Bass => "Bass",
+            Family::Strings => "Strings",
+            Family::Ensemble => "Ensemble",
+            Family::Brass => "Brass",
+            Family::Reed => "Reed",
+            Family::Pipe => "Pipe",
+            Family::SynthLead => "Synth Lead",
+            Family::SynthPad => "Synth Pad",
+            Family::SynthEffects => "Synth Effects",
+            Family::Ethnic => "Ethnic",
+            Family::Percussive => "Percussive",
+            Family::SoundEffects => "Sound Effects",
+        }
+    }
+
+    /// Programs of the family
+    pub fn programs(&self) -> std::ops::RangeInclusive<u8> {
+        let first = *self as u8 * 8;
+        first..=first + 7
+    }
+}
+
+impl fmt::Display for Family {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Name of a melodic program in [0; 127]. Data bytes above 127 are not valid programs.
+pub fn program_name(program: u8) -> &'static str {
+    PROGRAM_NAMES
+        .get(program as usize)
+        .copied()
+        .unwrap_or("Unknown Program")
+}
+
+/// Family of a melodic program in [0; 127]. Programs above 127 are counted as sound effects.
+pub fn family(program: u8) -> Family {
+    Family::ALL[(program.min(127) / 8) as usize]
+}
+
+/// Name of the drum played by `note` on the percussion channel, if the percussion map has one
+pub fn percussion_name(note: u8) -> Option<&'static str> {
+    note.checked_sub(FIRST_PERCUSSION_NOTE)
+        .and_then(|index| PERCUSSION_NAMES.get(index as usize))
+        .copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn every_program_has_a_distinct_name() {
+        let names = (0..=127).map(program_name).collect::<HashSet<_>>();
+        assert_eq!(names.len(), 128);
+        assert!(!names.contains("Unknown Program"));
+
+        assert_eq!(program_name(0), "Acoustic Grand Piano");
+        assert_eq!(program_name(40), "Violin");
+        assert_eq!(program_name(127), "Gunshot");
+        assert_eq!(program_name(128), "Unknown Program");
+    }
+
+    #[test]
+    fn families_group_eight_programs_each() {
+        for (index, family) in Family::ALL.iter().enumerate() {
+            assert_eq!(*family as usize, index);
+            assert_eq!(family.programs().count(), 8);
+            assert!(
+                family
+                    .programs()
+                    .all(|program| super::family(program) == *family)
+            );
+        }
+
+        assert_eq!(family(0), Family::Piano);
+        assert_eq!(family(33), Family::Bass);
+        assert_eq!(family(73), Family::Pipe);
+        assert_eq!(family(127), Family::SoundEffects);
+        assert_eq!(family(200), Family::SoundEffects);
+        assert_eq!(Family::SynthLead.to_string(), "Synth Lead");
+    }
+
+    #[test]
+    fn the_percussion_map_covers_keys_35_to_81() {
+        let names = (0..=127)
+            .filter_map(percussion_name)
+            .collect::<HashSet<_>>();
+        assert_eq!(names.len(), 47);
+
+        assert_eq!(percussion_name(34), None);
+        assert_eq!(percussion_name(35), Some("Acoustic Bass Drum"));
+        assert_eq!(percussion_name(38), Some("Acoustic Snare"));
+        assert_eq!(percussion_name(42), Some("Closed Hi-Hat"));
+        assert_eq!(percussion_name(49), Some("Crash Cymbal 1"));
+        assert_eq!(percussion_name(81), Some("Open Triangle"));
+        assert_eq!(percussion_name(82), None);
+    }
+}
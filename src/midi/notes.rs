@@ -0,0 +1,243 @@
+//! Pairing of note on and note off events into notes with a start and an end.
+//!
+//! The edge cases are settled as follows:
+//! - a note on with a velocity of 0 is a note off,
+//! - a note struck again while it is held ends the previous instance where the new one starts,
+//! - the all notes off and all sound off controllers end every held note of their channel,
+//! - notes still held at the end of the track end with it,
+//! - note offs without a held note are ignored.
+use std::{collections::HashMap, time::Duration};
+
+use crate::midi::{ChannelEventKind, ControllerKind, MIDIEventKind, MIDIFileData, MetaEvent};
+
+/// Release velocity of a note on with a velocity of 0, as given by the specification
+const DEFAULT_RELEASE_VELOCITY: u8 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    /// Index of the track the note is played by
+    pub track: usize,
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+    /// Velocity of the note off, 0 for notes which did not end with one
+    pub release_velocity: u8,
+    pub start: Duration,
+    pub end: Duration,
+    pub start_tick: u64,
+    pub end_tick: u64,
+}
+
+/// Notes of a single track, sorted by start. Times are counted from the first tick of the track.
+pub fn extract_track_notes(data: &MIDIFileData, track_index: usize) -> Vec<Note> {
+    let tempo_map = data.tempo_map(track_index);
+    let mut notes = vec![];
+    // Start tick and velocity of the notes being held, by channel and key
+    let mut held = HashMap::<(u8, u8), (u64, u8)>::new();
+    let mut tick = 0u64;
+
+    let mut end_note = |(channel, key): (u8, u8),
+                        (start_tick, velocity): (u64, u8),
+                        end_tick: u64,
+                        release_velocity: u8| {
+        notes.push(Note {
+            track: track_index,
+            channel,
+            key,
+            velocity,
+            release_velocity,
+            start: tempo_map.tick_to_duration(start_tick),
+            end: tempo_map.tick_to_duration(end_tick),
+            start_tick,
+            end_tick,
+        })
+    };
+
+    for event in data.tracks()[track_index].events() {
+        tick += event.delta_time() as u64;
+
+        match event.kind() {
+            MIDIEventKind::Channel(channel_event) => {
+                let channel = channel_event.channel();
+                match channel_event.kind() {
+                    ChannelEventKind::NoteOn { note, velocity } if *velocity > 0 => {
+                        if let Some(previous) = held.insert((channel, *note), (tick, *velocity)) {
+                            end_note((channel, *note), previous, tick, 0);
+                        }
+                    }
+                    ChannelEventKind::NoteOn { note, .. } => {
+                        if let Some(held_note) = held.remove(&(channel, *note)) {
+                            end_note((channel, *note), held_note, tick, DEFAULT_RELEASE_VELOCITY);
+                        }
+                    }
+                    ChannelEventKind::NoteOff { note, velocity } => {
+                        if let Some(held_note) = held.remove(&(channel, *note)) {
+                            end_note((channel, *note), held_note, tick, *velocity);
+                        }
+                    }
+                    ChannelEventKind::Controller {
+                        controller_number, ..
+                    } if matches!(
+                        ControllerKind::from(*controller_number),
+                        ControllerKind::AllNotesOff | ControllerKind::AllSoundOff
+                    ) =>
+                    {
+                        let ended = held
+                            .extract_if(|(note_channel, _), _| *note_channel == channel)
+                            .collect::<Vec<_>>();
+                        for (key, held_note) in ended {
+                            end_note(key, held_note, tick, 0);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            MIDIEventKind::Meta(MetaEvent::EndOfTrack) => break,
+            _ => {}
+        }
+    }
+
+    for (key, held_note) in held {
+        end_note(key, held_note, tick, 0);
+    }
+
+    // Notes are pushed as they end, and held ones come in no particular order
+    notes.sort_by_key(|note| (note.start_tick, note.channel, note.key));
+    notes
+}
+
+/// Notes of every track, sorted by start. Times are counted from the start of the file, so
+/// tracks played one after another and SMPTE offsets are taken into account.
+pub fn extract_notes(data: &MIDIFileData) -> Vec<Note> {
+    let mut notes = vec![];
+
+    for track_index in 0..data.tracks().len() {
+        let track_start = data.track_start(track_index);
+        notes.extend(
+            extract_track_notes(data, track_index)
+                .into_iter()
+                .map(|note| Note {
+                    start: track_start + note.start,
+                    end: track_start + note.end,
+                    ..note
+                }),
+        );
+    }
+
+    // Stable, so notes starting together keep the order of their tracks
+    notes.sort_by_key(|note| note.start);
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Single track file at 96 ticks per beat and 120 BPM, so 48 ticks last 250 ms
+    fn track_notes(events: &[u8]) -> Vec<Note> {
+        let mut buffer = vec![];
+        buffer.extend_from_slice(b"MThd");
+        buffer.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        buffer.extend_from_slice(b"MTrk");
+        buffer.extend_from_slice(&(events.len() as u32 + 4).to_be_bytes());
+        buffer.extend_from_slice(events);
+        buffer.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+
+        let data = MIDIFileData::try_from(&buffer[..]).unwrap();
+        extract_track_notes(&data, 0)
+    }
+
+    fn spans(notes: &[Note]) -> Vec<(u8, u8, u64, u64)> {
+        notes
+            .iter()
+            .map(|note| (note.channel, note.key, note.start_tick, note.end_tick))
+            .collect()
+    }
+
+    #[test]
+    fn note_offs_end_their_note() {
+        let notes = track_notes(&[
+            0x00, 0x90, 60, 100, // C on
+            0x30, 0x91, 60, 90, // C on, other channel
+            0x30, 0x80, 60, 30, // C off
+            0x30, 0x81, 60, 0, // C off, other channel
+        ]);
+
+        assert_eq!(spans(&notes), [(0, 60, 0, 96), (1, 60, 48, 144)]);
+        assert_eq!(notes[0].velocity, 100);
+        assert_eq!(notes[0].release_velocity, 30);
+        assert_eq!(notes[0].start, Duration::ZERO);
+        assert_eq!(notes[0].end, Duration::from_millis(500));
+        assert_eq!(notes[1].start, Duration::from_millis(250));
+    }
+
+    #[test]
+    fn zero_velocity_note_on_is_a_release() {
+        let notes = track_notes(&[
+            0x00, 0x90, 60, 100, // C on
+            0x30, 0x90, 60, 0, // C on with no velocity
+        ]);
+
+        assert_eq!(spans(&notes), [(0, 60, 0, 48)]);
+        assert_eq!(notes[0].release_velocity, DEFAULT_RELEASE_VELOCITY);
+    }
+
+    #[test]
+    fn restruck_notes_end_the_previous_instance() {
+        let notes = track_notes(&[
+            0x00, 0x90, 60, 100, // C on
+            0x30, 0x90, 60, 80, // C on again
+            0x30, 0x80, 60, 0, // C off
+            0x00, 0x80, 60, 0, // stray C off
+        ]);
+
+        assert_eq!(spans(&notes), [(0, 60, 0, 48), (0, 60, 48, 96)]);
+        assert_eq!(notes[1].velocity, 80);
+    }
+
+    #[test]
+    fn unterminated_notes_end_with_the_track() {
+        let notes = track_notes(&[
+            0x00, 0x90, 64, 100, // E on
+            0x00, 0x90, 60, 100, // C on
+            0x60, 0x80, 64, 0, // E off
+            0x60, 0x91, 0, 0, // nothing on another channel
+        ]);
+
+        assert_eq!(spans(&notes), [(0, 60, 0, 192), (0, 64, 0, 96)]);
+        assert_eq!(notes[0].release_velocity, 0);
+    }
+
+    #[test]
+    fn all_notes_off_ends_the_notes_of_its_channel() {
+        let notes = track_notes(&[
+            0x00, 0x90, 60, 100, // C on
+            0x00, 0x90, 64, 100, // E on
+            0x00, 0x91, 67, 100, // G on, other channel
+            0x30, 0xB0, 123, 0, // all notes off
+            0x30, 0xB1, 120, 0, // all sound off, other channel
+        ]);
+
+        assert_eq!(
+            spans(&notes),
+            [(0, 60, 0, 48), (0, 64, 0, 48), (1, 67, 0, 96)]
+        );
+    }
+
+    #[test]
+    fn file_notes_are_timed_from_the_file_start() {
+        let midi_bytes = include_bytes!("../assets/format2.mid");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let notes = extract_notes(&data);
+
+        // The second track only starts once the first one has ended
+        let second_track = notes.iter().find(|note| note.track == 1).unwrap();
+        let first_track_end = extract_track_notes(&data, 0)
+            .iter()
+            .map(|note| note.end)
+            .max()
+            .unwrap();
+        assert!(second_track.start >= first_track_end);
+        assert!(notes.windows(2).all(|pair| pair[0].start <= pair[1].start));
+    }
+}
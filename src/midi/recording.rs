@@ -0,0 +1,249 @@
+//! Recording of events played live into a MIDI file.
+use std::time::Duration;
+
+use crate::midi::{
+    ChannelEvent, ChannelEventKind, Controller, ControllerKind, MIDIFileData, MIDIFormat, Tempo,
+    TimeDivision,
+    builder::{FileBuilder, TrackBuilder},
+};
+
+/// Division of recorded files, fine enough for the timing of live playing
+pub const RECORDING_DIVISION: TimeDivision = TimeDivision::TicksPerBit(480);
+
+/// Channel events timed against a clock, such as the one of the audio context.
+#[derive(Debug, Clone)]
+pub struct Recording {
+    /// Clock time at which the recording started
+    start: Duration,
+    events: Vec<(Duration, ChannelEvent)>,
+}
+
+impl Recording {
+    /// Start recording at the clock time `start`
+    pub fn new(start: Duration) -> Self {
+        Self {
+            start,
+            events: vec![],
+        }
+    }
+
+    /// Record `event`, played at the clock time `time`
+    pub fn push(&mut self, time: Duration, event: ChannelEvent) {
+        self.events.push((time, event));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    /// File of a single track with the recorded events, ending at the clock time `end`. Notes and
+    /// the sustain pedal still held at the end are released then.
+    pub fn to_midi(&self, tempo: Tempo, end: Duration) -> MIDIFileData {
+        let ticks = event_ticks(
+            self.start,
+            self.events.iter().map(|(time, _)| *time),
+            RECORDING_DIVISION,
+            tempo,
+        );
+        let end_tick = event_ticks(self.start, [end], RECORDING_DIVISION, tempo)[0]
+            .max(ticks.last().copied().unwrap_or_default());
+
+        let mut track = TrackBuilder::new().set_tempo(tempo);
+        // Notes and pedals held down, by channel
+        let mut held_notes = vec![];
+        let mut held_pedals = vec![];
+        for ((_, event), tick) in self.events.iter().zip(ticks) {
+            let channel = event.channel();
+            match *event.kind() {
+                ChannelEventKind::NoteOn { note, velocity } if velocity > 0 => {
+                    held_notes.push((channel, note));
+                }
+                ChannelEventKind::NoteOn { note, .. } | ChannelEventKind::NoteOff { note, .. } => {
+                    held_notes.retain(|held| *held != (channel, note));
+                }
+                ChannelEventKind::Controller {
+                    controller_number,
+                    controller_value,
+                } => {
+                    let controller = Controller::new(controller_number, controller_value);
+                    if controller.kind() == ControllerKind::Sustain {
+                        held_pedals.retain(|held| *held != channel);
+                        if controller.is_on() {
+                            held_pedals.push(channel);
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            track = track.at(tick).channel_event(channel, event.kind().clone());
+        }
+
+        track = track.at(end_tick);
+        for (channel, note) in held_notes {
+            track = track.note_off(channel, note, 0);
+        }
+        for channel in held_pedals {
+            track = track.controller(channel, ControllerKind::Sustain.number(), 0);
+        }
+
+        FileBuilder::new(MIDIFormat::SingleMultiChannelTrack, RECORDING_DIVISION)
+            .track(track)
+            .build()
+    }
+}
+
+/// Absolute ticks since `start` of events at the clock `times`, at a constant `tempo`.
+///
+/// Each time is converted on its own rather than adding up deltas, so rounding never drifts.
+/// Events from before the start are placed at it, and ticks never go backwards.
+fn event_ticks(
+    start: Duration,
+    times: impl IntoIterator<Item = Duration>,
+    time_division: TimeDivision,
+    tempo: Tempo,
+) -> Vec<u64> {
+    let mut last_tick = 0;
+    times
+        .into_iter()
+        .map(|time| {
+            let tick = time_division.duration_ticks(tempo, time.saturating_sub(start));
+            last_tick = tick.max(last_tick);
+            last_tick
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{MIDIEventKind, MetaEvent};
+
+    fn note_on(note: u8) -> ChannelEvent {
+        ChannelEvent::new(
+            0,
+            ChannelEventKind::NoteOn {
+                note,
+                velocity: 100,
+            },
+        )
+    }
+
+    fn note_off(note: u8) -> ChannelEvent {
+        ChannelEvent::new(0, ChannelEventKind::NoteOff { note, velocity: 0 })
+    }
+
+    #[test]
+    fn event_times_become_ticks_from_the_start() {
+        let tempo = Tempo::default();
+        let ms = Duration::from_millis;
+        // At 120 BPM and 480 ticks per quarter, a tick lasts 1/960 of a second
+        let start = ms(10_000);
+
+        assert_eq!(
+            event_ticks(
+                start,
+                [
+                    // before the start
+                    ms(9_990),
+                    // the first event is offset from the start
+                    ms(10_250),
+                    // simultaneous events
+                    ms(10_500),
+                    ms(10_500),
+                    // less than a tick later
+                    ms(10_500) + Duration::from_micros(1_000),
+                    ms(11_000),
+                ],
+                RECORDING_DIVISION,
+                tempo
+            ),
+            vec![0, 240, 480, 480, 480, 960]
+        );
+
+        // Each time is rounded on its own, so a long recording doesn't drift
+        let tick = RECORDING_DIVISION.ticks_duration(tempo, 1);
+        let times = (1..=10_000).map(|index| start + tick * index);
+        let ticks = event_ticks(start, times, RECORDING_DIVISION, tempo);
+        assert_eq!(ticks.last(), Some(&10_000));
+        assert!(ticks.windows(2).all(|pair| pair[1] - pair[0] == 1));
+
+        // A clock going backwards leaves the events at the last tick
+        assert_eq!(
+            event_ticks(start, [ms(10_500), ms(10_400)], RECORDING_DIVISION, tempo),
+            vec![480, 480]
+        );
+    }
+
+    #[test]
+    fn recordings_become_tracks_of_delta_times() {
+        let ms = Duration::from_millis;
+        let mut recording = Recording::new(ms(1_000));
+        assert!(recording.is_empty());
+
+        recording.push(ms(1_500), note_on(60));
+        recording.push(ms(1_500), note_on(64));
+        recording.push(ms(2_000), note_off(60));
+        recording.push(
+            ms(2_000),
+            ChannelEvent::new(
+                0,
+                ChannelEventKind::Controller {
+                    controller_number: ControllerKind::Sustain.number(),
+                    controller_value: 127,
+                },
+            ),
+        );
+        assert!(!recording.is_empty());
+
+        let data = recording.to_midi(Tempo::default(), ms(3_000));
+        assert_eq!(*data.time_division(), RECORDING_DIVISION);
+
+        let events = data.tracks()[0]
+            .events()
+            .iter()
+            .map(|event| (event.delta_time, event.kind().clone()))
+            .collect::<Vec<_>>();
+        let channel = MIDIEventKind::Channel;
+        assert_eq!(
+            events,
+            vec![
+                (
+                    0,
+                    MIDIEventKind::Meta(MetaEvent::SetTempo {
+                        tempo: Tempo::default()
+                    })
+                ),
+                (480, channel(note_on(60))),
+                (0, channel(note_on(64))),
+                (480, channel(note_off(60))),
+                (
+                    0,
+                    channel(ChannelEvent::new(
+                        0,
+                        ChannelEventKind::Controller {
+                            controller_number: ControllerKind::Sustain.number(),
+                            controller_value: 127,
+                        }
+                    ))
+                ),
+                // the held note and pedal are released at the end
+                (960, channel(note_off(64))),
+                (
+                    0,
+                    channel(ChannelEvent::new(
+                        0,
+                        ChannelEventKind::Controller {
+                            controller_number: ControllerKind::Sustain.number(),
+                            controller_value: 0,
+                        }
+                    ))
+                ),
+                (0, MIDIEventKind::Meta(MetaEvent::EndOfTrack)),
+            ]
+        );
+
+        let parsed = MIDIFileData::try_from(&data.to_bytes()[..]).unwrap();
+        assert_eq!(parsed.tracks(), data.tracks());
+    }
+}
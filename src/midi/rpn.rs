@@ -0,0 +1,214 @@
+//! Decoding of registered and non-registered parameters (RPN and NRPN), which are selected and set
+//! by sequences of controller changes rather than events of their own.
+//!
+//! A parameter is selected by its number, sent in two halves with the RPN (CC101, CC100) or NRPN
+//! (CC99, CC98) controllers, then set with the data entry controllers (CC6, CC38). Selecting the
+//! null RPN, 127/127, leaves data entries without effect.
+use crate::midi::{Controller, ControllerKind};
+
+/// Registered parameter number of the pitch bend sensitivity
+pub const PITCH_BEND_SENSITIVITY: u16 = 0;
+
+/// Parameter number of the null RPN, which deselects any parameter
+const NULL_PARAMETER: u16 = 0x3FFF;
+
+/// Parameter selected on a channel, with its 14-bit number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    Registered(u16),
+    NonRegistered(u16),
+}
+
+/// Parameter set by a data entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterChange {
+    /// Range of the pitch bend wheel in either direction (RPN 0)
+    PitchBendRange { semitones: u8, cents: u8 },
+    /// Any other parameter, with the 14-bit value of the data entry
+    Other { parameter: Parameter, value: u16 },
+}
+
+impl ParameterChange {
+    /// Range of the pitch bend wheel in semitones, if the change sets it
+    pub fn pitch_bend_range(&self) -> Option<f32> {
+        match *self {
+            ParameterChange::PitchBendRange { semitones, cents } => {
+                Some(semitones as f32 + cents as f32 / 100.0)
+            }
+            ParameterChange::Other { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelParameters {
+    /// Halves of the parameter number being selected, with whether it is a registered one
+    number: (u8, u8),
+    registered: Option<bool>,
+    /// Halves of the value being entered
+    data: (u8, u8),
+}
+
+impl ChannelParameters {
+    fn select(&mut self, registered: bool, number: (Option<u8>, Option<u8>)) {
+        // Switching between RPN and NRPN starts a new number
+        if self.registered != Some(registered) {
+            self.number = (0, 0);
+        }
+        self.registered = Some(registered);
+        self.number = (
+            number.0.unwrap_or(self.number.0),
+            number.1.unwrap_or(self.number.1),
+        );
+        self.data = (0, 0);
+    }
+
+    fn parameter(&self) -> Option<Parameter> {
+        let number = ((self.number.0 as u16 & 0x7F) << 7) | (self.number.1 as u16 & 0x7F);
+        match self.registered? {
+            true if number == NULL_PARAMETER => None,
+            true => Some(Parameter::Registered(number)),
+            false => Some(Parameter::NonRegistered(number)),
+        }
+    }
+
+    fn change(&self) -> Option<ParameterChange> {
+        let change = match self.parameter()? {
+            Parameter::Registered(PITCH_BEND_SENSITIVITY) => ParameterChange::PitchBendRange {
+                semitones: self.data.0,
+                cents: self.data.1,
+            },
+            parameter => ParameterChange::Other {
+                parameter,
+                value: ((self.data.0 as u16 & 0x7F) << 7) | (self.data.1 as u16 & 0x7F),
+            },
+        };
+        Some(change)
+    }
+}
+
+/// Follows the controller changes of the 16 channels, decoding the parameters they set.
+#[derive(Debug, Clone, Default)]
+pub struct ParameterDecoder {
+    channels: [ChannelParameters; 16],
+}
+
+impl ParameterDecoder {
+    /// Follow a controller change of `channel`, returning the parameter it sets, if any. Every
+    /// data entry sets the parameter again, with the halves of the value entered so far.
+    pub fn update(&mut self, channel: u8, controller: Controller) -> Option<ParameterChange> {
+        let parameters = &mut self.channels[channel as usize & 0x0F];
+        let value = Some(controller.value());
+        match controller.kind() {
+            ControllerKind::RpnMsb => parameters.select(true, (value, None)),
+            ControllerKind::RpnLsb => parameters.select(true, (None, value)),
+            ControllerKind::NrpnMsb => parameters.select(false, (value, None)),
+            ControllerKind::NrpnLsb => parameters.select(false, (None, value)),
+            ControllerKind::DataEntry => {
+                parameters.data.0 = controller.value();
+                return parameters.change();
+            }
+            ControllerKind::DataEntryLsb => {
+                parameters.data.1 = controller.value();
+                return parameters.change();
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(
+        decoder: &mut ParameterDecoder,
+        channel: u8,
+        controllers: &[(u8, u8)],
+    ) -> Vec<ParameterChange> {
+        controllers
+            .iter()
+            .filter_map(|&(number, value)| decoder.update(channel, Controller::new(number, value)))
+            .collect()
+    }
+
+    #[test]
+    fn pitch_bend_range_is_decoded_from_rpn_0() {
+        let mut decoder = ParameterDecoder::default();
+        let changes = feed(&mut decoder, 0, &[(101, 0), (100, 0), (6, 12), (38, 50)]);
+        assert_eq!(
+            changes,
+            [
+                ParameterChange::PitchBendRange {
+                    semitones: 12,
+                    cents: 0
+                },
+                ParameterChange::PitchBendRange {
+                    semitones: 12,
+                    cents: 50
+                },
+            ]
+        );
+        assert_eq!(changes[1].pitch_bend_range(), Some(12.5));
+
+        // Channels are followed apart
+        assert_eq!(feed(&mut decoder, 1, &[(6, 24)]), []);
+        assert_eq!(
+            feed(&mut decoder, 0, &[(6, 24)]),
+            [ParameterChange::PitchBendRange {
+                semitones: 24,
+                cents: 50
+            }]
+        );
+    }
+
+    #[test]
+    fn data_entries_without_a_parameter_are_ignored() {
+        let mut decoder = ParameterDecoder::default();
+        assert_eq!(feed(&mut decoder, 0, &[(6, 12), (38, 0)]), []);
+
+        // The null RPN deselects the pitch bend range
+        let changes = feed(
+            &mut decoder,
+            0,
+            &[(101, 0), (100, 0), (101, 127), (100, 127), (6, 12)],
+        );
+        assert_eq!(changes, []);
+    }
+
+    #[test]
+    fn other_parameters_carry_their_numbers_and_values() {
+        let mut decoder = ParameterDecoder::default();
+        assert_eq!(
+            feed(&mut decoder, 3, &[(99, 1), (98, 8), (6, 64), (38, 1)]),
+            [
+                ParameterChange::Other {
+                    parameter: Parameter::NonRegistered(136),
+                    value: 64 << 7
+                },
+                ParameterChange::Other {
+                    parameter: Parameter::NonRegistered(136),
+                    value: (64 << 7) | 1
+                },
+            ]
+        );
+
+        // Selecting an RPN after an NRPN starts from a new number
+        assert_eq!(
+            feed(&mut decoder, 3, &[(100, 2), (6, 64)]),
+            [ParameterChange::Other {
+                parameter: Parameter::Registered(2),
+                value: 64 << 7
+            }]
+        );
+        assert_eq!(
+            ParameterChange::Other {
+                parameter: Parameter::Registered(2),
+                value: 0
+            }
+            .pitch_bend_range(),
+            None
+        );
+    }
+}
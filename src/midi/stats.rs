@@ -0,0 +1,233 @@
+//! Summary of the contents of a MIDI file.
+use std::{collections::BTreeSet, time::Duration};
+
+use crate::midi::{
+    ChannelEventKind, MIDIEventKind, MIDIFileData, MetaEvent, PERCUSSION_CHANNEL, Tempo,
+    gm::{self, Family},
+    notes::{self, Note},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statistics {
+    pub note_count: usize,
+    /// Lowest and highest key played
+    pub pitch_range: Option<(u8, u8)>,
+    /// Largest number of notes sounding at once, across all channels and tracks
+    pub max_polyphony: usize,
+    /// Notes played on each channel, indexed by channel number
+    pub channel_note_counts: [usize; 16],
+    /// General MIDI programs selected by program changes, in ascending order
+    pub programs: Vec<u8>,
+    /// Families of `programs`, in ascending order
+    pub families: Vec<Family>,
+    /// Keys played on the percussion channel, in ascending order
+    pub percussion_keys: Vec<u8>,
+    pub duration: Duration,
+    /// Slowest and fastest tempo in beats per minute
+    pub tempo_range: (f64, f64),
+}
+
+impl From<&MIDIFileData> for Statistics {
+    fn from(data: &MIDIFileData) -> Self {
+        let notes = notes::extract_notes(data);
+
+        let mut channel_note_counts = [0; 16];
+        for note in &notes {
+            channel_note_counts[note.channel as usize] += 1;
+        }
+
+        let pitch_range = notes
+            .iter()
+            .map(|note| note.key)
+            .min()
+            .zip(notes.iter().map(|note| note.key).max());
+
+        let percussion_keys = notes
+            .iter()
+            .filter(|note| note.channel == PERCUSSION_CHANNEL)
+            .map(|note| note.key)
+            .collect::<BTreeSet<_>>();
+
+        let mut programs = BTreeSet::new();
+        let mut tempos = vec![];
+        for (track_index, track) in data.tracks().iter().enumerate() {
+            let mut tick = 0u64;
+            let mut first_tempo_tick = None;
+
+            for event in track.events() {
+                tick += event.delta_time() as u64;
+
+                match event.kind() {
+                    // Program changes of the percussion channel select drum kits instead
+                    MIDIEventKind::Channel(channel_event)
+                        if channel_event.channel() != PERCUSSION_CHANNEL =>
+                    {
+                        if let ChannelEventKind::ProgramChange { program_number } =
+                            channel_event.kind()
+                        {
+                            programs.insert(*program_number);
+                        }
+                    }
+                    MIDIEventKind::Meta(MetaEvent::SetTempo { tempo }) => {
+                        first_tempo_tick.get_or_insert(tick);
+                        tempos.push(*tempo);
+                    }
+                    MIDIEventKind::Meta(MetaEvent::EndOfTrack) => break,
+                    _ => {}
+                }
+            }
+
+            // Until the first tempo change, the tempo map of the track plays at the default tempo
+            let has_tempo_map = data.is_sequential() || track_index == 0;
+            if has_tempo_map && first_tempo_tick != Some(0) {
+                tempos.push(Tempo::default());
+            }
+        }
+
+        let bpms = tempos
+            .iter()
+            .map(|tempo| 60_000_000.0 / tempo.as_mpqn() as f64);
+        let tempo_range = bpms.fold(None, |range, bpm| match range {
+            None => Some((bpm, bpm)),
+            Some((slowest, fastest)) => Some((f64::min(slowest, bpm), f64::max(fastest, bpm))),
+        });
+
+        Self {
+            note_count: notes.len(),
+            pitch_range,
+            max_polyphony: max_polyphony(&notes),
+            channel_note_counts,
+            families: programs
+                .iter()
+                .map(|program| gm::family(*program))
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+            programs: programs.into_iter().collect(),
+            percussion_keys: percussion_keys.into_iter().collect(),
+            duration: data.duration(),
+            tempo_range: tempo_range.unwrap_or((120.0, 120.0)),
+        }
+    }
+}
+
+/// Sweep over the starts and ends of the notes, counting the ones sounding in between. A note
+/// ending when another one starts does not overlap with it.
+fn max_polyphony(notes: &[Note]) -> usize {
+    let mut edges = notes
+        .iter()
+        .flat_map(|note| [(note.start, 1), (note.end, -1)])
+        .collect::<Vec<(Duration, i32)>>();
+    // Ends sort before starts at the same time
+    edges.sort_unstable();
+
+    let mut sounding = 0i32;
+    let mut max = 0i32;
+    for (_, change) in edges {
+        sounding += change;
+        max = max.max(sounding);
+    }
+    max as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Format 1 file at 96 ticks per beat, with the given track bodies
+    fn file(tracks: &[&[u8]]) -> MIDIFileData {
+        let mut buffer = vec![];
+        buffer.extend_from_slice(b"MThd");
+        buffer.extend_from_slice(&[0, 0, 0, 6, 0, 1, 0, tracks.len() as u8, 0, 96]);
+        for events in tracks {
+            buffer.extend_from_slice(b"MTrk");
+            buffer.extend_from_slice(&(events.len() as u32 + 4).to_be_bytes());
+            buffer.extend_from_slice(events);
+            buffer.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+        }
+
+        MIDIFileData::try_from(&buffer[..]).unwrap()
+    }
+
+    #[test]
+    fn polyphony_counts_overlaps_across_channels_and_tracks() {
+        let data = file(&[
+            &[
+                0x00, 0x90, 60, 100, // C on
+                0x00, 0x91, 64, 100, // E on, other channel
+                0x60, 0x80, 60, 0, // C off
+                0x00, 0x81, 64, 0, // E off
+            ],
+            &[
+                0x30, 0x92, 67, 100, // G on, while C and E sound
+                0x60, 0x82, 67, 0, // G off
+            ],
+        ]);
+        let stats = Statistics::from(&data);
+
+        assert_eq!(stats.note_count, 3);
+        assert_eq!(stats.max_polyphony, 3);
+        assert_eq!(stats.pitch_range, Some((60, 67)));
+        assert_eq!(stats.channel_note_counts[..3], [1, 1, 1]);
+    }
+
+    #[test]
+    fn notes_touching_end_to_start_do_not_overlap() {
+        let data = file(&[
+            &[
+                0x00, 0x90, 60, 100, // C on
+                0x60, 0x80, 60, 0, // C off
+                0x00, 0x90, 62, 100, // D on
+                0x60, 0x80, 62, 0, // D off
+            ],
+            &[
+                0x60, 0x91, 64, 100, // E on, as C ends
+                0x60, 0x81, 64, 0, // E off
+            ],
+        ]);
+        let stats = Statistics::from(&data);
+
+        assert_eq!(stats.max_polyphony, 2);
+        assert_eq!(stats.channel_note_counts[..2], [2, 1]);
+    }
+
+    #[test]
+    fn percussion_keys_are_collected_from_the_drum_channel() {
+        let data = file(&[&[
+            0x00, 0x99, 42, 100, // closed hi-hat
+            0x00, 0x99, 36, 100, // kick
+            0x00, 0x90, 60, 100, // C on a melodic channel
+            0x60, 0x89, 42, 0, // hi-hat off
+            0x00, 0x89, 36, 0, // kick off
+            0x00, 0x80, 60, 0, // C off
+            0x00, 0x99, 42, 100, // hi-hat again
+            0x60, 0x89, 42, 0, // hi-hat off
+        ]]);
+        let stats = Statistics::from(&data);
+
+        assert_eq!(stats.percussion_keys, [36, 42]);
+        assert_eq!(stats.families, []);
+    }
+
+    #[test]
+    fn chords_and_programs_are_summarized() {
+        let midi_bytes = include_bytes!("../assets/chord.mid");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let stats = Statistics::from(&data);
+        assert!(stats.max_polyphony >= 3);
+
+        let data = file(&[&[
+            0x00, 0xC0, 24, // guitar
+            0x00, 0xC1, 32, // bass
+            0x00, 0xC9, 1, // drum kit
+            0x00, 0xFF, 0x51, 0x03, 0x07, 0xA1, 0x20, // 120 BPM
+            0x60, 0xFF, 0x51, 0x03, 0x0F, 0x42, 0x40, // 60 BPM
+        ]]);
+        let stats = Statistics::from(&data);
+        assert_eq!(stats.programs, [24, 32]);
+        assert_eq!(stats.families, [Family::Guitar, Family::Bass]);
+        assert_eq!(stats.tempo_range, (60.0, 120.0));
+        assert_eq!(stats.max_polyphony, 0);
+        assert_eq!(stats.pitch_range, None);
+    }
+}
@@ -0,0 +1,794 @@
+//! Edits of a parsed file which work on its events, so the result can be played or exported.
+use std::{collections::HashMap, time::Duration};
+
+use crate::midi::{
+    ChannelEvent, ChannelEventKind, MAX_DELTA_TIME, MIDIEvent, MIDIEventKind, MIDIFileData,
+    MIDIFormat, MIDITrack, MetaEvent, PERCUSSION_CHANNEL, Tempo,
+    builder::TrackBuilder,
+    notes::{self, Note},
+};
+
+/// Shift every note by `semitones`, apart from the percussion channel whose notes pick drums.
+/// Notes are clamped to the MIDI range.
+pub fn transpose(data: &MIDIFileData, semitones: i8) -> MIDIFileData {
+    let shift = |note: u8| (note as i16 + semitones as i16).clamp(0, 127) as u8;
+
+    map_tracks(data, |_, track| {
+        let mut track = track.clone();
+        for event in &mut track.events {
+            let MIDIEventKind::Channel(channel_event) = &mut event.kind else {
+                continue;
+            };
+            if channel_event.channel == PERCUSSION_CHANNEL {
+                continue;
+            }
+
+            match &mut channel_event.kind {
+                ChannelEventKind::NoteOn { note, .. }
+                | ChannelEventKind::NoteOff { note, .. }
+                | ChannelEventKind::NoteAftertouch { note, .. } => *note = shift(*note),
+                _ => {}
+            }
+        }
+        track
+    })
+}
+
+/// Keep the part of the file between `start` and `end`, counted from the start of the file.
+///
+/// Settings made before `start`, like tempo, meter, programs and controllers, are moved to its
+/// beginning so the part sounds the same. Notes sounding across `start` are struck again there,
+/// and notes sounding across `end` are released there.
+pub fn crop(data: &MIDIFileData, start: Duration, end: Duration) -> MIDIFileData {
+    map_tracks(data, |track_index, track| {
+        let tempo_map = data.tempo_map(track_index);
+        let track_start = data.track_start(track_index);
+        let start_tick = tempo_map.duration_to_tick(start.saturating_sub(track_start));
+        let end_tick = tempo_map
+            .duration_to_tick(end.saturating_sub(track_start))
+            .clamp(start_tick, track.end_tick().max(start_tick));
+
+        // The last setting of every kind made before the start, in their original order
+        let mut settings = Vec::<Option<MIDIEventKind>>::new();
+        let mut setting_indices = HashMap::<SettingKind, usize>::new();
+        let mut window = vec![];
+        let mut tick = 0u64;
+
+        for event in track.events() {
+            tick += event.delta_time() as u64;
+            if tick >= end_tick {
+                break;
+            }
+
+            let kind = event.kind();
+            if is_note(kind) || matches!(kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack)) {
+                // Notes are added from their intervals, and the end of track is moved
+                continue;
+            }
+
+            if tick >= start_tick {
+                window.push((tick - start_tick, kind.clone()));
+            } else if let Some(setting_kind) = SettingKind::of(kind) {
+                // SysEx messages don't replace each other, so all of them are kept
+                if setting_kind != SettingKind::SysEx
+                    && let Some(index) = setting_indices.insert(setting_kind, settings.len())
+                {
+                    settings[index] = None;
+                }
+                settings.push(Some(kind.clone()));
+            }
+        }
+
+        // Settings from before the start come first, followed by the events of the part
+        let mut cropped = TrackBuilder::new();
+        for setting in settings.into_iter().flatten() {
+            cropped = cropped.event(setting);
+        }
+        for (tick, kind) in window {
+            cropped = cropped.at(tick).event(kind);
+        }
+
+        let notes = notes::extract_track_notes(data, track_index)
+            .into_iter()
+            .filter(|note| overlaps(note, start_tick, end_tick))
+            .collect::<Vec<_>>();
+        // Note offs go first, so a note ending where another one starts doesn't cut it short
+        for note in &notes {
+            cropped = cropped
+                .at(note.end_tick.min(end_tick) - start_tick)
+                .note_off(note.channel, note.key, note.release_velocity);
+        }
+        for note in &notes {
+            cropped = cropped
+                .at(note.start_tick.max(start_tick) - start_tick)
+                .note_on(note.channel, note.key, note.velocity);
+        }
+
+        cropped.at(end_tick - start_tick).build()
+    })
+}
+
+/// Play the file `factor` times faster by rewriting its tempo changes. Tempo maps which start
+/// at the default tempo get it written out, so it is scaled as well.
+pub fn scale_tempo(data: &MIDIFileData, factor: f64) -> MIDIFileData {
+    let scale = |tempo: Tempo| {
+        let mpqn = (tempo.as_mpqn() as f64 / factor).round();
+        Tempo::from_mpqn(mpqn.clamp(1.0, 0xFF_FFFF as f64) as u32)
+    };
+
+    map_tracks(data, |track_index, track| {
+        let mut track = track.clone();
+        for event in &mut track.events {
+            if let MIDIEventKind::Meta(MetaEvent::SetTempo { tempo }) = &mut event.kind {
+                *tempo = scale(*tempo);
+            }
+        }
+
+        // Tempo changes of formats 0 and 1 are read from the first track only
+        let has_tempo_map = data.is_sequential() || track_index == 0;
+        let starts_with_tempo = data
+            .tempo_map(track_index)
+            .changes()
+            .first()
+            .map(|(tick, _)| *tick)
+            == Some(0);
+        if has_tempo_map && !starts_with_tempo {
+            track.events.insert(
+                0,
+                MIDIEvent {
+                    delta_time: 0,
+                    kind: MIDIEventKind::Meta(MetaEvent::SetTempo {
+                        tempo: scale(Tempo::default()),
+                    }),
+                },
+            );
+        }
+        track
+    })
+}
+
+/// Events to drop from a file with [`MIDIFileData::filter_events`]. Meta events, which carry the
+/// tempo and the meter, are always kept.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventFilter {
+    /// Channels whose events are all dropped
+    pub dropped_channels: Vec<u8>,
+    /// Drop controller changes, such as the flood of damper pedal events of piano recordings
+    pub drop_controllers: bool,
+    /// Drop polyphonic and channel aftertouch
+    pub drop_aftertouch: bool,
+    /// Drop every channel event apart from note ons and offs, along with SysEx events
+    pub notes_only: bool,
+}
+
+impl EventFilter {
+    /// Keep the events of `channel` only, such as the percussion channel
+    pub fn only_channel(channel: u8) -> Self {
+        Self {
+            dropped_channels: (0..16).filter(|&other| other != channel).collect(),
+            ..Self::default()
+        }
+    }
+
+    pub fn keeps(&self, kind: &MIDIEventKind) -> bool {
+        match kind {
+            MIDIEventKind::Meta(_) => true,
+            MIDIEventKind::SysEx(_) => !self.notes_only,
+            MIDIEventKind::Channel(ChannelEvent { channel, kind }) => {
+                if self.dropped_channels.contains(channel) {
+                    return false;
+                }
+
+                match kind {
+                    ChannelEventKind::NoteOn { .. } | ChannelEventKind::NoteOff { .. } => true,
+                    ChannelEventKind::Controller { .. } => {
+                        !self.drop_controllers && !self.notes_only
+                    }
+                    ChannelEventKind::NoteAftertouch { .. }
+                    | ChannelEventKind::ChannelAftertouch { .. } => {
+                        !self.drop_aftertouch && !self.notes_only
+                    }
+                    _ => !self.notes_only,
+                }
+            }
+        }
+    }
+}
+
+impl MIDIFileData {
+    /// Keep the events for which `keep` returns true, along with the end of every track. The
+    /// delta time of a dropped event is added to the next kept one, so the kept events and the
+    /// ends of the tracks stay at their ticks.
+    ///
+    /// When the gap left by dropped events would be longer than a delta time can hold, the last
+    /// of them before the limit is kept to break it up.
+    pub fn filter_events(&self, mut keep: impl FnMut(&MIDIEventKind) -> bool) -> MIDIFileData {
+        map_tracks(self, |_, track| {
+            let mut events = vec![];
+            // Delta time of the dropped events since the last kept one, and the last of them
+            let mut dropped_delta = 0u64;
+            let mut last_dropped: Option<&MIDIEventKind> = None;
+
+            for event in track.events() {
+                let mut delta_time = dropped_delta + event.delta_time as u64;
+                if delta_time > MAX_DELTA_TIME as u64
+                    && let Some(kind) = last_dropped.take()
+                {
+                    events.push(MIDIEvent {
+                        delta_time: dropped_delta as u32,
+                        kind: kind.clone(),
+                    });
+                    delta_time = event.delta_time as u64;
+                }
+
+                let is_end_of_track =
+                    matches!(event.kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack));
+                if is_end_of_track || keep(&event.kind) {
+                    events.push(MIDIEvent {
+                        delta_time: delta_time as u32,
+                        kind: event.kind.clone(),
+                    });
+                    dropped_delta = 0;
+                    last_dropped = None;
+                } else {
+                    dropped_delta = delta_time;
+                    last_dropped = Some(&event.kind);
+                }
+
+                if is_end_of_track {
+                    break;
+                }
+            }
+
+            MIDITrack { events }
+        })
+    }
+
+    /// Flatten the file into a single track of format 0. Events are interleaved by their
+    /// absolute tick, in the order given by [`simultaneous_order`], and format 2 tracks are
+    /// placed one after another.
+    pub fn merge_tracks(&self) -> MIDIFileData {
+        let (events, end_tick) = self.merged_events();
+
+        let mut merged = TrackBuilder::new();
+        for (tick, kind) in events {
+            merged = merged.at(tick).event(kind.clone());
+        }
+
+        MIDIFileData::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            self.time_division,
+            vec![merged.at(end_tick).build()],
+        )
+    }
+
+    /// Split the file into format 1 tracks, one for each channel in use. Meta and SysEx events
+    /// are kept in the first track, which lasts as long as the whole file.
+    pub fn split_by_channel(&self) -> MIDIFileData {
+        let (events, end_tick) = self.merged_events();
+
+        let mut meta_track = TrackBuilder::new();
+        let mut channel_tracks: [Option<TrackBuilder>; 16] = Default::default();
+        for (tick, kind) in events {
+            match kind {
+                MIDIEventKind::Channel(channel_event) => {
+                    let track = channel_tracks[channel_event.channel as usize]
+                        .take()
+                        .unwrap_or_default();
+                    channel_tracks[channel_event.channel as usize] =
+                        Some(track.at(tick).event(kind.clone()));
+                }
+                _ => meta_track = meta_track.at(tick).event(kind.clone()),
+            }
+        }
+
+        let tracks = std::iter::once(meta_track.at(end_tick))
+            .chain(channel_tracks.into_iter().flatten())
+            .map(TrackBuilder::build)
+            .collect();
+
+        MIDIFileData::new(MIDIFormat::MultiTracks, self.time_division, tracks)
+    }
+
+    /// Events of every track at their absolute tick, sorted as they would be in a single track,
+    /// along with the tick at which the last track ends
+    fn merged_events(&self) -> (Vec<(u64, &MIDIEventKind)>, u64) {
+        let mut events = vec![];
+        let mut end_tick = 0u64;
+        let mut track_start = 0u64;
+
+        for track in &self.tracks {
+            let mut tick = track_start;
+            for event in track.events() {
+                tick += event.delta_time as u64;
+                if matches!(event.kind, MIDIEventKind::Meta(MetaEvent::EndOfTrack)) {
+                    break;
+                }
+                events.push((tick, &event.kind));
+            }
+
+            end_tick = end_tick.max(tick);
+            if self.is_sequential() {
+                track_start = tick;
+            }
+        }
+
+        // Stable, so events of the same order keep the order of their tracks
+        events.sort_by_key(|(tick, kind)| (*tick, simultaneous_order(kind)));
+        (events, end_tick)
+    }
+}
+
+/// Rank of an event among the events at the same tick. Meta and SysEx events come before channel
+/// events, so tempo and meter apply to the notes next to them, and note offs come before note ons,
+/// so a note ending where the same key is struck again doesn't cut the new one short.
+pub fn simultaneous_order(kind: &MIDIEventKind) -> u8 {
+    match kind {
+        MIDIEventKind::Meta(_) => 0,
+        MIDIEventKind::SysEx(_) => 1,
+        MIDIEventKind::Channel(channel_event) => match channel_event.kind {
+            ChannelEventKind::NoteOff { .. } | ChannelEventKind::NoteOn { velocity: 0, .. } => 3,
+            ChannelEventKind::NoteOn { .. } => 4,
+            _ => 2,
+        },
+    }
+}
+
+/// Settings which replace the previous one of their kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum SettingKind {
+    Tempo,
+    TimeSignature,
+    KeySignature,
+    TrackName,
+    InstrumentName,
+    Controller(u8, u8),
+    Program(u8),
+    PitchBend(u8),
+    ChannelAftertouch(u8),
+    SysEx,
+}
+
+impl SettingKind {
+    /// Kind of setting made by the event, or `None` for events which only matter when they occur
+    fn of(kind: &MIDIEventKind) -> Option<Self> {
+        match kind {
+            MIDIEventKind::Channel(ChannelEvent { channel, kind }) => match kind {
+                ChannelEventKind::Controller {
+                    controller_number, ..
+                } => Some(SettingKind::Controller(*channel, *controller_number)),
+                ChannelEventKind::ProgramChange { .. } => Some(SettingKind::Program(*channel)),
+                ChannelEventKind::PitchBend { .. } => Some(SettingKind::PitchBend(*channel)),
+                ChannelEventKind::ChannelAftertouch { .. } => {
+                    Some(SettingKind::ChannelAftertouch(*channel))
+                }
+                _ => None,
+            },
+            MIDIEventKind::Meta(meta_event) => match meta_event {
+                MetaEvent::SetTempo { .. } => Some(SettingKind::Tempo),
+                MetaEvent::TimeSignature { .. } => Some(SettingKind::TimeSignature),
+                MetaEvent::KeySignature { .. } => Some(SettingKind::KeySignature),
+                MetaEvent::SequenceTrackName { .. } => Some(SettingKind::TrackName),
+                MetaEvent::InstrumentName { .. } => Some(SettingKind::InstrumentName),
+                _ => None,
+            },
+            MIDIEventKind::SysEx(_) => Some(SettingKind::SysEx),
+        }
+    }
+}
+
+fn is_note(kind: &MIDIEventKind) -> bool {
+    matches!(
+        kind,
+        MIDIEventKind::Channel(ChannelEvent {
+            kind: ChannelEventKind::NoteOn { .. } | ChannelEventKind::NoteOff { .. },
+            ..
+        })
+    )
+}
+
+/// Whether the note sounds within `[start_tick, end_tick)`
+fn overlaps(note: &Note, start_tick: u64, end_tick: u64) -> bool {
+    note.start_tick < end_tick && (note.end_tick > start_tick || note.start_tick >= start_tick)
+}
+
+fn map_tracks(
+    data: &MIDIFileData,
+    mut f: impl FnMut(usize, &MIDITrack) -> MIDITrack,
+) -> MIDIFileData {
+    let tracks = data
+        .tracks()
+        .iter()
+        .enumerate()
+        .map(|(track_index, track)| f(track_index, track))
+        .collect();
+
+    MIDIFileData::new(*data.format(), *data.time_division(), tracks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{MIDIFormat, SysExEvent, TimeDivision, builder::FileBuilder};
+
+    /// Track starting at 120 BPM, so at 480 ticks per beat 960 ticks last a second
+    fn track() -> TrackBuilder {
+        TrackBuilder::new().set_tempo(Tempo::from_bpm(120))
+    }
+
+    /// Format 0 file at 480 ticks per beat
+    fn file(track: TrackBuilder) -> MIDIFileData {
+        FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(480),
+        )
+        .track(track)
+        .build()
+    }
+
+    fn spans(data: &MIDIFileData) -> Vec<(u8, u8, u64, u64)> {
+        notes::extract_track_notes(data, 0)
+            .iter()
+            .map(|note| (note.channel, note.key, note.start_tick, note.end_tick))
+            .collect()
+    }
+
+    #[test]
+    fn transposition_skips_percussion_and_clamps() {
+        let data = file(
+            track()
+                .note(0, 60, 100, 96)
+                .note(1, 120, 100, 96)
+                .note(9, 36, 100, 96),
+        );
+
+        let transposed = transpose(&data, 12);
+        assert_eq!(
+            spans(&transposed),
+            [(0, 72, 0, 96), (1, 127, 0, 96), (9, 36, 0, 96)]
+        );
+        assert_eq!(spans(&transpose(&transposed, -12))[0], (0, 60, 0, 96));
+    }
+
+    #[test]
+    fn cropping_a_held_note_strikes_it_again_at_the_start() {
+        let data = file(
+            track()
+                .program_change(0, 40)
+                .note(0, 60, 100, 1920)
+                .at(1440)
+                .note(0, 64, 90, 960),
+        );
+
+        let cropped = crop(&data, Duration::from_millis(500), Duration::from_secs(2));
+        let events = cropped.tracks()[0].events();
+        let mut tick = 0;
+        let events = events
+            .iter()
+            .map(|event| {
+                tick += event.delta_time() as u64;
+                (tick, event.kind().clone())
+            })
+            .collect::<Vec<_>>();
+
+        let program = MIDIEventKind::Channel(ChannelEvent {
+            channel: 0,
+            kind: ChannelEventKind::ProgramChange { program_number: 40 },
+        });
+        assert!(events.contains(&(0, program)));
+        // The held C goes on for the remaining 1.5 seconds, and E is cut off at the end
+        assert_eq!(spans(&cropped), [(0, 60, 0, 1440), (0, 64, 960, 1440)]);
+        assert_eq!(cropped.tracks()[0].end_tick(), 1440);
+        assert_eq!(cropped.tempo_map(0).changes(), [(0, Tempo::from_bpm(120))]);
+    }
+
+    #[test]
+    fn tempo_scaling_rewrites_tempo_changes() {
+        let data = FileBuilder::new(MIDIFormat::MultiTracks, TimeDivision::TicksPerBit(480))
+            .track(TrackBuilder::new().at(960).set_tempo(Tempo::from_bpm(60)))
+            .track(TrackBuilder::new().note(0, 60, 100, 1920))
+            .build();
+
+        let scaled = scale_tempo(&data, 2.0);
+        assert_eq!(
+            scaled.tempo_map(0).changes(),
+            [(0, Tempo::from_bpm(240)), (960, Tempo::from_bpm(120))]
+        );
+        assert_eq!(scaled.tracks()[1], data.tracks()[1]);
+        assert_eq!(scaled.duration(), data.duration() / 2);
+    }
+
+    fn absolute_events(track: &MIDITrack) -> Vec<(u64, MIDIEventKind)> {
+        let mut tick = 0;
+        track
+            .events()
+            .iter()
+            .map(|event| {
+                tick += event.delta_time() as u64;
+                (tick, event.kind().clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn splitting_by_channel_round_trips_through_merging() {
+        let data = file(
+            track()
+                .program_change(0, 40)
+                .program_change(1, 32)
+                .note(0, 60, 100, 480)
+                .note(1, 36, 100, 960)
+                .at(480)
+                .meta(MetaEvent::Marker {
+                    text: b"Verse".to_vec(),
+                })
+                .note(0, 62, 100, 480),
+        );
+
+        let split = data.split_by_channel();
+        assert_eq!(*split.format(), MIDIFormat::MultiTracks);
+        assert_eq!(split.tracks().len(), 3);
+        assert!(
+            split.tracks()[0]
+                .events()
+                .iter()
+                .all(|event| !matches!(event.kind(), MIDIEventKind::Channel(_)))
+        );
+        assert!(split.tracks()[2].events().iter().all(|event| matches!(
+            event.kind(),
+            MIDIEventKind::Channel(ChannelEvent { channel: 1, .. })
+                | MIDIEventKind::Meta(MetaEvent::EndOfTrack)
+        )));
+        assert_eq!(split.duration(), data.duration());
+
+        let merged = split.merge_tracks();
+        assert_eq!(*merged.format(), MIDIFormat::SingleMultiChannelTrack);
+        // Simultaneous events of different channels come in channel order after a split
+        assert_eq!(merged.split_by_channel().tracks(), split.tracks());
+        assert_eq!(spans(&merged), spans(&data));
+
+        let bytes = merged.to_bytes();
+        let parsed = MIDIFileData::try_from(&bytes[..]).unwrap();
+        assert_eq!(parsed.tracks(), merged.tracks());
+    }
+
+    fn channel(channel: u8, kind: ChannelEventKind) -> MIDIEventKind {
+        MIDIEventKind::Channel(ChannelEvent { channel, kind })
+    }
+
+    #[test]
+    fn filtered_events_keep_their_ticks() {
+        let data = file(
+            track()
+                .controller(0, 64, 127)
+                .controller(0, 64, 0)
+                .note(0, 60, 100, 480)
+                .at(120)
+                .controller(0, 64, 127)
+                .at(240)
+                .controller(0, 64, 0)
+                .channel_event(0, ChannelEventKind::ChannelAftertouch { aftertouch: 30 })
+                .at(360)
+                .note(1, 64, 90, 240)
+                .controller(1, 7, 80),
+        );
+        let filter = EventFilter {
+            drop_controllers: true,
+            ..EventFilter::default()
+        };
+
+        let filtered = data.filter_events(|kind| filter.keeps(kind));
+        let expected = absolute_events(&data.tracks()[0])
+            .into_iter()
+            .filter(|(_, kind)| filter.keeps(kind))
+            .collect::<Vec<_>>();
+        assert_eq!(absolute_events(&filtered.tracks()[0]), expected);
+        assert_eq!(spans(&filtered), spans(&data));
+        assert_eq!(filtered.duration(), data.duration());
+
+        let filter = EventFilter {
+            drop_aftertouch: true,
+            ..filter
+        };
+        let filtered = filtered.filter_events(|kind| filter.keeps(kind));
+        assert!(
+            absolute_events(&filtered.tracks()[0])
+                .iter()
+                .all(|(_, kind)| !matches!(
+                    kind,
+                    MIDIEventKind::Channel(ChannelEvent {
+                        kind: ChannelEventKind::ChannelAftertouch { .. },
+                        ..
+                    })
+                ))
+        );
+        assert_eq!(spans(&filtered), spans(&data));
+    }
+
+    #[test]
+    fn dropping_the_last_event_keeps_the_end_of_the_track() {
+        let data = file(
+            track()
+                .note(0, 60, 100, 480)
+                .at(720)
+                .controller(0, 64, 0)
+                .at(960),
+        );
+
+        let filtered = data.filter_events(|kind| {
+            !matches!(
+                kind,
+                MIDIEventKind::Channel(ChannelEvent {
+                    kind: ChannelEventKind::Controller { .. },
+                    ..
+                })
+            )
+        });
+        let events = filtered.tracks()[0].events();
+        let end_of_track = events.last().unwrap();
+        assert_eq!(
+            *end_of_track.kind(),
+            MIDIEventKind::Meta(MetaEvent::EndOfTrack)
+        );
+        assert_eq!(end_of_track.delta_time(), 480);
+        assert_eq!(filtered.tracks()[0].end_tick(), 960);
+        assert_eq!(events.len(), data.tracks()[0].events().len() - 1);
+
+        // Even when every event is dropped
+        let filtered = data.filter_events(|_| false);
+        assert_eq!(filtered.tracks()[0].events().len(), 1);
+        assert_eq!(filtered.tracks()[0].end_tick(), 960);
+    }
+
+    #[test]
+    fn percussion_is_isolated_with_the_meta_events() {
+        let data = file(
+            track()
+                .program_change(0, 40)
+                .note(0, 60, 100, 480)
+                .note(9, 36, 100, 120)
+                .at(480)
+                .meta(MetaEvent::Marker {
+                    text: b"Fill".to_vec(),
+                })
+                .note(9, 38, 100, 120)
+                .note(1, 48, 100, 960),
+        );
+
+        let filter = EventFilter::only_channel(PERCUSSION_CHANNEL);
+        let filtered = data.filter_events(|kind| filter.keeps(kind));
+        assert_eq!(spans(&filtered), [(9, 36, 0, 120), (9, 38, 480, 600)]);
+        assert_eq!(filtered.tempo_map(0).changes(), data.tempo_map(0).changes());
+        assert_eq!(filtered.duration(), data.duration());
+        assert!(absolute_events(&filtered.tracks()[0]).contains(&(
+            480,
+            MIDIEventKind::Meta(MetaEvent::Marker {
+                text: b"Fill".to_vec()
+            })
+        )));
+    }
+
+    #[test]
+    fn notes_only_drops_every_other_channel_event() {
+        let filter = EventFilter {
+            notes_only: true,
+            ..EventFilter::default()
+        };
+        let note_on = channel(
+            0,
+            ChannelEventKind::NoteOn {
+                note: 60,
+                velocity: 100,
+            },
+        );
+
+        assert!(filter.keeps(&note_on));
+        assert!(filter.keeps(&MIDIEventKind::Meta(MetaEvent::SetTempo {
+            tempo: Tempo::default()
+        })));
+        assert!(!filter.keeps(&channel(
+            0,
+            ChannelEventKind::ProgramChange { program_number: 1 }
+        )));
+        assert!(!filter.keeps(&channel(0, ChannelEventKind::PitchBend { lsb: 0, msb: 64 })));
+        assert!(!filter.keeps(&MIDIEventKind::SysEx(SysExEvent::Normal {
+            data: vec![0x7E, 0xF7]
+        })));
+        assert!(!EventFilter::only_channel(9).keeps(&note_on));
+    }
+
+    #[test]
+    fn gaps_longer_than_a_delta_time_keep_a_dropped_event() {
+        let max = MAX_DELTA_TIME as u64;
+        let data = file(
+            track()
+                .note_on(0, 60, 100)
+                .at(max)
+                .controller(0, 64, 127)
+                .at(2 * max)
+                .controller(0, 64, 0)
+                .at(2 * max + 10)
+                .note_off(0, 60, 0),
+        );
+
+        let filtered = data.filter_events(|kind| {
+            !matches!(
+                kind,
+                MIDIEventKind::Channel(ChannelEvent {
+                    kind: ChannelEventKind::Controller { .. },
+                    ..
+                })
+            )
+        });
+        let events = filtered.tracks()[0].events();
+        assert!(
+            events
+                .iter()
+                .all(|event| event.delta_time() <= MAX_DELTA_TIME)
+        );
+        assert_eq!(spans(&filtered), [(0, 60, 0, 2 * max + 10)]);
+
+        let parsed = MIDIFileData::try_from(&filtered.to_bytes()[..]).unwrap();
+        assert_eq!(parsed.tracks(), filtered.tracks());
+    }
+
+    #[test]
+    fn merged_events_at_the_same_tick_are_ordered() {
+        let data = FileBuilder::new(MIDIFormat::MultiTracks, TimeDivision::TicksPerBit(96))
+            .track(
+                TrackBuilder::new()
+                    .note(0, 60, 100, 96)
+                    .at(96)
+                    .note_on(0, 62, 90),
+            )
+            .track(
+                TrackBuilder::new()
+                    .at(96)
+                    .note_on(1, 64, 80)
+                    .program_change(1, 5),
+            )
+            .track(TrackBuilder::new().at(96).set_tempo(Tempo::from_bpm(90)))
+            .build();
+
+        let merged = data.merge_tracks();
+        let events = absolute_events(&merged.tracks()[0]);
+        let at_96 = events
+            .iter()
+            .filter(|(tick, _)| *tick == 96)
+            .map(|(_, kind)| kind.clone())
+            .collect::<Vec<_>>();
+
+        let channel = |channel, kind| MIDIEventKind::Channel(ChannelEvent { channel, kind });
+        assert_eq!(
+            at_96,
+            [
+                MIDIEventKind::Meta(MetaEvent::SetTempo {
+                    tempo: Tempo::from_bpm(90)
+                }),
+                channel(1, ChannelEventKind::ProgramChange { program_number: 5 }),
+                channel(
+                    0,
+                    ChannelEventKind::NoteOff {
+                        note: 60,
+                        velocity: 0
+                    }
+                ),
+                channel(
+                    0,
+                    ChannelEventKind::NoteOn {
+                        note: 62,
+                        velocity: 90
+                    }
+                ),
+                channel(
+                    1,
+                    ChannelEventKind::NoteOn {
+                        note: 64,
+                        velocity: 80
+                    }
+                ),
+                MIDIEventKind::Meta(MetaEvent::EndOfTrack),
+            ]
+        );
+    }
+}
@@ -0,0 +1,2242 @@
+//! The player page, wiring the controls of `index.html` to the synthesizers and effects.
+use std::{
+    cell::{Cell, OnceCell, RefCell},
+    rc::Rc,
+    time::Duration,
+};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    audio_recording::{self, AudioRecorder},
+    dom::{
+        self, AveragingToggle, ChannelList, DelayControls, EffectsControls, EqualizerControls,
+        FftSizeSelect, HarmonicEditor, LogFrequencyToggle, LoopRegionControls, LoopToggle,
+        LyricsDisplay, MetronomeControls, MidiDeviceSelect, MidiUrlInput, PeakHoldToggle,
+        PianoKeyboard, PlaybackControls, PlaylistView, RateControl, RecordingControls,
+        RenderProgress, ReverbControl, StatisticsTable, StatusPanel, SynthKind, SynthKindOption,
+        TrackInfoPanel, TrackList, TransposeControl, UnisonControls, VolumeControl, WaveKind,
+        WaveKindOption, WavePresetInput,
+    },
+    dsp::{self, Biquad, BiquadCoefficients, LimiterSettings},
+    effects::{DelaySettings, DelayTime, EQ_BANDS, EffectsChain, EqGains, FilterSettings},
+    loop_region::{LoopPoint, LoopPoints},
+    lyrics::LyricsTimeline,
+    midi::{
+        BarBeat, ChannelEvent, ChannelEventKind, MIDIFileData, Tempo, TimeSignature, notes,
+        recording::Recording, stats::Statistics,
+    },
+    playlist::Playlist,
+    plotter::{self, AudioVisualizer, PianoRollPlotter},
+    settings::Settings,
+    synth::{
+        self, ChannelFilter, FilterEnvelope, Marker, SynthSettings, TrackFilter, TrackInfo, Unison,
+        live::{self, LiveSynth},
+        metronome::{self, CountIn},
+    },
+    wave::{
+        BellWave, CustomWave, HarmonicWave, OrganWave, PulseWave, SawtoothWave, SineWave,
+        SquareWave, StringsWave, TriangleWave, Wave, WhiteNoise,
+    },
+};
+
+thread_local! {
+    /// Panel the logged warnings and errors are shown on, once the page is set up
+    static STATUS_PANEL: RefCell<Option<StatusPanel>> = const { RefCell::new(None) };
+}
+
+/// Logs to the console, and shows warnings and errors on the status panel as well
+struct PageLogger;
+
+impl log::Log for PageLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        console_log::log(record);
+        STATUS_PANEL.with_borrow(|panel| match (panel, record.level()) {
+            (Some(panel), log::Level::Error) => panel.error(&record.args().to_string()),
+            (Some(panel), log::Level::Warn) => panel.warning(&record.args().to_string()),
+            _ => {}
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: PageLogger = PageLogger;
+
+/// What is needed to (re)start the playback of the loaded file from any position
+enum Playback {
+    /// Files are rendered a slice per animation frame, so the page stays responsive while they are
+    Raw {
+        synth: synth::raw::MidiSynth,
+        wave_kind: WaveKindOption,
+        audio: RenderedAudio<synth::raw::Rendering>,
+    },
+    WebAudio {
+        synth: synth::web_audio::MidiSynth,
+        wave_kind: WaveKindOption,
+    },
+    /// The Web Audio synthesizer, rendered into a buffer by an offline audio context
+    Offline {
+        synth: synth::web_audio::MidiSynth,
+        wave_kind: WaveKindOption,
+        audio: RenderedAudio<OfflineRendering>,
+    },
+}
+
+/// Audio rendered before it is played, so it can be seeked through and exported
+enum RenderedAudio<R> {
+    Rendering {
+        rendering: R,
+        /// Position to start the playback from once the rendering is done
+        start_at: Duration,
+    },
+    Rendered(web_sys::AudioBuffer),
+}
+
+/// Time over which volume changes are ramped, to avoid zipper noise
+const VOLUME_RAMP: Duration = Duration::from_millis(30);
+
+struct MidiPlayerState {
+    audio_context: web_sys::AudioContext,
+    /// Master volume, between the synthesizers and the effects
+    master: web_sys::GainNode,
+    /// Between the master volume and the visualizer, so the effects are shown and recorded
+    effects: EffectsChain,
+    /// Synced to the tempo of the loaded file as it plays, unless it's free
+    delay_time: DelayTime,
+    /// Also applied to exported audio, and to the notes of the Web Audio synthesizer if they have
+    /// a filter envelope
+    filter: FilterSettings,
+    /// Also applied to exported audio
+    equalizer: EqGains,
+    /// Also stands for the limiter applied to exported audio
+    compressor: bool,
+    audio_source: web_sys::AudioBufferSourceNode,
+    scheduled_playback: Option<synth::web_audio::ScheduledPlayback>,
+    /// Clicks of the count-in before the file, while it's being counted in
+    count_in_clicks: Option<synth::web_audio::ScheduledClicks>,
+    controls: PlaybackControls,
+    render_progress: RenderProgress,
+    piano_roll: PianoRollPlotter,
+    playlist_view: PlaylistView,
+    /// Woken whenever the playback may start
+    draw_loop: DrawLoop,
+    playback: Option<Playback>,
+    /// Files selected by the user, the current one being loaded or played
+    playlist: Playlist<web_sys::File>,
+    /// Reads and parses a file of the playlist, which is then loaded with
+    /// [`MidiPlayerState::set_buffer`]
+    open_file: Box<dyn Fn(&web_sys::File)>,
+    /// Kept when the loaded file is played by another synthesizer
+    synth_settings: SynthSettings,
+    channel_filter: ChannelFilter,
+    track_filter: TrackFilter,
+    /// Speed of the playback relative to the tempo of the file
+    playback_rate: f32,
+    /// Semitones added to all notes but the drums
+    transpose: i8,
+    /// Volume of the clicks on every beat, when the metronome is on
+    metronome: Option<f32>,
+    /// Bars clicked before the file when it plays from the start
+    count_in: CountIn,
+    /// Volume of the clicks of the count-in
+    count_in_volume: f32,
+    /// Duration of the loaded file
+    duration: Duration,
+    /// Time after the start at which the last notes have faded out
+    end: Duration,
+    /// The playback reached the end and was reset to the start
+    ended: bool,
+    /// Restart the playback instead of ending it
+    looping: bool,
+    /// Points of the A-B loop, kept until they are cleared or another file is loaded
+    loop_points: LoopPoints,
+    /// Time signature changes of the loaded file, for showing the position in bars
+    time_signatures: Vec<(u64, TimeSignature)>,
+    /// Lyrics of the loaded file, timed at the current playback rate
+    lyrics: LyricsTimeline,
+    user_waves: UserWaves,
+    /// Notes played on the keyboard
+    live: LiveSynth,
+    /// Events played live since the recording started
+    recording: Option<Recording>,
+    /// Records the audio played, whichever synthesizer plays it
+    audio_recorder: Option<AudioRecorder>,
+    /// Context time at which the position in the file would be zero
+    started_at: f64,
+    /// Position the playback was last started from
+    started_from: Duration,
+    playing: bool,
+}
+
+impl MidiPlayerState {
+    /// All audio is played through `output`
+    pub fn new(
+        audio_context: web_sys::AudioContext,
+        output: &web_sys::AudioNode,
+        controls: PlaybackControls,
+        render_progress: RenderProgress,
+        piano_roll: PianoRollPlotter,
+        playlist_view: PlaylistView,
+        draw_loop: DrawLoop,
+    ) -> Result<Self, JsValue> {
+        let audio_source = audio_context.create_buffer_source()?;
+        let master = audio_context.create_gain()?;
+        let effects = EffectsChain::new(&audio_context)?;
+        master.connect_with_audio_node(effects.input())?;
+        effects.output().connect_with_audio_node(output)?;
+
+        Ok(Self {
+            audio_context,
+            master,
+            effects,
+            delay_time: DelayTime::Free(Duration::ZERO),
+            filter: FilterSettings::default(),
+            equalizer: EqGains::default(),
+            compressor: false,
+            audio_source,
+            scheduled_playback: None,
+            count_in_clicks: None,
+            controls,
+            render_progress,
+            piano_roll,
+            playlist_view,
+            draw_loop,
+            playback: None,
+            playlist: Playlist::default(),
+            open_file: Box::new(|_| {}),
+            synth_settings: SynthSettings::default(),
+            channel_filter: ChannelFilter::default(),
+            track_filter: TrackFilter::default(),
+            playback_rate: 1.0,
+            transpose: 0,
+            metronome: None,
+            count_in: CountIn::Off,
+            count_in_volume: 0.0,
+            duration: Duration::ZERO,
+            end: Duration::ZERO,
+            ended: false,
+            looping: false,
+            loop_points: LoopPoints::default(),
+            time_signatures: vec![],
+            lyrics: LyricsTimeline::default(),
+            user_waves: UserWaves {
+                preset: None,
+                harmonics: HarmonicWave::new(vec![1.0]),
+            },
+            live: LiveSynth::default(),
+            recording: None,
+            audio_recorder: None,
+            started_at: 0.0,
+            started_from: Duration::ZERO,
+            playing: false,
+        })
+    }
+
+    /// Pause or resume the playback.
+    ///
+    /// Both synthesizers schedule their audio against the context time, so suspending the
+    /// context pauses them at the current position.
+    pub fn set_playing(&mut self, playing: bool) -> Result<(), JsValue> {
+        if self.playback.is_none() {
+            self.controls.set_playing(false);
+            return Ok(());
+        }
+
+        if playing && self.ended {
+            self.start_counting_in(Duration::ZERO)?;
+        }
+
+        if playing != self.playing {
+            if playing {
+                self.draw_loop.wake();
+                self.resume_context()?;
+            } else {
+                self.suspend_context()?;
+            }
+
+            self.playing = playing;
+        }
+
+        self.controls.set_playing(playing);
+        Ok(())
+    }
+
+    /// Set the master volume, from 0 (silent) to 1. It is kept when another file is loaded.
+    pub fn set_volume(&self, volume: f32) -> Result<(), JsValue> {
+        let now = self.audio_context.current_time();
+        let gain = self.master.gain();
+
+        gain.cancel_scheduled_values(now)?;
+        gain.set_value_at_time(gain.value(), now)?;
+        gain.linear_ramp_to_value_at_time(volume, now + VOLUME_RAMP.as_secs_f64())?;
+        Ok(())
+    }
+
+    /// Set the gain of each band of the equalizer, in dB
+    pub fn set_equalizer(&mut self, gains: EqGains) -> Result<(), JsValue> {
+        self.equalizer = gains;
+        self.effects.set_equalizer(&self.audio_context, gains)
+    }
+
+    /// Set the level of the reverberation, from 0 (none) to 1
+    pub fn set_reverb(&self, amount: f32) -> Result<(), JsValue> {
+        self.effects.set_reverb(&self.audio_context, amount)
+    }
+
+    /// Switch the compressor at the end of the effects chain on or off
+    pub fn set_compressor(&mut self, enabled: bool) -> Result<(), JsValue> {
+        self.compressor = enabled;
+        self.effects.set_compressor(enabled)
+    }
+
+    /// Set the low-pass filter of the effects chain. The filter envelope of the notes only
+    /// follows once [`MidiPlayerState::apply_filter_envelope`] is called, as it restarts the
+    /// playback.
+    pub fn set_filter(&mut self, settings: FilterSettings) -> Result<(), JsValue> {
+        self.filter = settings;
+        self.effects.set_filter(&self.audio_context, settings)
+    }
+
+    /// Give the notes of the Web Audio synthesizer the filter envelope last set, restarting its
+    /// playback from the current position if it changed
+    pub fn apply_filter_envelope(&mut self) -> Result<(), JsValue> {
+        let filter_envelope = self.filter_envelope();
+        let changed = match &self.playback {
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.filter_envelope() != filter_envelope
+            }
+            _ => false,
+        };
+
+        if changed {
+            self.apply_settings(self.position())?;
+        }
+        Ok(())
+    }
+
+    /// Play the notes of the Web Audio synthesizer with `unison` oscillators, detuned up to
+    /// `spread` cents, restarting its playback from the current position if they changed
+    pub fn set_unison(&mut self, unison: Unison, spread: f32) -> Result<(), JsValue> {
+        self.synth_settings.unison = unison;
+        self.synth_settings.unison_spread = spread;
+        let changed = match &self.playback {
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.settings() != self.synth_settings
+            }
+            _ => false,
+        };
+
+        if changed {
+            self.apply_settings(self.position())?;
+        }
+        Ok(())
+    }
+
+    /// Click on every beat at `volume`, or stop clicking, restarting the playback from the current
+    /// position if it changed. The raw synthesizer mixes the clicks into its rendering.
+    pub fn set_metronome(&mut self, volume: Option<f32>) -> Result<(), JsValue> {
+        self.metronome = volume;
+        let changed = match &self.playback {
+            Some(Playback::Raw { synth, .. }) => synth.metronome() != volume,
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.metronome() != volume
+            }
+            None => false,
+        };
+
+        if changed {
+            self.apply_settings(self.position())?;
+        }
+        Ok(())
+    }
+
+    /// Click `count_in` bars at `volume` before the file whenever it plays from the start. Takes
+    /// effect the next time it does.
+    pub fn set_count_in(&mut self, count_in: CountIn, volume: f32) {
+        self.count_in = count_in;
+        self.count_in_volume = volume;
+    }
+
+    fn filter_envelope(&self) -> Option<FilterEnvelope> {
+        self.filter
+            .envelope
+            .then(|| FilterEnvelope::new(self.filter.cutoff, self.filter.q))
+    }
+
+    /// Set the delay, whose time is kept synced to the loaded file if chosen so
+    pub fn set_delay(&mut self, settings: DelaySettings) -> Result<(), JsValue> {
+        self.delay_time = settings.time;
+        self.effects
+            .set_delay_levels(&self.audio_context, settings)?;
+        self.sync_delay_time()
+    }
+
+    /// Follow the tempo at the current position with the delay time
+    fn sync_delay_time(&mut self) -> Result<(), JsValue> {
+        let time = self
+            .delay_time
+            .duration(self.data().map(|data| data.tempo_map(0)), self.position());
+        self.effects.set_delay_time(&self.audio_context, time)
+    }
+
+    /// MIDI channels used by the loaded file
+    pub fn channels(&self) -> Vec<u8> {
+        match &self.playback {
+            None => vec![],
+            Some(Playback::Raw { synth, .. }) => synth.channels(),
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.channels()
+            }
+        }
+    }
+
+    /// Names of the tracks of the loaded file
+    pub fn track_names(&self) -> Vec<String> {
+        match &self.playback {
+            None => vec![],
+            Some(Playback::Raw { synth, .. }) => synth.track_names(),
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.track_names()
+            }
+        }
+    }
+
+    /// Description of the tracks of the loaded file
+    pub fn track_infos(&self) -> Vec<TrackInfo> {
+        match &self.playback {
+            None => vec![],
+            Some(Playback::Raw { synth, .. }) => synth.track_infos(),
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.track_infos()
+            }
+        }
+    }
+
+    /// Markers and cue points of the loaded file, at the current playback rate
+    pub fn markers(&self) -> Vec<Marker> {
+        match &self.playback {
+            None => vec![],
+            Some(Playback::Raw { synth, .. }) => synth.markers().to_vec(),
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.markers().to_vec()
+            }
+        }
+    }
+
+    /// Copyright notice of the loaded file
+    pub fn copyright(&self) -> Option<String> {
+        match &self.playback {
+            None => None,
+            Some(Playback::Raw { synth, .. }) => synth.copyright().map(str::to_owned),
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.copyright().map(str::to_owned)
+            }
+        }
+    }
+
+    /// Silence channels of the loaded file, restarting it from the current position to apply it.
+    pub fn set_channel_filter(&mut self, channel_filter: ChannelFilter) -> Result<(), JsValue> {
+        self.channel_filter = channel_filter;
+        self.apply_settings(self.position())
+    }
+
+    /// Leave out tracks of the loaded file, restarting it from the current position to apply it.
+    pub fn set_track_filter(&mut self, track_filter: TrackFilter) -> Result<(), JsValue> {
+        self.track_filter = track_filter;
+        self.apply_settings(self.position())
+    }
+
+    /// Play faster (`rate` above 1) or slower, staying at the same point of the music. The rate is
+    /// kept when another file is loaded.
+    pub fn set_playback_rate(&mut self, rate: f32) -> Result<(), JsValue> {
+        // All event times scale with the rate, so the musical position scales with it too
+        let factor = self.playback_rate as f64 / rate as f64;
+        let position = self.position().mul_f64(factor);
+        self.loop_points.scale(factor);
+        self.playback_rate = rate;
+        self.apply_settings(position)
+    }
+
+    /// Play the loaded file with another synthesizer, from the current position
+    pub fn set_synth_kind(
+        &mut self,
+        synth_kind: SynthKindOption,
+        wave_kind: WaveKindOption,
+    ) -> Result<(), JsValue> {
+        let position = self.position();
+        let midi_data = match self.playback.take() {
+            None => return Ok(()),
+            Some(Playback::Raw { synth, .. }) => synth.into_data(),
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                synth.into_data()
+            }
+        };
+
+        self.load(midi_data, synth_kind, wave_kind, position)
+    }
+
+    /// Transpose all notes but the drums by the given number of semitones. The transposition is
+    /// kept when another file is loaded.
+    pub fn set_transpose(&mut self, semitones: i8) -> Result<(), JsValue> {
+        self.transpose = semitones;
+        self.apply_settings(self.position())
+    }
+
+    /// Play all notes with a wavetable preset, from the current position. The preset is kept when
+    /// another file is loaded.
+    pub fn set_custom_wave(&mut self, custom_wave: CustomWave) -> Result<(), JsValue> {
+        self.user_waves.preset = Some(custom_wave);
+        self.set_wave_kind(WaveKindOption::Custom)
+    }
+
+    /// Play all notes with the harmonics drawn by the user, from the current position. The
+    /// harmonics are kept when another file is loaded.
+    pub fn set_harmonic_wave(&mut self, harmonic_wave: HarmonicWave) -> Result<(), JsValue> {
+        self.user_waves.harmonics = harmonic_wave;
+        self.set_wave_kind(WaveKindOption::Harmonics)
+    }
+
+    /// Play the loaded file with another wave, from the current position
+    fn set_wave_kind(&mut self, new_wave_kind: WaveKindOption) -> Result<(), JsValue> {
+        match &mut self.playback {
+            None => return Ok(()),
+            Some(
+                Playback::Raw { wave_kind, .. }
+                | Playback::WebAudio { wave_kind, .. }
+                | Playback::Offline { wave_kind, .. },
+            ) => {
+                *wave_kind = new_wave_kind;
+            }
+        }
+        self.apply_settings(self.position())
+    }
+
+    /// Apply the player settings to the loaded synthesizer, and restart it from `position`
+    fn apply_settings(&mut self, position: Duration) -> Result<(), JsValue> {
+        let filter_envelope = self.filter_envelope();
+        match &mut self.playback {
+            None => return Ok(()),
+            Some(Playback::Raw { synth, audio, .. }) => {
+                synth.set_channel_filter(self.channel_filter.clone());
+                synth.set_track_filter(self.track_filter.clone());
+                synth.set_playback_rate(self.playback_rate);
+                synth.set_transpose(self.transpose);
+                synth.set_metronome(self.metronome);
+                self.lyrics = LyricsTimeline::new(synth.data());
+                *audio = RenderedAudio::Rendering {
+                    rendering: synth.start_rendering(self.audio_context.sample_rate() as u32),
+                    start_at: position,
+                };
+                self.render_progress.show(0.0);
+                self.controls.set_download_enabled(false);
+
+                self.duration = synth.total_duration();
+                self.end = synth.playback_duration();
+            }
+            Some(Playback::WebAudio { synth, .. }) => {
+                synth.set_channel_filter(self.channel_filter.clone());
+                synth.set_track_filter(self.track_filter.clone());
+                synth.set_playback_rate(self.playback_rate);
+                synth.set_transpose(self.transpose);
+                synth.set_metronome(self.metronome);
+                synth.set_settings(self.synth_settings);
+                synth.set_filter_envelope(filter_envelope);
+                self.lyrics = LyricsTimeline::new(synth.data());
+
+                self.duration = synth.total_duration();
+                self.end = synth.playback_duration();
+            }
+            Some(Playback::Offline {
+                synth,
+                wave_kind,
+                audio,
+            }) => {
+                synth.set_channel_filter(self.channel_filter.clone());
+                synth.set_track_filter(self.track_filter.clone());
+                synth.set_playback_rate(self.playback_rate);
+                synth.set_transpose(self.transpose);
+                synth.set_metronome(self.metronome);
+                synth.set_settings(self.synth_settings);
+                synth.set_filter_envelope(filter_envelope);
+                self.lyrics = LyricsTimeline::new(synth.data());
+                *audio = RenderedAudio::Rendering {
+                    rendering: OfflineRendering::start(
+                        synth,
+                        select_wave(*wave_kind, &self.user_waves),
+                        self.audio_context.sample_rate(),
+                    )?,
+                    start_at: position,
+                };
+                self.render_progress.show(0.0);
+                self.controls.set_download_enabled(false);
+
+                self.duration = synth.total_duration();
+                self.end = synth.playback_duration();
+            }
+        }
+
+        self.controls.set_duration(self.duration);
+        self.controls.set_markers(&self.markers(), self.duration)?;
+        self.show_loop_points()?;
+        self.show_notes()?;
+        if position < self.duration {
+            self.start_at(position)?;
+            self.show_position(position);
+        }
+
+        Ok(())
+    }
+
+    /// Current position in the loaded file
+    pub fn position(&self) -> Duration {
+        if let Some(
+            Playback::Raw {
+                audio: RenderedAudio::Rendering { start_at, .. },
+                ..
+            }
+            | Playback::Offline {
+                audio: RenderedAudio::Rendering { start_at, .. },
+                ..
+            },
+        ) = &self.playback
+        {
+            return *start_at;
+        }
+
+        Duration::from_secs_f64((self.audio_context.current_time() - self.started_at).max(0.0))
+            .min(self.duration)
+    }
+
+    /// Loaded file, timed at the current playback rate
+    fn data(&self) -> Option<&MIDIFileData> {
+        match &self.playback {
+            None => None,
+            Some(Playback::Raw { synth, .. }) => Some(synth.data()),
+            Some(Playback::WebAudio { synth, .. } | Playback::Offline { synth, .. }) => {
+                Some(synth.data())
+            }
+        }
+    }
+
+    /// Musical position in the loaded file. Unknown for files with tracks played one after another,
+    /// which may each have their own meter.
+    fn bar_beat(&self, position: Duration) -> Option<BarBeat> {
+        let data = self.data()?;
+        if data.is_sequential() {
+            return None;
+        }
+
+        let tempo_map = data.tempo_map(0);
+        let tick = tempo_map.duration_to_tick(position.saturating_sub(tempo_map.start_offset()));
+        BarBeat::from_tick(tick, *data.time_division(), &self.time_signatures)
+    }
+
+    pub fn lyrics(&self) -> &LyricsTimeline {
+        &self.lyrics
+    }
+
+    /// Index of the lyrics fragment being sung
+    pub fn current_lyric(&self) -> Option<usize> {
+        self.lyrics.current(self.position())
+    }
+
+    /// Time left before the file starts, while it's being counted in
+    fn count_in_remaining(&self) -> Option<Duration> {
+        if self.count_in_clicks.is_none() || self.is_rendering() {
+            return None;
+        }
+
+        let remaining = self.started_at - self.audio_context.current_time();
+        (remaining > 0.0).then(|| Duration::from_secs_f64(remaining))
+    }
+
+    /// Show the position in the playback controls and on the piano roll. The count-in is shown as
+    /// the time left before the start.
+    fn show_position(&self, position: Duration) {
+        match self.count_in_remaining() {
+            Some(remaining) => self.controls.set_count_in(remaining),
+            None => self
+                .controls
+                .set_position(position, self.bar_beat(position)),
+        }
+        if let Err(error) = self.piano_roll.draw(position) {
+            log::error!("failed to draw the piano roll: {:?}", error);
+        }
+    }
+
+    /// Draw the notes of the loaded file on the piano roll, with its bar lines unless its tracks
+    /// are played one after another
+    fn show_notes(&self) -> Result<(), JsValue> {
+        let Some(data) = self.data() else {
+            return Ok(());
+        };
+
+        let notes = notes::extract_notes(data);
+        let bar_starts = if data.is_sequential() {
+            vec![]
+        } else {
+            let tempo_map = data.tempo_map(0);
+            let end_tick = notes.iter().map(|note| note.end_tick).max().unwrap_or(0);
+            BarBeat::bar_starts(end_tick, *data.time_division(), &self.time_signatures)
+                .into_iter()
+                .map(|tick| tempo_map.start_offset() + tempo_map.tick_to_duration(tick))
+                .collect()
+        };
+        self.piano_roll
+            .set_notes(&notes, &bar_starts, self.duration)
+    }
+
+    /// Move the playback to the given position. Seeking past the end stops the playback.
+    pub fn seek(&mut self, position: Duration) -> Result<(), JsValue> {
+        if self.playback.is_none() {
+            return Ok(());
+        }
+
+        if position >= self.duration {
+            return self.finish();
+        }
+
+        self.start_at(position)?;
+        self.show_position(self.position());
+        Ok(())
+    }
+
+    /// Restart the playback from the start once it ends, instead of stopping
+    pub fn set_looping(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    /// Set a point of the A-B loop at the current position. Once both are set, the playback jumps
+    /// back to the first whenever it passes the second.
+    pub fn set_loop_point(&mut self, point: LoopPoint) -> Result<(), JsValue> {
+        if self.playback.is_none() {
+            return Ok(());
+        }
+
+        self.loop_points.set(point, self.position());
+        self.show_loop_points()
+    }
+
+    /// Stop looping between A and B, letting the playback carry on from where it is
+    pub fn clear_loop_points(&mut self) -> Result<(), JsValue> {
+        self.loop_points = LoopPoints::default();
+        self.show_loop_points()
+    }
+
+    fn show_loop_points(&self) -> Result<(), JsValue> {
+        self.controls.set_loop_points(
+            &self.loop_points,
+            self.loop_points.region(self.duration),
+            self.duration,
+        )
+    }
+
+    /// Refresh the position shown by the playback controls, and finish the playback once the last
+    /// notes have faded out.
+    ///
+    /// When looping, the playback is restarted instead. The loop boundary is at the end of the
+    /// release tails, so they are not cut off. Both synthesizers restart the same way, which for
+    /// the Web Audio one means scheduling the file again. Passing the end of the A-B loop region
+    /// seeks back to its start the same way, retriggering the notes sounding there.
+    pub fn update_position(&mut self) -> Result<(), JsValue> {
+        if let Some(audio_recorder) = &self.audio_recorder {
+            self.controls
+                .set_recording_time(Some(audio_recorder.duration(self.context_time())));
+        }
+        self.sync_delay_time()?;
+
+        if !self.playing || self.is_rendering() {
+            return Ok(());
+        }
+
+        let region = self.loop_points.region(self.duration);
+        let elapsed = self.audio_context.current_time() - self.started_at;
+        if let Some(region) = region
+            && region.passed_end(self.started_from, self.position())
+        {
+            self.start_at(region.start)?;
+        } else if elapsed >= self.end.as_secs_f64() {
+            if !self.looping {
+                return self.finish();
+            }
+
+            self.start_at(Duration::ZERO)?;
+        }
+
+        self.show_position(self.position());
+        Ok(())
+    }
+
+    /// Whether there is nothing to refresh on the page: nothing is playing, being rendered, nor
+    /// being recorded
+    pub fn is_idle(&self) -> bool {
+        !self.playing
+            && !self.is_rendering()
+            && self.live.is_silent()
+            && (self.audio_recorder.is_none() || self.is_paused())
+    }
+
+    /// Start playing `key` live with `wave_kind`, until [`MidiPlayerState::release_key`].
+    ///
+    /// Pausing suspends the audio context, so keys pressed while the loaded file is paused are
+    /// silent.
+    pub fn press_key(&mut self, key: u8, wave_kind: WaveKindOption) -> Result<(), JsValue> {
+        if self.is_paused() {
+            return Ok(());
+        }
+
+        // Browsers only let the context start from a user gesture, such as this key press
+        self.resume_context()?;
+        self.live.note_on(
+            &self.audio_context,
+            select_wave(wave_kind, &self.user_waves),
+            &self.master,
+            key,
+            live::KEYBOARD_VELOCITY,
+        )?;
+        self.record(ChannelEvent::new(
+            0,
+            ChannelEventKind::NoteOn {
+                note: key,
+                velocity: live::KEYBOARD_VELOCITY,
+            },
+        ));
+        self.draw_loop.wake();
+        Ok(())
+    }
+
+    pub fn release_key(&mut self, key: u8) -> Result<(), JsValue> {
+        self.record(ChannelEvent::new(
+            0,
+            ChannelEventKind::NoteOff {
+                note: key,
+                velocity: 0,
+            },
+        ));
+        self.live.note_off(&self.audio_context, key)
+    }
+
+    /// Play an event of a MIDI device live with `wave_kind`, as with
+    /// [`MidiPlayerState::press_key`]. Only the notes are left out while the loaded file is paused,
+    /// so that none of them is left hanging.
+    pub fn play_device_event(
+        &mut self,
+        event: &ChannelEvent,
+        wave_kind: WaveKindOption,
+    ) -> Result<(), JsValue> {
+        if matches!(event.kind(), ChannelEventKind::NoteOn { velocity, .. } if *velocity > 0) {
+            if self.is_paused() {
+                return Ok(());
+            }
+            self.resume_context()?;
+        }
+
+        self.live.handle_event(
+            &self.audio_context,
+            select_wave(wave_kind, &self.user_waves),
+            &self.master,
+            event,
+        )?;
+        self.record(event.clone());
+        self.draw_loop.wake();
+        Ok(())
+    }
+
+    /// Record the notes and controllers played live from now on, until
+    /// [`MidiPlayerState::stop_recording`]
+    pub fn start_recording(&mut self) -> Result<(), JsValue> {
+        // Events are timed by the audio context, whose clock only runs while it is resumed
+        if !self.is_paused() {
+            self.resume_context()?;
+        }
+        self.recording = Some(Recording::new(self.context_time()));
+        Ok(())
+    }
+
+    /// File of the events played live since the recording started, unless none were
+    pub fn stop_recording(&mut self) -> Option<MIDIFileData> {
+        let recording = self.recording.take()?;
+        (!recording.is_empty()).then(|| recording.to_midi(Tempo::default(), self.context_time()))
+    }
+
+    fn record(&mut self, event: ChannelEvent) {
+        let time = self.context_time();
+        if let Some(recording) = &mut self.recording {
+            recording.push(time, event);
+        }
+    }
+
+    /// Record the audio played from now on, until [`MidiPlayerState::stop_audio_recording`].
+    /// Nothing is recorded while the loaded file is paused.
+    pub fn start_audio_recording(&mut self) -> Result<(), JsValue> {
+        if self.audio_recorder.is_some() {
+            return Ok(());
+        }
+
+        let audio_recorder = AudioRecorder::start(&self.audio_context, self.effects.output())?;
+        if self.is_paused() {
+            audio_recorder.pause()?;
+        } else {
+            // The context clock times the recording, so it has to run even while nothing plays
+            let _ = self.audio_context.resume()?;
+        }
+
+        self.audio_recorder = Some(audio_recorder);
+        self.draw_loop.wake();
+        Ok(())
+    }
+
+    /// Stop recording the audio, and pass the recorded file with its MIME type to `recorded_cb`
+    /// once it's ready
+    pub fn stop_audio_recording<F: FnOnce(web_sys::Blob, String) + 'static>(
+        &mut self,
+        recorded_cb: F,
+    ) -> Result<(), JsValue> {
+        self.controls.set_recording_time(None);
+        match self.audio_recorder.take() {
+            Some(audio_recorder) => audio_recorder.stop(recorded_cb),
+            None => Ok(()),
+        }
+    }
+
+    /// Resume the audio context, and the audio recording along with it
+    fn resume_context(&self) -> Result<(), JsValue> {
+        let _ = self.audio_context.resume()?;
+        if let Some(audio_recorder) = &self.audio_recorder {
+            audio_recorder.resume()?;
+        }
+        Ok(())
+    }
+
+    /// Suspend the audio context, pausing the audio recording so it doesn't fill with silence
+    fn suspend_context(&self) -> Result<(), JsValue> {
+        let _ = self.audio_context.suspend()?;
+        if let Some(audio_recorder) = &self.audio_recorder {
+            audio_recorder.pause()?;
+        }
+        Ok(())
+    }
+
+    fn context_time(&self) -> Duration {
+        Duration::from_secs_f64(self.audio_context.current_time())
+    }
+
+    /// The loaded file is paused before its end, holding the audio context suspended
+    fn is_paused(&self) -> bool {
+        self.playback.is_some() && !self.playing && !self.ended
+    }
+
+    /// Whether the loaded file is still being rendered, by the raw synthesizer or an offline
+    /// audio context
+    fn is_rendering(&self) -> bool {
+        matches!(
+            self.playback,
+            Some(
+                Playback::Raw {
+                    audio: RenderedAudio::Rendering { .. },
+                    ..
+                } | Playback::Offline {
+                    audio: RenderedAudio::Rendering { .. },
+                    ..
+                }
+            )
+        )
+    }
+
+    /// Render the next slice of the loaded file with the raw synthesizer, or check whether the
+    /// offline audio context is done with it. Once the whole file is rendered, it is played from
+    /// the position the playback was meant to start at.
+    pub fn advance_rendering(&mut self) -> Result<(), JsValue> {
+        let done = match &mut self.playback {
+            Some(Playback::Raw {
+                synth,
+                wave_kind,
+                audio: RenderedAudio::Rendering { rendering, .. },
+            }) => {
+                synth.render_chunk(rendering, select_wave(*wave_kind, &self.user_waves));
+                self.render_progress.show(rendering.progress());
+                rendering.is_done()
+            }
+            Some(Playback::Offline {
+                audio: RenderedAudio::Rendering { rendering, .. },
+                ..
+            }) => {
+                self.render_progress.show(rendering.progress());
+                rendering.is_done()
+            }
+            _ => return Ok(()),
+        };
+        if !done {
+            return Ok(());
+        }
+
+        let (playback, start_at) = match self.playback.take() {
+            Some(Playback::Raw {
+                synth,
+                wave_kind,
+                audio:
+                    RenderedAudio::Rendering {
+                        rendering,
+                        start_at,
+                    },
+            }) => {
+                let audio_buffer = mix_rendering(&self.audio_context, &synth, rendering)?;
+                let audio = RenderedAudio::Rendered(audio_buffer);
+                let playback = Playback::Raw {
+                    synth,
+                    wave_kind,
+                    audio,
+                };
+                (playback, start_at)
+            }
+            Some(Playback::Offline {
+                synth,
+                wave_kind,
+                audio:
+                    RenderedAudio::Rendering {
+                        rendering,
+                        start_at,
+                    },
+            }) => {
+                let audio = RenderedAudio::Rendered(rendering.finish()?);
+                let playback = Playback::Offline {
+                    synth,
+                    wave_kind,
+                    audio,
+                };
+                (playback, start_at)
+            }
+            _ => unreachable!("the rendering was just advanced"),
+        };
+
+        self.playback = Some(playback);
+        self.render_progress.hide();
+        self.controls.set_download_enabled(true);
+
+        if start_at < self.duration {
+            self.start_counting_in(start_at)?;
+            self.show_position(start_at);
+        }
+        Ok(())
+    }
+
+    /// Stop the playback at the end of the file and rewind the controls to the start. Playing
+    /// again restarts the file.
+    fn finish(&mut self) -> Result<(), JsValue> {
+        self.stop()?;
+        self.set_playing(false)?;
+
+        self.ended = true;
+        self.started_at = self.audio_context.current_time();
+        self.show_position(Duration::ZERO);
+        self.play_next()
+    }
+
+    /// Set how files of the playlist are opened when they are about to play
+    pub fn on_open_file<F: Fn(&web_sys::File) + 'static>(&mut self, open_file: F) {
+        self.open_file = Box::new(open_file);
+    }
+
+    /// Queue `files` and open the first one. Files are only read once they are about to play.
+    pub fn set_playlist(&mut self, files: Vec<web_sys::File>) -> Result<(), JsValue> {
+        self.playlist = Playlist::new(files);
+        self.show_playlist()?;
+        self.play_next()
+    }
+
+    /// Open the file of the playlist at `index`
+    pub fn select_entry(&mut self, index: usize) -> Result<(), JsValue> {
+        if self.playlist.select(index).is_some() {
+            self.open_current_entry()?;
+        }
+        Ok(())
+    }
+
+    /// Open the file after the current one in the playlist, if any
+    pub fn play_next(&mut self) -> Result<(), JsValue> {
+        if self.playlist.advance().is_some() {
+            self.open_current_entry()?;
+        }
+        Ok(())
+    }
+
+    /// Open the file before the current one in the playlist, if any
+    pub fn play_previous(&mut self) -> Result<(), JsValue> {
+        if self.playlist.previous().is_some() {
+            self.open_current_entry()?;
+        }
+        Ok(())
+    }
+
+    /// Move on from the current file of the playlist, which failed to parse
+    pub fn skip_entry(&mut self) -> Result<(), JsValue> {
+        let Some(file) = self.playlist.current_entry() else {
+            return Ok(());
+        };
+        log::warn!("skipped {} in the playlist", file.name());
+        self.play_next()
+    }
+
+    fn open_current_entry(&self) -> Result<(), JsValue> {
+        self.show_playlist()?;
+        if let Some(file) = self.playlist.current_entry() {
+            (self.open_file)(file);
+        }
+        Ok(())
+    }
+
+    fn show_playlist(&self) -> Result<(), JsValue> {
+        let names = self
+            .playlist
+            .entries()
+            .iter()
+            .map(web_sys::File::name)
+            .collect::<Vec<_>>();
+        self.playlist_view
+            .set_entries(&names, self.playlist.current())
+    }
+
+    /// Encode the audio rendered by the raw synthesizer or an offline audio context as a WAV file,
+    /// through the same low-pass filter and equalizer as the playback. A limiter stands in for the compressor,
+    /// keeping the loudest passages from clipping.
+    pub fn export_wav(&self) -> Result<Option<Vec<u8>>, JsValue> {
+        let Some(
+            Playback::Raw {
+                audio: RenderedAudio::Rendered(audio_buffer),
+                ..
+            }
+            | Playback::Offline {
+                audio: RenderedAudio::Rendered(audio_buffer),
+                ..
+            },
+        ) = &self.playback
+        else {
+            return Ok(None);
+        };
+
+        let mut channels = (0..audio_buffer.number_of_channels())
+            .map(|channel| audio_buffer.get_channel_data(channel))
+            .collect::<Result<Vec<_>, _>>()?;
+        let coefficients = BiquadCoefficients::lowpass(
+            audio_buffer.sample_rate() as f64,
+            self.filter.cutoff as f64,
+            self.filter.q as f64,
+        );
+        // Bands left flat change nothing
+        let equalizer = EQ_BANDS
+            .iter()
+            .zip(self.equalizer)
+            .filter(|(_, gain)| *gain != 0.0)
+            .map(|(band, gain)| band.coefficients(audio_buffer.sample_rate() as f64, gain))
+            .collect::<Vec<_>>();
+        for channel in &mut channels {
+            Biquad::new(coefficients).process(channel);
+            for coefficients in &equalizer {
+                Biquad::new(*coefficients).process(channel);
+            }
+        }
+        if self.compressor {
+            dsp::limit(
+                &mut channels,
+                audio_buffer.sample_rate(),
+                LimiterSettings::default(),
+            );
+        }
+
+        Ok(Some(synth::wav::encode(
+            audio_buffer.sample_rate() as u32,
+            audio_buffer.length() as usize,
+            &[channels],
+            synth::wav::SampleFormat::Pcm16,
+        )))
+    }
+
+    /// Stop whatever is currently playing, regardless of the synthesizer used
+    fn stop(&mut self) -> Result<(), JsValue> {
+        self.audio_source.disconnect()?;
+
+        if let Some(scheduled_playback) = self.scheduled_playback.take() {
+            scheduled_playback.stop()?;
+        }
+        if let Some(count_in_clicks) = self.count_in_clicks.take() {
+            count_in_clicks.stop()?;
+        }
+
+        Ok(())
+    }
+
+    /// Restart the loaded file from the given position
+    fn start_at(&mut self, offset: Duration) -> Result<(), JsValue> {
+        self.start_after(offset, Duration::ZERO)
+    }
+
+    /// Restart the loaded file from the given position, counting it in first if that's the start.
+    /// Used when the playback starts anew, rather than when it's moved or looped.
+    fn start_counting_in(&mut self, offset: Duration) -> Result<(), JsValue> {
+        let count_in = self
+            .data()
+            .filter(|_| offset.is_zero() && !self.is_rendering())
+            .and_then(|data| metronome::count_in(data, self.count_in));
+        let Some((duration, clicks)) = count_in else {
+            return self.start_at(offset);
+        };
+
+        self.start_after(offset, duration)?;
+        let start = Duration::from_secs_f64(self.started_at).saturating_sub(duration);
+        self.count_in_clicks = Some(synth::web_audio::ScheduledClicks::schedule(
+            &self.audio_context,
+            &self.master,
+            self.count_in_volume,
+            clicks,
+            start,
+        )?);
+        Ok(())
+    }
+
+    /// Restart the loaded file from the given position, `delay` after the current time
+    fn start_after(&mut self, offset: Duration, delay: Duration) -> Result<(), JsValue> {
+        self.stop()?;
+        self.draw_loop.wake();
+        let now = self.audio_context.current_time();
+
+        match &mut self.playback {
+            None => return Ok(()),
+            Some(
+                Playback::Raw {
+                    audio: RenderedAudio::Rendering { start_at, .. },
+                    ..
+                }
+                | Playback::Offline {
+                    audio: RenderedAudio::Rendering { start_at, .. },
+                    ..
+                },
+            ) => {
+                // Started once the rendering is done
+                *start_at = offset;
+                return Ok(());
+            }
+            Some(
+                Playback::Raw {
+                    audio: RenderedAudio::Rendered(audio_buffer),
+                    ..
+                }
+                | Playback::Offline {
+                    audio: RenderedAudio::Rendered(audio_buffer),
+                    ..
+                },
+            ) => {
+                self.audio_source = self.audio_context.create_buffer_source()?;
+                self.audio_source.set_buffer(Some(audio_buffer));
+                self.audio_source.connect_with_audio_node(&self.master)?;
+                self.audio_source.start_with_when_and_grain_offset(
+                    now + delay.as_secs_f64(),
+                    offset.as_secs_f64(),
+                )?;
+            }
+            Some(Playback::WebAudio { synth, wave_kind }) => {
+                self.scheduled_playback = Some(synth.schedule(
+                    &self.audio_context,
+                    select_wave(*wave_kind, &self.user_waves),
+                    &self.master,
+                    offset,
+                    delay,
+                )?);
+            }
+        }
+
+        self.started_at = now + delay.as_secs_f64() - offset.as_secs_f64();
+        self.started_from = offset;
+        self.ended = false;
+        Ok(())
+    }
+
+    /// Load a new file and play it from the start, with the channels of `channel_filter` and all
+    /// tracks playing
+    pub fn set_buffer(
+        &mut self,
+        midi_data: MIDIFileData,
+        synth_kind: SynthKindOption,
+        wave_kind: WaveKindOption,
+        channel_filter: ChannelFilter,
+    ) -> Result<(), JsValue> {
+        self.channel_filter = channel_filter;
+        self.track_filter = TrackFilter::default();
+        self.loop_points = LoopPoints::default();
+        self.load(midi_data, synth_kind, wave_kind, Duration::ZERO)
+    }
+
+    fn load(
+        &mut self,
+        midi_data: MIDIFileData,
+        synth_kind: SynthKindOption,
+        wave_kind: WaveKindOption,
+        offset: Duration,
+    ) -> Result<(), JsValue> {
+        self.stop()?;
+        self.resume_context()?;
+        self.time_signatures = midi_data.time_signatures();
+
+        let playback = match synth_kind {
+            SynthKindOption::Raw => {
+                let mut synth = synth::raw::MidiSynth::new(midi_data, self.synth_settings);
+                synth.set_channel_filter(self.channel_filter.clone());
+                synth.set_track_filter(self.track_filter.clone());
+                synth.set_playback_rate(self.playback_rate);
+                synth.set_transpose(self.transpose);
+                synth.set_metronome(self.metronome);
+                self.lyrics = LyricsTimeline::new(synth.data());
+                let rendering = synth.start_rendering(self.audio_context.sample_rate() as u32);
+                self.render_progress.show(0.0);
+
+                self.duration = synth.total_duration();
+                self.end = synth.playback_duration();
+                Playback::Raw {
+                    synth,
+                    wave_kind,
+                    // Set when starting the playback below
+                    audio: RenderedAudio::Rendering {
+                        rendering,
+                        start_at: Duration::ZERO,
+                    },
+                }
+            }
+            SynthKindOption::WebAudio => {
+                self.render_progress.hide();
+                let mut synth = synth::web_audio::MidiSynth::new(midi_data, self.synth_settings);
+                synth.set_channel_filter(self.channel_filter.clone());
+                synth.set_track_filter(self.track_filter.clone());
+                synth.set_playback_rate(self.playback_rate);
+                synth.set_transpose(self.transpose);
+                synth.set_metronome(self.metronome);
+                synth.set_filter_envelope(self.filter_envelope());
+                self.lyrics = LyricsTimeline::new(synth.data());
+
+                self.duration = synth.total_duration();
+                self.end = synth.playback_duration();
+                Playback::WebAudio { synth, wave_kind }
+            }
+            SynthKindOption::Offline => {
+                let mut synth = synth::web_audio::MidiSynth::new(midi_data, self.synth_settings);
+                synth.set_channel_filter(self.channel_filter.clone());
+                synth.set_track_filter(self.track_filter.clone());
+                synth.set_playback_rate(self.playback_rate);
+                synth.set_transpose(self.transpose);
+                synth.set_metronome(self.metronome);
+                synth.set_filter_envelope(self.filter_envelope());
+                self.lyrics = LyricsTimeline::new(synth.data());
+                let rendering = OfflineRendering::start(
+                    &synth,
+                    select_wave(wave_kind, &self.user_waves),
+                    self.audio_context.sample_rate(),
+                )?;
+                self.render_progress.show(0.0);
+
+                self.duration = synth.total_duration();
+                self.end = synth.playback_duration();
+                Playback::Offline {
+                    synth,
+                    wave_kind,
+                    // Set when starting the playback below
+                    audio: RenderedAudio::Rendering {
+                        rendering,
+                        start_at: Duration::ZERO,
+                    },
+                }
+            }
+        };
+
+        self.playback = Some(playback);
+        let offset = if offset < self.duration {
+            offset
+        } else {
+            Duration::ZERO
+        };
+        self.start_counting_in(offset)?;
+
+        self.playing = true;
+        self.controls.set_enabled(true);
+        // Enabled once the raw synthesizer is done rendering
+        self.controls.set_download_enabled(false);
+        self.controls.set_playing(true);
+        self.controls.set_duration(self.duration);
+        self.controls.set_markers(&self.markers(), self.duration)?;
+        self.show_loop_points()?;
+        self.show_notes()?;
+        self.show_position(offset);
+
+        Ok(())
+    }
+}
+
+/// Mix a finished rendering of the raw synthesizer down to a stereo buffer
+fn mix_rendering(
+    audio_context: &web_sys::AudioContext,
+    synth: &synth::raw::MidiSynth,
+    rendering: synth::raw::Rendering,
+) -> Result<web_sys::AudioBuffer, JsValue> {
+    let sample_rate = audio_context.sample_rate();
+    let (buffer_length, buffers) = rendering.finish();
+
+    let channels = synth.mix_down(sample_rate as u32, buffer_length, &buffers);
+
+    let audio_buffer =
+        audio_context.create_buffer(channels.len() as u32, buffer_length as u32, sample_rate)?;
+
+    for (channel, samples) in channels.iter().enumerate() {
+        audio_buffer.copy_to_channel(samples, channel as i32)?;
+    }
+
+    Ok(audio_buffer)
+}
+
+/// Rendering of the Web Audio synthesizer by an offline audio context, which runs in the
+/// background and resolves a promise with the rendered buffer
+struct OfflineRendering {
+    context: web_sys::OfflineAudioContext,
+    /// Of the rendered audio, including the release tails of the last notes
+    duration: Duration,
+    /// Set once the promise settles
+    rendered: Rc<RefCell<Option<Result<web_sys::AudioBuffer, JsValue>>>>,
+    /// Kept alive until the promise settles
+    _on_settled: [Closure<dyn FnMut(JsValue)>; 2],
+}
+
+impl OfflineRendering {
+    /// Schedule the whole file on a new offline context, and start rendering it
+    fn start(
+        synth: &synth::web_audio::MidiSynth,
+        wave: Option<&dyn Wave>,
+        sample_rate: f32,
+    ) -> Result<Self, JsValue> {
+        let duration = synth.playback_duration();
+        let length = (duration.as_secs_f64() * sample_rate as f64)
+            .ceil()
+            .max(1.0) as u32;
+        let context =
+            web_sys::OfflineAudioContext::new_with_number_of_channels_and_length_and_sample_rate(
+                2,
+                length,
+                sample_rate,
+            )?;
+        // The nodes stay in the graph of the context, so the playback handle isn't needed
+        synth.schedule(
+            &context,
+            wave,
+            &context.destination(),
+            Duration::ZERO,
+            Duration::ZERO,
+        )?;
+
+        let rendered = Rc::new(RefCell::new(None));
+        let rendered_c = rendered.clone();
+        let on_rendered = Closure::wrap(Box::new(move |audio_buffer: JsValue| {
+            *rendered_c.borrow_mut() = Some(audio_buffer.dyn_into::<web_sys::AudioBuffer>());
+        }) as Box<dyn FnMut(JsValue)>);
+        let rendered_c = rendered.clone();
+        let on_failed = Closure::wrap(Box::new(move |error: JsValue| {
+            *rendered_c.borrow_mut() = Some(Err(error));
+        }) as Box<dyn FnMut(JsValue)>);
+        let _ = context.start_rendering()?.then2(&on_rendered, &on_failed);
+
+        Ok(Self {
+            context,
+            duration,
+            rendered,
+            _on_settled: [on_rendered, on_failed],
+        })
+    }
+
+    fn is_done(&self) -> bool {
+        self.rendered.borrow().is_some()
+    }
+
+    /// Part of the file rendered so far, from 0 to 1
+    fn progress(&self) -> f64 {
+        if self.duration.is_zero() {
+            1.0
+        } else {
+            (self.context.current_time() / self.duration.as_secs_f64()).min(1.0)
+        }
+    }
+
+    /// The rendered buffer, once [`Self::is_done`]
+    fn finish(self) -> Result<web_sys::AudioBuffer, JsValue> {
+        self.rendered
+            .take()
+            .expect("the offline rendering is not done")
+    }
+}
+
+static NOISE: WhiteNoise = WhiteNoise::new(0x5EED);
+static PULSE_QUARTER: PulseWave = PulseWave::new(0.25);
+static PULSE_EIGHTH: PulseWave = PulseWave::new(0.125);
+
+/// Waves made by the user, kept when another file is loaded
+pub(crate) struct UserWaves {
+    /// Wavetable preset played by [`WaveKindOption::Custom`]
+    pub(crate) preset: Option<CustomWave>,
+    /// Played by [`WaveKindOption::Harmonics`]
+    pub(crate) harmonics: HarmonicWave,
+}
+
+/// The wave forced for all notes, if any. Without a loaded preset, the custom wave kind falls back
+/// to the instruments.
+pub(crate) fn select_wave(wave_kind: WaveKindOption, user_waves: &UserWaves) -> Option<&dyn Wave> {
+    match wave_kind {
+        WaveKindOption::Instrument => None,
+        WaveKindOption::Sine => Some(&SineWave),
+        WaveKindOption::Square => Some(&SquareWave),
+        WaveKindOption::Sawtooth => Some(&SawtoothWave),
+        WaveKindOption::Triangle => Some(&TriangleWave),
+        WaveKindOption::PulseQuarter => Some(&PULSE_QUARTER),
+        WaveKindOption::PulseEighth => Some(&PULSE_EIGHTH),
+        WaveKindOption::Noise => Some(&NOISE),
+        WaveKindOption::Organ => Some(&OrganWave),
+        WaveKindOption::Bell => Some(&BellWave),
+        WaveKindOption::Strings => Some(&StringsWave),
+        WaveKindOption::Custom => user_waves.preset.as_ref().map(|wave| wave as &dyn Wave),
+        WaveKindOption::Harmonics => Some(&user_waves.harmonics),
+    }
+}
+
+/// Animation frame loop of the page, which stops while the player is idle until woken again
+#[derive(Clone, Default)]
+struct DrawLoop {
+    /// Called on every animation frame, set once by [`start_draw_loop`]
+    frame: Rc<OnceCell<Closure<dyn FnMut()>>>,
+    /// A frame is requested
+    running: Rc<Cell<bool>>,
+}
+
+impl DrawLoop {
+    /// Request the next frame, unless one already is
+    fn wake(&self) {
+        if let Some(frame) = self.frame.get()
+            && !self.running.replace(true)
+        {
+            log::debug!("draw loop started");
+            dom::request_animation_frame(frame);
+        }
+    }
+}
+
+/// Refresh the playback position, the highlighted lyrics and the plots on every animation frame
+/// while the player is busy. Once it is idle, the plots are cleared and the loop stops until the
+/// player wakes it.
+fn start_draw_loop(
+    player_state: Rc<RefCell<MidiPlayerState>>,
+    lyrics_display: Rc<LyricsDisplay>,
+    visualizer: Rc<RefCell<AudioVisualizer>>,
+) {
+    let draw_loop = player_state.borrow().draw_loop.clone();
+    let draw_loop_c = draw_loop.clone();
+
+    let frame = Closure::wrap(Box::new(move || {
+        if let Err(error) = player_state.borrow_mut().advance_rendering() {
+            log::error!("failed to render the file: {:?}", error);
+        }
+        if let Err(error) = player_state.borrow_mut().update_position() {
+            log::error!("failed to update the playback position: {:?}", error);
+        }
+        if let Err(error) = lyrics_display.highlight(player_state.borrow().current_lyric()) {
+            log::error!("failed to highlight lyrics: {:?}", error);
+        }
+
+        if player_state.borrow().is_idle() {
+            visualizer.borrow_mut().clear();
+            draw_loop_c.running.set(false);
+            log::debug!("draw loop stopped");
+            return;
+        }
+
+        visualizer.borrow_mut().draw();
+        dom::request_animation_frame(draw_loop_c.frame.get().unwrap());
+    }) as Box<dyn FnMut()>);
+
+    assert!(
+        draw_loop.frame.set(frame).is_ok(),
+        "the draw loop is already started"
+    );
+    draw_loop.wake();
+}
+
+#[wasm_bindgen(start)]
+pub fn main() -> Result<(), JsValue> {
+    console_error_panic_hook::set_once();
+    // Already set if the player was set up before, such as by the tests
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(log::LevelFilter::Debug);
+    }
+
+    // Pages and workers using the crate through its JavaScript API have no player to set up
+    let Some(window) = web_sys::window() else {
+        return Ok(());
+    };
+    let document = window.document().expect("should have a document on window");
+    if document.get_element_by_id("play-pause").is_none() {
+        return Ok(());
+    }
+    let _body = document.body().expect("document should have a body");
+
+    let status_panel = StatusPanel::new(&document);
+    STATUS_PANEL.set(Some(status_panel.clone()));
+
+    let settings = Rc::new(RefCell::new(Settings::load()));
+
+    let audio_context = web_sys::AudioContext::new()?;
+    let visualizer = Rc::new(RefCell::new(AudioVisualizer::new(
+        &audio_context,
+        dom::canvas(&document, "spectrum"),
+        dom::canvas(&document, "waveform"),
+        dom::canvas(&document, "meters"),
+    )?));
+    let set_fft_size = {
+        let visualizer = visualizer.clone();
+        move |fft_size| {
+            let (min_db, max_db) = plotter::DEFAULT_DECIBEL_RANGE;
+            if let Err(error) = visualizer.borrow_mut().configure(
+                fft_size,
+                plotter::DEFAULT_SMOOTHING,
+                min_db,
+                max_db,
+            ) {
+                log::error!("failed to configure the analyser: {error}");
+            }
+        }
+    };
+    let fft_size_select = FftSizeSelect::new(&document);
+    fft_size_select.set_fft_size(settings.borrow().fft_size);
+    set_fft_size(fft_size_select.fft_size());
+    let settings_c = settings.clone();
+    fft_size_select.on_change(move |fft_size| {
+        set_fft_size(fft_size);
+        let mut settings = settings_c.borrow_mut();
+        settings.fft_size = fft_size;
+        settings.save();
+    });
+
+    let log_frequency_toggle = LogFrequencyToggle::new(&document);
+    visualizer
+        .borrow_mut()
+        .set_log_frequency(log_frequency_toggle.is_log());
+    let visualizer_c = visualizer.clone();
+    log_frequency_toggle.on_change(move |log_frequency| {
+        visualizer_c.borrow_mut().set_log_frequency(log_frequency);
+    });
+
+    let peak_decay = |holding: bool| holding.then_some(plotter::DEFAULT_PEAK_DECAY);
+    let peak_hold_toggle = PeakHoldToggle::new(&document);
+    visualizer
+        .borrow_mut()
+        .set_peak_hold(peak_decay(peak_hold_toggle.is_holding()));
+    let visualizer_c = visualizer.clone();
+    peak_hold_toggle.on_change(move |holding| {
+        visualizer_c.borrow_mut().set_peak_hold(peak_decay(holding));
+    });
+
+    let averaging = |averaging: bool| averaging.then_some(plotter::DEFAULT_AVERAGING);
+    let averaging_toggle = AveragingToggle::new(&document);
+    visualizer
+        .borrow_mut()
+        .set_averaging(averaging(averaging_toggle.is_averaging()));
+    let visualizer_c = visualizer.clone();
+    averaging_toggle.on_change(move |enabled| {
+        visualizer_c.borrow_mut().set_averaging(averaging(enabled));
+    });
+
+    let player_state = Rc::new(RefCell::new(MidiPlayerState::new(
+        audio_context,
+        visualizer.borrow().input(),
+        PlaybackControls::new(&document),
+        RenderProgress::new(&document),
+        PianoRollPlotter::new(&document, dom::canvas(&document, "piano-roll"))?,
+        PlaylistView::new(&document),
+        DrawLoop::default(),
+    )?));
+    let player_state_c = player_state.clone();
+
+    let player_state_c15 = player_state.clone();
+    player_state.borrow().playlist_view.on_select(move |index| {
+        if let Err(error) = player_state_c15.borrow_mut().select_entry(index) {
+            log::error!("failed to open playlist entry: {:?}", error);
+        }
+    });
+
+    let player_state_c16 = player_state.clone();
+    player_state.borrow().playlist_view.on_previous(move || {
+        if let Err(error) = player_state_c16.borrow_mut().play_previous() {
+            log::error!("failed to open playlist entry: {:?}", error);
+        }
+    });
+
+    let player_state_c17 = player_state.clone();
+    player_state.borrow().playlist_view.on_next(move || {
+        if let Err(error) = player_state_c17.borrow_mut().play_next() {
+            log::error!("failed to open playlist entry: {:?}", error);
+        }
+    });
+
+    let volume_control = VolumeControl::new(&document);
+    volume_control.set_volume(settings.borrow().volume);
+    if let Err(error) = player_state.borrow().set_volume(volume_control.volume()) {
+        log::error!("failed to set volume: {:?}", error);
+    }
+
+    let player_state_c5 = player_state.clone();
+    let settings_c2 = settings.clone();
+    volume_control.on_volume_change(move |volume| {
+        if let Err(error) = player_state_c5.borrow().set_volume(volume) {
+            log::error!("failed to set volume: {:?}", error);
+        }
+        let mut settings = settings_c2.borrow_mut();
+        settings.volume = volume;
+        settings.save();
+    });
+
+    let reverb_control = ReverbControl::new(&document);
+    reverb_control.set_amount(settings.borrow().reverb);
+    if let Err(error) = player_state.borrow().set_reverb(reverb_control.amount()) {
+        log::error!("failed to set reverb: {:?}", error);
+    }
+
+    let player_state_c28 = player_state.clone();
+    let settings_c6 = settings.clone();
+    reverb_control.on_change(move |amount| {
+        if let Err(error) = player_state_c28.borrow().set_reverb(amount) {
+            log::error!("failed to set reverb: {:?}", error);
+        }
+        let mut settings = settings_c6.borrow_mut();
+        settings.reverb = amount;
+        settings.save();
+    });
+
+    let equalizer_controls = EqualizerControls::new(&document);
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_equalizer(equalizer_controls.gains())
+    {
+        log::error!("failed to set equalizer: {:?}", error);
+    }
+
+    let player_state_c34 = player_state.clone();
+    equalizer_controls.on_change(move |gains| {
+        if let Err(error) = player_state_c34.borrow_mut().set_equalizer(gains) {
+            log::error!("failed to set equalizer: {:?}", error);
+        }
+    });
+
+    let delay_controls = DelayControls::new(&document);
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_delay(delay_controls.settings())
+    {
+        log::error!("failed to set delay: {:?}", error);
+    }
+
+    let player_state_c29 = player_state.clone();
+    delay_controls.on_change(move |settings| {
+        if let Err(error) = player_state_c29.borrow_mut().set_delay(settings) {
+            log::error!("failed to set delay: {:?}", error);
+        }
+    });
+
+    let effects_controls = EffectsControls::new(&document);
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_filter(effects_controls.filter())
+    {
+        log::error!("failed to set filter: {:?}", error);
+    }
+
+    let player_state_c30 = player_state.clone();
+    effects_controls.on_filter_input(move |settings| {
+        if let Err(error) = player_state_c30.borrow_mut().set_filter(settings) {
+            log::error!("failed to set filter: {:?}", error);
+        }
+    });
+
+    let player_state_c31 = player_state.clone();
+    effects_controls.on_filter_change(move |settings| {
+        let mut player_state = player_state_c31.borrow_mut();
+        if let Err(error) = player_state
+            .set_filter(settings)
+            .and_then(|_| player_state.apply_filter_envelope())
+        {
+            log::error!("failed to set filter: {:?}", error);
+        }
+    });
+
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_compressor(effects_controls.compressor())
+    {
+        log::error!("failed to set compressor: {:?}", error);
+    }
+
+    let player_state_c32 = player_state.clone();
+    effects_controls.on_compressor_change(move |enabled| {
+        if let Err(error) = player_state_c32.borrow_mut().set_compressor(enabled) {
+            log::error!("failed to set compressor: {:?}", error);
+        }
+    });
+
+    let unison_controls = UnisonControls::new(&document);
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_unison(unison_controls.unison(), unison_controls.spread())
+    {
+        log::error!("failed to set unison: {:?}", error);
+    }
+
+    let player_state_c33 = player_state.clone();
+    unison_controls.on_change(move |unison, spread| {
+        if let Err(error) = player_state_c33.borrow_mut().set_unison(unison, spread) {
+            log::error!("failed to set unison: {:?}", error);
+        }
+    });
+
+    let metronome_controls = MetronomeControls::new(&document);
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_metronome(metronome_controls.volume())
+    {
+        log::error!("failed to set metronome: {:?}", error);
+    }
+
+    let player_state_c35 = player_state.clone();
+    metronome_controls.on_change(move |volume| {
+        if let Err(error) = player_state_c35.borrow_mut().set_metronome(volume) {
+            log::error!("failed to set metronome: {:?}", error);
+        }
+    });
+
+    player_state.borrow_mut().set_count_in(
+        metronome_controls.count_in(),
+        metronome_controls.click_volume(),
+    );
+    let player_state_c36 = player_state.clone();
+    metronome_controls.on_count_in_change(move |count_in, volume| {
+        player_state_c36.borrow_mut().set_count_in(count_in, volume);
+    });
+
+    let player_state_c2 = player_state.clone();
+    player_state
+        .borrow()
+        .controls
+        .on_play_pause(move |playing| {
+            if let Err(error) = player_state_c2.borrow_mut().set_playing(playing) {
+                log::error!("failed to toggle playback: {:?}", error);
+            }
+        });
+
+    let player_state_c3 = player_state.clone();
+    player_state
+        .borrow()
+        .controls
+        .on_position_change(move |position| {
+            if let Err(error) = player_state_c3.borrow_mut().seek(position) {
+                log::error!("failed to seek: {:?}", error);
+            }
+        });
+
+    let player_state_c14 = player_state.clone();
+    player_state.borrow().piano_roll.on_click(move |position| {
+        if let Err(error) = player_state_c14.borrow_mut().seek(position) {
+            log::error!("failed to seek: {:?}", error);
+        }
+    });
+
+    let player_state_c4 = player_state.clone();
+    let document_c = document.clone();
+    player_state.borrow().controls.on_download(move || {
+        let result = player_state_c4
+            .borrow()
+            .export_wav()
+            .and_then(|wav| match wav {
+                Some(wav) => dom::download_bytes(&document_c, &wav, "syntezator.wav", "audio/wav"),
+                None => Ok(()),
+            });
+
+        if let Err(error) = result {
+            log::error!("failed to download wav: {:?}", error);
+        }
+    });
+
+    let player_state_c27 = player_state.clone();
+    let document_c = document.clone();
+    player_state
+        .borrow()
+        .controls
+        .on_record_audio(move |recording| {
+            let mut player_state = player_state_c27.borrow_mut();
+            if recording {
+                if let Err(error) = player_state.start_audio_recording() {
+                    log::error!("failed to start recording audio: {:?}", error);
+                    player_state.controls.set_recording_audio(false);
+                }
+                return;
+            }
+
+            let document = document_c.clone();
+            let result = player_state.stop_audio_recording(move |blob, mime_type| {
+                let file_name = format!(
+                    "performance.{}",
+                    audio_recording::file_extension(&mime_type)
+                );
+                if let Err(error) = dom::download_blob(&document, &blob, &file_name) {
+                    log::error!("failed to download recorded audio: {:?}", error);
+                }
+            });
+            if let Err(error) = result {
+                log::error!("failed to stop recording audio: {:?}", error);
+            }
+        });
+
+    let lyrics_display = Rc::new(LyricsDisplay::new(&document));
+    start_draw_loop(player_state.clone(), lyrics_display.clone(), visualizer);
+
+    let player_state_c6 = player_state.clone();
+    let settings_c3 = settings.clone();
+    let channel_list = ChannelList::new(&document, "channels", move |channel_filter| {
+        let mut settings = settings_c3.borrow_mut();
+        settings.set_channel_filter(&channel_filter);
+        settings.save();
+        if let Err(error) = player_state_c6
+            .borrow_mut()
+            .set_channel_filter(channel_filter)
+        {
+            log::error!("failed to filter channels: {:?}", error);
+        }
+    });
+
+    let player_state_c7 = player_state.clone();
+    let track_list = TrackList::new(&document, "tracks", move |track_filter| {
+        if let Err(error) = player_state_c7.borrow_mut().set_track_filter(track_filter) {
+            log::error!("failed to filter tracks: {:?}", error);
+        }
+    });
+
+    let loop_toggle = LoopToggle::new(&document);
+    player_state
+        .borrow_mut()
+        .set_looping(loop_toggle.is_looping());
+
+    let player_state_c9 = player_state.clone();
+    loop_toggle.on_change(move |looping| {
+        player_state_c9.borrow_mut().set_looping(looping);
+    });
+
+    let loop_region_controls = LoopRegionControls::new(&document);
+    let player_state_c37 = player_state.clone();
+    loop_region_controls.on_set(move |point| {
+        if let Err(error) = player_state_c37.borrow_mut().set_loop_point(point) {
+            log::error!("failed to set loop point: {:?}", error);
+        }
+    });
+
+    let player_state_c38 = player_state.clone();
+    loop_region_controls.on_clear(move || {
+        if let Err(error) = player_state_c38.borrow_mut().clear_loop_points() {
+            log::error!("failed to clear loop points: {:?}", error);
+        }
+    });
+
+    let rate_control = RateControl::new(&document);
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_playback_rate(rate_control.rate())
+    {
+        log::error!("failed to change the playback rate: {:?}", error);
+    }
+
+    let player_state_c10 = player_state.clone();
+    rate_control.on_rate_change(move |rate| {
+        if let Err(error) = player_state_c10.borrow_mut().set_playback_rate(rate) {
+            log::error!("failed to change the playback rate: {:?}", error);
+        }
+    });
+
+    let transpose_control = TransposeControl::new(&document);
+    if let Err(error) = player_state
+        .borrow_mut()
+        .set_transpose(transpose_control.semitones())
+    {
+        log::error!("failed to transpose: {:?}", error);
+    }
+
+    let player_state_c11 = player_state.clone();
+    transpose_control.on_change(move |semitones| {
+        if let Err(error) = player_state_c11.borrow_mut().set_transpose(semitones) {
+            log::error!("failed to transpose: {:?}", error);
+        }
+    });
+
+    let synth_kind = SynthKind::new(&document);
+    let wave_kind = Rc::new(WaveKind::new(&document));
+    synth_kind.select(settings.borrow().synth_kind);
+    wave_kind.select(settings.borrow().wave_kind);
+
+    let player_state_c8 = player_state.clone();
+    let wave_kind_c = wave_kind.clone();
+    let settings_c4 = settings.clone();
+    synth_kind.on_change(move |synth_kind| {
+        if let Err(error) = player_state_c8
+            .borrow_mut()
+            .set_synth_kind(synth_kind, wave_kind_c.get_selected())
+        {
+            log::error!("failed to switch synthesizer: {:?}", error);
+        }
+        let mut settings = settings_c4.borrow_mut();
+        settings.synth_kind = synth_kind;
+        settings.save();
+    });
+
+    let player_state_c21 = player_state.clone();
+    let settings_c5 = settings.clone();
+    wave_kind.on_change(move |wave_kind| {
+        if let Err(error) = player_state_c21.borrow_mut().set_wave_kind(wave_kind) {
+            log::error!("failed to switch wave: {:?}", error);
+        }
+        let mut settings = settings_c5.borrow_mut();
+        settings.wave_kind = wave_kind;
+        settings.save();
+    });
+
+    let player_state_c12 = player_state.clone();
+    let wave_kind_c2 = wave_kind.clone();
+    let _wave_preset = WavePresetInput::new(
+        &document,
+        move |custom_wave| {
+            wave_kind_c2.select(WaveKindOption::Custom);
+            if let Err(error) = player_state_c12.borrow_mut().set_custom_wave(custom_wave) {
+                log::error!("failed to play wavetable preset: {:?}", error);
+            }
+        },
+        |error| {
+            log::error!("invalid wavetable preset supplied: {:?}", error);
+        },
+    );
+
+    let player_state_c13 = player_state.clone();
+    let wave_kind_c3 = wave_kind.clone();
+    let _harmonic_editor = HarmonicEditor::new(&document, move |amplitudes| {
+        wave_kind_c3.select(WaveKindOption::Harmonics);
+        if let Err(error) = player_state_c13
+            .borrow_mut()
+            .set_harmonic_wave(HarmonicWave::new(amplitudes))
+        {
+            log::error!("failed to play harmonics: {:?}", error);
+        }
+    })?;
+
+    let player_state_c22 = player_state.clone();
+    let player_state_c23 = player_state.clone();
+    let wave_kind_c4 = wave_kind.clone();
+    PianoKeyboard::new(&document)?.on_play(
+        move |key| {
+            if let Err(error) = player_state_c22
+                .borrow_mut()
+                .press_key(key, wave_kind_c4.get_selected())
+            {
+                log::error!("failed to play key: {:?}", error);
+            }
+        },
+        move |key| {
+            if let Err(error) = player_state_c23.borrow_mut().release_key(key) {
+                log::error!("failed to release key: {:?}", error);
+            }
+        },
+    );
+
+    let player_state_c24 = player_state.clone();
+    let wave_kind_c5 = wave_kind.clone();
+    MidiDeviceSelect::new(&document).on_event(move |event| {
+        if let Err(error) = player_state_c24
+            .borrow_mut()
+            .play_device_event(&event, wave_kind_c5.get_selected())
+        {
+            log::error!("failed to play MIDI event: {:?}", error);
+        }
+    });
+
+    let track_info_panel = TrackInfoPanel::new(&document);
+    let statistics_table = StatisticsTable::new(&document);
+
+    let player_state_c18 = player_state.clone();
+    let player_state_c19 = player_state.clone();
+    let midi_input = Rc::new(dom::MidiInput::new(
+        &document,
+        move |files| {
+            if let Err(error) = player_state_c18.borrow_mut().set_playlist(files) {
+                log::error!("failed to open playlist: {:?}", error);
+            }
+        },
+        move |midi_data| {
+            log::info!("midi file uploaded! tracks: {}", midi_data.num_tracks());
+            for track in midi_data.tracks() {
+                log::info!("track with {} events", track.events().len())
+            }
+            if let Err(error) = statistics_table.set_statistics(&Statistics::from(&midi_data)) {
+                log::error!("failed to show statistics: {:?}", error);
+            }
+
+            let channel_filter = settings.borrow().channel_filter();
+            if let Err(error) = player_state_c.borrow_mut().set_buffer(
+                midi_data,
+                synth_kind.get_selected(),
+                wave_kind.get_selected(),
+                channel_filter.clone(),
+            ) {
+                log::error!("invalid midi file supplied: {:?}", error);
+            } else {
+                let player_state = player_state_c.borrow();
+                status_panel.info(&format!(
+                    "Loaded a file with {} tracks",
+                    player_state.track_names().len()
+                ));
+                if let Err(error) =
+                    channel_list.set_channels(&player_state.channels(), &channel_filter)
+                {
+                    log::error!("failed to list channels: {:?}", error);
+                }
+                if let Err(error) = track_list.set_tracks(&player_state.track_names()) {
+                    log::error!("failed to list tracks: {:?}", error);
+                }
+                if let Err(error) = lyrics_display.set_lyrics(player_state.lyrics()) {
+                    log::error!("failed to show lyrics: {:?}", error);
+                }
+                if let Err(error) = track_info_panel.set_tracks(
+                    &player_state.track_infos(),
+                    player_state.copyright().as_deref(),
+                ) {
+                    log::error!("failed to show track info: {:?}", error);
+                }
+            }
+        },
+        move |error| {
+            log::error!("invalid midi file supplied: {}", error);
+            // A broken file doesn't stop the playlist
+            if let Err(error) = player_state_c19.borrow_mut().skip_entry() {
+                log::error!("failed to open playlist entry: {:?}", error);
+            }
+        },
+    ));
+
+    let midi_input_c2 = midi_input.clone();
+    player_state
+        .borrow_mut()
+        .on_open_file(move |file| midi_input_c2.read_file(file));
+
+    let midi_url_input = MidiUrlInput::new(&document);
+    let midi_input_c = midi_input.clone();
+    let player_state_c20 = player_state.clone();
+    let load_url = move |url: &str| {
+        // Links are played on their own
+        if let Err(error) = player_state_c20.borrow_mut().set_playlist(vec![]) {
+            log::error!("failed to clear playlist: {:?}", error);
+        }
+        midi_input_c.load_url(url);
+    };
+    // Shared links load their file right away
+    if let Some(url) = dom::query_parameter("midi") {
+        midi_url_input.set_url(&url);
+        load_url(&url);
+    }
+    midi_url_input.on_load(move |url| load_url(&url));
+
+    let recording_controls = Rc::new(RecordingControls::new(&document));
+    // Serialized file of the last recording
+    let recorded = Rc::new(RefCell::new(Vec::<u8>::new()));
+
+    let player_state_c25 = player_state.clone();
+    let recording_controls_c = recording_controls.clone();
+    let recorded_c = recorded.clone();
+    recording_controls.on_record(move |recording| {
+        let mut player_state = player_state_c25.borrow_mut();
+        if recording {
+            if let Err(error) = player_state.start_recording() {
+                log::error!("failed to start recording: {:?}", error);
+            }
+        } else if let Some(data) = player_state.stop_recording() {
+            *recorded_c.borrow_mut() = data.to_bytes();
+            recording_controls_c.set_recorded(true);
+        } else {
+            log::warn!("nothing was played while recording");
+        }
+    });
+
+    let player_state_c26 = player_state.clone();
+    let recorded_c = recorded.clone();
+    recording_controls.on_play(move || {
+        // Recordings are played on their own
+        if let Err(error) = player_state_c26.borrow_mut().set_playlist(vec![]) {
+            log::error!("failed to clear playlist: {:?}", error);
+        }
+        midi_input.load_bytes(&recorded_c.borrow());
+    });
+
+    recording_controls.on_download(move || {
+        if let Err(error) =
+            dom::download_bytes(&document, &recorded.borrow(), "recording.mid", "audio/midi")
+        {
+            log::error!("failed to download recording: {:?}", error);
+        }
+    });
+
+    Ok(())
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn settings_are_restored_and_saved_by_the_page() {
+        Settings {
+            wave_kind: WaveKindOption::Organ,
+            volume: 0.25,
+            fft_size: 8192,
+            ..Settings::default()
+        }
+        .save();
+
+        let document = web_sys::window().unwrap().document().unwrap();
+        document
+            .body()
+            .unwrap()
+            .set_inner_html(include_str!("../index.html"));
+        main().unwrap();
+
+        let fft_size_select = FftSizeSelect::new(&document);
+        assert_eq!(fft_size_select.fft_size(), 8192);
+        assert_eq!(VolumeControl::new(&document).volume(), 0.25);
+        assert_eq!(
+            WaveKind::new(&document).get_selected(),
+            WaveKindOption::Organ
+        );
+
+        // Choosing another size stores it
+        fft_size_select.set_fft_size(512);
+        document
+            .get_element_by_id("fft-size")
+            .unwrap()
+            .dispatch_event(&web_sys::Event::new("change").unwrap())
+            .unwrap();
+        let settings = Settings::load();
+        assert_eq!(settings.fft_size, 512);
+        assert_eq!(settings.volume, 0.25);
+
+        Settings::default().save();
+    }
+}
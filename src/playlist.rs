@@ -0,0 +1,91 @@
+//! Queue of files played one after another.
+
+/// Entries played in order, with the one playing or about to play as the current one.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Playlist<T> {
+    entries: Vec<T>,
+    current: Option<usize>,
+}
+
+impl<T> Default for Playlist<T> {
+    fn default() -> Self {
+        Self {
+            entries: vec![],
+            current: None,
+        }
+    }
+}
+
+impl<T> Playlist<T> {
+    /// Queue `entries`, none of them current yet
+    pub fn new(entries: Vec<T>) -> Self {
+        Self {
+            entries,
+            current: None,
+        }
+    }
+
+    pub fn entries(&self) -> &[T] {
+        &self.entries
+    }
+
+    /// Index of the current entry
+    pub fn current(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn current_entry(&self) -> Option<&T> {
+        self.entries.get(self.current?)
+    }
+
+    /// Make the entry at `index` current, unless there is none
+    pub fn select(&mut self, index: usize) -> Option<&T> {
+        let entry = self.entries.get(index)?;
+        self.current = Some(index);
+        Some(entry)
+    }
+
+    /// Move on to the entry after the current one, or to the first one if none is current. Stays
+    /// at the last entry.
+    pub fn advance(&mut self) -> Option<&T> {
+        self.select(self.current.map_or(0, |current| current + 1))
+    }
+
+    /// Move back to the entry before the current one. Stays at the first entry.
+    pub fn previous(&mut self) -> Option<&T> {
+        self.select(self.current?.checked_sub(1)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn playlists_advance_and_stop_at_their_ends() {
+        let mut playlist = Playlist::new(vec!["a", "b", "c"]);
+        assert_eq!(playlist.current(), None);
+        assert_eq!(playlist.previous(), None);
+
+        assert_eq!(playlist.advance(), Some(&"a"));
+        assert_eq!(playlist.advance(), Some(&"b"));
+        assert_eq!(playlist.advance(), Some(&"c"));
+        assert_eq!(playlist.advance(), None);
+        assert_eq!(playlist.current(), Some(2));
+        assert_eq!(playlist.current_entry(), Some(&"c"));
+
+        assert_eq!(playlist.previous(), Some(&"b"));
+        assert_eq!(playlist.previous(), Some(&"a"));
+        assert_eq!(playlist.previous(), None);
+        assert_eq!(playlist.current(), Some(0));
+
+        assert_eq!(playlist.select(2), Some(&"c"));
+        assert_eq!(playlist.select(3), None);
+        assert_eq!(playlist.current(), Some(2));
+
+        let mut empty = Playlist::<&str>::default();
+        assert_eq!(empty.advance(), None);
+        assert_eq!(empty.current(), None);
+        assert_eq!(empty.current_entry(), None);
+    }
+}
@@ -0,0 +1,1260 @@
+//! Plots of the audio being played, drawn onto canvases from an analyser node on the master bus,
+//! and of the notes of the loaded file.
+use std::{cell::Cell, fmt, ops::Range, rc::Rc, time::Duration};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    midi::{gm, notes::Note},
+    synth::percussion::PERCUSSION_CHANNEL,
+};
+
+/// FFT size of the analyser unless configured otherwise
+pub const DEFAULT_FFT_SIZE: u32 = 2048;
+/// Smoothing of the spectrum between frames unless configured otherwise, in [0.0; 1.0]
+pub const DEFAULT_SMOOTHING: f64 = 0.8;
+/// Range of the spectrum plot unless configured otherwise, in dB
+pub const DEFAULT_DECIBEL_RANGE: (f64, f64) = (-100.0, -30.0);
+/// Speed at which held peaks of the spectrum fall unless configured otherwise, in dB per second
+pub const DEFAULT_PEAK_DECAY: f32 = 20.0;
+/// Weight of the previous frames when averaging the spectrum unless configured otherwise,
+/// in [0.0; 1.0)
+pub const DEFAULT_AVERAGING: f32 = 0.7;
+
+/// Bounds of the FFT sizes accepted by analyser nodes
+const FFT_SIZE_RANGE: (u32, u32) = (32, 32768);
+
+const PLOT_COLOR: &str = "#0172ad";
+const PEAK_COLOR: &str = "#d93526";
+const GRID_COLOR: &str = "rgba(128, 128, 128, 0.4)";
+/// Height of the text labeling the drum lanes of the piano roll, in pixels
+const DRUM_LABEL_SIZE: f64 = 9.0;
+
+/// Range of the level meters, in dBFS
+const METER_RANGE: (f32, f32) = (-60.0, 0.0);
+/// Samples the level meters are measured over, about 20 ms at usual sample rates
+const METER_WINDOW: u32 = 1024;
+
+/// Lowest frequency of a logarithmic axis, of A0, the lowest key of a piano
+const LOG_AXIS_MIN_FREQUENCY: f32 = 27.5;
+
+/// Analyser settings rejected by [`AudioVisualizer::configure`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum AnalyserSettingsError {
+    /// Not a power of two from 32 to 32768
+    FftSize(u32),
+    /// Not in [0.0; 1.0]
+    Smoothing(f64),
+    /// The minimum isn't below the maximum
+    DecibelRange(f64, f64),
+}
+
+impl fmt::Display for AnalyserSettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnalyserSettingsError::FftSize(fft_size) => {
+                write!(
+                    f,
+                    "FFT size {fft_size} is not a power of two from 32 to 32768"
+                )
+            }
+            AnalyserSettingsError::Smoothing(smoothing) => {
+                write!(f, "Smoothing {smoothing} is not between 0 and 1")
+            }
+            AnalyserSettingsError::DecibelRange(min_db, max_db) => {
+                write!(
+                    f,
+                    "Minimum of {min_db} dB is not below the maximum of {max_db} dB"
+                )
+            }
+        }
+    }
+}
+
+/// Check settings before they are applied, since the analyser throws on invalid ones
+fn check_analyser_settings(
+    fft_size: u32,
+    smoothing: f64,
+    min_db: f64,
+    max_db: f64,
+) -> Result<(), AnalyserSettingsError> {
+    if !fft_size.is_power_of_two() || fft_size < FFT_SIZE_RANGE.0 || fft_size > FFT_SIZE_RANGE.1 {
+        return Err(AnalyserSettingsError::FftSize(fft_size));
+    }
+    if !(0.0..=1.0).contains(&smoothing) {
+        return Err(AnalyserSettingsError::Smoothing(smoothing));
+    }
+    if min_db.is_nan() || max_db.is_nan() || min_db >= max_db {
+        return Err(AnalyserSettingsError::DecibelRange(min_db, max_db));
+    }
+    Ok(())
+}
+
+/// Spectrum and waveform of the audio passing through its analyser, and levels of its left and
+/// right channels
+pub struct AudioVisualizer {
+    analyser: web_sys::AnalyserNode,
+    /// Of the left and right channels, split from the output of the combined analyser
+    channel_analysers: [web_sys::AnalyserNode; 2],
+    /// Samples of the last meter window of a channel
+    channel_data: Vec<f32>,
+    /// Level of every frequency bin in dB, resized along with the FFT
+    freq_data: Vec<f32>,
+    /// Samples of the last FFT window
+    time_data: Vec<f32>,
+    /// Range of the spectrum plot, in dB
+    decibel_range: (f32, f32),
+    sample_rate: f32,
+    /// Context time of the last frame drawn, in seconds
+    last_draw: f64,
+    spectrum: BarPlotter,
+    waveform: LinePlotter,
+    meters: MeterPlotter,
+    /// Fits the canvases to their size on the page whenever it changes
+    _resize_observer: web_sys::ResizeObserver,
+    _on_resize: Closure<dyn FnMut()>,
+}
+
+impl AudioVisualizer {
+    /// Create an analyser passing its input through to the destination of `audio_context`. Its
+    /// output is also split into channels, each measured by an analyser of its own.
+    pub fn new(
+        audio_context: &web_sys::AudioContext,
+        spectrum: web_sys::HtmlCanvasElement,
+        waveform: web_sys::HtmlCanvasElement,
+        meters: web_sys::HtmlCanvasElement,
+    ) -> Result<Self, JsValue> {
+        let analyser = audio_context.create_analyser()?;
+        analyser.connect_with_audio_node(&audio_context.destination())?;
+
+        let splitter = audio_context.create_channel_splitter_with_number_of_outputs(2)?;
+        analyser.connect_with_audio_node(&splitter)?;
+        let channel_analyser = |output| -> Result<web_sys::AnalyserNode, JsValue> {
+            let channel_analyser = audio_context.create_analyser()?;
+            channel_analyser.set_fft_size(METER_WINDOW);
+            splitter.connect_with_audio_node_and_output(&channel_analyser, output)?;
+            Ok(channel_analyser)
+        };
+        let channel_analysers = [channel_analyser(0)?, channel_analyser(1)?];
+
+        let canvases = [spectrum.clone(), waveform.clone(), meters.clone()];
+        let on_resize = {
+            let canvases = canvases.clone();
+            Closure::wrap(Box::new(move || {
+                // Zooming the page changes the ratio, and also resizes the canvases
+                let device_pixel_ratio = web_sys::window()
+                    .expect("no global `window` exists")
+                    .device_pixel_ratio();
+                for canvas in &canvases {
+                    fit_canvas(canvas, device_pixel_ratio);
+                }
+            }) as Box<dyn FnMut()>)
+        };
+        let resize_observer = web_sys::ResizeObserver::new(on_resize.as_ref().unchecked_ref())?;
+        // Observing a canvas also fits it right away
+        for canvas in &canvases {
+            resize_observer.observe(canvas);
+        }
+
+        let sample_rate = audio_context.sample_rate();
+        let mut visualizer = Self {
+            analyser,
+            channel_analysers,
+            channel_data: vec![0.0; METER_WINDOW as usize],
+            freq_data: vec![],
+            time_data: vec![],
+            decibel_range: (0.0, 0.0),
+            sample_rate,
+            last_draw: audio_context.current_time(),
+            spectrum: BarPlotter::new(spectrum, FrequencyAxis::Log { sample_rate })?,
+            waveform: LinePlotter::new(waveform)?,
+            meters: MeterPlotter::new(meters)?,
+            _resize_observer: resize_observer,
+            _on_resize: on_resize,
+        };
+        let (min_db, max_db) = DEFAULT_DECIBEL_RANGE;
+        visualizer
+            .configure(DEFAULT_FFT_SIZE, DEFAULT_SMOOTHING, min_db, max_db)
+            .expect("default analyser settings are valid");
+        Ok(visualizer)
+    }
+
+    /// Node the audio to visualize is connected to
+    pub fn input(&self) -> &web_sys::AudioNode {
+        &self.analyser
+    }
+
+    /// Change the FFT size, which must be a power of two from 32 to 32768, the smoothing of the
+    /// spectrum between frames, in [0.0; 1.0], and the range of the spectrum plot in dB.
+    ///
+    /// Nothing is changed if any of the settings is invalid.
+    pub fn configure(
+        &mut self,
+        fft_size: u32,
+        smoothing: f64,
+        min_db: f64,
+        max_db: f64,
+    ) -> Result<(), AnalyserSettingsError> {
+        check_analyser_settings(fft_size, smoothing, min_db, max_db)?;
+
+        self.analyser.set_fft_size(fft_size);
+        self.analyser.set_smoothing_time_constant(smoothing);
+        // The minimum must stay below the maximum after each call
+        if min_db < self.analyser.max_decibels() {
+            self.analyser.set_min_decibels(min_db);
+            self.analyser.set_max_decibels(max_db);
+        } else {
+            self.analyser.set_max_decibels(max_db);
+            self.analyser.set_min_decibels(min_db);
+        }
+
+        self.freq_data
+            .resize(self.analyser.frequency_bin_count() as usize, 0.0);
+        self.time_data.resize(fft_size as usize, 0.0);
+        self.decibel_range = (min_db as f32, max_db as f32);
+        Ok(())
+    }
+
+    /// Space the spectrum by octaves rather than by hertz
+    pub fn set_log_frequency(&mut self, log_frequency: bool) {
+        self.spectrum.set_axis(if log_frequency {
+            FrequencyAxis::Log {
+                sample_rate: self.sample_rate,
+            }
+        } else {
+            FrequencyAxis::Linear
+        });
+    }
+
+    /// Mark the loudest level of every bar of the spectrum, falling by `decay` dB per second, or
+    /// stop marking them when `None`
+    pub fn set_peak_hold(&mut self, decay: Option<f32>) {
+        self.spectrum.set_peak_hold(decay);
+    }
+
+    /// Average the spectrum over frames, with `averaging` as the weight of the previous ones in
+    /// [0.0; 1.0), or plot every frame as it is when `None`
+    pub fn set_averaging(&mut self, averaging: Option<f32>) {
+        self.spectrum.set_averaging(averaging);
+    }
+
+    /// Read the analyser and redraw the plots
+    pub fn draw(&mut self) {
+        self.analyser.get_float_frequency_data(&mut self.freq_data);
+        self.analyser
+            .get_float_time_domain_data(&mut self.time_data);
+
+        // Context time stands still while the playback is paused, and so do the plots
+        let now = self.analyser.context().current_time();
+        let elapsed = (now - self.last_draw).max(0.0) as f32;
+        self.last_draw = now;
+
+        let (min_db, max_db) = self.decibel_range;
+        self.spectrum.plot(&self.freq_data, min_db, max_db, elapsed);
+        self.waveform.plot(&self.time_data, -1.0, 1.0);
+
+        let levels = self.channel_analysers.each_ref().map(|analyser| {
+            analyser.get_float_time_domain_data(&mut self.channel_data);
+            Level::measure(&self.channel_data)
+        });
+        self.meters.plot(&levels, elapsed);
+    }
+
+    /// Redraw the plots flat, as if the audio went silent, forgetting the held peaks and averages
+    pub fn clear(&mut self) {
+        self.freq_data.fill(f32::NEG_INFINITY);
+        self.time_data.fill(0.0);
+        self.last_draw = self.analyser.context().current_time();
+
+        let (min_db, max_db) = self.decibel_range;
+        self.spectrum.reset();
+        self.spectrum.plot(&self.freq_data, min_db, max_db, 0.0);
+        self.waveform.plot(&self.time_data, -1.0, 1.0);
+        self.meters.reset();
+        self.meters.plot(&[Level::SILENCE; 2], 0.0);
+    }
+}
+
+/// Size of the backing store of a canvas laid out `client_size` CSS pixels large, one pixel for
+/// every pixel of a display with `device_pixel_ratio`
+fn backing_size(client_size: i32, device_pixel_ratio: f64) -> u32 {
+    (client_size.max(0) as f64 * device_pixel_ratio).round() as u32
+}
+
+/// Fit the backing store of `canvas` to its size on the page, so that its plots are crisp on high
+/// density displays and are neither clipped nor stretched after it is resized
+fn fit_canvas(canvas: &web_sys::HtmlCanvasElement, device_pixel_ratio: f64) {
+    let width = backing_size(canvas.client_width(), device_pixel_ratio);
+    let height = backing_size(canvas.client_height(), device_pixel_ratio);
+    // Setting the size clears the canvas even when it doesn't change
+    if width > 0 && width != canvas.width() {
+        canvas.set_width(width);
+    }
+    if height > 0 && height != canvas.height() {
+        canvas.set_height(height);
+    }
+}
+
+/// Clear `canvas` and scale `context` so that it draws in CSS pixels. Returns the size of the
+/// canvas in CSS pixels.
+///
+/// The scale is taken from the canvas as it is now, so plots stay right whether or not its backing
+/// store was fitted since it was resized.
+fn begin_plot(
+    canvas: &web_sys::HtmlCanvasElement,
+    context: &web_sys::CanvasRenderingContext2d,
+) -> (f64, f64) {
+    let (backing_width, backing_height) = (canvas.width() as f64, canvas.height() as f64);
+    let (width, height) = match (canvas.client_width(), canvas.client_height()) {
+        // Not laid out, such as when hidden
+        (0, _) | (_, 0) => (backing_width, backing_height),
+        (width, height) => (width as f64, height as f64),
+    };
+
+    let _ = context.set_transform(
+        backing_width / width,
+        0.0,
+        0.0,
+        backing_height / height,
+        0.0,
+        0.0,
+    );
+    context.clear_rect(0.0, 0.0, width, height);
+    (width, height)
+}
+
+fn context_2d(
+    canvas: &web_sys::HtmlCanvasElement,
+) -> Result<web_sys::CanvasRenderingContext2d, JsValue> {
+    canvas
+        .get_context("2d")?
+        .ok_or_else(|| JsValue::from_str("canvas has no 2d context"))?
+        .dyn_into()
+        .map_err(JsValue::from)
+}
+
+/// Height of a bar for `value` on a plot from `min` to `max`, clamped to the plot
+fn bar_height(value: f32, min: f32, max: f32, height: f64) -> f64 {
+    let fraction = ((value - min) / (max - min)).clamp(0.0, 1.0);
+    // Silent bins are -inf dB, which the clamp maps to 0, but NaN goes through
+    if fraction.is_nan() {
+        0.0
+    } else {
+        fraction as f64 * height
+    }
+}
+
+/// Spacing of the bins of a spectrum, which are evenly spaced from 0 Hz to the Nyquist frequency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrequencyAxis {
+    /// A bar per bin
+    Linear,
+    /// Octaves of equal width from A0 to the Nyquist frequency, so low notes get as much room as
+    /// high ones
+    Log { sample_rate: f32 },
+}
+
+/// Position of `frequency` on a logarithmic axis from A0 to `max_frequency`, in [0.0; 1.0] for
+/// frequencies within it
+fn log_axis_position(frequency: f32, max_frequency: f32) -> f32 {
+    (frequency / LOG_AXIS_MIN_FREQUENCY).log2() / (max_frequency / LOG_AXIS_MIN_FREQUENCY).log2()
+}
+
+/// Frequency at `position` on a logarithmic axis from A0 to `max_frequency`
+fn log_axis_frequency(position: f32, max_frequency: f32) -> f32 {
+    LOG_AXIS_MIN_FREQUENCY * (max_frequency / LOG_AXIS_MIN_FREQUENCY).powf(position)
+}
+
+/// Values of the evenly spaced bins from 0 Hz to `max_frequency`, spread over `columns` of a
+/// logarithmic axis. Columns covering several bins take the highest of them, and columns between
+/// two bins are interpolated.
+fn log_axis_columns(bins: &[f32], max_frequency: f32, columns: usize) -> Vec<f32> {
+    let Some(last_bin) = bins.len().checked_sub(1) else {
+        return vec![];
+    };
+    let bin_at = |column: usize| {
+        log_axis_frequency(column as f32 / columns as f32, max_frequency) / max_frequency
+            * bins.len() as f32
+    };
+
+    (0..columns)
+        .map(|column| {
+            let (from, to) = (bin_at(column), bin_at(column + 1));
+            let covered =
+                (from.ceil() as usize).min(last_bin)..(to.ceil() as usize).min(bins.len());
+            if let Some(highest) = bins[covered].iter().copied().reduce(f32::max) {
+                return highest;
+            }
+
+            let middle = (from + to) / 2.0;
+            let below = (middle as usize).min(last_bin);
+            let above = (below + 1).min(last_bin);
+            let (low, high) = (bins[below], bins[above]);
+            if low == high {
+                // Also avoids interpolating between silent bins of -inf dB
+                low
+            } else {
+                low + (high - low) * (middle - below as f32)
+            }
+        })
+        .collect()
+}
+
+/// Frequencies of the C notes within a logarithmic axis up to `max_frequency`, which start the
+/// octaves, along with their names
+fn octave_boundaries(max_frequency: f32) -> Vec<(f32, String)> {
+    (1..)
+        // C1 is MIDI note 24, and A4 is 440 Hz
+        .map(|octave| {
+            (
+                octave,
+                440.0 * 2f32.powf((12.0 * (octave + 1) as f32 - 69.0) / 12.0),
+            )
+        })
+        .take_while(|(_, frequency)| *frequency < max_frequency)
+        .map(|(octave, frequency)| (frequency, format!("C{octave}")))
+        .collect()
+}
+
+/// Plots every value as a vertical bar, such as the bins of a spectrum. The bars share the width
+/// of the canvas, so the number of values may change between plots.
+pub struct BarPlotter {
+    canvas: web_sys::HtmlCanvasElement,
+    context: web_sys::CanvasRenderingContext2d,
+    axis: FrequencyAxis,
+    /// Fall of the held peaks in units per second, if they are held
+    peak_decay: Option<f32>,
+    /// Weight of the previous frames in the averaged bars, if they are averaged
+    averaging: Option<f32>,
+    /// Held peak of every bar, or nothing until the next plot
+    peaks: Vec<f32>,
+    /// Averaged height of every bar, or nothing until the next plot
+    averages: Vec<f32>,
+}
+
+impl BarPlotter {
+    pub fn new(canvas: web_sys::HtmlCanvasElement, axis: FrequencyAxis) -> Result<Self, JsValue> {
+        let context = context_2d(&canvas)?;
+        Ok(Self {
+            canvas,
+            context,
+            axis,
+            peak_decay: None,
+            averaging: None,
+            peaks: vec![],
+            averages: vec![],
+        })
+    }
+
+    pub fn set_axis(&mut self, axis: FrequencyAxis) {
+        self.axis = axis;
+    }
+
+    pub fn set_peak_hold(&mut self, decay: Option<f32>) {
+        self.peak_decay = decay;
+        self.peaks.clear();
+    }
+
+    pub fn set_averaging(&mut self, averaging: Option<f32>) {
+        self.averaging = averaging;
+        self.averages.clear();
+    }
+
+    /// Forget the held peaks and averages
+    pub fn reset(&mut self) {
+        self.peaks.clear();
+        self.averages.clear();
+    }
+
+    /// Replace the plot with `values`, scaled from `min` at the bottom to `max` at the top.
+    /// `elapsed` is the time since the last plot in seconds, over which held peaks fall.
+    pub fn plot(&mut self, values: &[f32], min: f32, max: f32, elapsed: f32) {
+        let (width, height) = begin_plot(&self.canvas, &self.context);
+        if values.is_empty() {
+            return;
+        }
+
+        let columns;
+        let values = match self.axis {
+            FrequencyAxis::Linear => values,
+            FrequencyAxis::Log { sample_rate } => {
+                let nyquist = sample_rate / 2.0;
+                self.draw_octave_grid(nyquist, width, height);
+                columns = log_axis_columns(values, nyquist, width as usize);
+                &columns
+            }
+        };
+
+        let values = match self.averaging {
+            None => values,
+            Some(averaging) => {
+                // Levels below the plot may be infinitely low, which would never average out
+                average_bars(&mut self.averages, values, averaging, min);
+                &self.averages
+            }
+        };
+
+        let bar_width = width / values.len() as f64;
+        self.context.set_fill_style_str(PLOT_COLOR);
+        for (index, value) in values.iter().enumerate() {
+            let bar_height = bar_height(*value, min, max, height);
+            self.context.fill_rect(
+                index as f64 * bar_width,
+                height - bar_height,
+                bar_width,
+                bar_height,
+            );
+        }
+
+        if let Some(decay) = self.peak_decay {
+            hold_peaks(&mut self.peaks, values, decay, elapsed);
+            self.context.set_fill_style_str(PEAK_COLOR);
+            for (index, peak) in self.peaks.iter().enumerate() {
+                let bar_height = bar_height(*peak, min, max, height);
+                if bar_height > 0.0 {
+                    self.context.fill_rect(
+                        index as f64 * bar_width,
+                        height - bar_height,
+                        bar_width,
+                        1.0,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Faint lines where octaves start on a logarithmic axis, labeled with their C note
+    fn draw_octave_grid(&self, max_frequency: f32, width: f64, height: f64) {
+        self.context.set_stroke_style_str(GRID_COLOR);
+        self.context.set_fill_style_str(GRID_COLOR);
+        self.context.set_font("10px sans-serif");
+        self.context.begin_path();
+        for (frequency, name) in octave_boundaries(max_frequency) {
+            let x = (log_axis_position(frequency, max_frequency) as f64 * width).round() + 0.5;
+            self.context.move_to(x, 0.0);
+            self.context.line_to(x, height);
+            let _ = self.context.fill_text(&name, x + 2.0, 10.0);
+        }
+        self.context.stroke();
+    }
+}
+
+/// Peak after falling by `decay` units per second for `elapsed` seconds, unless `value` rises
+/// above it
+fn decay_peak(peak: f32, value: f32, decay: f32, elapsed: f32) -> f32 {
+    value.max(peak - decay * elapsed)
+}
+
+/// Move every held peak of `peaks` to the bars of `values`. Peaks are reset when the number of
+/// bars changes, such as with the FFT size.
+fn hold_peaks(peaks: &mut Vec<f32>, values: &[f32], decay: f32, elapsed: f32) {
+    if peaks.len() != values.len() {
+        peaks.clear();
+        peaks.extend_from_slice(values);
+        return;
+    }
+
+    for (peak, value) in peaks.iter_mut().zip(values) {
+        *peak = decay_peak(*peak, *value, decay, elapsed);
+    }
+}
+
+/// Exponential moving average of a bar, with `averaging` as the weight of the `previous` frames
+fn average(previous: f32, value: f32, averaging: f32) -> f32 {
+    averaging * previous + (1.0 - averaging) * value
+}
+
+/// Average every bar of `averages` with the bars of `values`, raised to at least `floor`.
+/// Averages are reset when the number of bars changes, such as with the FFT size.
+fn average_bars(averages: &mut Vec<f32>, values: &[f32], averaging: f32, floor: f32) {
+    if averages.len() != values.len() {
+        averages.clear();
+        averages.extend(values.iter().map(|value| value.max(floor)));
+        return;
+    }
+
+    for (previous, value) in averages.iter_mut().zip(values) {
+        *previous = average(*previous, value.max(floor), averaging);
+    }
+}
+
+/// Ranges of the samples drawn in each pixel column, when there are more `samples` than
+/// `columns`. All are equally long but the last one, which holds what is left.
+fn sample_buckets(samples: usize, columns: usize) -> impl Iterator<Item = Range<usize>> {
+    let bucket_length = samples.div_ceil(columns.max(1)).max(1);
+    (0..samples)
+        .step_by(bucket_length)
+        .map(move |start| start..(start + bucket_length).min(samples))
+}
+
+/// Level of a signal in full scale decibels, -inf dBFS for silence
+fn to_dbfs(amplitude: f32) -> f32 {
+    20.0 * amplitude.log10()
+}
+
+/// Root mean square of `samples`, 0 when there are none
+fn rms(samples: &[f32]) -> f32 {
+    if samples.is_empty() {
+        return 0.0;
+    }
+    (samples.iter().map(|sample| sample * sample).sum::<f32>() / samples.len() as f32).sqrt()
+}
+
+/// Loudness of a channel over a window of samples, in dBFS
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Level {
+    rms: f32,
+    peak: f32,
+}
+
+impl Level {
+    const SILENCE: Self = Self {
+        rms: f32::NEG_INFINITY,
+        peak: f32::NEG_INFINITY,
+    };
+
+    fn measure(samples: &[f32]) -> Self {
+        let peak = samples
+            .iter()
+            .fold(0.0f32, |peak, sample| peak.max(sample.abs()));
+        Self {
+            rms: to_dbfs(rms(samples)),
+            peak: to_dbfs(peak),
+        }
+    }
+}
+
+/// Meters of the levels of channels side by side: a bar up to the RMS level over a fainter one up
+/// to the peak level, with a marker at the recent peak falling slowly
+pub struct MeterPlotter {
+    canvas: web_sys::HtmlCanvasElement,
+    context: web_sys::CanvasRenderingContext2d,
+    /// Held peak of every channel, or nothing until the next plot
+    peaks: Vec<f32>,
+}
+
+impl MeterPlotter {
+    pub fn new(canvas: web_sys::HtmlCanvasElement) -> Result<Self, JsValue> {
+        let context = context_2d(&canvas)?;
+        Ok(Self {
+            canvas,
+            context,
+            peaks: vec![],
+        })
+    }
+
+    /// Forget the held peaks
+    pub fn reset(&mut self) {
+        self.peaks.clear();
+    }
+
+    /// Replace the meters with `levels`. `elapsed` is the time since the last plot in seconds,
+    /// over which held peaks fall.
+    fn plot(&mut self, levels: &[Level], elapsed: f32) {
+        let (width, height) = begin_plot(&self.canvas, &self.context);
+
+        let peaks = levels.iter().map(|level| level.peak).collect::<Vec<_>>();
+        hold_peaks(&mut self.peaks, &peaks, DEFAULT_PEAK_DECAY, elapsed);
+
+        let (min, max) = METER_RANGE;
+        let meter_width = width / levels.len() as f64;
+        // A gap between the meters
+        let bar_width = (meter_width - 2.0).max(1.0);
+        for (index, (level, held_peak)) in levels.iter().zip(&self.peaks).enumerate() {
+            let x = index as f64 * meter_width;
+
+            self.context.set_fill_style_str(GRID_COLOR);
+            let peak_height = bar_height(level.peak, min, max, height);
+            self.context
+                .fill_rect(x, height - peak_height, bar_width, peak_height);
+
+            self.context.set_fill_style_str(PLOT_COLOR);
+            let rms_height = bar_height(level.rms, min, max, height);
+            self.context
+                .fill_rect(x, height - rms_height, bar_width, rms_height);
+
+            let held_height = bar_height(*held_peak, min, max, height);
+            if held_height > 0.0 {
+                self.context.set_fill_style_str(PEAK_COLOR);
+                self.context
+                    .fill_rect(x, height - held_height, bar_width, 1.0);
+            }
+        }
+    }
+}
+
+/// Plots values as a line across the canvas, such as the samples of a waveform.
+///
+/// When there are more values than pixels, each column shows the range between the lowest and the
+/// highest value drawn in it instead, so that short transients are not lost.
+pub struct LinePlotter {
+    canvas: web_sys::HtmlCanvasElement,
+    context: web_sys::CanvasRenderingContext2d,
+}
+
+impl LinePlotter {
+    pub fn new(canvas: web_sys::HtmlCanvasElement) -> Result<Self, JsValue> {
+        let context = context_2d(&canvas)?;
+        Ok(Self { canvas, context })
+    }
+
+    /// Replace the plot with `values`, scaled from `min` at the bottom to `max` at the top
+    pub fn plot(&self, values: &[f32], min: f32, max: f32) {
+        let (width, height) = begin_plot(&self.canvas, &self.context);
+        if values.len() < 2 {
+            return;
+        }
+
+        if values.len() > width as usize {
+            self.plot_envelope(values, min, max, width, height);
+            return;
+        }
+
+        let step = width / (values.len() - 1) as f64;
+        self.context.set_stroke_style_str(PLOT_COLOR);
+        self.context.begin_path();
+        for (index, value) in values.iter().enumerate() {
+            let (x, y) = (
+                index as f64 * step,
+                height - bar_height(*value, min, max, height),
+            );
+            if index == 0 {
+                self.context.move_to(x, y);
+            } else {
+                self.context.line_to(x, y);
+            }
+        }
+        self.context.stroke();
+    }
+
+    /// Plot the lowest and highest of the `values` drawn in each pixel column
+    fn plot_envelope(&self, values: &[f32], min: f32, max: f32, width: f64, height: f64) {
+        let step = width / values.len() as f64;
+
+        self.context.set_fill_style_str(PLOT_COLOR);
+        for bucket in sample_buckets(values.len(), width as usize) {
+            // Starting from the last value of the previous column joins the columns up
+            let from = bucket.start.saturating_sub(1);
+            let (lowest, highest) = values[from..bucket.end].iter().fold(
+                (f32::INFINITY, f32::NEG_INFINITY),
+                |(lowest, highest), value| (lowest.min(*value), highest.max(*value)),
+            );
+
+            let top = height - bar_height(highest, min, max, height);
+            let bottom = height - bar_height(lowest, min, max, height);
+            let left = (bucket.start as f64 * step).floor();
+            let right = (bucket.end as f64 * step).ceil();
+            self.context
+                .fill_rect(left, top, right - left, (bottom - top).max(1.0));
+        }
+    }
+}
+
+/// Horizontal position of `time` on a plot of `duration` over `width`
+fn time_to_x(time: Duration, duration: Duration, width: f64) -> f64 {
+    if duration.is_zero() {
+        0.0
+    } else {
+        time.as_secs_f64() / duration.as_secs_f64() * width
+    }
+}
+
+/// Time at the horizontal position `x` of a plot of `duration` over `width`, clamped to the plot
+fn x_to_time(x: f64, duration: Duration, width: f64) -> Duration {
+    if width <= 0.0 {
+        return Duration::ZERO;
+    }
+    duration.mul_f64((x / width).clamp(0.0, 1.0))
+}
+
+/// Keys shown by a piano roll of `notes`: from the lowest to the highest of them, widened to at
+/// least two octaves around them
+fn key_range(notes: &[Note]) -> Range<u8> {
+    const MIN_KEYS: u8 = 24;
+
+    let lowest = notes.iter().map(|note| note.key).min().unwrap_or(60);
+    let highest = notes.iter().map(|note| note.key).max().unwrap_or(60);
+    let missing = MIN_KEYS.saturating_sub(highest - lowest + 1);
+    let lowest = lowest.saturating_sub(missing / 2).min(128 - MIN_KEYS);
+    let highest = (lowest + MIN_KEYS - 1).max(highest);
+    lowest..highest + 1
+}
+
+/// Top and height of the lane of `key` on a piano roll of `keys` over `height`, with the highest
+/// key at the top
+fn key_lane(key: u8, keys: &Range<u8>, height: f64) -> (f64, f64) {
+    let lane_height = height / keys.len() as f64;
+    ((keys.end - 1 - key) as f64 * lane_height, lane_height)
+}
+
+/// Lanes of drums played on the percussion channel, labeled with their names, in ascending order
+fn drum_lanes(notes: &[Note]) -> Vec<(u8, &'static str)> {
+    let mut lanes = notes
+        .iter()
+        .filter(|note| note.channel == PERCUSSION_CHANNEL)
+        .filter_map(|note| Some((note.key, gm::percussion_name(note.key)?)))
+        .collect::<Vec<_>>();
+    lanes.sort_unstable();
+    lanes.dedup();
+    lanes
+}
+
+/// Color of the notes of a MIDI channel, spread around the color wheel
+fn channel_color(channel: u8) -> String {
+    format!("hsl({}, 70%, 50%)", channel as u32 * 360 / 16)
+}
+
+/// Notes of the loaded file as rectangles in lanes of their keys, with bar lines and a playhead.
+///
+/// The notes are drawn once onto a detached canvas, so that moving the playhead only copies it,
+/// however many notes the file has.
+pub struct PianoRollPlotter {
+    canvas: web_sys::HtmlCanvasElement,
+    context: web_sys::CanvasRenderingContext2d,
+    /// Notes and bar lines of the file
+    background: web_sys::HtmlCanvasElement,
+    /// Of the file, shared with the click handler
+    duration: Rc<Cell<Duration>>,
+}
+
+impl PianoRollPlotter {
+    pub fn new(
+        document: &web_sys::Document,
+        canvas: web_sys::HtmlCanvasElement,
+    ) -> Result<Self, JsValue> {
+        let context = context_2d(&canvas)?;
+        let background = document
+            .create_element("canvas")?
+            .dyn_into::<web_sys::HtmlCanvasElement>()?;
+        background.set_width(canvas.width());
+        background.set_height(canvas.height());
+
+        Ok(Self {
+            canvas,
+            context,
+            background,
+            duration: Rc::new(Cell::new(Duration::ZERO)),
+        })
+    }
+
+    /// Draw the `notes` of a file lasting `duration`, with bars starting at `bar_starts`
+    pub fn set_notes(
+        &self,
+        notes: &[Note],
+        bar_starts: &[Duration],
+        duration: Duration,
+    ) -> Result<(), JsValue> {
+        self.duration.set(duration);
+        let context = context_2d(&self.background)?;
+        let width = self.background.width() as f64;
+        let height = self.background.height() as f64;
+        context.clear_rect(0.0, 0.0, width, height);
+
+        context.set_stroke_style_str(GRID_COLOR);
+        context.begin_path();
+        for bar_start in bar_starts {
+            let x = time_to_x(*bar_start, duration, width).round() + 0.5;
+            context.move_to(x, 0.0);
+            context.line_to(x, height);
+        }
+        context.stroke();
+
+        let keys = key_range(notes);
+        let drums = drum_lanes(notes);
+        // Notes are grouped by channel, so the fill style changes at most 16 times
+        let mut notes = notes.iter().collect::<Vec<_>>();
+        notes.sort_by_key(|note| note.channel);
+        let mut channel = None;
+        for note in notes {
+            if channel != Some(note.channel) {
+                channel = Some(note.channel);
+                context.set_fill_style_str(&channel_color(note.channel));
+            }
+
+            let x = time_to_x(note.start, duration, width);
+            // At least a pixel wide, so short notes stay visible
+            let note_width = (time_to_x(note.end, duration, width) - x).max(1.0);
+            let (y, lane_height) = key_lane(note.key, &keys, height);
+            context.fill_rect(x, y, note_width, lane_height.max(1.0));
+        }
+
+        // Labels only fit lanes tall enough for a line of text
+        let lane_height = height / keys.len() as f64;
+        if lane_height >= DRUM_LABEL_SIZE {
+            context.set_fill_style_str(GRID_COLOR);
+            context.set_font(&format!("{DRUM_LABEL_SIZE}px sans-serif"));
+            for (key, name) in drums {
+                let (y, _) = key_lane(key, &keys, height);
+                let _ = context.fill_text(name, 2.0, y + lane_height - 1.0);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Redraw the notes with the playhead at `position`
+    pub fn draw(&self, position: Duration) -> Result<(), JsValue> {
+        let width = self.canvas.width() as f64;
+        let height = self.canvas.height() as f64;
+        self.context.clear_rect(0.0, 0.0, width, height);
+        self.context
+            .draw_image_with_html_canvas_element(&self.background, 0.0, 0.0)?;
+
+        let x = time_to_x(position, self.duration.get(), width).round() + 0.5;
+        self.context.set_stroke_style_str(PLOT_COLOR);
+        self.context.begin_path();
+        self.context.move_to(x, 0.0);
+        self.context.line_to(x, height);
+        self.context.stroke();
+        Ok(())
+    }
+
+    /// Register a callback fired with the position in the file the user clicks on.
+    pub fn on_click<F: FnMut(Duration) + 'static>(&self, mut position_cb: F) {
+        let canvas = self.canvas.clone();
+        let duration = self.duration.clone();
+        let on_click_closure = Closure::wrap(Box::new(move |event: web_sys::MouseEvent| {
+            // The canvas may be scaled by CSS
+            let width = canvas.client_width() as f64;
+            position_cb(x_to_time(event.offset_x() as f64, duration.get(), width));
+        }) as Box<dyn FnMut(_)>);
+
+        self.canvas
+            .add_event_listener_with_callback("click", on_click_closure.as_ref().unchecked_ref())
+            .expect("failed to set click event handler");
+        on_click_closure.forget();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fft_sizes_must_be_powers_of_two_in_range() {
+        for fft_size in [32, 128, 512, 2048, 8192, 32768] {
+            assert_eq!(
+                check_analyser_settings(fft_size, 0.8, -100.0, -30.0),
+                Ok(())
+            );
+        }
+        for fft_size in [0, 16, 100, 65536] {
+            assert_eq!(
+                check_analyser_settings(fft_size, 0.8, -100.0, -30.0),
+                Err(AnalyserSettingsError::FftSize(fft_size))
+            );
+        }
+
+        assert_eq!(
+            check_analyser_settings(2048, 1.5, -100.0, -30.0),
+            Err(AnalyserSettingsError::Smoothing(1.5))
+        );
+        assert_eq!(
+            check_analyser_settings(2048, 0.0, -30.0, -30.0),
+            Err(AnalyserSettingsError::DecibelRange(-30.0, -30.0))
+        );
+        assert!(check_analyser_settings(2048, 0.0, f64::NAN, -30.0).is_err());
+    }
+
+    #[test]
+    fn octaves_are_equally_wide_on_a_log_axis() {
+        let max_frequency = 22050.0;
+        assert_eq!(log_axis_position(27.5, max_frequency), 0.0);
+        assert!((log_axis_position(max_frequency, max_frequency) - 1.0).abs() < 1e-6);
+
+        let octave = log_axis_position(55.0, max_frequency);
+        for frequency in [110.0, 440.0, 3520.0] {
+            let width = log_axis_position(frequency, max_frequency)
+                - log_axis_position(frequency / 2.0, max_frequency);
+            assert!((width - octave).abs() < 1e-6);
+        }
+
+        let position = log_axis_position(1000.0, max_frequency);
+        assert!((log_axis_frequency(position, max_frequency) - 1000.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn log_axis_columns_group_high_bins_and_interpolate_low_ones() {
+        // 8 bins of 25 Hz up to 200 Hz, over 6 columns from A0
+        let bins = [0.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, 70.0];
+        let columns = log_axis_columns(&bins, 200.0, 6);
+
+        // The lowest columns are narrower than a bin, and fall between the bins around them
+        assert!(columns[0] > 10.0 && columns[0] < 20.0);
+        assert!(columns[2] > 20.0 && columns[2] < 30.0);
+        // The highest ones cover a bin or more
+        assert_eq!(columns[1], 20.0);
+        assert_eq!(columns[3..], [40.0, 50.0, 70.0]);
+
+        assert_eq!(log_axis_columns(&[], 220.0, 3), []);
+        assert_eq!(
+            log_axis_columns(&[f32::NEG_INFINITY; 4], 220.0, 2),
+            [f32::NEG_INFINITY; 2]
+        );
+    }
+
+    #[test]
+    fn octave_boundaries_are_c_notes() {
+        let boundaries = octave_boundaries(300.0);
+        let names = boundaries
+            .iter()
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["C1", "C2", "C3", "C4"]);
+        assert!((boundaries[3].0 - 261.63).abs() < 0.01);
+    }
+
+    #[test]
+    fn piano_roll_maps_times_and_keys() {
+        let duration = Duration::from_secs(10);
+        assert_eq!(time_to_x(Duration::from_secs(5), duration, 800.0), 400.0);
+        assert_eq!(
+            x_to_time(200.0, duration, 800.0),
+            Duration::from_millis(2500)
+        );
+        assert_eq!(x_to_time(-10.0, duration, 800.0), Duration::ZERO);
+        assert_eq!(x_to_time(900.0, duration, 800.0), duration);
+        assert_eq!(
+            time_to_x(Duration::from_secs(1), Duration::ZERO, 800.0),
+            0.0
+        );
+
+        let note = |key| Note {
+            track: 0,
+            channel: 0,
+            key,
+            velocity: 100,
+            release_velocity: 0,
+            start: Duration::ZERO,
+            end: Duration::from_secs(1),
+            start_tick: 0,
+            end_tick: 96,
+        };
+        assert_eq!(key_range(&[note(40), note(90)]), 40..91);
+        // A single key is widened to two octaves around it
+        assert_eq!(key_range(&[note(60)]), 49..73);
+        assert_eq!(key_range(&[note(126)]), 104..128);
+        assert_eq!(key_range(&[note(1)]), 0..24);
+        assert_eq!(key_range(&[]).len(), 24);
+
+        let keys = 40..91;
+        assert_eq!(key_lane(90, &keys, 510.0), (0.0, 10.0));
+        assert_eq!(key_lane(40, &keys, 510.0), (500.0, 10.0));
+    }
+
+    #[test]
+    fn drum_lanes_are_labeled_once_per_key() {
+        let note = |channel, key| Note {
+            track: 0,
+            channel,
+            key,
+            velocity: 100,
+            release_velocity: 0,
+            start: Duration::ZERO,
+            end: Duration::from_secs(1),
+            start_tick: 0,
+            end_tick: 96,
+        };
+        let notes = [
+            note(PERCUSSION_CHANNEL, 42),
+            note(PERCUSSION_CHANNEL, 36),
+            note(PERCUSSION_CHANNEL, 42),
+            // melodic notes and keys outside of the percussion map have no label
+            note(0, 38),
+            note(PERCUSSION_CHANNEL, 20),
+        ];
+        assert_eq!(
+            drum_lanes(&notes),
+            [(36, "Bass Drum 1"), (42, "Closed Hi-Hat")]
+        );
+    }
+
+    #[test]
+    fn held_peaks_fall_at_the_decay_rate() {
+        // Half a second of frames at 60 fps, falling by 20 dB per second
+        let mut peak = -30.0;
+        for _ in 0..30 {
+            peak = decay_peak(peak, -100.0, 20.0, 1.0 / 60.0);
+        }
+        assert!((peak - -40.0).abs() < 1e-3);
+
+        // Louder levels replace the peak
+        assert_eq!(decay_peak(-40.0, -35.0, 20.0, 1.0 / 60.0), -35.0);
+        assert_eq!(decay_peak(-40.0, -70.0, 20.0, 0.0), -40.0);
+    }
+
+    #[test]
+    fn peaks_and_averages_are_reset_when_the_bars_change() {
+        let mut peaks = vec![];
+        hold_peaks(&mut peaks, &[-50.0, -60.0], 10.0, 1.0);
+        assert_eq!(peaks, vec![-50.0, -60.0]);
+        hold_peaks(&mut peaks, &[-70.0, -40.0], 10.0, 1.0);
+        assert_eq!(peaks, vec![-60.0, -40.0]);
+        hold_peaks(&mut peaks, &[-90.0; 3], 10.0, 1.0);
+        assert_eq!(peaks, vec![-90.0; 3]);
+
+        let mut averages = vec![];
+        average_bars(&mut averages, &[f32::NEG_INFINITY, -20.0], 0.5, -100.0);
+        assert_eq!(averages, vec![-100.0, -20.0]);
+        average_bars(&mut averages, &[-50.0, f32::NEG_INFINITY], 0.5, -100.0);
+        assert_eq!(averages, vec![-75.0, -60.0]);
+        average_bars(&mut averages, &[-10.0], 0.5, -100.0);
+        assert_eq!(averages, vec![-10.0]);
+    }
+
+    #[test]
+    fn levels_are_measured_in_dbfs() {
+        assert_eq!(to_dbfs(1.0), 0.0);
+        assert!((to_dbfs(0.5) - -6.0206).abs() < 1e-3);
+        assert_eq!(to_dbfs(0.0), f32::NEG_INFINITY);
+
+        assert_eq!(rms(&[]), 0.0);
+        assert_eq!(rms(&[0.5, -0.5, 0.5, -0.5]), 0.5);
+
+        // A full scale sine is 3 dB below a full scale square
+        let sine = (0..1024)
+            .map(|index| (index as f32 / 64.0 * std::f32::consts::TAU).sin())
+            .collect::<Vec<_>>();
+        let level = Level::measure(&sine);
+        assert!((level.rms - -3.0103).abs() < 1e-3);
+        assert!(level.peak.abs() < 1e-3);
+
+        let silence = Level::measure(&[0.0; 16]);
+        assert_eq!(silence.rms, f32::NEG_INFINITY);
+        assert_eq!(silence.peak, f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn backing_stores_have_a_pixel_per_device_pixel() {
+        assert_eq!(backing_size(640, 1.0), 640);
+        assert_eq!(backing_size(640, 2.0), 1280);
+        // Zoomed pages may have fractional ratios
+        assert_eq!(backing_size(333, 1.5), 500);
+        assert_eq!(backing_size(0, 2.0), 0);
+        assert_eq!(backing_size(-1, 2.0), 0);
+    }
+
+    #[test]
+    fn samples_are_bucketed_per_column() {
+        assert_eq!(
+            sample_buckets(8, 4).collect::<Vec<_>>(),
+            vec![0..2, 2..4, 4..6, 6..8]
+        );
+        // The last bucket is ragged
+        assert_eq!(
+            sample_buckets(10, 4).collect::<Vec<_>>(),
+            vec![0..3, 3..6, 6..9, 9..10]
+        );
+        assert_eq!(
+            sample_buckets(4096, 640)
+                .map(|bucket| bucket.len())
+                .sum::<usize>(),
+            4096
+        );
+        assert_eq!(sample_buckets(4096, 640).count(), 586);
+        assert_eq!(sample_buckets(4096, 640).last(), Some(4095..4096));
+        assert_eq!(sample_buckets(0, 640).count(), 0);
+        assert_eq!(sample_buckets(3, 0).collect::<Vec<_>>(), vec![0..3]);
+    }
+
+    #[test]
+    fn bars_are_clamped_to_the_plot() {
+        assert_eq!(bar_height(-65.0, -100.0, -30.0, 100.0), 50.0);
+        assert_eq!(bar_height(0.0, -100.0, -30.0, 100.0), 100.0);
+        assert_eq!(bar_height(f32::NEG_INFINITY, -100.0, -30.0, 100.0), 0.0);
+        assert_eq!(bar_height(f32::NAN, -100.0, -30.0, 100.0), 0.0);
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    /// Canvas laid out on the page, `style` giving its size
+    fn canvas(style: &str) -> web_sys::HtmlCanvasElement {
+        let document = web_sys::window().unwrap().document().unwrap();
+        let canvas = document.create_element("canvas").unwrap();
+        canvas.set_attribute("style", style).unwrap();
+        document.body().unwrap().append_child(&canvas).unwrap();
+        canvas.dyn_into().unwrap()
+    }
+
+    fn visualizer() -> AudioVisualizer {
+        let audio_context = web_sys::AudioContext::new().unwrap();
+        AudioVisualizer::new(
+            &audio_context,
+            canvas("width: 320px; height: 120px"),
+            canvas("width: 320px; height: 120px"),
+            canvas("width: 40px; height: 120px"),
+        )
+        .unwrap()
+    }
+
+    #[wasm_bindgen_test]
+    fn reconfiguring_resizes_the_analyser_data() {
+        let mut visualizer = visualizer();
+        assert_eq!(visualizer.freq_data.len(), DEFAULT_FFT_SIZE as usize / 2);
+        assert_eq!(visualizer.time_data.len(), DEFAULT_FFT_SIZE as usize);
+        visualizer.draw();
+
+        for fft_size in [128, 8192, 512] {
+            visualizer.configure(fft_size, 0.5, -90.0, -10.0).unwrap();
+            assert_eq!(visualizer.freq_data.len(), fft_size as usize / 2);
+            assert_eq!(visualizer.time_data.len(), fft_size as usize);
+            assert_eq!(visualizer.analyser.fft_size(), fft_size);
+            assert_eq!(visualizer.decibel_range, (-90.0, -10.0));
+            visualizer.draw();
+        }
+
+        // Invalid settings leave the analyser as it was
+        assert!(visualizer.configure(1000, 0.5, -90.0, -10.0).is_err());
+        assert_eq!(visualizer.freq_data.len(), 256);
+        assert_eq!(visualizer.analyser.fft_size(), 512);
+
+        visualizer.set_log_frequency(false);
+        visualizer.configure(32, 0.0, -100.0, -30.0).unwrap();
+        visualizer.draw();
+        visualizer.clear();
+    }
+
+    #[wasm_bindgen_test]
+    fn plots_are_redrawn_after_canvases_are_resized() {
+        let mut visualizer = visualizer();
+        let canvases = [
+            visualizer.spectrum.canvas.clone(),
+            visualizer.waveform.canvas.clone(),
+            visualizer.meters.canvas.clone(),
+        ];
+        visualizer.draw();
+
+        // Resized by the page, then fitted as the resize observer would
+        for canvas in &canvases {
+            canvas
+                .set_attribute("style", "width: 500px; height: 80px")
+                .unwrap();
+            fit_canvas(canvas, 2.0);
+            assert_eq!((canvas.width(), canvas.height()), (1000, 160));
+        }
+        visualizer.draw();
+
+        // Backing stores of any size, whether or not they were fitted since
+        for (width, height) in [(7, 3), (1, 1), (4000, 50)] {
+            for canvas in &canvases {
+                canvas.set_width(width);
+                canvas.set_height(height);
+            }
+            visualizer.draw();
+        }
+
+        // Hidden canvases aren't laid out
+        for canvas in &canvases {
+            canvas.set_attribute("style", "display: none").unwrap();
+            fit_canvas(canvas, 2.0);
+            assert_eq!((canvas.width(), canvas.height()), (4000, 50));
+        }
+        visualizer.draw();
+        visualizer.clear();
+    }
+}
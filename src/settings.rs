@@ -0,0 +1,247 @@
+//! Settings of the player kept across page loads, in the local storage of the browser.
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::js_sys;
+
+use crate::{
+    dom::{self, SynthKindOption, WaveKindOption},
+    plotter::DEFAULT_FFT_SIZE,
+    synth::ChannelFilter,
+};
+
+/// Local storage key of the settings
+const STORAGE_KEY: &str = "syntezator-settings";
+
+/// Choices of the user restored on startup. Fields missing from the stored settings, or invalid,
+/// keep their defaults.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub synth_kind: SynthKindOption,
+    pub wave_kind: WaveKindOption,
+    /// From 0 (silent) to 1
+    pub volume: f32,
+    /// From 0 (none) to 1
+    pub reverb: f32,
+    pub fft_size: u32,
+    /// Applied to every file loaded
+    pub muted_channels: Vec<u8>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            synth_kind: SynthKindOption::WebAudio,
+            wave_kind: WaveKindOption::Instrument,
+            volume: 1.0,
+            reverb: 0.0,
+            fft_size: DEFAULT_FFT_SIZE,
+            muted_channels: vec![],
+        }
+    }
+}
+
+impl Settings {
+    /// Settings stored by [`Settings::save`], or the defaults if there are none or they can't be
+    /// read
+    pub fn load() -> Self {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .and_then(|storage| storage.get_item(STORAGE_KEY).ok().flatten())
+            .map(|json| Self::from_json(&json))
+            .unwrap_or_default()
+    }
+
+    /// Store the settings, to be restored by [`Settings::load`]. Nothing is stored when the
+    /// browser doesn't allow it, such as in some private windows.
+    pub fn save(&self) {
+        if let Some(storage) =
+            web_sys::window().and_then(|window| window.local_storage().ok().flatten())
+        {
+            let _ = storage.set_item(STORAGE_KEY, &self.to_json());
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let muted_channels = self
+            .muted_channels
+            .iter()
+            .map(|&channel| JsValue::from(channel))
+            .collect::<js_sys::Array>();
+        let fields: [(&str, JsValue); 6] = [
+            ("synth_kind", dom::synth_kind_value(self.synth_kind).into()),
+            ("wave_kind", dom::wave_kind_value(self.wave_kind).into()),
+            ("volume", self.volume.into()),
+            ("reverb", self.reverb.into()),
+            ("fft_size", self.fft_size.into()),
+            ("muted_channels", muted_channels.into()),
+        ];
+
+        let object = js_sys::Object::new();
+        for (key, value) in fields {
+            js_sys::Reflect::set(&object, &key.into(), &value).expect("object is extensible");
+        }
+        js_sys::JSON::stringify(&object)
+            .expect("settings are plain data")
+            .into()
+    }
+
+    /// Read settings written by [`Settings::to_json`]. Unreadable JSON gives the defaults.
+    pub fn from_json(json: &str) -> Self {
+        let mut settings = Self::default();
+        let Ok(object) = js_sys::JSON::parse(json) else {
+            return settings;
+        };
+        if !object.is_object() || js_sys::Array::is_array(&object) {
+            return settings;
+        }
+        let field = |key: &str| js_sys::Reflect::get(&object, &key.into()).unwrap_or_default();
+
+        if let Some(synth_kind) = field("synth_kind")
+            .as_string()
+            .and_then(|value| dom::synth_kind_option(&value))
+        {
+            settings.synth_kind = synth_kind;
+        }
+        // Presets aren't stored, so there is none to restore
+        if let Some(wave_kind) = field("wave_kind")
+            .as_string()
+            .and_then(|value| dom::wave_kind_option(&value))
+            .filter(|&wave_kind| wave_kind != WaveKindOption::Custom)
+        {
+            settings.wave_kind = wave_kind;
+        }
+        if let Some(volume) = field("volume")
+            .as_f64()
+            .filter(|volume| (0.0..=1.0).contains(volume))
+        {
+            settings.volume = volume as f32;
+        }
+        if let Some(reverb) = field("reverb")
+            .as_f64()
+            .filter(|reverb| (0.0..=1.0).contains(reverb))
+        {
+            settings.reverb = reverb as f32;
+        }
+        if let Some(fft_size) = field("fft_size")
+            .as_f64()
+            .filter(|fft_size| fft_size.fract() == 0.0 && (32.0..=32768.0).contains(fft_size))
+        {
+            settings.fft_size = fft_size as u32;
+        }
+        if let Ok(channels) = field("muted_channels").dyn_into::<js_sys::Array>() {
+            settings.muted_channels = channels
+                .iter()
+                .filter_map(|channel| channel.as_f64())
+                .filter(|channel| channel.fract() == 0.0 && (0.0..16.0).contains(channel))
+                .map(|channel| channel as u8)
+                .collect();
+        }
+
+        settings
+    }
+
+    pub fn channel_filter(&self) -> ChannelFilter {
+        let mut channel_filter = ChannelFilter::default();
+        for &channel in &self.muted_channels {
+            channel_filter.set_muted(channel, true);
+        }
+        channel_filter
+    }
+
+    /// Keep the channels muted by `channel_filter`
+    pub fn set_channel_filter(&mut self, channel_filter: &ChannelFilter) {
+        self.muted_channels = (0..16)
+            .filter(|&channel| channel_filter.is_muted(channel))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn muted_channels_become_a_channel_filter() {
+        let mut settings = Settings {
+            muted_channels: vec![0, 9],
+            ..Settings::default()
+        };
+        let mut channel_filter = settings.channel_filter();
+        assert!(channel_filter.is_muted(9));
+        assert!(!channel_filter.is_muted(1));
+
+        channel_filter.set_muted(0, false);
+        channel_filter.set_muted(15, true);
+        settings.set_channel_filter(&channel_filter);
+        assert_eq!(settings.muted_channels, vec![9, 15]);
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+
+    #[wasm_bindgen_test]
+    fn settings_round_trip_through_json() {
+        let settings = Settings {
+            synth_kind: SynthKindOption::Offline,
+            wave_kind: WaveKindOption::PulseEighth,
+            volume: 0.375,
+            reverb: 0.25,
+            fft_size: 8192,
+            muted_channels: vec![2, 9],
+        };
+        let json = settings.to_json();
+        assert_eq!(
+            json,
+            r#"{"synth_kind":"offline","wave_kind":"pulse_12_5","volume":0.375,"reverb":0.25,"fft_size":8192,"muted_channels":[2,9]}"#
+        );
+        assert_eq!(Settings::from_json(&json), settings);
+
+        assert_eq!(
+            Settings::from_json(&Settings::default().to_json()),
+            Settings::default()
+        );
+    }
+
+    #[wasm_bindgen_test]
+    fn missing_and_invalid_settings_are_defaults() {
+        let settings = Settings::from_json(
+            r#" { "wave_kind" : "bell", "volume": 3, "reverb": -0.5, "fft_size": 1000.5,
+                  "muted_channels": [1, 16, 2.5], "unknown": "kept out" } "#,
+        );
+        assert_eq!(
+            settings,
+            Settings {
+                wave_kind: WaveKindOption::Bell,
+                muted_channels: vec![1],
+                ..Settings::default()
+            }
+        );
+
+        assert_eq!(
+            Settings::from_json(r#"{"synth_kind":"analog","wave_kind":"custom"}"#),
+            Settings::default()
+        );
+        assert_eq!(Settings::from_json("{}"), Settings::default());
+    }
+
+    #[wasm_bindgen_test]
+    fn corrupt_settings_are_defaults() {
+        for json in [
+            "",
+            "null",
+            r#"{"volume":0.5"#,
+            r#"{"volume":0.5,}"#,
+            r#"{"synth_kind":"raw\"s"}"#,
+            r#"{"volume":0.5} trailing"#,
+            r#"{"fft_size":[1,]}"#,
+            "[0.5]",
+            r#""volume""#,
+            r#"{"volume":"0.5","muted_channels":"1"}"#,
+        ] {
+            assert_eq!(Settings::from_json(json), Settings::default(), "{json}");
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Coarse instrument timbres for General MIDI programs.
+
+use crate::{
+    midi::gm::{self, Family},
+    wave::{CustomWave, SawtoothWave, SineWave, SquareWave, TriangleWave, Wave},
+};
+
+/// Instrument family of a General MIDI program, each played with its own wave
+#[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
+pub enum Timbre {
+    Piano,
+    Organ,
+    Guitar,
+    Bass,
+    Strings,
+    Brass,
+    Reed,
+    Pipe,
+    Lead,
+    Pad,
+}
+
+static PIANO: CustomWave = CustomWave::new(&[0.0; 8], &[0.0, 1.0, 0.5, 0.3, 0.15, 0.1, 0.05, 0.03]);
+static ORGAN: CustomWave =
+    CustomWave::new(&[0.0; 9], &[0.0, 1.0, 0.8, 0.0, 0.5, 0.0, 0.3, 0.0, 0.2]);
+static GUITAR: CustomWave =
+    CustomWave::new(&[0.0; 8], &[0.0, 1.0, 0.7, 0.5, 0.35, 0.25, 0.15, 0.1]);
+static BRASS: CustomWave = CustomWave::new(&[0.0; 8], &[0.0, 1.0, 0.85, 0.7, 0.55, 0.4, 0.3, 0.2]);
+
+impl Timbre {
+    /// Timbre of a program number in [0; 127]
+    pub fn from_program(program: u8) -> Self {
+        Self::from_family(gm::family(program))
+    }
+
+    /// Timbre of a General MIDI family. Sound effects and other families without a wave of their
+    /// own are mapped to the closest sounding one.
+    pub fn from_family(family: Family) -> Self {
+        match family {
+            Family::Piano | Family::ChromaticPercussion | Family::Percussive => Timbre::Piano,
+            Family::Organ => Timbre::Organ,
+            Family::Guitar | Family::Ethnic => Timbre::Guitar,
+            Family::Bass => Timbre::Bass,
+            Family::Strings | Family::Ensemble => Timbre::Strings,
+            Family::Brass => Timbre::Brass,
+            Family::Reed => Timbre::Reed,
+            Family::Pipe => Timbre::Pipe,
+            Family::SynthLead => Timbre::Lead,
+            Family::SynthPad | Family::SynthEffects | Family::SoundEffects => Timbre::Pad,
+        }
+    }
+
+    pub fn wave(&self) -> &'static dyn Wave {
+        match self {
+            Timbre::Piano => &PIANO,
+            Timbre::Organ => &ORGAN,
+            Timbre::Guitar => &GUITAR,
+            Timbre::Bass => &TriangleWave,
+            Timbre::Strings => &SawtoothWave,
+            Timbre::Brass => &BRASS,
+            Timbre::Reed => &SquareWave,
+            Timbre::Pipe => &SineWave,
+            Timbre::Lead => &SawtoothWave,
+            Timbre::Pad => &TriangleWave,
+        }
+    }
+}
+
+impl Default for Timbre {
+    /// Channels play the first program, the acoustic grand piano, until they change it
+    fn default() -> Self {
+        Timbre::from_program(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn programs_map_to_families() {
+        assert_eq!(Timbre::from_program(0), Timbre::Piano);
+        assert_eq!(Timbre::from_program(19), Timbre::Organ);
+        assert_eq!(Timbre::from_program(33), Timbre::Bass);
+        assert_eq!(Timbre::from_program(40), Timbre::Strings);
+        assert_eq!(Timbre::from_program(73), Timbre::Pipe);
+        assert_eq!(Timbre::from_program(127), Timbre::Pad);
+        assert_eq!(Timbre::from_program(105), Timbre::Guitar);
+        assert_eq!(Timbre::from_program(114), Timbre::Piano);
+    }
+
+    #[test]
+    fn different_programs_have_different_waves() {
+        let flute = Timbre::from_program(73).wave().decompose();
+        let strings = Timbre::from_program(48).wave().decompose();
+        let piano = Timbre::from_program(0).wave().decompose();
+
+        assert_ne!(flute, strings);
+        assert_ne!(flute, piano);
+        assert_ne!(strings, piano);
+    }
+}
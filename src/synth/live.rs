@@ -0,0 +1,329 @@
+//! Notes played live, sounding for as long as their keys are held.
+use std::{collections::HashMap, time::Duration};
+
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    midi::{
+        ChannelEvent, ChannelEventKind, Controller, ControllerKind, PitchBend,
+        rpn::ParameterDecoder,
+    },
+    synth::{
+        DEFAULT_PITCH_BEND_RANGE, Envelope, MidiNote, Vibrato,
+        instruments::Timbre,
+        web_audio::{self, MidiSynth},
+    },
+    wave::Wave,
+};
+
+/// Velocity of the notes played on the on-screen or computer keyboard, which have none of their own
+pub const KEYBOARD_VELOCITY: u8 = 100;
+
+/// Note of the key with the code `KeyA` on the computer keyboard, middle C. The keys of its row
+/// play the white keys from it, and the keys of the row above the black ones.
+const COMPUTER_KEYBOARD_BASE: u8 = 60;
+
+/// Keys of the computer keyboard, by their layout independent code, from the lowest note
+const COMPUTER_KEYBOARD_CODES: [&str; 13] = [
+    "KeyA", "KeyW", "KeyS", "KeyE", "KeyD", "KeyF", "KeyT", "KeyG", "KeyY", "KeyH", "KeyU", "KeyJ",
+    "KeyK",
+];
+
+/// Note played by the key of the computer keyboard with the given `KeyboardEvent.code`, if any
+pub fn computer_key_note(code: &str) -> Option<u8> {
+    COMPUTER_KEYBOARD_CODES
+        .iter()
+        .position(|&key_code| key_code == code)
+        .map(|offset| COMPUTER_KEYBOARD_BASE + offset as u8)
+}
+
+/// Plays notes started and stopped as keys are pressed and released, with the envelope and
+/// vibrato of the Web Audio synthesizer. Notes of all channels are played alike.
+#[derive(Default)]
+pub struct LiveSynth {
+    envelope: Envelope,
+    vibrato: Vibrato,
+    /// Sounding notes, by key
+    voices: HashMap<u8, LiveVoice>,
+    /// The sustain pedal is down
+    sustain: bool,
+    /// Detune of all notes, in cents
+    pitch_bend: f32,
+    /// Range of the pitch bend wheel in semitones, once the device sets it through RPN 0
+    pitch_bend_range: Option<f32>,
+    parameters: ParameterDecoder,
+    /// Position of the modulation wheel
+    modulation: u8,
+    /// Created along with the first note
+    vibrato_nodes: Option<VibratoNodes>,
+}
+
+struct LiveVoice {
+    source: web_sys::AudioScheduledSourceNode,
+    gain: web_sys::GainNode,
+    velocity: u8,
+    /// Context time at which the note started
+    start: Duration,
+    /// Released while the sustain pedal is down, the note sounds until it is lifted
+    sustained: bool,
+}
+
+/// Low frequency oscillator shared by the notes, driving their vibrato
+struct VibratoNodes {
+    _oscillator: web_sys::OscillatorNode,
+    /// Scales the oscillator to the detune in cents set by the modulation wheel
+    depth: web_sys::GainNode,
+}
+
+impl LiveSynth {
+    /// Play a channel event of a MIDI device, such as a note or a controller change
+    pub fn handle_event(
+        &mut self,
+        ctx: &web_sys::BaseAudioContext,
+        wave: Option<&dyn Wave>,
+        destination: &web_sys::AudioNode,
+        event: &ChannelEvent,
+    ) -> Result<(), JsValue> {
+        match *event.kind() {
+            ChannelEventKind::NoteOn { note, velocity } if velocity > 0 => {
+                self.note_on(ctx, wave, destination, note, velocity)
+            }
+            ChannelEventKind::NoteOn { note, .. } | ChannelEventKind::NoteOff { note, .. } => {
+                self.note_off(ctx, note)
+            }
+            ChannelEventKind::Controller {
+                controller_number,
+                controller_value,
+            } => {
+                let controller = Controller::new(controller_number, controller_value);
+                match controller.kind() {
+                    ControllerKind::Sustain => self.set_sustain(ctx, controller.is_on()),
+                    ControllerKind::Modulation => self.set_modulation(ctx, controller.value()),
+                    ControllerKind::AllNotesOff | ControllerKind::AllSoundOff => {
+                        self.all_notes_off(ctx)
+                    }
+                    ControllerKind::DataEntry
+                    | ControllerKind::DataEntryLsb
+                    | ControllerKind::NrpnLsb
+                    | ControllerKind::NrpnMsb
+                    | ControllerKind::RpnLsb
+                    | ControllerKind::RpnMsb => {
+                        if let Some(range) = self
+                            .parameters
+                            .update(event.channel(), controller)
+                            .and_then(|change| change.pitch_bend_range())
+                        {
+                            self.pitch_bend_range = Some(range);
+                        }
+                        Ok(())
+                    }
+                    ControllerKind::Volume
+                    | ControllerKind::Pan
+                    | ControllerKind::Expression
+                    | ControllerKind::Other(_) => {
+                        // Ignored
+                        Ok(())
+                    }
+                }
+            }
+            ChannelEventKind::PitchBend { lsb, msb } => {
+                let range = self.pitch_bend_range.unwrap_or(DEFAULT_PITCH_BEND_RANGE);
+                self.set_pitch_bend(
+                    ctx,
+                    PitchBend::from_bytes(lsb, msb).semitones(range) * 100.0,
+                )
+            }
+            ChannelEventKind::NoteAftertouch { .. }
+            | ChannelEventKind::ProgramChange { .. }
+            | ChannelEventKind::ChannelAftertouch { .. } => {
+                // Ignored
+                Ok(())
+            }
+        }
+    }
+
+    /// Start playing `key` with `wave`, or with the timbre of the default program, until
+    /// [`LiveSynth::note_off`]. A key which is already sounding is left as it is, such as when it
+    /// is repeated by a held key, unless it is only held by the sustain pedal.
+    pub fn note_on(
+        &mut self,
+        ctx: &web_sys::BaseAudioContext,
+        wave: Option<&dyn Wave>,
+        destination: &web_sys::AudioNode,
+        key: u8,
+        velocity: u8,
+    ) -> Result<(), JsValue> {
+        match self.voices.get(&key) {
+            Some(voice) if !voice.sustained => return Ok(()),
+            Some(_) => self.release(ctx, key)?,
+            None => {}
+        }
+
+        let note_source =
+            MidiSynth::create_note_source(ctx, wave.unwrap_or(Timbre::default().wave()))?;
+        let start = Duration::from_secs_f64(ctx.current_time());
+        let gain = web_sys::GainNode::new(ctx)?;
+        let source = note_source.create_node(ctx, MidiNote::new(key))?;
+
+        // Buffers don't follow the pitch
+        if let Some(oscillator) = source.dyn_ref::<web_sys::OscillatorNode>() {
+            oscillator.detune().set_value(self.pitch_bend);
+            self.vibrato_nodes(ctx)?
+                .depth
+                .connect_with_audio_param(&oscillator.detune())?;
+        }
+
+        web_audio::automate_envelope(&gain, &self.envelope.hold_automation(), velocity, start)?;
+        source.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(destination)?;
+        source.start_with_when(start.as_secs_f64())?;
+
+        self.voices.insert(
+            key,
+            LiveVoice {
+                source,
+                gain,
+                velocity,
+                start,
+                sustained: false,
+            },
+        );
+        Ok(())
+    }
+
+    /// Release `key`, which fades out for the envelope release, or once the sustain pedal is
+    /// lifted
+    pub fn note_off(&mut self, ctx: &web_sys::BaseAudioContext, key: u8) -> Result<(), JsValue> {
+        if self.sustain {
+            if let Some(voice) = self.voices.get_mut(&key) {
+                voice.sustained = true;
+            }
+            return Ok(());
+        }
+
+        self.release(ctx, key)
+    }
+
+    /// Release all notes, even the ones held by the sustain pedal
+    pub fn all_notes_off(&mut self, ctx: &web_sys::BaseAudioContext) -> Result<(), JsValue> {
+        for key in self.voices.keys().copied().collect::<Vec<_>>() {
+            self.release(ctx, key)?;
+        }
+        Ok(())
+    }
+
+    /// Press or lift the sustain pedal. Lifting it releases the notes it held.
+    pub fn set_sustain(
+        &mut self,
+        ctx: &web_sys::BaseAudioContext,
+        down: bool,
+    ) -> Result<(), JsValue> {
+        self.sustain = down;
+        if !down {
+            let sustained = self
+                .voices
+                .iter()
+                .filter(|(_, voice)| voice.sustained)
+                .map(|(&key, _)| key)
+                .collect::<Vec<_>>();
+            for key in sustained {
+                self.release(ctx, key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Detune all notes, including the sounding ones, by `cents`
+    pub fn set_pitch_bend(
+        &mut self,
+        ctx: &web_sys::BaseAudioContext,
+        cents: f32,
+    ) -> Result<(), JsValue> {
+        self.pitch_bend = cents;
+        for voice in self.voices.values() {
+            if let Some(oscillator) = voice.source.dyn_ref::<web_sys::OscillatorNode>() {
+                oscillator
+                    .detune()
+                    .set_value_at_time(cents, ctx.current_time())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the depth of the vibrato from the position of the modulation wheel
+    pub fn set_modulation(
+        &mut self,
+        ctx: &web_sys::BaseAudioContext,
+        modulation: u8,
+    ) -> Result<(), JsValue> {
+        self.modulation = modulation;
+        if let Some(vibrato_nodes) = &self.vibrato_nodes {
+            vibrato_nodes
+                .depth
+                .gain()
+                .set_value_at_time(self.vibrato.depth(modulation), ctx.current_time())?;
+        }
+        Ok(())
+    }
+
+    /// No note is sounding, apart from the release of the last ones
+    pub fn is_silent(&self) -> bool {
+        self.voices.is_empty()
+    }
+
+    /// Fade `key` out for the envelope release
+    fn release(&mut self, ctx: &web_sys::BaseAudioContext, key: u8) -> Result<(), JsValue> {
+        let Some(voice) = self.voices.remove(&key) else {
+            return Ok(());
+        };
+
+        let now = Duration::from_secs_f64(ctx.current_time());
+        let level = self
+            .envelope
+            .level(now.saturating_sub(voice.start).as_secs_f32());
+        let end = (now + self.envelope.release).as_secs_f64();
+
+        // Released during the attack or the decay, the note fades out from where it got to
+        let gain = voice.gain.gain();
+        gain.cancel_scheduled_values(now.as_secs_f64())?;
+        gain.set_value_at_time(level * voice.velocity as f32 / 127.0, now.as_secs_f64())?;
+        gain.linear_ramp_to_value_at_time(0.0, end)?;
+        voice.source.stop_with_when(end)
+    }
+
+    fn vibrato_nodes(&mut self, ctx: &web_sys::BaseAudioContext) -> Result<&VibratoNodes, JsValue> {
+        if self.vibrato_nodes.is_none() {
+            let oscillator = web_sys::OscillatorNode::new(ctx)?;
+            let depth = web_sys::GainNode::new(ctx)?;
+            oscillator.frequency().set_value(self.vibrato.rate);
+            depth.gain().set_value(self.vibrato.depth(self.modulation));
+            oscillator.connect_with_audio_node(&depth)?;
+            oscillator.start()?;
+
+            self.vibrato_nodes = Some(VibratoNodes {
+                _oscillator: oscillator,
+                depth,
+            });
+        }
+
+        Ok(self
+            .vibrato_nodes
+            .as_ref()
+            .expect("the vibrato nodes were just created"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computer_keys_play_an_octave_from_middle_c() {
+        assert_eq!(computer_key_note("KeyA"), Some(60));
+        assert_eq!(computer_key_note("KeyW"), Some(61));
+        assert_eq!(computer_key_note("KeyJ"), Some(71));
+        assert_eq!(computer_key_note("KeyK"), Some(72));
+        assert_eq!(computer_key_note("KeyQ"), None);
+        assert_eq!(computer_key_note("a"), None);
+    }
+}
@@ -0,0 +1,278 @@
+//! Click track on the beats of a file, for practicing along with it.
+
+use std::{f32::consts::TAU, time::Duration};
+
+use crate::midi::{BarBeat, MIDIFileData, TimeDivision, TimeSignature};
+
+const CLICK_LENGTH: Duration = Duration::from_millis(20);
+/// Time constant of the exponential decay of a click
+const CLICK_DECAY: Duration = Duration::from_micros(2500);
+const CLICK_FREQUENCY: f32 = 1000.0;
+/// Pitch of the clicks on the first beat of a bar, an octave above the others
+const ACCENT_FREQUENCY: f32 = 2000.0;
+/// Level of the clicks which aren't accented, relative to the accented ones
+const BEAT_LEVEL: f32 = 0.6;
+
+/// Click on a beat
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Click {
+    /// From the start of the file
+    pub time: Duration,
+    /// The beat is the first of its bar
+    pub accent: bool,
+}
+
+/// Bars of clicks played before a file starts, to count the player in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CountIn {
+    #[default]
+    Off,
+    OneBar,
+    TwoBars,
+}
+
+impl CountIn {
+    pub fn bars(self) -> u64 {
+        match self {
+            CountIn::Off => 0,
+            CountIn::OneBar => 1,
+            CountIn::TwoBars => 2,
+        }
+    }
+}
+
+/// Length of the count-in before `data`, and its clicks timed from the start of the count-in. It
+/// keeps the tempo and the meter the file starts with, whatever changes follow. `None` when it's
+/// off, or for files without beats to count, see `click_schedule`.
+pub fn count_in(data: &MIDIFileData, count_in: CountIn) -> Option<(Duration, Vec<Click>)> {
+    let TimeDivision::TicksPerBit(ticks_per_quarter) = *data.time_division() else {
+        return None;
+    };
+    if data.is_sequential() || count_in == CountIn::Off {
+        return None;
+    }
+
+    let time_signature = data
+        .time_signatures()
+        .first()
+        .filter(|&&(tick, _)| tick == 0)
+        .map_or_else(TimeSignature::default, |&(_, time_signature)| {
+            time_signature
+        });
+    let end_tick = time_signature.bar_ticks(ticks_per_quarter) * count_in.bars();
+    let tempo_map = data.tempo_map(0);
+    let clicks = BarBeat::beat_starts(end_tick, *data.time_division(), &[(0, time_signature)])
+        .into_iter()
+        .map(|(tick, accent)| Click {
+            time: tempo_map.initial_ticks_duration(tick),
+            accent,
+        })
+        .collect();
+    Some((tempo_map.initial_ticks_duration(end_tick), clicks))
+}
+
+/// Clicks on the beats of `data` before `end`, following its tempo map and time signature
+/// changes. Files with tracks played one after another, which may each have their own meter, and
+/// files timed in SMPTE frames have no beats to click on.
+pub fn click_schedule(data: &MIDIFileData, end: Duration) -> Vec<Click> {
+    if data.is_sequential() {
+        return vec![];
+    }
+
+    let tempo_map = data.tempo_map(0);
+    let end_tick = tempo_map.duration_to_tick(end.saturating_sub(tempo_map.start_offset()));
+    BarBeat::beat_starts(end_tick + 1, *data.time_division(), &data.time_signatures())
+        .into_iter()
+        .map(|(tick, accent)| Click {
+            time: tempo_map.start_offset() + tempo_map.tick_to_duration(tick),
+            accent,
+        })
+        .filter(|click| click.time < end)
+        .collect()
+}
+
+/// Samples of a click, a short decaying sine blip. Accented clicks are higher and louder.
+pub fn render_click(accent: bool, sample_rate: u32) -> Vec<f32> {
+    let (frequency, level) = if accent {
+        (ACCENT_FREQUENCY, 1.0)
+    } else {
+        (CLICK_FREQUENCY, BEAT_LEVEL)
+    };
+    let length = (CLICK_LENGTH.as_secs_f32() * sample_rate as f32) as usize;
+    (0..length)
+        .map(|i| {
+            let time = i as f32 / sample_rate as f32;
+            level * (-time / CLICK_DECAY.as_secs_f32()).exp() * (TAU * frequency * time).sin()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{
+        MIDIFormat, MetaEvent, Tempo, TimeDivision,
+        builder::{FileBuilder, TrackBuilder},
+    };
+
+    #[test]
+    fn accents_follow_meter_changes() {
+        let time_signature = |number| MetaEvent::TimeSignature {
+            number,
+            denom: 2,
+            metro: 24,
+            _32nds: 8,
+        };
+        // Two bars of 3/4 at 120 BPM, then two bars of 4/4 at 60 BPM
+        let data = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(
+            TrackBuilder::new()
+                .meta(time_signature(3))
+                .set_tempo(Tempo::from_bpm(120))
+                .note(0, 60, 100, 2 * 288 + 2 * 384)
+                .at(2 * 288)
+                .meta(time_signature(4))
+                .set_tempo(Tempo::from_bpm(60)),
+        )
+        .build();
+
+        let ms = Duration::from_millis;
+        let clicks = click_schedule(&data, ms(11_000));
+        let click = |time, accent| Click {
+            time: ms(time),
+            accent,
+        };
+        assert_eq!(
+            clicks,
+            vec![
+                click(0, true),
+                click(500, false),
+                click(1000, false),
+                click(1500, true),
+                click(2000, false),
+                click(2500, false),
+                click(3000, true),
+                click(4000, false),
+                click(5000, false),
+                click(6000, false),
+                click(7000, true),
+                click(8000, false),
+                click(9000, false),
+                click(10_000, false),
+            ]
+        );
+
+        // Only the beats before the end
+        assert_eq!(click_schedule(&data, ms(2000)), clicks[..4]);
+        assert_eq!(click_schedule(&data, Duration::ZERO), []);
+    }
+
+    #[test]
+    fn count_in_lasts_whole_bars_of_the_initial_meter_and_tempo() {
+        let file = |numerator, denominator_power, bpm| {
+            FileBuilder::new(
+                MIDIFormat::SingleMultiChannelTrack,
+                TimeDivision::TicksPerBit(96),
+            )
+            .track(
+                TrackBuilder::new()
+                    .meta(MetaEvent::TimeSignature {
+                        number: numerator,
+                        denom: denominator_power,
+                        metro: 24,
+                        _32nds: 8,
+                    })
+                    .set_tempo(Tempo::from_bpm(bpm))
+                    .note(0, 60, 100, 96)
+                    // Changes after the start don't matter
+                    .at(48)
+                    .meta(MetaEvent::TimeSignature {
+                        number: 5,
+                        denom: 2,
+                        metro: 24,
+                        _32nds: 8,
+                    })
+                    .set_tempo(Tempo::from_bpm(30)),
+            )
+            .build()
+        };
+        let ms = Duration::from_millis;
+        let beats = |count_in: Option<(Duration, Vec<Click>)>| {
+            count_in.map(|(duration, clicks)| {
+                let clicks = clicks
+                    .iter()
+                    .map(|click| (click.time.as_millis(), click.accent))
+                    .collect::<Vec<_>>();
+                (duration, clicks)
+            })
+        };
+
+        // A bar of 4/4 at 120 BPM lasts two seconds
+        assert_eq!(
+            beats(count_in(&file(4, 2, 120), CountIn::OneBar)),
+            Some((
+                ms(2000),
+                vec![(0, true), (500, false), (1000, false), (1500, false)]
+            ))
+        );
+        assert_eq!(count_in(&file(4, 2, 120), CountIn::Off), None);
+
+        // Two bars of 3/4 at 100 BPM
+        assert_eq!(
+            beats(count_in(&file(3, 2, 100), CountIn::TwoBars)),
+            Some((
+                ms(3600),
+                vec![
+                    (0, true),
+                    (600, false),
+                    (1200, false),
+                    (1800, true),
+                    (2400, false),
+                    (3000, false),
+                ]
+            ))
+        );
+
+        // 6/8 counts eighth notes, each lasting a quarter of a second at 120 BPM
+        let (duration, clicks) = beats(count_in(&file(6, 3, 120), CountIn::OneBar)).unwrap();
+        assert_eq!(duration, ms(1500));
+        assert_eq!(clicks.len(), 6);
+        assert_eq!(clicks[3], (750, false));
+
+        // Shortened along with the file when it's played faster
+        let mut data = file(4, 2, 120);
+        data.set_playback_rate(2.0);
+        assert_eq!(
+            count_in(&data, CountIn::TwoBars).map(|(duration, _)| duration),
+            Some(ms(2000))
+        );
+
+        // Files without a time signature or tempo are in 4/4 at 120 BPM
+        let data = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(TrackBuilder::new().note(0, 60, 100, 96))
+        .build();
+        assert_eq!(
+            count_in(&data, CountIn::OneBar).map(|(duration, _)| duration),
+            Some(ms(2000))
+        );
+    }
+
+    #[test]
+    fn accented_clicks_are_louder() {
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+        let accent = render_click(true, 48000);
+        let beat = render_click(false, 48000);
+        assert_eq!(accent.len(), 960);
+        assert_eq!(beat.len(), 960);
+        assert!(peak(&accent) > peak(&beat));
+        assert!(peak(&accent) <= 1.0);
+        // Faded out by the end, so it doesn't click when it stops
+        assert!(peak(&accent[900..]) < 1e-3);
+    }
+}
@@ -0,0 +1,107 @@
+//! Mixes per-channel buffers down to stereo.
+
+/// Pan position of a channel which didn't set one
+const CENTER: f32 = 0.0;
+
+/// Convert a pan controller (CC10) value to a position in [-1.0; 1.0], 64 being the center.
+pub fn pan_position(controller_value: u8) -> f32 {
+    ((controller_value as f32 - 64.0) / 63.0).clamp(-1.0, 1.0)
+}
+
+/// Left and right gains of a pan position, keeping the power constant
+fn pan_gains(pan: f32) -> (f32, f32) {
+    let angle = (pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+    (angle.cos(), angle.sin())
+}
+
+/// Mix buffers into a left and a right channel, with equal power panning.
+///
+/// `pans` holds the pan changes of each buffer, in the same layout as `buffers`, as sorted pairs of
+/// the sample they start at and a position in [-1.0; 1.0]. Buffers are centered until their first
+/// change. The mix is scaled down if summing the channels would clip.
+pub fn mix_down(
+    buffer_length: usize,
+    buffers: &[Vec<Vec<f32>>],
+    pans: &[Vec<Vec<(usize, f32)>>],
+) -> [Vec<f32>; 2] {
+    let mut left = vec![0.0f32; buffer_length];
+    let mut right = vec![0.0f32; buffer_length];
+
+    for (track_buffers, track_pans) in buffers.iter().zip(pans) {
+        for (buffer, pan_changes) in track_buffers.iter().zip(track_pans) {
+            let mut pan_changes = pan_changes.iter().peekable();
+            let (mut left_gain, mut right_gain) = pan_gains(CENTER);
+
+            for (i, &sample) in buffer.iter().enumerate().take(buffer_length) {
+                while let Some(&&(start_sample, pan)) = pan_changes.peek()
+                    && start_sample <= i
+                {
+                    (left_gain, right_gain) = pan_gains(pan);
+                    pan_changes.next();
+                }
+
+                left[i] += sample * left_gain;
+                right[i] += sample * right_gain;
+            }
+        }
+    }
+
+    let peak = left
+        .iter()
+        .chain(right.iter())
+        .fold(0.0f32, |acc, s| acc.max(s.abs()));
+    if peak > 1.0 {
+        for sample in left.iter_mut().chain(right.iter_mut()) {
+            *sample /= peak;
+        }
+    }
+
+    [left, right]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pan_controller_maps_to_position() {
+        assert_eq!(pan_position(0), -1.0);
+        assert_eq!(pan_position(64), 0.0);
+        assert_eq!(pan_position(127), 1.0);
+    }
+
+    #[test]
+    fn panning_keeps_equal_power() {
+        let buffers = vec![vec![vec![0.5; 4]], vec![vec![0.25; 4], vec![0.25; 4]]];
+        let pans = vec![vec![vec![]], vec![vec![(0, -1.0)], vec![(0, 1.0)]]];
+        let [left, right] = mix_down(4, &buffers, &pans);
+
+        let center = 0.5 * std::f32::consts::FRAC_1_SQRT_2;
+        assert!(left.iter().all(|s| (s - (center + 0.25)).abs() < 1e-6));
+        assert!(right.iter().all(|s| (s - (center + 0.25)).abs() < 1e-6));
+    }
+
+    #[test]
+    fn pan_changes_apply_from_their_sample() {
+        let buffers = vec![vec![vec![0.5; 4]]];
+        let pans = vec![vec![vec![(1, -1.0), (3, 1.0)]]];
+        let [left, right] = mix_down(4, &buffers, &pans);
+
+        let center = 0.5 * std::f32::consts::FRAC_1_SQRT_2;
+        assert!((left[0] - center).abs() < 1e-6 && (right[0] - center).abs() < 1e-6);
+        assert!(left[1..3].iter().all(|s| (s - 0.5).abs() < 1e-6));
+        assert!(right[1..3].iter().all(|s| s.abs() < 1e-6));
+        assert!(left[3].abs() < 1e-6 && (right[3] - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn loud_mix_is_normalized() {
+        let buffers = vec![vec![vec![1.0, -1.0], vec![1.0, -0.5]]];
+        let pans = vec![vec![vec![(0, -1.0)], vec![(0, -1.0)]]];
+        let [left, right] = mix_down(2, &buffers, &pans);
+
+        assert!((left[0] - 1.0).abs() < 1e-6);
+        assert!((left[1] + 0.75).abs() < 1e-6);
+        assert!(right.iter().all(|s| s.abs() < 1e-6));
+    }
+}
@@ -1,7 +1,177 @@
-#[allow(dead_code)]
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
+};
+
+use crate::midi::{
+    self, ChannelEventKind, Controller, ControllerKind, MIDIEventKind, MIDIFileData, MetaEvent, gm,
+};
+
+pub mod instruments;
+#[cfg(feature = "web")]
+pub mod live;
+pub mod metronome;
+pub mod mix;
+pub mod percussion;
 pub mod raw;
+pub mod wav;
+#[cfg(feature = "web")]
 pub mod web_audio;
 
+/// MIDI channels silenced before synthesis, by muting them or soloing another channel.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelFilter {
+    muted: HashSet<u8>,
+    /// When set, every other channel is silent
+    solo: Option<u8>,
+}
+
+impl ChannelFilter {
+    pub fn set_muted(&mut self, channel: u8, muted: bool) {
+        if muted {
+            self.muted.insert(channel);
+        } else {
+            self.muted.remove(&channel);
+        }
+    }
+
+    pub fn set_solo(&mut self, solo: Option<u8>) {
+        self.solo = solo;
+    }
+
+    pub fn is_muted(&self, channel: u8) -> bool {
+        self.muted.contains(&channel)
+    }
+
+    /// Whether the notes of `channel` should be synthesized. A soloed channel plays even if muted.
+    fn is_audible(&self, channel: u8) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted.contains(&channel),
+        }
+    }
+}
+
+/// Tracks left out of the synthesis. All tracks are enabled by default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackFilter {
+    disabled: HashSet<usize>,
+}
+
+impl TrackFilter {
+    pub fn set_enabled(&mut self, track_index: usize, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&track_index);
+        } else {
+            self.disabled.insert(track_index);
+        }
+    }
+
+    fn is_enabled(&self, track_index: usize) -> bool {
+        !self.disabled.contains(&track_index)
+    }
+}
+
+/// Notes sounding at once unless configured otherwise
+pub const DEFAULT_MAX_VOICES: usize = 32;
+
+/// Detune between the outermost unison oscillators and the note, in cents, unless configured
+/// otherwise
+pub const DEFAULT_UNISON_SPREAD: f32 = 12.0;
+
+/// Length after which songs are cut off unless configured otherwise. Only corrupt files, with
+/// huge delta times or slow tempos, run longer.
+pub const DEFAULT_MAX_DURATION: Duration = Duration::from_secs(4 * 60 * 60);
+
+/// Pan of the outermost unison oscillators, to the left and to the right
+const UNISON_PAN_SPREAD: f32 = 0.3;
+
+/// Settings a synthesizer is created with
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SynthSettings {
+    /// Notes which may sound at once. Starting another one steals the oldest of them, which fades
+    /// out just before. Drum hits are not counted.
+    pub max_voices: usize,
+    /// Oscillators playing each note of the Web Audio synthesizer. Each of them takes a voice.
+    pub unison: Unison,
+    /// Detune of the outermost unison oscillators, in cents
+    pub unison_spread: f32,
+    /// Songs are cut off after this time, so that corrupt files can't take up all the memory
+    pub max_duration: Duration,
+}
+
+impl Default for SynthSettings {
+    fn default() -> Self {
+        Self {
+            max_voices: DEFAULT_MAX_VOICES,
+            unison: Unison::default(),
+            unison_spread: DEFAULT_UNISON_SPREAD,
+            max_duration: DEFAULT_MAX_DURATION,
+        }
+    }
+}
+
+impl SynthSettings {
+    /// Notes which may sound at once when they are played in unison, so that their oscillators
+    /// don't outnumber the voices. At least one note always sounds.
+    fn unison_notes(&self) -> usize {
+        (self.max_voices / self.unison.oscillators()).max(1)
+    }
+}
+
+/// Oscillators playing each note, detuned from each other to thicken the sound
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum Unison {
+    #[default]
+    Off,
+    Two,
+    Three,
+}
+
+/// Oscillator of a note played in unison
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct UnisonOscillator {
+    /// In cents, added to the pitch bends
+    pub detune: f32,
+    /// From -1 (left) to 1 (right)
+    pub pan: f32,
+}
+
+impl Unison {
+    pub fn oscillators(self) -> usize {
+        match self {
+            Unison::Off => 1,
+            Unison::Two => 2,
+            Unison::Three => 3,
+        }
+    }
+
+    /// Detune and pan of each oscillator, spread evenly from the lowest and leftmost one, which
+    /// is `spread` cents flat, to the highest and rightmost one
+    fn spread(self, spread: f32) -> Vec<UnisonOscillator> {
+        let count = self.oscillators();
+        (0..count)
+            .map(|index| {
+                let position = match count {
+                    1 => 0.0,
+                    _ => index as f32 * 2.0 / (count - 1) as f32 - 1.0,
+                };
+                UnisonOscillator {
+                    detune: position * spread,
+                    pan: position * UNISON_PAN_SPREAD,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Gain of each of `oscillators` playing a note in unison. Detuned oscillators drift in and out
+/// of phase, so they add up in power rather than in amplitude, and keep the loudness of a single
+/// one at this gain.
+fn unison_gain(oscillators: usize) -> f32 {
+    (oscillators.max(1) as f32).sqrt().recip()
+}
+
 #[derive(Debug, Copy, Clone, Hash, Eq, PartialEq)]
 struct MidiNote {
     note: u8,
@@ -12,6 +182,11 @@ impl MidiNote {
         Self { note }
     }
 
+    /// The note shifted by the given number of semitones, kept within the MIDI range
+    fn transposed(&self, semitones: i8) -> Self {
+        Self::new((self.note as i16 + semitones as i16).clamp(0, 127) as u8)
+    }
+
     fn frequency(&self) -> f32 {
         const A4_FREQUENCY: f32 = 440.0;
         const A4_MIDI_NOTE: f32 = 69.0;
@@ -20,3 +195,1131 @@ impl MidiNote {
         A4_FREQUENCY * 2.0f32.powf((self.note as f32 - A4_MIDI_NOTE) / NOTE_COUNT)
     }
 }
+
+#[derive(Debug)]
+struct MidiTrackMeta {
+    /// Stores channel numbers. The index in this vector represents the continuous channel index
+    channel_idx: Vec<u8>,
+    /// Pan controller changes of each channel, relative to the track start, in the same order as
+    /// `channel_idx`
+    channel_pan: Vec<Vec<(Duration, u8)>>,
+    /// First program of each channel, in the same order as `channel_idx`
+    channel_programs: Vec<Option<u8>>,
+    duration: Duration,
+    /// Text of the first sequence/track name event
+    name: Option<String>,
+    /// Text of the first instrument name event without a channel prefix
+    instrument: Option<String>,
+    /// Text of the first instrument name event following a prefix of each channel
+    channel_instruments: HashMap<u8, String>,
+    /// Number of notes played by the track
+    note_count: usize,
+    /// Delay before the first tick, from the SMPTE offset event
+    start_offset: Duration,
+}
+
+impl MidiTrackMeta {
+    fn new(
+        channel_idx: Vec<u8>,
+        channel_pan: Vec<Vec<(Duration, u8)>>,
+        channel_programs: Vec<Option<u8>>,
+        duration: Duration,
+        name: Option<String>,
+        instrument: Option<String>,
+        channel_instruments: HashMap<u8, String>,
+        note_count: usize,
+        start_offset: Duration,
+    ) -> Self {
+        Self {
+            channel_idx,
+            channel_pan,
+            channel_programs,
+            duration,
+            name,
+            instrument,
+            channel_instruments,
+            note_count,
+            start_offset,
+        }
+    }
+
+    /// Time taken by the track, including its start offset
+    fn span(&self) -> Duration {
+        self.start_offset.saturating_add(self.duration)
+    }
+
+    fn channel_index(&self, channel: u8) -> usize {
+        self.channel_idx
+            .iter()
+            .position(|&ch| ch == channel)
+            .expect("channel is not part of this track")
+    }
+}
+
+/// Description of a track, for showing it to the user
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackInfo {
+    pub name: Option<String>,
+    pub instrument: Option<String>,
+    /// MIDI channels used by the track, in ascending order
+    pub channels: Vec<u8>,
+    /// First program selected on each of `channels`, if any
+    pub programs: Vec<Option<u8>>,
+    /// Instrument named for each of `channels` through a channel prefix, if any
+    pub channel_instruments: Vec<Option<String>>,
+    pub note_count: usize,
+    pub duration: Duration,
+}
+
+impl TrackInfo {
+    /// Channels of the track with their instruments, such as "Ch 1: Acoustic Grand Piano".
+    /// Instruments named by the file come first, and channels without program changes play the
+    /// first program.
+    pub fn channel_labels(&self) -> Vec<String> {
+        self.channels
+            .iter()
+            .zip(&self.programs)
+            .zip(&self.channel_instruments)
+            .map(|((channel, program), name)| {
+                let instrument = match name {
+                    Some(name) => name,
+                    None if *channel == percussion::PERCUSSION_CHANNEL => "Percussion",
+                    None => gm::program_name(program.unwrap_or(0)),
+                };
+                format!("Ch {}: {instrument}", channel + 1)
+            })
+            .collect()
+    }
+}
+
+/// Label of a song section, from a marker or cue point event
+#[derive(Debug, Clone, PartialEq)]
+pub struct Marker {
+    /// Time from the start of the file
+    pub time: Duration,
+    pub label: String,
+}
+
+#[derive(Debug)]
+struct MidiMeta {
+    tracks: Vec<MidiTrackMeta>,
+    /// Tracks are played one after another (format 2)
+    sequential: bool,
+    /// Text of the first copyright notice event of any track
+    copyright: Option<String>,
+    /// Markers and cue points of all tracks, sorted by time
+    markers: Vec<Marker>,
+    /// Time after which the song is cut off
+    max_duration: Duration,
+}
+
+impl MidiMeta {
+    fn new(data: &MIDIFileData, max_duration: Duration) -> Self {
+        let mut tracks = vec![];
+        let mut copyright = None;
+        let mut markers = vec![];
+        for track_index in 0..data.tracks().len() {
+            let track_start = data.track_start(track_index);
+            let mut channels = HashMap::<u8, (Vec<(Duration, u8)>, Option<u8>)>::new();
+            let mut name = None;
+            let mut instrument = None;
+            let mut channel_instruments = HashMap::<u8, String>::new();
+            // Channel of the last channel prefix, which applies to the meta events following it
+            // until the next channel event
+            let mut prefix_channel = None;
+            let mut note_count = 0;
+
+            let mut walker = data.walk_track(track_index);
+            for (time, _, event) in walker.by_ref() {
+                match event.kind() {
+                    MIDIEventKind::Channel(channel_event) => {
+                        prefix_channel = None;
+                        let (pan, program) = channels.entry(channel_event.channel()).or_default();
+                        match channel_event.kind() {
+                            ChannelEventKind::Controller {
+                                controller_number,
+                                controller_value,
+                            } if ControllerKind::from(*controller_number)
+                                == ControllerKind::Pan =>
+                            {
+                                pan.push((time - track_start, *controller_value))
+                            }
+                            ChannelEventKind::NoteOn { velocity, .. } if *velocity > 0 => {
+                                note_count += 1;
+                            }
+                            ChannelEventKind::ProgramChange { program_number } => {
+                                program.get_or_insert(*program_number);
+                            }
+                            _ => {}
+                        }
+                    }
+                    MIDIEventKind::Meta(MetaEvent::SequenceTrackName { text }) => {
+                        name.get_or_insert_with(|| midi::decode_text(text));
+                    }
+                    MIDIEventKind::Meta(MetaEvent::ChannelPrefix { channel }) => {
+                        prefix_channel = (*channel < 16).then_some(*channel);
+                    }
+                    MIDIEventKind::Meta(MetaEvent::InstrumentName { text }) => match prefix_channel
+                    {
+                        Some(channel) => {
+                            channel_instruments
+                                .entry(channel)
+                                .or_insert_with(|| midi::decode_text(text));
+                        }
+                        None => {
+                            instrument.get_or_insert_with(|| midi::decode_text(text));
+                        }
+                    },
+                    MIDIEventKind::Meta(MetaEvent::CopyrightNotice { text }) => {
+                        copyright.get_or_insert_with(|| midi::decode_text(text));
+                    }
+                    MIDIEventKind::Meta(
+                        MetaEvent::Marker { text } | MetaEvent::CuePoint { text },
+                    ) => markers.push(Marker {
+                        time,
+                        label: midi::decode_text(text),
+                    }),
+                    MIDIEventKind::Meta(MetaEvent::EndOfTrack)
+                    | MIDIEventKind::Meta(MetaEvent::SetTempo { .. })
+                    | MIDIEventKind::Meta(MetaEvent::SMPTEOffset { .. })
+                    | MIDIEventKind::Meta(MetaEvent::Lyrics { .. }) => {
+                        // Ignored
+                    }
+                    MIDIEventKind::Meta(_) => {
+                        log::warn!("Unhandled meta in meta collection event: {event:?}")
+                    }
+                    MIDIEventKind::SysEx(_) => {
+                        // Ignored
+                    }
+                }
+            }
+
+            let (channel_idx, (channel_pan, channel_programs)) = channels.into_iter().unzip();
+            tracks.push(MidiTrackMeta::new(
+                channel_idx,
+                channel_pan,
+                channel_programs,
+                walker.time() - track_start,
+                name,
+                instrument,
+                channel_instruments,
+                note_count,
+                data.tempo_map(track_index).start_offset(),
+            ));
+        }
+
+        // Stable, so markers at the same time keep their order
+        markers.sort_by_key(|marker| marker.time);
+
+        let meta = Self {
+            tracks,
+            sequential: data.is_sequential(),
+            copyright,
+            markers,
+            max_duration,
+        };
+        if meta.song_duration() > max_duration {
+            log::warn!(
+                "the song lasts {} s, only the first {} s are played",
+                meta.song_duration().as_secs(),
+                max_duration.as_secs()
+            );
+        }
+        meta
+    }
+
+    /// Time until the last track ends, whatever `max_duration`
+    fn song_duration(&self) -> Duration {
+        let durations = self.tracks.iter().map(MidiTrackMeta::span);
+        if self.sequential {
+            durations.fold(Duration::ZERO, Duration::saturating_add)
+        } else {
+            durations.max().unwrap_or_default()
+        }
+    }
+
+    fn total_duration(&self) -> Duration {
+        self.song_duration().min(self.max_duration)
+    }
+
+    /// MIDI channels used by any of the tracks, in ascending order
+    fn channels(&self) -> Vec<u8> {
+        let mut channels = self
+            .tracks
+            .iter()
+            .flat_map(|track| track.channel_idx.iter().copied())
+            .collect::<Vec<_>>();
+        channels.sort_unstable();
+        channels.dedup();
+        channels
+    }
+
+    /// Name of every track, falling back to "Track N" for unnamed ones
+    fn track_names(&self) -> Vec<String> {
+        self.tracks
+            .iter()
+            .enumerate()
+            .map(|(track_index, track)| {
+                track
+                    .name
+                    .clone()
+                    .unwrap_or_else(|| format!("Track {}", track_index + 1))
+            })
+            .collect()
+    }
+
+    /// Description of every track, in order
+    fn track_infos(&self) -> Vec<TrackInfo> {
+        self.tracks
+            .iter()
+            .map(|track| {
+                let mut channels = track
+                    .channel_idx
+                    .iter()
+                    .copied()
+                    .zip(track.channel_programs.iter().copied())
+                    .collect::<Vec<_>>();
+                channels.sort_unstable();
+                let (channels, programs): (Vec<_>, _) = channels.into_iter().unzip();
+                let channel_instruments = channels
+                    .iter()
+                    .map(|channel| track.channel_instruments.get(channel).cloned())
+                    .collect();
+
+                TrackInfo {
+                    name: track.name.clone(),
+                    instrument: track.instrument.clone(),
+                    channels,
+                    programs,
+                    channel_instruments,
+                    note_count: track.note_count,
+                    duration: track.duration,
+                }
+            })
+            .collect()
+    }
+
+    /// Time at which the given track starts playing
+    fn track_start(&self, track_index: usize) -> Duration {
+        let previous_tracks = if self.sequential {
+            self.tracks[..track_index]
+                .iter()
+                .map(MidiTrackMeta::span)
+                .fold(Duration::ZERO, Duration::saturating_add)
+        } else {
+            Duration::ZERO
+        };
+
+        previous_tracks.saturating_add(self.tracks[track_index].start_offset)
+    }
+}
+
+/// Volume and expression controllers of a channel, applied on top of note velocities.
+///
+/// Both start at their maximum, so files which don't use them play at full velocity.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct ChannelGain {
+    volume: u8,
+    expression: u8,
+}
+
+impl Default for ChannelGain {
+    fn default() -> Self {
+        Self {
+            volume: 127,
+            expression: 127,
+        }
+    }
+}
+
+impl ChannelGain {
+    /// Apply a volume or expression controller change. Other controllers are ignored.
+    fn update(&mut self, controller: Controller) {
+        match controller.kind() {
+            ControllerKind::Volume => self.volume = controller.value(),
+            ControllerKind::Expression => self.expression = controller.value(),
+            _ => {}
+        }
+    }
+
+    fn gain(&self) -> f32 {
+        (self.volume as f32 / 127.0) * (self.expression as f32 / 127.0)
+    }
+}
+
+/// Vibrato applied by the modulation wheel
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Vibrato {
+    /// Pitch deviation in cents with the modulation wheel all the way up
+    pub depth: f32,
+    /// Frequency of the pitch oscillation, in Hz
+    pub rate: f32,
+}
+
+impl Default for Vibrato {
+    fn default() -> Self {
+        Self {
+            depth: 50.0,
+            rate: 5.0,
+        }
+    }
+}
+
+impl Vibrato {
+    /// Pitch deviation in cents for the given modulation wheel value
+    fn depth(&self, modulation: u8) -> f32 {
+        self.depth * modulation as f32 / 127.0
+    }
+}
+
+/// Pitch bend range used unless configured otherwise, in semitones
+const DEFAULT_PITCH_BEND_RANGE: f32 = 2.0;
+
+/// Pitch bend changes of a channel, in cents, sorted by time
+#[derive(Debug, Default)]
+struct PitchBends {
+    changes: Vec<(Duration, f32)>,
+}
+
+impl PitchBends {
+    fn push(&mut self, time: Duration, cents: f32) {
+        self.changes.push((time, cents));
+    }
+
+    /// Detune of a note played from `start` to `end`: the bend in effect at `start`, followed by
+    /// the changes made while the note sounds.
+    fn automation(&self, start: Duration, end: Duration) -> Vec<(Duration, f32)> {
+        let initial = self
+            .changes
+            .iter()
+            .take_while(|(time, _)| *time <= start)
+            .last()
+            .map_or(0.0, |(_, cents)| *cents);
+
+        std::iter::once((start, initial))
+            .chain(
+                self.changes
+                    .iter()
+                    .copied()
+                    .filter(|(time, _)| *time > start && *time < end),
+            )
+            .collect()
+    }
+}
+
+/// Amplitude added to a note at full aftertouch pressure
+const AFTERTOUCH_GAIN: f32 = 0.5;
+/// Raise of the cutoff of the filter of a note at full aftertouch pressure, in cents
+const AFTERTOUCH_BRIGHTNESS: f32 = 1200.0;
+/// Time constant of the approach to a new pressure, so that notes don't swell in steps
+const AFTERTOUCH_SMOOTHING: Duration = Duration::from_millis(20);
+
+/// Gain of a note under an aftertouch `pressure`
+fn aftertouch_gain(pressure: u8) -> f32 {
+    1.0 + AFTERTOUCH_GAIN * pressure.min(127) as f32 / 127.0
+}
+
+/// Cutoff raise of the filter of a note under an aftertouch `pressure`, in cents
+fn aftertouch_brightness(pressure: u8) -> f32 {
+    AFTERTOUCH_BRIGHTNESS * pressure.min(127) as f32 / 127.0
+}
+
+/// Aftertouch of a channel, sorted by time. Channel aftertouch presses every note of the channel,
+/// polyphonic aftertouch a single key.
+#[derive(Debug, Default)]
+struct Pressures {
+    channel: Vec<(Duration, u8)>,
+    keys: HashMap<u8, Vec<(Duration, u8)>>,
+}
+
+impl Pressures {
+    fn push_channel(&mut self, time: Duration, pressure: u8) {
+        self.channel.push((time, pressure));
+    }
+
+    fn push_key(&mut self, time: Duration, key: u8, pressure: u8) {
+        self.keys.entry(key).or_default().push((time, pressure));
+    }
+
+    /// Pressure on a note of `key` played from `start` to `end`: the one in effect at `start`,
+    /// followed by the changes made while the note sounds. The key is pressed by the larger of
+    /// the channel and key pressures, and a key pressure only applies from the start of the note.
+    /// Empty when the note is never pressed.
+    fn automation(&self, key: u8, start: Duration, end: Duration) -> Vec<(Duration, u8)> {
+        let key_changes = self.keys.get(&key).map_or(&[][..], Vec::as_slice);
+        let during = |changes: &[(Duration, u8)]| {
+            changes
+                .iter()
+                .copied()
+                .filter(|(time, _)| *time >= start && *time < end)
+                .collect::<Vec<_>>()
+        };
+        let channel_changes = during(&self.channel);
+        let key_changes = during(key_changes);
+
+        let mut channel_pressure = self
+            .channel
+            .iter()
+            .take_while(|(time, _)| *time < start)
+            .last()
+            .map_or(0, |(_, pressure)| *pressure);
+        let mut key_pressure = 0;
+
+        let mut times = channel_changes
+            .iter()
+            .chain(&key_changes)
+            .map(|(time, _)| *time)
+            .collect::<Vec<_>>();
+        times.sort_unstable();
+        times.dedup();
+
+        let mut automation = vec![(start, channel_pressure)];
+        let (mut channel_changes, mut key_changes) = (
+            channel_changes.iter().peekable(),
+            key_changes.iter().peekable(),
+        );
+        for time in times {
+            while let Some((_, pressure)) = channel_changes.next_if(|(change, _)| *change == time) {
+                channel_pressure = *pressure;
+            }
+            while let Some((_, pressure)) = key_changes.next_if(|(change, _)| *change == time) {
+                key_pressure = *pressure;
+            }
+
+            let pressure = channel_pressure.max(key_pressure);
+            match automation.last_mut() {
+                Some(last) if last.1 == pressure => {}
+                Some(last) if last.0 == time => last.1 = pressure,
+                _ => automation.push((time, pressure)),
+            }
+        }
+
+        if automation.iter().all(|(_, pressure)| *pressure == 0) {
+            automation.clear();
+        }
+        automation
+    }
+}
+
+/// Sustain pedal and all notes off events of a channel, which decide how long released notes
+/// keep sounding
+#[derive(Debug, Default)]
+struct Damper {
+    /// Presses (`true`) and releases of the pedal, sorted by time
+    pedal: Vec<(Duration, bool)>,
+    notes_off: Vec<Duration>,
+}
+
+impl Damper {
+    /// End of a note released at `release`. With the pedal down it sounds until the pedal is
+    /// lifted, the notes of the channel are turned off, it is struck again or the track ends.
+    fn sounding_end(
+        &self,
+        release: Duration,
+        restrike: Option<Duration>,
+        track_end: Duration,
+    ) -> Duration {
+        let pedal_down = self
+            .pedal
+            .iter()
+            .take_while(|(time, _)| *time <= release)
+            .last()
+            .is_some_and(|(_, down)| *down);
+        if !pedal_down {
+            return release;
+        }
+
+        let pedal_up = self
+            .pedal
+            .iter()
+            .find(|(time, down)| *time > release && !down)
+            .map(|(time, _)| *time);
+        let notes_off = self.notes_off.iter().copied().find(|time| *time >= release);
+
+        [pedal_up, notes_off, restrike]
+            .into_iter()
+            .flatten()
+            .fold(track_end, Duration::min)
+    }
+}
+
+/// Fade out of a stolen note, ending where the note taking its voice starts
+const STEAL_FADE: Duration = Duration::from_millis(5);
+
+/// Time a note occupies a voice
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct Voice {
+    start: Duration,
+    /// When the note is released, and its envelope starts fading out
+    release: Duration,
+    /// Length of the fade out after the release
+    release_length: Duration,
+    /// When the note stops sounding, at the end of the fade out unless cut short
+    end: Duration,
+}
+
+impl Voice {
+    /// Release the note in time to be silent at `time`
+    fn steal(&mut self, time: Duration) {
+        self.release = self
+            .release
+            .min(time.saturating_sub(STEAL_FADE))
+            .max(self.start);
+        self.release_length = self.release_length.min(time - self.release);
+        self.end = self.end.min(time);
+    }
+}
+
+/// Shorten notes so that no more than `max_voices` of them sound at once. When a note starts with
+/// all voices taken, the oldest sounding note is stolen. Notes starting at once are stolen in
+/// order, and a limit of 0 is taken as 1.
+fn steal_voices(voices: &mut [Voice], max_voices: usize) {
+    let max_voices = max_voices.max(1);
+    let mut order = (0..voices.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&index| voices[index].start);
+
+    // Notes sounding at the start of the current one, oldest first
+    let mut sounding = VecDeque::<usize>::new();
+    for index in order {
+        let start = voices[index].start;
+        sounding.retain(|&other| voices[other].end > start);
+        while sounding.len() >= max_voices
+            && let Some(stolen) = sounding.pop_front()
+        {
+            voices[stolen].steal(start);
+        }
+        sounding.push_back(index);
+    }
+}
+
+/// Maps note velocity onto amplitude
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum VelocityCurve {
+    #[default]
+    Linear,
+    Squared,
+}
+
+impl VelocityCurve {
+    fn gain(&self, velocity: u8) -> f32 {
+        let velocity = velocity as f32 / 127.0;
+        match self {
+            VelocityCurve::Linear => velocity,
+            VelocityCurve::Squared => velocity * velocity,
+        }
+    }
+}
+
+/// Attack, decay, sustain, release amplitude envelope applied to every note.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Envelope {
+    pub attack: Duration,
+    pub decay: Duration,
+    /// Level held after the decay, in [0.0; 1.0]
+    pub sustain: f32,
+    pub release: Duration,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            attack: Duration::from_millis(5),
+            decay: Duration::ZERO,
+            sustain: 1.0,
+            release: Duration::from_millis(50),
+        }
+    }
+}
+
+impl Envelope {
+    /// Level of a held note, `time` seconds after it started
+    fn level(&self, time: f32) -> f32 {
+        let attack = self.attack.as_secs_f32();
+        let decay = self.decay.as_secs_f32();
+
+        if time < attack {
+            time / attack
+        } else if time < attack + decay {
+            1.0 - (1.0 - self.sustain) * (time - attack) / decay
+        } else {
+            self.sustain
+        }
+    }
+
+    /// Level automation of a note released `duration` after it started, as pairs of the time since
+    /// the note start and the level to reach linearly by then.
+    ///
+    /// Stages cut short by the release are clamped to it, so times never go backwards.
+    fn automation(&self, duration: Duration) -> Vec<(Duration, f32)> {
+        let level = |time: Duration| self.level(time.as_secs_f32());
+        let attack_end = self.attack.min(duration);
+        let decay_end = (self.attack + self.decay).min(duration);
+
+        let mut points: Vec<(Duration, f32)> = vec![];
+        for point in [
+            (Duration::ZERO, 0.0),
+            (attack_end, level(attack_end)),
+            (decay_end, level(decay_end)),
+            (duration, level(duration)),
+        ] {
+            // Of points at the same time, the last one wins
+            match points.last_mut() {
+                Some(last) if last.0 == point.0 => *last = point,
+                _ => points.push(point),
+            }
+        }
+
+        // Without a release, the level drops at once rather than fading over the previous stage
+        let release = (duration + self.release, 0.0);
+        if points.last() != Some(&release) {
+            points.push(release);
+        }
+
+        points
+    }
+
+    /// Level automation of a note held until it is released at an unknown time, up to its
+    /// sustain
+    fn hold_automation(&self) -> Vec<(Duration, f32)> {
+        let mut points = self.automation(self.attack + self.decay);
+        // The release is up to the player
+        points.pop();
+        points
+    }
+}
+
+/// Times the cutoff a [`FilterEnvelope`] opens at
+const FILTER_ENVELOPE_RANGE: f32 = 16.0;
+
+/// Low-pass filter of each note, closing from four octaves above the cutoff down to it over its
+/// attack, for a plucked sound.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FilterEnvelope {
+    /// Frequency the filter settles at, in Hz
+    pub cutoff: f32,
+    /// Resonance, in dB as the Q of a Web Audio low-pass filter
+    pub q: f32,
+    pub attack: Duration,
+}
+
+impl FilterEnvelope {
+    pub fn new(cutoff: f32, q: f32) -> Self {
+        Self {
+            cutoff,
+            q,
+            attack: Duration::from_millis(250),
+        }
+    }
+
+    /// Cutoff automation of a note, as the frequency it starts at and the one it reaches
+    /// exponentially by the end of the attack, both kept from 1 Hz up to `nyquist`
+    fn automation(&self, nyquist: f32) -> [(Duration, f32); 2] {
+        let frequency = |frequency: f32| frequency.clamp(1.0, nyquist.max(1.0));
+        [
+            (
+                Duration::ZERO,
+                frequency(self.cutoff * FILTER_ENVELOPE_RANGE),
+            ),
+            (self.attack, frequency(self.cutoff)),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::midi::{
+        MIDIFormat, Tempo, TimeDivision,
+        builder::{FileBuilder, TrackBuilder},
+    };
+
+    #[test]
+    fn unison_oscillators_are_spread_around_the_note() {
+        assert_eq!(
+            Unison::Off.spread(10.0),
+            vec![UnisonOscillator {
+                detune: 0.0,
+                pan: 0.0
+            }]
+        );
+        assert_eq!(
+            Unison::Two.spread(10.0),
+            vec![
+                UnisonOscillator {
+                    detune: -10.0,
+                    pan: -UNISON_PAN_SPREAD
+                },
+                UnisonOscillator {
+                    detune: 10.0,
+                    pan: UNISON_PAN_SPREAD
+                },
+            ]
+        );
+        let three = Unison::Three.spread(10.0);
+        assert_eq!(
+            three
+                .iter()
+                .map(|oscillator| oscillator.detune)
+                .collect::<Vec<_>>(),
+            vec![-10.0, 0.0, 10.0]
+        );
+        assert_eq!(three[1].pan, 0.0);
+
+        // The power of the oscillators adds up to the one of a single oscillator
+        for unison in [Unison::Off, Unison::Two, Unison::Three] {
+            let power = unison.oscillators() as f32 * unison_gain(unison.oscillators()).powi(2);
+            assert!((power - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn unison_oscillators_take_voices() {
+        let settings = |max_voices, unison| SynthSettings {
+            max_voices,
+            unison,
+            ..SynthSettings::default()
+        };
+        assert_eq!(settings(32, Unison::Off).unison_notes(), 32);
+        assert_eq!(settings(32, Unison::Two).unison_notes(), 16);
+        assert_eq!(settings(32, Unison::Three).unison_notes(), 10);
+        // No more oscillators than voices, but always a note
+        assert_eq!(settings(2, Unison::Three).unison_notes(), 1);
+        assert_eq!(settings(0, Unison::Two).unison_notes(), 1);
+    }
+
+    #[test]
+    fn filter_envelopes_close_down_to_the_cutoff() {
+        let filter_envelope = FilterEnvelope::new(500.0, 3.0);
+        let attack = filter_envelope.attack;
+        assert_eq!(
+            filter_envelope.automation(22050.0),
+            [(Duration::ZERO, 8000.0), (attack, 500.0)]
+        );
+
+        // Frequencies past the Nyquist frequency are the same as it
+        assert_eq!(
+            FilterEnvelope::new(5000.0, 0.0).automation(22050.0),
+            [(Duration::ZERO, 22050.0), (attack, 5000.0)]
+        );
+        assert_eq!(
+            FilterEnvelope::new(30000.0, 0.0).automation(22050.0),
+            [(Duration::ZERO, 22050.0), (attack, 22050.0)]
+        );
+        // Exponential ramps can't reach zero
+        assert_eq!(
+            FilterEnvelope::new(0.0, 0.0).automation(22050.0),
+            [(Duration::ZERO, 1.0), (attack, 1.0)]
+        );
+    }
+
+    #[test]
+    fn envelope_automation_is_clamped_for_short_notes() {
+        let envelope = Envelope {
+            attack: Duration::from_millis(5),
+            decay: Duration::from_millis(10),
+            sustain: 0.5,
+            release: Duration::from_millis(50),
+        };
+        let ms = Duration::from_millis;
+
+        assert_eq!(
+            envelope.automation(ms(100)),
+            vec![
+                (ms(0), 0.0),
+                (ms(5), 1.0),
+                (ms(15), 0.5),
+                (ms(100), 0.5),
+                (ms(150), 0.0)
+            ]
+        );
+
+        // a 10 ms note is released during its decay
+        let points = envelope.automation(ms(10));
+        assert_eq!(points.len(), 4);
+        assert_eq!(points[1], (ms(5), 1.0));
+        assert_eq!(points[2].0, ms(10));
+        assert!((points[2].1 - 0.75).abs() < 1e-6);
+        assert_eq!(points[3], (ms(60), 0.0));
+
+        // a note shorter than the attack never reaches the full level
+        let points = envelope.automation(ms(2));
+        assert_eq!(points.len(), 3);
+        assert!((points[1].1 - 0.4).abs() < 1e-6);
+        assert!(points.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        // a note cut by All Sound Off drops to silence at its release
+        let cut = Envelope {
+            release: Duration::ZERO,
+            ..envelope
+        };
+        assert_eq!(
+            cut.automation(ms(100)),
+            vec![
+                (ms(0), 0.0),
+                (ms(5), 1.0),
+                (ms(15), 0.5),
+                (ms(100), 0.5),
+                (ms(100), 0.0)
+            ]
+        );
+
+        assert_eq!(
+            envelope.hold_automation(),
+            vec![(ms(0), 0.0), (ms(5), 1.0), (ms(15), 0.5)]
+        );
+    }
+
+    #[test]
+    fn the_oldest_voices_are_stolen() {
+        let ms = Duration::from_millis;
+        let voice = |start, release| Voice {
+            start: ms(start),
+            release: ms(release),
+            release_length: ms(50),
+            end: ms(release + 50),
+        };
+        let mut voices = [
+            voice(0, 1000),
+            voice(100, 1000),
+            voice(200, 300),
+            voice(400, 1000),
+            voice(500, 1000),
+        ];
+
+        steal_voices(&mut voices, 2);
+        // The third note finds both voices taken, the fourth one the third note ended, and the
+        // fifth one the second and fourth notes sounding
+        let stolen = |start, time| Voice {
+            start: ms(start),
+            release: ms(time - 5),
+            release_length: ms(5),
+            end: ms(time),
+        };
+        assert_eq!(
+            voices,
+            [
+                stolen(0, 200),
+                stolen(100, 500),
+                voice(200, 300),
+                voice(400, 1000),
+                voice(500, 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn transposing_by_an_octave_doubles_the_frequency() {
+        let note = MidiNote::new(57);
+        assert_eq!(note.transposed(12).frequency(), 2.0 * note.frequency());
+        assert_eq!(note.transposed(-12).frequency(), 0.5 * note.frequency());
+        assert_eq!(MidiNote::new(120).transposed(12), MidiNote::new(127));
+    }
+
+    #[test]
+    fn pitch_bend_mid_note_is_automated() {
+        let mut bends = PitchBends::default();
+        bends.push(Duration::from_millis(100), -50.0);
+        bends.push(Duration::from_millis(500), 100.0);
+        bends.push(Duration::from_millis(900), 0.0);
+
+        // a note from 200ms to 800ms starts bent, and bends once more while it sounds
+        assert_eq!(
+            bends.automation(Duration::from_millis(200), Duration::from_millis(800)),
+            vec![
+                (Duration::from_millis(200), -50.0),
+                (Duration::from_millis(500), 100.0)
+            ]
+        );
+        assert_eq!(
+            bends.automation(Duration::ZERO, Duration::from_millis(50)),
+            vec![(Duration::ZERO, 0.0)]
+        );
+    }
+
+    #[test]
+    fn aftertouch_combines_channel_and_key_pressures() {
+        let ms = Duration::from_millis;
+        let mut pressures = Pressures::default();
+        pressures.push_channel(ms(100), 40);
+        pressures.push_key(ms(150), 60, 90);
+        pressures.push_key(ms(250), 62, 127);
+        pressures.push_channel(ms(300), 100);
+        pressures.push_key(ms(400), 60, 0);
+
+        // the channel pressure carries over into the note, the key pressure is of the note only
+        assert_eq!(
+            pressures.automation(60, ms(200), ms(500)),
+            vec![(ms(200), 40), (ms(300), 100)]
+        );
+        assert_eq!(
+            pressures.automation(60, ms(120), ms(350)),
+            vec![(ms(120), 40), (ms(150), 90), (ms(300), 100)]
+        );
+        assert_eq!(
+            pressures.automation(62, ms(200), ms(280)),
+            vec![(ms(200), 40), (ms(250), 127)]
+        );
+
+        // notes which are never pressed have no automation
+        assert_eq!(pressures.automation(60, ms(0), ms(100)), vec![]);
+        assert_eq!(aftertouch_gain(0), 1.0);
+        assert_eq!(aftertouch_gain(127), 1.0 + AFTERTOUCH_GAIN);
+    }
+
+    #[test]
+    fn track_infos_decode_legacy_names() {
+        let midi_bytes = include_bytes!("../assets/track_info.mid");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let meta = MidiMeta::new(&data, DEFAULT_MAX_DURATION);
+
+        assert_eq!(meta.copyright.as_deref(), Some("© 1995 Syntezator"));
+        assert_eq!(
+            meta.track_infos(),
+            vec![
+                TrackInfo {
+                    // Latin-1 encoded in the file
+                    name: Some("Café Élégant".to_string()),
+                    instrument: Some("Piano".to_string()),
+                    channels: vec![0, 2],
+                    programs: vec![None, None],
+                    channel_instruments: vec![None, None],
+                    note_count: 3,
+                    duration: Duration::from_millis(1500),
+                },
+                TrackInfo {
+                    name: Some("Bass ♪".to_string()),
+                    instrument: None,
+                    channels: vec![1],
+                    programs: vec![None],
+                    channel_instruments: vec![None],
+                    note_count: 1,
+                    duration: Duration::from_millis(1000),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn track_infos_name_the_instruments_of_their_channels() {
+        let data = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(
+            TrackBuilder::new()
+                .program_change(1, 40)
+                .note_on(9, 36, 100)
+                .note_on(0, 60, 100)
+                .note_on(1, 67, 100)
+                .program_change(1, 41)
+                .at(96)
+                .note_off(0, 60, 0),
+        )
+        .build();
+        let infos = MidiMeta::new(&data, DEFAULT_MAX_DURATION).track_infos();
+
+        assert_eq!(infos[0].channels, [0, 1, 9]);
+        assert_eq!(infos[0].programs, [None, Some(40), None]);
+        assert_eq!(
+            infos[0].channel_labels(),
+            [
+                "Ch 1: Acoustic Grand Piano",
+                "Ch 2: Violin",
+                "Ch 10: Percussion"
+            ]
+        );
+    }
+
+    #[test]
+    fn channel_prefixes_scope_instrument_names() {
+        let midi_bytes = include_bytes!("../assets/channel_prefix.mid");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let infos = MidiMeta::new(&data, DEFAULT_MAX_DURATION).track_infos();
+
+        assert_eq!(infos[0].channels, [0, 1]);
+        assert_eq!(
+            infos[0].channel_instruments,
+            [Some("Piano".to_string()), Some("Bass".to_string())]
+        );
+        assert_eq!(infos[0].channel_labels(), ["Ch 1: Piano", "Ch 2: Bass"]);
+
+        // The prefix ends with the first channel event
+        assert_eq!(infos[0].instrument.as_deref(), Some("Strings"));
+    }
+
+    #[test]
+    fn markers_are_timed_through_tempo_changes() {
+        let midi_bytes = include_bytes!("../assets/markers.mid");
+        let data = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let meta = MidiMeta::new(&data, DEFAULT_MAX_DURATION);
+
+        let markers = meta
+            .markers
+            .iter()
+            .map(|marker| (marker.time.as_millis(), marker.label.as_str()))
+            .collect::<Vec<_>>();
+        // 120 BPM for two beats, then 60 BPM for two beats, then 240 BPM
+        assert_eq!(
+            markers,
+            [
+                (0, "Intro"),
+                (1000, "Verse"),
+                (2000, "Bridge"),
+                (3000, "Solo"),
+                (3250, "Chorus"),
+            ]
+        );
+    }
+
+    #[test]
+    fn meta_durations_match_the_file() {
+        for midi_bytes in [
+            &include_bytes!("../assets/test.mid")[..],
+            &include_bytes!("../assets/format2.mid")[..],
+            &include_bytes!("../assets/smpte_offset.mid")[..],
+        ] {
+            let data = MIDIFileData::try_from(midi_bytes).unwrap();
+            let meta = MidiMeta::new(&data, DEFAULT_MAX_DURATION);
+
+            assert_eq!(meta.total_duration(), data.duration());
+            for track_index in 0..data.tracks().len() {
+                assert_eq!(meta.track_start(track_index), data.track_start(track_index));
+            }
+        }
+    }
+
+    #[test]
+    fn pathological_delta_times_are_clamped_to_the_maximum_duration() {
+        // Tracks played one after another, each with notes the longest delta time apart at the
+        // slowest tempo, so that they last for centuries
+        let track = || {
+            (0..8).fold(
+                TrackBuilder::new().set_tempo(Tempo::from_mpqn(0xFF_FFFF)),
+                |track, note| track.at(note * 0x0FFF_FFFF).note_on(0, 60, 100),
+            )
+        };
+        let data = FileBuilder::new(
+            MIDIFormat::MultiIndependentTracks,
+            TimeDivision::TicksPerBit(1),
+        )
+        .track(track())
+        .track(track())
+        .track(track())
+        .build();
+
+        let meta = MidiMeta::new(&data, DEFAULT_MAX_DURATION);
+        assert_eq!(meta.total_duration(), DEFAULT_MAX_DURATION);
+        assert!(meta.song_duration() > Duration::from_secs(3 * 500 * 365 * 24 * 60 * 60));
+        assert!(meta.track_start(2) > meta.track_start(1));
+
+        let times = data
+            .walk_track(2)
+            .map(|(time, _, _)| time)
+            .collect::<Vec<_>>();
+        assert!(times.is_sorted());
+        assert_eq!(times.first(), Some(&meta.track_start(2)));
+
+        let meta = MidiMeta::new(&data, Duration::from_secs(90));
+        assert_eq!(meta.total_duration(), Duration::from_secs(90));
+    }
+}
@@ -0,0 +1,130 @@
+//! Synthesized drum sounds for the General MIDI percussion channel.
+
+use std::{f32::consts::TAU, time::Duration};
+
+/// Channel 10, whose notes are drum hits rather than pitches
+pub const PERCUSSION_CHANNEL: u8 = 9;
+
+/// Drum sound of a percussion note
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Drum {
+    Kick,
+    Snare,
+    ClosedHiHat,
+    OpenHiHat,
+    /// Pitched drum, starting at the given frequency
+    Tom(f32),
+    Cymbal,
+}
+
+impl Drum {
+    /// Drum of a General MIDI percussion note. Notes without a close match sound like a snare.
+    pub fn from_note(note: u8) -> Self {
+        match note {
+            35 | 36 => Drum::Kick,
+            42 | 44 => Drum::ClosedHiHat,
+            46 => Drum::OpenHiHat,
+            41 | 43 | 45 | 47 | 48 | 50 => Drum::Tom(80.0 + (note - 41) as f32 * 20.0),
+            49 | 51 | 52 | 53 | 55 | 57 | 59 => Drum::Cymbal,
+            _ => Drum::Snare,
+        }
+    }
+
+    pub fn duration(&self) -> Duration {
+        match self {
+            Drum::Kick => Duration::from_millis(300),
+            Drum::Snare => Duration::from_millis(200),
+            Drum::ClosedHiHat => Duration::from_millis(60),
+            Drum::OpenHiHat => Duration::from_millis(300),
+            Drum::Tom(_) => Duration::from_millis(300),
+            Drum::Cymbal => Duration::from_millis(1000),
+        }
+    }
+
+    /// Render the whole hit, with values in [-1.0; 1.0]
+    pub fn render(&self, sample_rate: u32) -> Vec<f32> {
+        let length = (self.duration().as_secs_f32() * sample_rate as f32) as usize;
+        let mut noise = Noise::default();
+        let mut phase = 0.0f32;
+        let mut previous_noise = 0.0f32;
+
+        (0..length)
+            .map(|i| {
+                let time = i as f32 / sample_rate as f32;
+                let white = noise.next();
+                // Differentiated noise keeps only the high frequencies
+                let bright = (white - previous_noise) / 2.0;
+                previous_noise = white;
+
+                // Pitched drums sweep their frequency down
+                let mut sweep = |from: f32, to: f32, sweep_time: f32| {
+                    let frequency = to + (from - to) * (-time / sweep_time).exp();
+                    phase = (phase + frequency / sample_rate as f32).fract();
+                    (TAU * phase).sin()
+                };
+
+                match self {
+                    Drum::Kick => sweep(150.0, 45.0, 0.04) * (-time / 0.12).exp(),
+                    Drum::Snare => {
+                        (0.7 * white + 0.3 * sweep(220.0, 180.0, 0.02)) * (-time / 0.05).exp()
+                    }
+                    Drum::ClosedHiHat => bright * (-time / 0.015).exp(),
+                    Drum::OpenHiHat => bright * (-time / 0.1).exp(),
+                    Drum::Tom(frequency) => {
+                        sweep(*frequency, 0.7 * frequency, 0.1) * (-time / 0.1).exp()
+                    }
+                    Drum::Cymbal => bright * (-time / 0.3).exp(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Xorshift noise, so that rendered hits are reproducible
+struct Noise {
+    state: u32,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self { state: 0x9E37_79B9 }
+    }
+}
+
+impl Noise {
+    /// Next value in [-1.0; 1.0]
+    fn next(&mut self) -> f32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        self.state as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hits_decay_within_their_duration() {
+        let drums = [
+            Drum::Kick,
+            Drum::Snare,
+            Drum::ClosedHiHat,
+            Drum::OpenHiHat,
+            Drum::Tom(100.0),
+            Drum::Cymbal,
+        ];
+
+        for drum in drums {
+            let hit = drum.render(8000);
+            assert_eq!(hit.len(), (drum.duration().as_secs_f32() * 8000.0) as usize);
+            assert!(hit.iter().all(|s| (-1.0..=1.0).contains(s)), "{drum:?}");
+
+            let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+            let tenth = hit.len() / 10;
+            assert!(peak(&hit[..tenth]) > 0.3, "{drum:?}");
+            assert!(peak(&hit[hit.len() - tenth..]) < 0.1, "{drum:?}");
+        }
+    }
+}
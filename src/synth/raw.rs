@@ -1,209 +1,1970 @@
-use core::f32;
-use std::{
-    collections::{HashMap, HashSet},
-    time::Duration,
-    vec,
-};
+use core::f32::{self, consts::TAU};
+use std::{collections::HashMap, ops::Range, time::Duration, vec};
 
 use crate::{
-    midi::{ChannelEventKind, MIDIEventKind, MIDIFileData, MetaEvent, Tempo},
-    synth::MidiNote,
-    wave::Wave,
+    midi::{
+        ChannelEventKind, Controller, ControllerKind, MIDIEventKind, MIDIFileData, MetaEvent,
+        PitchBend, notes, rpn::ParameterDecoder,
+    },
+    synth::{
+        AFTERTOUCH_SMOOTHING, ChannelFilter, ChannelGain, DEFAULT_PITCH_BEND_RANGE, Damper,
+        Envelope, Marker, MidiMeta, MidiNote, Pressures, SynthSettings, TrackFilter, TrackInfo,
+        VelocityCurve, Vibrato, Voice, aftertouch_gain, mix, steal_voices,
+    },
+    synth::{
+        instruments::Timbre,
+        metronome,
+        percussion::{Drum, PERCUSSION_CHANNEL},
+    },
+    wave::{Wave, wrap_phase},
 };
 
-#[derive(Debug)]
-struct MidiTrackMeta {
-    /// Stores channel numbers. The index in this vector represents the continuous channel index
-    channel_idx: Vec<u8>,
-    duration: Duration,
+/// Length of the slices the file is rendered in by [`MidiSynth::render_chunk`]
+pub const RENDER_CHUNK: Duration = Duration::from_secs(1);
+
+pub struct MidiSynth {
+    data: MIDIFileData,
+    meta: MidiMeta,
+    settings: SynthSettings,
+    velocity_curve: VelocityCurve,
+    envelope: Envelope,
+    vibrato: Vibrato,
+    channel_filter: ChannelFilter,
+    track_filter: TrackFilter,
+    /// Semitones added to every note, apart from the percussion channel
+    transpose: i8,
+    /// Volume of the clicks mixed in on every beat, if any
+    metronome: Option<f32>,
 }
 
-impl MidiTrackMeta {
-    fn new(channel_idx: Vec<u8>, duration: Duration) -> Self {
+impl MidiSynth {
+    pub fn new(data: MIDIFileData, settings: SynthSettings) -> Self {
         Self {
-            channel_idx,
-            duration,
+            meta: MidiMeta::new(&data, settings.max_duration),
+            data,
+            settings,
+            velocity_curve: VelocityCurve::default(),
+            envelope: Envelope::default(),
+            vibrato: Vibrato::default(),
+            channel_filter: ChannelFilter::default(),
+            track_filter: TrackFilter::default(),
+            transpose: 0,
+            metronome: None,
         }
     }
 
-    fn channel_index(&self, channel: u8) -> usize {
-        self.channel_idx
-            .iter()
-            .position(|&ch| ch == channel)
-            .expect("channel is not part of this track")
+    pub fn set_velocity_curve(&mut self, velocity_curve: VelocityCurve) {
+        self.velocity_curve = velocity_curve;
     }
-}
 
-#[derive(Debug)]
-struct MidiMeta {
-    tracks: Vec<MidiTrackMeta>,
-}
+    pub fn set_envelope(&mut self, envelope: Envelope) {
+        self.envelope = envelope;
+    }
 
-impl MidiMeta {
-    fn new(data: &MIDIFileData) -> Self {
-        let mut tracks = vec![];
-        for track in data.tracks() {
-            let mut tick_duration = data.time_division().tick_duration(Tempo::default());
+    pub fn set_vibrato(&mut self, vibrato: Vibrato) {
+        self.vibrato = vibrato;
+    }
 
-            let mut channels = HashSet::new();
-            let mut duration = Duration::from_secs(0);
+    pub fn set_channel_filter(&mut self, channel_filter: ChannelFilter) {
+        self.channel_filter = channel_filter;
+    }
 
-            for event in track.events() {
-                duration += tick_duration * event.delta_time();
+    pub fn set_track_filter(&mut self, track_filter: TrackFilter) {
+        self.track_filter = track_filter;
+    }
 
-                match event.kind() {
-                    MIDIEventKind::Channel(channel_event) => {
-                        channels.insert(channel_event.channel());
-                    }
-                    MIDIEventKind::Meta(MetaEvent::EndOfTrack) => break,
-                    MIDIEventKind::Meta(MetaEvent::SetTempo { tempo }) => {
-                        tick_duration = data.time_division().tick_duration(*tempo);
-                    }
-                    MIDIEventKind::Meta(MetaEvent::CopyrightNotice { .. })
-                    | MIDIEventKind::Meta(MetaEvent::SequenceTrackName { .. })
-                    | MIDIEventKind::Meta(MetaEvent::InstrumentName { .. })
-                    | MIDIEventKind::Meta(MetaEvent::Lyrics { .. })
-                    | MIDIEventKind::Meta(MetaEvent::Marker { .. })
-                    | MIDIEventKind::Meta(MetaEvent::CuePoint { .. }) => {
-                        // Ignored
-                    }
-                    MIDIEventKind::Meta(_) => {
-                        log::warn!("Unhandled meta in meta collection event: {event:?}")
-                    }
-                }
-            }
+    pub fn set_transpose(&mut self, semitones: i8) {
+        self.transpose = semitones;
+    }
 
-            tracks.push(MidiTrackMeta::new(channels.into_iter().collect(), duration));
-        }
+    /// Play the file faster (`rate` above 1) or slower. Notes keep their pitch.
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        self.data.set_playback_rate(rate as f64);
+        self.meta = MidiMeta::new(&self.data, self.settings.max_duration);
+    }
 
-        Self { tracks }
+    pub fn metronome(&self) -> Option<f32> {
+        self.metronome
     }
 
-    fn total_duration(&self) -> Duration {
-        self.tracks
-            .iter()
-            .map(|track| track.duration)
-            .max()
-            .unwrap_or_default()
+    /// Mix a click in on every beat at `volume`, or none
+    pub fn set_metronome(&mut self, volume: Option<f32>) {
+        self.metronome = volume;
     }
-}
 
-pub struct MidiSynth {
-    data: MIDIFileData,
-    meta: MidiMeta,
-}
+    pub fn total_duration(&self) -> Duration {
+        self.meta.total_duration()
+    }
 
-impl MidiSynth {
-    pub fn new(data: MIDIFileData) -> Self {
-        Self {
-            meta: MidiMeta::new(&data),
-            data,
-        }
+    /// Time the playback takes, including the release tails of the last notes
+    pub fn playback_duration(&self) -> Duration {
+        self.total_duration() + self.envelope.release
+    }
+
+    pub fn data(&self) -> &MIDIFileData {
+        &self.data
+    }
+
+    /// Give back the file, so it can be played by another synthesizer
+    pub fn into_data(self) -> MIDIFileData {
+        self.data
+    }
+
+    /// MIDI channels used by the file, in ascending order
+    pub fn channels(&self) -> Vec<u8> {
+        self.meta.channels()
+    }
+
+    /// Names of the tracks of the file, in order
+    pub fn track_names(&self) -> Vec<String> {
+        self.meta.track_names()
+    }
+
+    /// Description of the tracks of the file, in order
+    pub fn track_infos(&self) -> Vec<TrackInfo> {
+        self.meta.track_infos()
+    }
+
+    /// Markers and cue points of the file, sorted by time
+    pub fn markers(&self) -> &[Marker] {
+        &self.meta.markers
+    }
+
+    /// Copyright notice of the file, if it has one
+    pub fn copyright(&self) -> Option<&str> {
+        self.meta.copyright.as_deref()
     }
 
     /// Create a vector per track per channel filled with values from -1 to 1.
     ///
-    /// All individual buffers are of the same length, equal to the first tuple element.
-    pub fn create_buffer(&self, sample_rate: u32, wave: &dyn Wave) -> (usize, Vec<Vec<Vec<f32>>>) {
-        let buffer_length =
-            (sample_rate as f32 * self.meta.total_duration().as_secs_f32()).floor() as usize;
+    /// Notes are played with the timbre of their channel's program, unless a `wave` is forced for
+    /// all of them. All individual buffers are of the same length, equal to the first tuple element.
+    pub fn create_buffer(
+        &self,
+        sample_rate: u32,
+        wave: Option<&dyn Wave>,
+    ) -> (usize, Vec<Vec<Vec<f32>>>) {
+        self.render_iter(sample_rate, wave).finish()
+    }
+
+    /// Render the file like [`Self::create_buffer`], one slice of [`RENDER_CHUNK`] at a time
+    pub fn render_iter<'a>(
+        &'a self,
+        sample_rate: u32,
+        wave: Option<&'a dyn Wave>,
+    ) -> RenderIter<'a> {
+        RenderIter {
+            synth: self,
+            wave,
+            rendering: self.start_rendering(sample_rate),
+        }
+    }
+
+    /// Prepare the rendering of the file, to be carried out with [`Self::render_chunk`]. Unlike
+    /// [`Self::render_iter`], the rendering doesn't borrow the synthesizer, so it can be spread
+    /// over several calls from the event loop.
+    pub fn start_rendering(&self, sample_rate: u32) -> Rendering {
+        let buffer_length = sample_index(sample_rate, self.meta.total_duration());
 
-        let mut buffers = self
+        let buffers = self
             .meta
             .tracks
             .iter()
             .map(|track| vec![vec![0.0f32; buffer_length]; track.channel_idx.len()])
             .collect::<Vec<Vec<Vec<f32>>>>();
 
-        for (track_index, track) in self.data.tracks().iter().enumerate() {
-            let mut sample_number = 0;
-            let mut samples_per_tick = (sample_rate
-                * self.data.time_division().tick_duration(Tempo::default()))
-            .as_secs_f32();
+        let prepared = (0..buffers.len())
+            .map(|track_index| {
+                self.track_filter
+                    .is_enabled(track_index)
+                    .then(|| self.prepare_track(track_index, sample_rate))
+            })
+            .collect::<Vec<_>>();
+
+        // Voices are shared by all tracks, so notes are stolen before their segments are made
+        let mut voices = prepared
+            .iter()
+            .flatten()
+            .flat_map(|(_, notes)| notes.iter().map(|note| note.voice))
+            .collect::<Vec<_>>();
+        steal_voices(&mut voices, self.settings.max_voices);
+
+        let mut voices = voices.into_iter();
+        let tracks = prepared
+            .into_iter()
+            .map(|prepared| {
+                prepared.map(|(mut track, notes)| {
+                    track.segments = notes
+                        .into_iter()
+                        .zip(voices.by_ref())
+                        .filter(|(_, voice)| voice.end > voice.start)
+                        .map(|(note, voice)| {
+                            self.segment(&track, note, voice, sample_rate, buffer_length)
+                        })
+                        .collect();
+                    track
+                })
+            })
+            .collect();
+
+        Rendering {
+            sample_rate,
+            buffer_length,
+            chunk_length: sample_index(sample_rate, RENDER_CHUNK),
+            buffers,
+            tracks,
+            rendered: 0,
+            done: false,
+        }
+    }
 
-            let mut active_notes = HashMap::<usize, HashSet<MidiNote>>::new();
+    /// Walk the events of a track and pair its notes, which are made into segments once they are
+    /// given voices. The segments of the returned track are left empty.
+    fn prepare_track(
+        &self,
+        track_index: usize,
+        sample_rate: u32,
+    ) -> (TrackRendering, Vec<PlannedNote>) {
+        let track_meta = &self.meta.tracks[track_index];
+        let mut channels = track_meta
+            .channel_idx
+            .iter()
+            .map(|_| ChannelTimeline::default())
+            .collect::<Vec<_>>();
+        let mut drum_hits = vec![];
+        let mut drum_sounds = HashMap::new();
+        let mut parameters = ParameterDecoder::default();
 
-            for event in track.events() {
-                let sample_delta = (event.delta_time() as f32 * samples_per_tick) as usize;
+        let mut walker = self.data.walk_track(track_index);
+        for (time, _, event) in walker.by_ref() {
+            let sample = sample_index(sample_rate, time);
 
-                // Fill notes from sample_number to sample_number + sample_delta with the currently active notes
+            match event.kind() {
+                MIDIEventKind::Channel(channel_event)
+                    if !self.channel_filter.is_audible(channel_event.channel()) =>
                 {
-                    for (channel_buffer_idx, notes) in &active_notes {
-                        let buffer = &mut buffers[track_index][*channel_buffer_idx]
-                            [sample_number..sample_number + sample_delta];
-
-                        for (sample_num, sample) in buffer.iter_mut().enumerate() {
-                            let freq = notes
-                                .iter()
-                                .map(|n| {
-                                    wave.value(
-                                        n.frequency(),
-                                        (sample_number + sample_num) as f32 / sample_rate as f32,
-                                    )
-                                })
-                                .sum::<f32>()
-                                / (notes.len() as f32).max(1.0);
-
-                            *sample = freq;
-                        }
-                    }
+                    // Left silent
                 }
+                MIDIEventKind::Channel(channel_event) => {
+                    let channel_buffer_idx = track_meta.channel_index(channel_event.channel());
+                    let timeline = &mut channels[channel_buffer_idx];
 
-                match event.kind() {
-                    MIDIEventKind::Channel(channel_event) => {
-                        let channel_buffer_idx =
-                            self.meta.tracks[track_index].channel_index(channel_event.channel());
-
-                        match channel_event.kind() {
-                            ChannelEventKind::NoteOff {
-                                note,
-                                // TODO: support velocity
-                                velocity: _,
-                            } => {
-                                if let Some(notes) = active_notes.get_mut(&channel_buffer_idx) {
-                                    notes.remove(&MidiNote::new(*note));
+                    match channel_event.kind() {
+                        ChannelEventKind::NoteOn { note, velocity }
+                            if channel_event.channel() == PERCUSSION_CHANNEL && *velocity > 0 =>
+                        {
+                            // Drum hits have a fixed length and ignore note offs
+                            drum_sounds
+                                .entry(*note)
+                                .or_insert_with(|| Drum::from_note(*note).render(sample_rate));
+                            drum_hits.push(DrumHit {
+                                channel_buffer_idx,
+                                sample,
+                                note: *note,
+                                gain: self.velocity_curve.gain(*velocity) * timeline.gain.gain(),
+                            });
+                        }
+                        ChannelEventKind::NoteOn { .. } | ChannelEventKind::NoteOff { .. } => {
+                            // Paired into notes below
+                        }
+                        ChannelEventKind::Controller {
+                            controller_number,
+                            controller_value,
+                        } => {
+                            let controller = Controller::new(*controller_number, *controller_value);
+                            match controller.kind() {
+                                ControllerKind::AllNotesOff => {
+                                    timeline.damper.notes_off.push(time);
+                                }
+                                ControllerKind::AllSoundOff => {
+                                    timeline.damper.notes_off.push(time);
+                                    timeline.sounds_off.push(time);
+                                }
+                                ControllerKind::Sustain => {
+                                    timeline.damper.pedal.push((time, controller.is_on()));
+                                }
+                                ControllerKind::Volume | ControllerKind::Expression => {
+                                    timeline.gain.update(controller);
+                                    timeline.gains.push((sample, timeline.gain.gain()));
+                                }
+                                ControllerKind::Modulation => {
+                                    timeline
+                                        .vibrato_depths
+                                        .push((sample, self.vibrato.depth(controller.value())));
+                                }
+                                ControllerKind::Pan => {
+                                    // Applied when mixing down
+                                }
+                                ControllerKind::DataEntry
+                                | ControllerKind::DataEntryLsb
+                                | ControllerKind::NrpnLsb
+                                | ControllerKind::NrpnMsb
+                                | ControllerKind::RpnLsb
+                                | ControllerKind::RpnMsb => {
+                                    if let Some(range) = parameters
+                                        .update(channel_event.channel(), controller)
+                                        .and_then(|change| change.pitch_bend_range())
+                                    {
+                                        timeline.pitch_bend_range = Some(range);
+                                    }
+                                }
+                                ControllerKind::Other(_) => {
+                                    log::warn!("Unhandled channel event: {channel_event:?}")
                                 }
-                            }
-                            ChannelEventKind::NoteOn {
-                                note,
-                                // TODO: support velocity
-                                velocity: _,
-                            } => {
-                                let notes = active_notes.entry(channel_buffer_idx).or_default();
-                                notes.insert(MidiNote::new(*note));
-                            }
-                            ChannelEventKind::NoteAftertouch { .. }
-                            | ChannelEventKind::Controller { .. }
-                            | ChannelEventKind::ProgramChange { .. }
-                            | ChannelEventKind::ChannelAftertouch { .. }
-                            | ChannelEventKind::PitchBend { .. } => {
-                                log::warn!("Unhandled channel event: {channel_event:?}")
                             }
                         }
+                        ChannelEventKind::ProgramChange { program_number } => {
+                            timeline
+                                .timbres
+                                .push((sample, Timbre::from_program(*program_number)));
+                        }
+                        ChannelEventKind::PitchBend { lsb, msb } => {
+                            let range = timeline
+                                .pitch_bend_range
+                                .unwrap_or(DEFAULT_PITCH_BEND_RANGE);
+                            timeline.pitch_bends.push((
+                                sample,
+                                PitchBend::from_bytes(*lsb, *msb).semitones(range) * 100.0,
+                            ));
+                        }
+                        ChannelEventKind::NoteAftertouch { note, aftertouch } => {
+                            timeline.pressures.push_key(time, *note, *aftertouch);
+                        }
+                        ChannelEventKind::ChannelAftertouch { aftertouch } => {
+                            timeline.pressures.push_channel(time, *aftertouch);
+                        }
                     }
-                    MIDIEventKind::Meta(MetaEvent::EndOfTrack) => break,
-                    MIDIEventKind::Meta(MetaEvent::SetTempo { tempo }) => {
-                        samples_per_tick = (sample_rate
-                            * self.data.time_division().tick_duration(*tempo))
-                        .as_secs_f32();
-                    }
-                    MIDIEventKind::Meta(MetaEvent::CopyrightNotice { .. })
-                    | MIDIEventKind::Meta(MetaEvent::SequenceTrackName { .. })
-                    | MIDIEventKind::Meta(MetaEvent::InstrumentName { .. })
-                    | MIDIEventKind::Meta(MetaEvent::Lyrics { .. })
-                    | MIDIEventKind::Meta(MetaEvent::Marker { .. })
-                    | MIDIEventKind::Meta(MetaEvent::CuePoint { .. }) => {
-                        // Ignored
-                    }
-                    MIDIEventKind::Meta(_) => {
-                        log::warn!("Unhandled meta in buffer creation event: {event:?}")
+                }
+                MIDIEventKind::Meta(MetaEvent::EndOfTrack)
+                | MIDIEventKind::Meta(MetaEvent::SetTempo { .. })
+                | MIDIEventKind::Meta(MetaEvent::CopyrightNotice { .. })
+                | MIDIEventKind::Meta(MetaEvent::SequenceTrackName { .. })
+                | MIDIEventKind::Meta(MetaEvent::InstrumentName { .. })
+                | MIDIEventKind::Meta(MetaEvent::Lyrics { .. })
+                | MIDIEventKind::Meta(MetaEvent::Marker { .. })
+                | MIDIEventKind::Meta(MetaEvent::CuePoint { .. }) => {
+                    // Ignored
+                }
+                MIDIEventKind::Meta(_) => {
+                    log::warn!("Unhandled meta in buffer creation event: {event:?}")
+                }
+                MIDIEventKind::SysEx(_) => {
+                    // Ignored
+                }
+            }
+        }
+
+        let track_start = self.meta.track_start(track_index);
+        let track_end = walker.time();
+        let notes = notes::extract_track_notes(&self.data, track_index);
+
+        // Walking the notes backwards, the start of the next note of every channel and key
+        let mut next_starts = HashMap::<(u8, u8), Duration>::new();
+        let mut planned = Vec::with_capacity(notes.len());
+        for note in notes.iter().rev() {
+            // Notes released while the pedal is down keep sounding until they are struck again
+            let restrike = next_starts.insert((note.channel, note.key), track_start + note.start);
+            if note.channel == PERCUSSION_CHANNEL || !self.channel_filter.is_audible(note.channel) {
+                continue;
+            }
+
+            let channel_buffer_idx = track_meta.channel_index(note.channel);
+            let timeline = &channels[channel_buffer_idx];
+            let start = track_start + note.start;
+            let release = timeline
+                .damper
+                .sounding_end(track_start + note.end, restrike, track_end);
+            // All sound off cuts the note without its release tail
+            let sound_off = timeline
+                .sounds_off
+                .iter()
+                .copied()
+                .find(|&time| time > start)
+                .unwrap_or(Duration::MAX);
+
+            planned.push(PlannedNote {
+                channel_buffer_idx,
+                key: note.key,
+                velocity: note.velocity,
+                voice: Voice {
+                    start,
+                    release,
+                    release_length: self.envelope.release,
+                    end: (release + self.envelope.release).min(sound_off),
+                },
+            });
+        }
+        planned.reverse();
+
+        let track = TrackRendering {
+            segments: vec![],
+            next_segment: 0,
+            sounding: vec![],
+            channels,
+            drum_hits,
+            next_hit: 0,
+            drum_sounds,
+            sum: vec![],
+            count: vec![],
+            levels: vec![],
+        };
+        (track, planned)
+    }
+
+    /// Segment of a note of `track`, sounding for the time of its `voice`
+    fn segment(
+        &self,
+        track: &TrackRendering,
+        note: PlannedNote,
+        voice: Voice,
+        sample_rate: u32,
+        buffer_length: usize,
+    ) -> Segment {
+        let timeline = &track.channels[note.channel_buffer_idx];
+        let start_sample = sample_index(sample_rate, voice.start);
+        let gain = self.velocity_curve.gain(note.velocity);
+        let pressure_gains = timeline
+            .pressures
+            .automation(note.key, voice.start, voice.end)
+            .into_iter()
+            .map(|(time, pressure)| (sample_index(sample_rate, time), aftertouch_gain(pressure)))
+            .collect::<Vec<_>>();
+        let envelope = Envelope {
+            release: voice.release_length,
+            ..self.envelope
+        };
+
+        Segment {
+            channel_buffer_idx: note.channel_buffer_idx,
+            start_sample,
+            end_sample: sample_index(sample_rate, voice.end).min(buffer_length),
+            envelope: envelope
+                .automation(voice.release - voice.start)
+                .into_iter()
+                .map(|(time, level)| (sample_index(sample_rate, voice.start + time), gain * level))
+                .collect(),
+            phase_increment: MidiNote::new(note.key)
+                .transposed(self.transpose)
+                .frequency()
+                / sample_rate as f32,
+            timbre: Changes::new(&timeline.timbres, Timbre::default()).at(start_sample),
+            phase: 0.0,
+            pressure_gain: pressure_gains.first().map_or(1.0, |(_, gain)| *gain),
+            pressure_gains,
+        }
+    }
+
+    /// Render the next slice of the file, of [`RENDER_CHUNK`]. Returns `None` once the whole file
+    /// has been rendered.
+    ///
+    /// The same `wave` must be given for every slice of a rendering.
+    pub fn render_chunk(
+        &self,
+        rendering: &mut Rendering,
+        wave: Option<&dyn Wave>,
+    ) -> Option<RenderedChunk> {
+        if rendering.done {
+            return None;
+        }
+
+        let chunk_end = rendering
+            .rendered
+            .saturating_add(rendering.chunk_length)
+            .min(rendering.buffer_length);
+        let samples = rendering.rendered..chunk_end;
+
+        for (track, track_buffers) in rendering.tracks.iter_mut().zip(&mut rendering.buffers) {
+            if let Some(track) = track {
+                self.render_track(
+                    track,
+                    track_buffers,
+                    samples.clone(),
+                    rendering.sample_rate,
+                    wave,
+                );
+            }
+        }
+
+        rendering.rendered = chunk_end;
+        rendering.done = chunk_end == rendering.buffer_length;
+
+        Some(RenderedChunk {
+            samples,
+            progress: rendering.progress(),
+        })
+    }
+
+    /// Render the notes of a track sounding within the `chunk` samples, along with the drum hits
+    /// starting there
+    fn render_track(
+        &self,
+        track: &mut TrackRendering,
+        track_buffers: &mut [Vec<f32>],
+        chunk: Range<usize>,
+        sample_rate: u32,
+        wave: Option<&dyn Wave>,
+    ) {
+        // Drum hits are added whole, reaching into the following slices
+        while let Some(hit) = track.drum_hits.get(track.next_hit)
+            && hit.sample < chunk.end
+        {
+            let sound = &track.drum_sounds[&hit.note];
+            for (sample, hit_sample) in track_buffers[hit.channel_buffer_idx]
+                .iter_mut()
+                .skip(hit.sample)
+                .zip(sound)
+            {
+                *sample += hit.gain * hit_sample;
+            }
+            track.next_hit += 1;
+        }
+
+        while let Some(segment) = track.segments.get(track.next_segment)
+            && segment.start_sample < chunk.end
+        {
+            track.sounding.push(track.next_segment);
+            track.next_segment += 1;
+        }
+
+        for (channel_buffer_idx, buffer) in track_buffers.iter_mut().enumerate() {
+            let is_on_channel =
+                |index: &usize| track.segments[*index].channel_buffer_idx == channel_buffer_idx;
+            if !track.sounding.iter().any(is_on_channel) {
+                continue;
+            }
+
+            let timeline = &track.channels[channel_buffer_idx];
+            track.sum.clear();
+            track.sum.resize(chunk.len(), 0.0);
+            track.count.clear();
+            track.count.resize(chunk.len() + 1, 0);
+
+            for &index in &track.sounding {
+                let segment = &mut track.segments[index];
+                if segment.channel_buffer_idx != channel_buffer_idx {
+                    continue;
+                }
+
+                let range =
+                    segment.start_sample.max(chunk.start)..segment.end_sample.min(chunk.end);
+                if range.is_empty() {
+                    continue;
+                }
+
+                self.render_segment(
+                    segment,
+                    range.clone(),
+                    sample_rate,
+                    timeline,
+                    wave.unwrap_or(segment.timbre.wave()),
+                    &mut track.levels,
+                    &mut track.sum[range.start - chunk.start..range.end - chunk.start],
+                );
+                // Counted by where the note starts and stops sounding
+                track.count[range.start - chunk.start] += 1;
+                track.count[range.end - chunk.start] -= 1;
+            }
+
+            // Notes are averaged, so that chords don't clip
+            let mut gains = Changes::new(&timeline.gains, ChannelGain::default().gain());
+            let mut count = 0;
+            for (sample_num, (sample, (sum, count_change))) in buffer[chunk.clone()]
+                .iter_mut()
+                .zip(track.sum.iter().zip(&track.count))
+                .enumerate()
+            {
+                count += count_change;
+                let gain = gains.at(chunk.start + sample_num);
+                if count > 0 {
+                    *sample += gain * sum / count as f32;
+                }
+            }
+        }
+
+        track
+            .sounding
+            .retain(|&index| track.segments[index].end_sample > chunk.end);
+    }
+
+    /// Add the `range` samples of a note to `output`, which starts with the first of them.
+    /// `levels` is reused for the envelope of the note.
+    fn render_segment(
+        &self,
+        segment: &mut Segment,
+        range: Range<usize>,
+        sample_rate: u32,
+        timeline: &ChannelTimeline,
+        wave: &dyn Wave,
+        levels: &mut Vec<f32>,
+        output: &mut [f32],
+    ) {
+        let seconds = |sample: usize| sample as f32 / sample_rate as f32;
+
+        // The envelope ramps linearly between its points, which cover the whole note
+        levels.clear();
+        for ramp in segment.envelope.windows(2) {
+            let [(from, from_level), (to, to_level)] = [ramp[0], ramp[1]];
+            let samples = from.max(range.start)..to.min(range.end);
+            if samples.is_empty() {
+                continue;
+            }
+
+            let step = (to_level - from_level) / (to - from) as f32;
+            levels.extend(samples.map(|sample_num| from_level + step * (sample_num - from) as f32));
+        }
+
+        // Pressed notes approach the gain of each pressure exponentially, rather than in a step
+        if !segment.pressure_gains.is_empty() {
+            let smoothing =
+                1.0 - (-1.0 / (AFTERTOUCH_SMOOTHING.as_secs_f32() * sample_rate as f32)).exp();
+            let mut pressure_gains = Changes::new(&segment.pressure_gains, 1.0);
+            for (sample_num, level) in range.clone().zip(levels.iter_mut()) {
+                let target = pressure_gains.at(sample_num);
+                segment.pressure_gain += (target - segment.pressure_gain) * smoothing;
+                *level *= segment.pressure_gain;
+            }
+        }
+
+        // Rendered in stretches of the same vibrato depth and pitch bend, which only needs the
+        // pitch to be recomputed on every sample while the modulation wheel is raised
+        let mut vibrato_depths = Changes::new(&timeline.vibrato_depths, 0.0);
+        let mut pitch_bends = Changes::new(&timeline.pitch_bends, 0.0);
+        let mut from = range.start;
+        while from < range.end {
+            let vibrato_depth = vibrato_depths.at(from);
+            let bend = pitch_bends.at(from);
+            let until = [vibrato_depths.next_change(), pitch_bends.next_change()]
+                .into_iter()
+                .flatten()
+                .fold(range.end, usize::min);
+            let output = &mut output[from - range.start..until - range.start];
+            let levels = &levels[from - range.start..until - range.start];
+            let bent_increment = segment.phase_increment * 2.0f32.powf(bend / 1200.0);
+
+            if vibrato_depth == 0.0 {
+                segment.phase = wave.add_to(output, levels, segment.phase, bent_increment);
+            } else {
+                for ((sample_num, sample), level) in (from..until).zip(output).zip(levels) {
+                    let detune =
+                        vibrato_depth * (TAU * self.vibrato.rate * seconds(sample_num)).sin();
+                    let phase_increment = bent_increment * 2.0f32.powf(detune / 1200.0);
+
+                    // The phase is accumulated, so that the frequency can change smoothly
+                    *sample += level * wave.value_band_limited(segment.phase, phase_increment);
+                    segment.phase = wrap_phase(segment.phase + phase_increment);
+                }
+            }
+            from = until;
+        }
+    }
+
+    /// Mix the output of [`Self::create_buffer`] down to a left and a right channel, following the
+    /// pan controller changes of every channel. Channels are centered until their first change.
+    /// The clicks of the metronome are added afterwards, so they aren't normalized with the notes.
+    pub fn mix_down(
+        &self,
+        sample_rate: u32,
+        buffer_length: usize,
+        buffers: &[Vec<Vec<f32>>],
+    ) -> [Vec<f32>; 2] {
+        let pans = self
+            .meta
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(track_index, track)| {
+                let track_start = self.meta.track_start(track_index);
+                track
+                    .channel_pan
+                    .iter()
+                    .map(|pan_changes| {
+                        pan_changes
+                            .iter()
+                            .map(|&(time, value)| {
+                                (
+                                    sample_index(sample_rate, track_start + time),
+                                    mix::pan_position(value),
+                                )
+                            })
+                            .collect()
+                    })
+                    .collect()
+            })
+            .collect::<Vec<Vec<Vec<(usize, f32)>>>>();
+
+        let mut channels = mix::mix_down(buffer_length, buffers, &pans);
+        if let Some(volume) = self.metronome {
+            let accent = metronome::render_click(true, sample_rate);
+            let beat = metronome::render_click(false, sample_rate);
+            for click in metronome::click_schedule(&self.data, self.total_duration()) {
+                let start = sample_index(sample_rate, click.time);
+                let samples = if click.accent { &accent } else { &beat };
+                for channel in &mut channels {
+                    let end = start.saturating_add(samples.len()).min(channel.len());
+                    for (sample, click_sample) in
+                        channel[start.min(end)..end].iter_mut().zip(samples)
+                    {
+                        *sample += volume * click_sample;
                     }
                 }
+            }
+        }
+
+        channels
+    }
+}
+
+/// Index of the sample playing at `time`, computed exactly so that positions never drift
+fn sample_index(sample_rate: u32, time: Duration) -> usize {
+    (time.as_nanos() * sample_rate as u128 / 1_000_000_000).min(usize::MAX as u128) as usize
+}
+
+/// Scale all buffers down if any sample exceeds [-1.0; 1.0]
+fn normalize(buffers: &mut [Vec<Vec<f32>>]) {
+    let peak = buffers
+        .iter()
+        .flatten()
+        .flatten()
+        .fold(0.0f32, |acc, s| acc.max(s.abs()));
+
+    if peak > 1.0 {
+        for sample in buffers.iter_mut().flatten().flatten() {
+            *sample /= peak;
+        }
+    }
+}
+
+/// A rendering of a file by the raw synthesizer, in progress
+pub struct Rendering {
+    sample_rate: u32,
+    buffer_length: usize,
+    /// Samples rendered by every call to [`MidiSynth::render_chunk`]
+    chunk_length: usize,
+    /// Per track per channel, as returned by [`MidiSynth::create_buffer`]
+    buffers: Vec<Vec<Vec<f32>>>,
+    /// State of every track, or `None` for the tracks left out
+    tracks: Vec<Option<TrackRendering>>,
+    /// Samples of the file rendered so far
+    rendered: usize,
+    done: bool,
+}
+
+impl Rendering {
+    /// Part of the file rendered so far, from 0 to 1
+    pub fn progress(&self) -> f64 {
+        if self.done || self.buffer_length == 0 {
+            1.0
+        } else {
+            self.rendered as f64 / self.buffer_length as f64
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
 
-                sample_number += sample_delta;
+    /// The rendered buffers, like the ones returned by [`MidiSynth::create_buffer`]. They are
+    /// normalized here, as that depends on the loudest sample of the whole file.
+    pub fn finish(mut self) -> (usize, Vec<Vec<Vec<f32>>>) {
+        normalize(&mut self.buffers);
+        (self.buffer_length, self.buffers)
+    }
+}
+
+/// A slice of the file rendered by [`MidiSynth::render_chunk`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderedChunk {
+    /// Samples rendered, though notes reaching past them may have been rendered further
+    pub samples: Range<usize>,
+    /// Part of the file rendered so far, from 0 to 1
+    pub progress: f64,
+}
+
+/// Iterator over the slices of a rendering, see [`MidiSynth::render_iter`]
+pub struct RenderIter<'a> {
+    synth: &'a MidiSynth,
+    wave: Option<&'a dyn Wave>,
+    rendering: Rendering,
+}
+
+impl RenderIter<'_> {
+    /// The rendered buffers, see [`Rendering::finish`]. Slices which weren't iterated over yet are
+    /// rendered first.
+    pub fn finish(mut self) -> (usize, Vec<Vec<Vec<f32>>>) {
+        self.by_ref().for_each(drop);
+        self.rendering.finish()
+    }
+}
+
+impl Iterator for RenderIter<'_> {
+    type Item = RenderedChunk;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.synth.render_chunk(&mut self.rendering, self.wave)
+    }
+}
+
+/// Where the rendering of a track stands between slices
+struct TrackRendering {
+    /// Notes of the audible channels, sorted by start
+    segments: Vec<Segment>,
+    /// Index of the first segment which hasn't started sounding yet
+    next_segment: usize,
+    /// Indices of the segments sounding in the slice being rendered, including the ones in their
+    /// release phase
+    sounding: Vec<usize>,
+    /// Per channel buffer
+    channels: Vec<ChannelTimeline>,
+    /// Sorted by sample
+    drum_hits: Vec<DrumHit>,
+    next_hit: usize,
+    /// Rendered once per percussion note
+    drum_sounds: HashMap<u8, Vec<f32>>,
+    /// Sum of the notes of a channel over the slice
+    sum: Vec<f32>,
+    /// Changes in the number of notes sounding, from one sample of the slice to the next
+    count: Vec<i32>,
+    /// Envelope of the note being rendered
+    levels: Vec<f32>,
+}
+
+/// A paired note of a track, before its voice is known
+#[derive(Debug, Clone, Copy)]
+struct PlannedNote {
+    channel_buffer_idx: usize,
+    key: u8,
+    velocity: u8,
+    voice: Voice,
+}
+
+/// A note prepared for rendering, from its start until the end of its release tail
+#[derive(Debug, Clone, PartialEq)]
+struct Segment {
+    channel_buffer_idx: usize,
+    start_sample: usize,
+    /// The note is silent from this sample on
+    end_sample: usize,
+    /// Points of the envelope as samples and levels, scaled by the velocity. The note reaches
+    /// each level linearly from the previous point.
+    envelope: Vec<(usize, f32)>,
+    /// Of the note after transposition, in periods per sample
+    phase_increment: f32,
+    /// Of the channel when the note was struck, played unless a wave is forced for all notes
+    timbre: Timbre,
+    /// Position within the wave period, in [0.0; 1.0)
+    phase: f32,
+    /// Gains of the aftertouch pressure changes, empty unless the note is pressed
+    pressure_gains: Vec<(usize, f32)>,
+    /// Gain of the pressure as it approaches the last change, by the last rendered sample
+    pressure_gain: f32,
+}
+
+/// A drum hit, rendered by adding the sound of its drum to the channel buffer
+#[derive(Debug, Clone, Copy)]
+struct DrumHit {
+    channel_buffer_idx: usize,
+    sample: usize,
+    note: u8,
+    /// Of the velocity and the channel volume at the time of the hit
+    gain: f32,
+}
+
+/// Changes made by the events of a channel, along with the samples they apply from
+#[derive(Debug, Default)]
+struct ChannelTimeline {
+    /// Volume and expression as of the last event walked
+    gain: ChannelGain,
+    gains: Vec<(usize, f32)>,
+    vibrato_depths: Vec<(usize, f32)>,
+    /// Range of the pitch bend wheel in semitones, as last set by RPN 0 while walking the events
+    pitch_bend_range: Option<f32>,
+    /// Detune of the channel in cents
+    pitch_bends: Vec<(usize, f32)>,
+    pressures: Pressures,
+    timbres: Vec<(usize, Timbre)>,
+    damper: Damper,
+    /// All sound off events, which end the sounding notes without their release tails
+    sounds_off: Vec<Duration>,
+}
+
+/// Reads the value of a channel setting at ascending samples, from its changes
+struct Changes<'a, T> {
+    changes: &'a [(usize, T)],
+    next: usize,
+    value: T,
+}
+
+impl<'a, T: Copy> Changes<'a, T> {
+    /// `initial` is the value before the first change
+    fn new(changes: &'a [(usize, T)], initial: T) -> Self {
+        Self {
+            changes,
+            next: 0,
+            value: initial,
+        }
+    }
+
+    /// Sample of the first change after the ones read so far
+    fn next_change(&self) -> Option<usize> {
+        self.changes.get(self.next).map(|(sample, _)| *sample)
+    }
+
+    /// Value at `sample`, which can't be earlier than the one of the previous call
+    fn at(&mut self, sample: usize) -> T {
+        while let Some(&(change, value)) = self.changes.get(self.next)
+            && change <= sample
+        {
+            self.value = value;
+            self.next += 1;
+        }
+        self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+    use crate::{
+        midi::{
+            MIDIFormat, Tempo, TimeDivision,
+            builder::{FileBuilder, TrackBuilder},
+        },
+        synth::DEFAULT_MAX_DURATION,
+        wave::{SineWave, SquareWave},
+    };
+
+    #[test]
+    fn format_1_tempo_applies_to_all_tracks() {
+        let midi_bytes = include_bytes!("../assets/format1.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let meta = MidiMeta::new(&midi, DEFAULT_MAX_DURATION);
+
+        // two quarter notes at 60 BPM followed by two quarter notes at 120 BPM
+        assert_eq!(meta.tracks.len(), 2);
+        assert_eq!(meta.tracks[1].duration, Duration::from_secs(3));
+        assert_eq!(meta.total_duration(), Duration::from_secs(3));
+    }
+
+    #[test]
+    fn format_2_tracks_are_sequential() {
+        let midi_bytes = include_bytes!("../assets/format2.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        assert_eq!(synth.meta.track_start(0), Duration::ZERO);
+        assert_eq!(synth.meta.track_start(1), Duration::from_secs(1));
+        assert_eq!(synth.meta.total_duration(), Duration::from_secs(2));
+
+        let sample_rate = 1000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 2000);
+
+        // the second track is silent while the first one plays and vice versa,
+        // apart from the release tail of the first track
+        assert!(buffers[0][0][..1000].iter().any(|&s| s != 0.0));
+        assert!(buffers[0][0][1050..].iter().all(|&s| s == 0.0));
+        assert!(buffers[1][0][..1000].iter().all(|&s| s == 0.0));
+        assert!(buffers[1][0][1000..].iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn meta_event_delta_time_is_kept() {
+        let midi_bytes = include_bytes!("../assets/tempo_change.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        // four beats at 120 BPM, then the tempo drops to 60 BPM for two more beats
+        assert_eq!(synth.meta.total_duration(), Duration::from_secs(4));
+
+        let sample_rate = 1000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 4000);
+
+        // the second note starts after the rest, right at the tempo change
+        assert!(buffers[0][0][550..2000].iter().all(|&s| s == 0.0));
+        assert!(buffers[0][0][2000..3000].iter().any(|&s| s != 0.0));
+        assert!(buffers[0][0][3050..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn velocity_scales_amplitude() {
+        let midi_bytes = include_bytes!("../assets/velocity.mid");
+        let sample_rate = 8000;
+
+        let peaks = |velocity_curve| {
+            let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+            let mut synth = MidiSynth::new(midi, SynthSettings::default());
+            synth.set_velocity_curve(velocity_curve);
+
+            let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+            let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+            (peak(&buffers[0][0][..4000]), peak(&buffers[0][0][4000..]))
+        };
+
+        // the same note, played at velocity 30 and then at velocity 127
+        let (quiet, loud) = peaks(VelocityCurve::Linear);
+        assert!((quiet - 30.0 / 127.0).abs() < 0.01, "{quiet}");
+        assert!((loud - 1.0).abs() < 0.01, "{loud}");
+
+        let (quiet, loud) = peaks(VelocityCurve::Squared);
+        assert!((quiet - (30.0f32 / 127.0).powi(2)).abs() < 0.01, "{quiet}");
+        assert!((loud - 1.0).abs() < 0.01, "{loud}");
+    }
+
+    #[test]
+    fn envelope_shapes_notes() {
+        let midi_bytes = include_bytes!("../assets/tempo_change.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+        synth.set_envelope(Envelope {
+            attack: Duration::from_millis(100),
+            decay: Duration::from_millis(100),
+            sustain: 0.5,
+            release: Duration::from_millis(200),
+        });
+
+        let sample_rate = 1000;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let buffer = &buffers[0][0];
+        let velocity_gain = 100.0 / 127.0;
+
+        // the attack ramps up linearly
+        for (i, sample) in buffer[..100].iter().enumerate() {
+            assert!(sample.abs() <= velocity_gain * i as f32 / 100.0 + 1e-3);
+        }
+
+        // sustained notes are held at the sustain level
+        assert!(
+            buffer[200..500]
+                .iter()
+                .all(|s| s.abs() <= velocity_gain * 0.5 + 1e-3)
+        );
+        assert!(
+            buffer[200..500]
+                .iter()
+                .any(|s| s.abs() > velocity_gain * 0.45)
+        );
+
+        // the release tail continues after the note off
+        assert!(buffer[500..700].iter().any(|s| s.abs() > 0.1));
+        assert!(buffer[700..2000].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn mix_down_honors_channel_pan() {
+        let midi_bytes = include_bytes!("../assets/pan.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let [left, right] = synth.mix_down(sample_rate, buffer_length, &buffers);
+
+        // channel 0 is panned hard left, channel 1 hard right
+        let track = &synth.meta.tracks[0];
+        let left_channel = &buffers[0][track.channel_index(0)];
+        let right_channel = &buffers[0][track.channel_index(1)];
+
+        for i in 0..buffer_length {
+            assert!((left[i] - left_channel[i]).abs() < 1e-3);
+            assert!((right[i] - right_channel[i]).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn metronome_clicks_are_mixed_in_on_the_beats() {
+        // A note held for a bar of 4/4
+        let midi = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(TrackBuilder::new().note(0, 60, 100, 384))
+        .build();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let without = synth.mix_down(sample_rate, buffer_length, &buffers);
+        synth.set_metronome(Some(0.5));
+        let with = synth.mix_down(sample_rate, buffer_length, &buffers);
+
+        let clicks = metronome::click_schedule(synth.data(), synth.total_duration());
+        assert_eq!(clicks.len(), 4);
+        let click = metronome::render_click(clicks[0].accent, sample_rate);
+        for (without, with) in without.iter().zip(&with) {
+            let added = with
+                .iter()
+                .zip(without)
+                .map(|(with, without)| with - without)
+                .collect::<Vec<_>>();
+            let start = sample_index(sample_rate, clicks[0].time);
+            for (added, click) in added[start..].iter().zip(&click) {
+                assert!((added - 0.5 * click).abs() < 1e-6);
+            }
+            // Nothing is added between the clicks
+            let first_end = start + click.len();
+            let second_start = sample_index(sample_rate, clicks[1].time);
+            assert!(added[first_end..second_start].iter().all(|&s| s == 0.0));
+        }
+    }
+
+    #[test]
+    fn dense_chord_does_not_clip() {
+        let midi_bytes = include_bytes!("../assets/chord.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let buffer = &buffers[0][0];
+
+        // a six note chord followed by a single note, all at full velocity
+        assert!(buffer.iter().all(|s| (-1.0..=1.0).contains(s)));
+
+        let melody_peak = buffer[8500..]
+            .iter()
+            .fold(0.0f32, |acc, s| acc.max(s.abs()));
+        assert!((melody_peak - 1.0).abs() < 0.01, "{melody_peak}");
+    }
+
+    #[test]
+    fn selected_wave_is_rendered() {
+        let midi_bytes = include_bytes!("../assets/tempo_change.mid");
+        let sample_rate = 8000;
+
+        let render = |wave: &dyn Wave| {
+            let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+            let synth = MidiSynth::new(midi, SynthSettings::default());
+            let (_, buffers) = synth.create_buffer(sample_rate, Some(wave));
+            buffers[0][0].clone()
+        };
+
+        let sine = render(&SineWave);
+        let square = render(&SquareWave);
+        assert_ne!(sine, square);
+
+        // past the attack, the square wave alternates between its two plateaus, apart from the
+        // samples smoothing its edges
+        let velocity_gain = 100.0 / 127.0;
+        assert!(
+            square[400..3600]
+                .iter()
+                .all(|s| s.abs() < velocity_gain + 1e-3)
+        );
+        let on_plateau = square[400..3600]
+            .iter()
+            .filter(|s| (s.abs() - velocity_gain).abs() < 1e-3)
+            .count();
+        assert!(on_plateau > 3200 * 3 / 4, "{on_plateau}");
+        assert!(square[400..3600].iter().any(|&s| s > 0.0));
+        assert!(square[400..3600].iter().any(|&s| s < 0.0));
+    }
+
+    #[test]
+    fn long_files_do_not_drift() {
+        let midi_bytes = include_bytes!("../assets/long.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+        synth.set_envelope(Envelope {
+            release: Duration::ZERO,
+            ..Envelope::default()
+        });
+
+        // a note held over 10,000 one-tick events, at 500001 us per quarter note and 96 ticks per quarter note
+        let sample_rate = 44100;
+        let expected_length =
+            (10_000u128 * 500_001 * sample_rate as u128 / 96 / 1_000_000) as usize;
+
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, expected_length);
+
+        // the note is held until the very end
+        assert!(
+            buffers[0][0][buffer_length - 10..]
+                .iter()
+                .any(|&s| s != 0.0)
+        );
+    }
+
+    #[test]
+    fn notes_become_segments_held_by_the_pedal() {
+        // At 120 BPM and 480 ticks per beat, 960 ticks last a second
+        let track = TrackBuilder::new()
+            .set_tempo(Tempo::from_bpm(120))
+            .controller(0, ControllerKind::Sustain.number(), 127)
+            .program_change(0, 40)
+            .note(0, 69, 127, 480)
+            .at(960)
+            .controller(0, ControllerKind::Sustain.number(), 0)
+            .note(0, 81, 127, 480);
+        let midi = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(480),
+        )
+        .track(track)
+        .build();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let rendering = synth.start_rendering(1000);
+        let segments = &rendering.tracks[0].as_ref().unwrap().segments;
+        let spans = segments
+            .iter()
+            .map(|segment| (segment.start_sample, segment.end_sample))
+            .collect::<Vec<_>>();
+
+        // The first note is held by the pedal until it is lifted, and the tail of the second one
+        // is cut off by the end of the file
+        assert_eq!(spans, [(0, 1050), (1000, 1500)]);
+        assert_eq!(
+            segments[0].envelope,
+            [(0, 0.0), (5, 1.0), (1000, 1.0), (1050, 0.0)]
+        );
+        assert_eq!(segments[0].timbre, Timbre::Strings);
+        assert!((segments[1].phase_increment - 880.0 / 1000.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn a_cluster_never_sounds_more_voices_than_allowed() {
+        // A 64 note cluster split across two tracks, one of them arpeggiated
+        let midi = || {
+            let chord = (0..32).fold(
+                TrackBuilder::new().set_tempo(Tempo::from_bpm(120)),
+                |track, i| track.note(0, 40 + i, 100, 960),
+            );
+            let arpeggio = (0..32).fold(TrackBuilder::new(), |track, i| {
+                track.at(i as u64 * 10).note(1, 72 + i, 100, 960)
+            });
+            FileBuilder::new(MIDIFormat::MultiTracks, TimeDivision::TicksPerBit(480))
+                .track(chord)
+                .track(arpeggio)
+                .build()
+        };
+
+        let most_voices = |max_voices| {
+            let synth = MidiSynth::new(
+                midi(),
+                SynthSettings {
+                    max_voices,
+                    ..SynthSettings::default()
+                },
+            );
+            let rendering = synth.start_rendering(1000);
+            let mut count = vec![0i32; rendering.buffer_length + 1];
+            for segment in rendering
+                .tracks
+                .iter()
+                .flatten()
+                .flat_map(|track| &track.segments)
+            {
+                count[segment.start_sample] += 1;
+                count[segment.end_sample] -= 1;
+            }
+            count
+                .iter()
+                .scan(0, |voices, change| {
+                    *voices += change;
+                    Some(*voices)
+                })
+                .max()
+                .unwrap()
+        };
+
+        assert_eq!(most_voices(64), 64);
+        assert_eq!(most_voices(32), 32);
+        assert_eq!(most_voices(0), 1);
+    }
+
+    #[test]
+    fn sustain_pedal_holds_notes() {
+        let midi_bytes = include_bytes!("../assets/sustain.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 1000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 5000);
+        let buffer = &buffers[0][0];
+
+        // the note is released after one beat, but the pedal is held until the third beat,
+        // then the pedal is pressed again and the next note is re-struck while held
+        assert!(buffer[1000..3000].iter().any(|s| s.abs() > 0.5));
+        assert!(buffer[3050..3500].iter().all(|&s| s == 0.0));
+        assert!(buffer[3500..5000].iter().any(|s| s.abs() > 0.5));
+        assert!(buffer.iter().all(|s| s.abs() <= 100.0 / 127.0 + 1e-3));
+    }
+
+    #[test]
+    fn expression_controls_amplitude() {
+        let midi_bytes = include_bytes!("../assets/crescendo.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+
+        // a single held note, with the expression raised every beat
+        let rms = buffers[0][0]
+            .chunks(sample_rate as usize)
+            .take(4)
+            .map(|beat| (beat.iter().map(|s| s * s).sum::<f32>() / beat.len() as f32).sqrt())
+            .collect::<Vec<_>>();
+
+        assert!(rms.windows(2).all(|pair| pair[0] < pair[1]), "{rms:?}");
+
+        // the channel volume is set to half at the start
+        let expected_peak = 64.0 / 127.0;
+        let last_peak = buffers[0][0][3 * sample_rate as usize..4 * sample_rate as usize]
+            .iter()
+            .fold(0.0f32, |acc, s| acc.max(s.abs()));
+        assert!((last_peak - expected_peak).abs() < 0.01, "{last_peak}");
+    }
+
+    #[test]
+    fn pan_changes_move_notes_across_channels() {
+        let midi_bytes = include_bytes!("../assets/pan_sweep.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let [left, right] = synth.mix_down(sample_rate, buffer_length, &buffers);
+
+        // the same note is played panned hard left, then hard right
+        let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+        let half = buffer_length / 2;
+
+        assert!(energy(&left[..half]) > 100.0 * energy(&right[..half]));
+        assert!(energy(&right[half..]) > 100.0 * energy(&left[half..]));
+    }
+
+    #[test]
+    fn modulation_wheel_adds_vibrato() {
+        let midi_bytes = include_bytes!("../assets/vibrato.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 44100;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let buffer = &buffers[0][0];
+
+        // lengths of the periods of the wave, measured between interpolated rising zero crossings
+        let period_spread = |samples: &[f32]| {
+            let crossings = samples
+                .windows(2)
+                .enumerate()
+                .filter(|(_, pair)| pair[0] < 0.0 && pair[1] >= 0.0)
+                .map(|(i, pair)| i as f32 + pair[0] / (pair[0] - pair[1]))
+                .collect::<Vec<_>>();
+            let periods = crossings
+                .windows(2)
+                .map(|pair| pair[1] - pair[0])
+                .collect::<Vec<_>>();
+
+            periods.iter().fold(f32::MIN, |acc, &p| acc.max(p))
+                - periods.iter().fold(f32::MAX, |acc, &p| acc.min(p))
+        };
+
+        // the modulation wheel is raised from 0 during the first beat to 127 during the last one
+        let second = sample_rate as usize;
+        assert!(period_spread(&buffer[second / 10..second]) < 0.1);
+        assert!(period_spread(&buffer[3 * second..4 * second - second / 10]) > 3.0);
+    }
+
+    #[test]
+    fn pitch_bend_range_is_set_by_rpn_0() {
+        // A held A3, bent all the way up, after the range is set to an octave on channel 0 only
+        let data = |channel| {
+            let rpn_channel = 0;
+            FileBuilder::new(
+                MIDIFormat::SingleMultiChannelTrack,
+                TimeDivision::TicksPerBit(96),
+            )
+            .track(
+                TrackBuilder::new()
+                    .controller(rpn_channel, ControllerKind::RpnMsb.number(), 0)
+                    .controller(rpn_channel, ControllerKind::RpnLsb.number(), 0)
+                    .controller(rpn_channel, ControllerKind::DataEntry.number(), 12)
+                    .controller(rpn_channel, ControllerKind::DataEntryLsb.number(), 0)
+                    .channel_event(
+                        channel,
+                        ChannelEventKind::PitchBend {
+                            lsb: 0x7F,
+                            msb: 0x7F,
+                        },
+                    )
+                    .note(channel, 57, 100, 192),
+            )
+            .build()
+        };
+
+        let sample_rate = 8000;
+        // Frequency from the rising zero crossings of the middle of the note
+        let frequency = |channel| {
+            let synth = MidiSynth::new(data(channel), SynthSettings::default());
+            let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+            let samples = &buffers[0][synth.meta.tracks[0].channel_index(channel)][2000..6000];
+            let crossings = samples
+                .windows(2)
+                .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+                .count();
+            crossings as f32 * sample_rate as f32 / samples.len() as f32
+        };
+
+        let a3 = MidiNote::new(57).frequency();
+        assert!((frequency(0) - 2.0 * a3).abs() < 2.0);
+        // Other channels keep the default range of 2 semitones
+        assert!((frequency(1) - a3 * 2.0f32.powf(2.0 / 12.0)).abs() < 2.0);
+    }
+
+    #[test]
+    fn aftertouch_swells_the_pressed_notes() {
+        // The same A3 held for four beats on two channels. The first one is pressed by channel
+        // aftertouch from the second beat, the second one by key aftertouch from the third.
+        let data = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(
+            TrackBuilder::new()
+                .note_on(0, 57, 100)
+                .note_on(1, 57, 100)
+                .at(96)
+                .channel_event(0, ChannelEventKind::ChannelAftertouch { aftertouch: 64 })
+                // a key which isn't sounding
+                .channel_event(
+                    1,
+                    ChannelEventKind::NoteAftertouch {
+                        note: 60,
+                        aftertouch: 127,
+                    },
+                )
+                .at(192)
+                .channel_event(0, ChannelEventKind::ChannelAftertouch { aftertouch: 127 })
+                .channel_event(
+                    1,
+                    ChannelEventKind::NoteAftertouch {
+                        note: 57,
+                        aftertouch: 127,
+                    },
+                )
+                .at(288)
+                .channel_event(0, ChannelEventKind::ChannelAftertouch { aftertouch: 0 })
+                .at(384)
+                .note_off(0, 57, 0)
+                .note_off(1, 57, 0),
+        )
+        .build();
+        let synth = MidiSynth::new(data, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let track = &synth.meta.tracks[0];
+        let beat = sample_rate as usize / 2;
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+        // Peaks of the second half of every beat, once the pressure settled, relative to the first
+        let swell = |channel| {
+            let buffer = &buffers[0][track.channel_index(channel)];
+            let peaks = (0..4)
+                .map(|beat_num| peak(&buffer[beat_num * beat + beat / 2..(beat_num + 1) * beat]))
+                .collect::<Vec<_>>();
+            peaks.iter().map(|p| p / peaks[0]).collect::<Vec<_>>()
+        };
+        let follows = |swell: Vec<f32>, pressures: [u8; 4]| {
+            swell
+                .iter()
+                .zip(pressures)
+                .all(|(swell, pressure)| (swell - aftertouch_gain(pressure)).abs() < 0.02)
+        };
+
+        assert!(follows(swell(0), [0, 64, 127, 0]));
+        assert!(follows(swell(1), [0, 0, 127, 127]));
+
+        // The first period after a change is still close to the previous level
+        let buffer = &buffers[0][track.channel_index(0)];
+        let period = (sample_rate as f32 / MidiNote::new(57).frequency()).ceil() as usize;
+        assert!(
+            peak(&buffer[2 * beat..2 * beat + period])
+                < 1.1 * peak(&buffer[beat / 2..beat]) * aftertouch_gain(64)
+        );
+    }
+
+    #[test]
+    fn all_notes_off_ends_hanging_notes() {
+        let midi_bytes = include_bytes!("../assets/all_notes_off.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 1000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 4000);
+        let buffer = &buffers[0][0];
+
+        // a note without a note off is released by All Notes Off after one beat
+        assert!(buffer[..1000].iter().any(|s| s.abs() > 0.5));
+        assert!(buffer[1000..1050].iter().any(|&s| s != 0.0));
+        assert!(buffer[1050..2000].iter().all(|&s| s == 0.0));
+
+        // another one is cut by All Sound Off after half a beat, without a release tail
+        assert!(buffer[2000..2500].iter().any(|s| s.abs() > 0.5));
+        assert!(buffer[2500..].iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn programs_select_channel_timbres() {
+        let midi_bytes = include_bytes!("../assets/programs.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (_, buffers) = synth.create_buffer(sample_rate, None);
+        let track = &synth.meta.tracks[0];
+
+        // the same note played by a flute on channel 0 and by strings on channel 1
+        let flute = &buffers[0][track.channel_index(0)];
+        let strings = &buffers[0][track.channel_index(1)];
+
+        // ratio of the second harmonic to the fundamental, which a sine doesn't have
+        let second_harmonic = |samples: &[f32]| {
+            let magnitude = |frequency: f32| {
+                let (re, im) = samples
+                    .iter()
+                    .enumerate()
+                    .fold((0.0, 0.0), |(re, im), (i, s)| {
+                        let angle = TAU * frequency * i as f32 / sample_rate as f32;
+                        (re + s * angle.cos(), im + s * angle.sin())
+                    });
+                f32::sqrt(re * re + im * im)
+            };
+
+            let fundamental = MidiNote::new(57).frequency();
+            magnitude(2.0 * fundamental) / magnitude(fundamental)
+        };
+        assert!(second_harmonic(flute) < 0.05);
+        assert!(second_harmonic(strings) > 0.3);
+
+        // forcing a wave plays both channels the same way
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(
+            buffers[0][track.channel_index(0)],
+            buffers[0][track.channel_index(1)]
+        );
+    }
+
+    #[test]
+    fn percussion_channel_plays_drum_hits() {
+        let midi_bytes = include_bytes!("../assets/drums.mid");
+        let midi = MIDIFileData::try_from(&midi_bytes[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let sample_rate = 8000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, None);
+        assert_eq!(buffer_length, 16000);
+        let buffer = &buffers[0][0];
+
+        // a kick on the first beat and a snare on the second, both held for the whole beat
+        let beat = sample_rate as usize;
+        let peak = |samples: &[f32]| samples.iter().fold(0.0f32, |acc, s| acc.max(s.abs()));
+        assert!(peak(&buffer[..beat / 10]) > 0.3);
+        assert!(peak(&buffer[beat / 2..beat]) < 0.01);
+        assert!(peak(&buffer[beat..beat + beat / 10]) > 0.3);
+        assert!(peak(&buffer[beat + beat / 2..]) < 0.01);
+
+        // the snare is noisy, the kick is a low tone
+        let zero_crossings = |samples: &[f32]| {
+            samples
+                .windows(2)
+                .filter(|pair| pair[0].signum() != pair[1].signum())
+                .count()
+        };
+        assert!(zero_crossings(&buffer[..beat / 10]) < 20);
+        assert!(zero_crossings(&buffer[beat..beat + beat / 10]) > 100);
+    }
+
+    #[test]
+    fn soloed_channel_is_the_only_one_rendered() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/channels.mid")[..]).unwrap();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+        assert_eq!(synth.channels(), vec![0, 3, 5]);
+
+        let mut channel_filter = ChannelFilter::default();
+        channel_filter.set_solo(Some(3));
+        synth.set_channel_filter(channel_filter.clone());
+
+        let sample_rate = 8000;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let track = &synth.meta.tracks[0];
+        assert!(buffers[0][track.channel_index(3)].iter().any(|&s| s != 0.0));
+        assert!(buffers[0][track.channel_index(0)].iter().all(|&s| s == 0.0));
+        assert!(buffers[0][track.channel_index(5)].iter().all(|&s| s == 0.0));
+
+        channel_filter.set_solo(None);
+        channel_filter.set_muted(0, true);
+        synth.set_channel_filter(channel_filter);
+
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        let track = &synth.meta.tracks[0];
+        assert!(buffers[0][track.channel_index(0)].iter().all(|&s| s == 0.0));
+        assert!(buffers[0][track.channel_index(3)].iter().any(|&s| s != 0.0));
+        assert!(buffers[0][track.channel_index(5)].iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn disabled_tracks_are_not_rendered() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/tracks.mid")[..]).unwrap();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+        assert_eq!(synth.track_names(), vec!["Track 1", "Melody", "Bass"]);
+
+        let mut track_filter = TrackFilter::default();
+        track_filter.set_enabled(1, false);
+        synth.set_track_filter(track_filter);
+
+        let sample_rate = 8000;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert!(buffers[1].iter().flatten().all(|&s| s == 0.0));
+        assert!(buffers[2].iter().flatten().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn playback_lasts_until_the_release_tails_end() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/format2.mid")[..]).unwrap();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+        synth.set_envelope(Envelope {
+            release: Duration::from_millis(300),
+            ..Envelope::default()
+        });
+
+        assert_eq!(synth.total_duration(), Duration::from_secs(2));
+        assert_eq!(synth.playback_duration(), Duration::from_millis(2300));
+
+        // the buffer itself ends with the file, cutting off the last tail
+        let sample_rate = 1000;
+        let (buffer_length, _) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 2000);
+    }
+
+    #[test]
+    fn release_tails_past_the_buffer_are_cut_off() {
+        // The note of the second track lasts until the end of the file, so its release tail
+        // would be rendered past the buffer
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/format2.mid")[..]).unwrap();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+        synth.set_envelope(Envelope {
+            release: Duration::from_secs(1),
+            ..Envelope::default()
+        });
+
+        let sample_rate = 1000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 2000);
+        assert!(
+            buffers
+                .iter()
+                .flatten()
+                .all(|buffer| buffer.len() == buffer_length)
+        );
+
+        // the note sounds until the last sample, where it is cut off
+        assert!(buffers[1][0][1990..].iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn notes_outlasting_the_file_end_with_the_buffer() {
+        // At 120 BPM and 480 ticks per beat, 960 ticks last a second. The first note is still
+        // sounding at the end of track, the second one is cut by the maximum duration.
+        let track = TrackBuilder::new()
+            .set_tempo(Tempo::from_bpm(120))
+            .note_on(0, 69, 127)
+            .note(1, 72, 127, 4 * 960)
+            .at(2 * 960);
+        let midi = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(480),
+        )
+        .track(track)
+        .build();
+        let synth = MidiSynth::new(
+            midi,
+            SynthSettings {
+                max_duration: Duration::from_millis(1500),
+                ..SynthSettings::default()
+            },
+        );
+
+        let sample_rate = 1000;
+        let rendering = synth.start_rendering(sample_rate);
+        assert_eq!(rendering.buffer_length, 1500);
+        let segments = &rendering.tracks[0].as_ref().unwrap().segments;
+        assert_eq!(segments.len(), 2);
+        assert!(
+            segments
+                .iter()
+                .all(|segment| segment.end_sample <= rendering.buffer_length)
+        );
+
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 1500);
+        assert!(buffers.iter().flatten().all(
+            |buffer| buffer.len() == buffer_length && buffer[1490..].iter().any(|&s| s != 0.0)
+        ));
+    }
+
+    #[test]
+    fn playback_rate_scales_the_buffer() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/format2.mid")[..]).unwrap();
+        let mut synth = MidiSynth::new(midi, SynthSettings::default());
+        synth.set_playback_rate(2.0);
+
+        assert_eq!(synth.total_duration(), Duration::from_secs(1));
+        assert_eq!(synth.meta.track_start(1), Duration::from_millis(500));
+
+        let sample_rate = 1000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 1000);
+        assert!(buffers[1][0][..500].iter().all(|&s| s == 0.0));
+        assert!(buffers[1][0][500..].iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn transpose_shifts_notes_by_semitones() {
+        // Rising zero crossings of the first track over its first second
+        let rising_crossings = |transpose: i8| {
+            let midi =
+                MIDIFileData::try_from(&include_bytes!("../assets/format2.mid")[..]).unwrap();
+            let mut synth = MidiSynth::new(midi, SynthSettings::default());
+            synth.set_transpose(transpose);
+
+            let (_, buffers) = synth.create_buffer(8000, Some(&SineWave));
+            buffers[0][0][..8000]
+                .windows(2)
+                .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+                .count() as i32
+        };
+
+        let original = rising_crossings(0);
+        let octave_up = rising_crossings(12);
+        assert!(original > 100);
+        assert!((octave_up - 2 * original).abs() <= 1);
+    }
+
+    #[test]
+    fn high_square_notes_do_not_alias() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/high_note.mid")[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        // Power of the given frequency over a Hann-windowed 0.1 second window
+        let sample_rate = 44100;
+        let (_, buffers) = synth.create_buffer(sample_rate, Some(&SquareWave));
+        let window = &buffers[0][0][4410..8820];
+        let power = |frequency: f32| {
+            let (re, im) = window
+                .iter()
+                .enumerate()
+                .fold((0.0, 0.0), |(re, im), (i, &s)| {
+                    let hann = 0.5 - 0.5 * (TAU * i as f32 / window.len() as f32).cos();
+                    let angle = TAU * frequency * i as f32 / sample_rate as f32;
+                    (re + hann * s * angle.cos(), im + hann * s * angle.sin())
+                });
+            re * re + im * im
+        };
+
+        // A square wave has no partials below its fundamental (note 100 is ~2637 Hz), so anything
+        // there was aliased back from above Nyquist
+        let fundamental = power(MidiNote::new(100).frequency());
+        let aliased = (1..250).map(|bin| power(bin as f32 * 10.0)).sum::<f32>();
+        assert!(
+            aliased / fundamental < 1e-4,
+            "aliased: {}",
+            aliased / fundamental
+        );
+    }
+
+    #[test]
+    fn smpte_offset_delays_the_track() {
+        let midi =
+            MIDIFileData::try_from(&include_bytes!("../assets/smpte_offset.mid")[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        assert_eq!(synth.meta.track_start(0), Duration::from_secs(2));
+        assert_eq!(synth.total_duration(), Duration::from_millis(2500));
+
+        let sample_rate = 1000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(buffer_length, 2500);
+        assert!(buffers[0][0][..2000].iter().all(|&s| s == 0.0));
+        assert!(buffers[0][0][2000..].iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn quarter_note_at_120_bpm_lasts_half_a_second() {
+        let track = TrackBuilder::new()
+            .set_tempo(Tempo::from_bpm(120))
+            .note(0, 69, 100, 480);
+        let midi = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(480),
+        )
+        .track(track)
+        .build();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        let (buffer_length, buffers) = synth.create_buffer(44100, Some(&SineWave));
+        assert_eq!(buffer_length, 22050);
+        assert!(buffers[0][0].iter().any(|&s| s != 0.0));
+    }
+
+    #[test]
+    fn test_asset_renders_to_finite_output() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/test.mid")[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        // Notes take the timbres of their programs
+        let sample_rate = 4000;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, None);
+        assert_eq!(
+            buffer_length,
+            sample_index(sample_rate, synth.total_duration())
+        );
+
+        let mix = synth.mix_down(sample_rate, buffer_length, &buffers);
+        let samples = mix.iter().flatten();
+        assert!(samples.clone().all(|sample| sample.is_finite()));
+        assert!(samples.clone().any(|&sample| sample != 0.0));
+    }
+
+    #[test]
+    fn songs_are_cut_off_at_the_maximum_duration() {
+        // A single note lasting over four hours, at 16.7 s per tick
+        let data = || {
+            FileBuilder::new(
+                MIDIFormat::SingleMultiChannelTrack,
+                TimeDivision::TicksPerBit(1),
+            )
+            .track(
+                TrackBuilder::new()
+                    .set_tempo(Tempo::from_mpqn(0xFF_FFFF))
+                    .note(0, 69, 100, 1000),
+            )
+            .build()
+        };
+        let mut synth = MidiSynth::new(data(), SynthSettings::default());
+        synth.set_metronome(Some(0.5));
+        assert_eq!(synth.total_duration(), DEFAULT_MAX_DURATION);
+
+        let sample_rate = 100;
+        let (buffer_length, buffers) = synth.create_buffer(sample_rate, Some(&SineWave));
+        assert_eq!(
+            buffer_length,
+            sample_index(sample_rate, DEFAULT_MAX_DURATION)
+        );
+        let [left, _] = synth.mix_down(sample_rate, buffer_length, &buffers);
+        assert_eq!(left.len(), buffer_length);
+
+        let max_duration = Duration::from_secs(60);
+        let mut synth = MidiSynth::new(
+            data(),
+            SynthSettings {
+                max_duration,
+                ..SynthSettings::default()
+            },
+        );
+        assert_eq!(synth.total_duration(), max_duration);
+        synth.set_playback_rate(2.0);
+        assert_eq!(synth.total_duration(), max_duration);
+    }
+
+    #[test]
+    fn chunked_rendering_matches_a_single_pass() {
+        let assets: [&[u8]; 6] = [
+            include_bytes!("../assets/test.mid"),
+            include_bytes!("../assets/sustain.mid"),
+            include_bytes!("../assets/drums.mid"),
+            include_bytes!("../assets/all_notes_off.mid"),
+            include_bytes!("../assets/format2.mid"),
+            include_bytes!("../assets/smpte_offset.mid"),
+        ];
+
+        for midi_bytes in assets {
+            let midi = MIDIFileData::try_from(midi_bytes).unwrap();
+            let synth = MidiSynth::new(midi, SynthSettings::default());
+            let sample_rate = 4000;
+
+            let mut single_pass = synth.start_rendering(sample_rate);
+            single_pass.chunk_length = usize::MAX;
+            assert!(synth.render_chunk(&mut single_pass, None).is_some());
+            assert!(single_pass.is_done());
+            let (single_length, single_buffers) = single_pass.finish();
+
+            // Slices which don't line up with seconds or events
+            let mut chunked = synth.start_rendering(sample_rate);
+            chunked.chunk_length = 997;
+            let mut progress = vec![];
+            while let Some(chunk) = synth.render_chunk(&mut chunked, None) {
+                progress.push(chunk.progress);
             }
+            assert_eq!(progress.len(), single_length.div_ceil(997).max(1));
+            assert!(progress.windows(2).all(|pair| pair[0] < pair[1]));
+            assert_eq!(progress.last(), Some(&1.0));
+
+            let (chunked_length, chunked_buffers) = chunked.finish();
+            assert_eq!(chunked_length, single_length);
+            let bits = |buffers: &[Vec<Vec<f32>>]| {
+                buffers
+                    .iter()
+                    .flatten()
+                    .flatten()
+                    .map(|sample| sample.to_bits())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(bits(&chunked_buffers), bits(&single_buffers));
         }
+    }
+
+    #[test]
+    fn render_iter_reports_one_second_slices() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/format2.mid")[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
 
-        (buffer_length, buffers)
+        let mut render = synth.render_iter(1000, Some(&SineWave));
+        let chunks = render.by_ref().collect::<Vec<_>>();
+        assert_eq!(
+            chunks,
+            [
+                RenderedChunk {
+                    samples: 0..1000,
+                    progress: 0.5
+                },
+                RenderedChunk {
+                    samples: 1000..2000,
+                    progress: 1.0
+                },
+            ]
+        );
+        assert_eq!(render.finish(), synth.create_buffer(1000, Some(&SineWave)));
+    }
+
+    #[test]
+    #[ignore = "benchmark, run in release mode"]
+    fn test_asset_renders_over_a_thousand_times_faster_than_real_time() {
+        let midi = MIDIFileData::try_from(&include_bytes!("../assets/test.mid")[..]).unwrap();
+        let synth = MidiSynth::new(midi, SynthSettings::default());
+
+        // Every note is sampled by its own loop, after its segment was prepared. The previous
+        // renderer, which went over every sounding note on every sample, managed about 250 times
+        // real time on the same machine.
+        for wave in [None, Some(&SineWave as &dyn Wave)] {
+            let elapsed = (0..3)
+                .map(|_| {
+                    let start = Instant::now();
+                    synth.create_buffer(44100, wave);
+                    start.elapsed()
+                })
+                .min()
+                .unwrap();
+
+            println!("{:?} rendered in {elapsed:?}", synth.total_duration());
+            assert!(elapsed * 1000 < synth.total_duration(), "{elapsed:?}");
+        }
     }
 }
@@ -0,0 +1,203 @@
+//! Encodes synthesized buffers as RIFF/WAVE files.
+
+/// Sample encoding used in the `data` chunk
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
+pub enum SampleFormat {
+    #[default]
+    Pcm16,
+    Float32,
+}
+
+impl SampleFormat {
+    fn format_tag(&self) -> u16 {
+        match self {
+            SampleFormat::Pcm16 => 1,
+            SampleFormat::Float32 => 3,
+        }
+    }
+
+    fn bytes_per_sample(&self) -> u16 {
+        match self {
+            SampleFormat::Pcm16 => 2,
+            SampleFormat::Float32 => 4,
+        }
+    }
+
+    fn write_sample(&self, bytes: &mut Vec<u8>, sample: f32) {
+        let sample = if sample.is_nan() {
+            0.0
+        } else {
+            sample.clamp(-1.0, 1.0)
+        };
+
+        match self {
+            SampleFormat::Pcm16 => {
+                bytes.extend_from_slice(&((sample * i16::MAX as f32).round() as i16).to_le_bytes())
+            }
+            SampleFormat::Float32 => bytes.extend_from_slice(&sample.to_le_bytes()),
+        }
+    }
+}
+
+/// Encode the output of [`super::raw::MidiSynth::create_buffer`] as a WAV file.
+///
+/// Every channel of every track becomes a separate channel of the file, in order. Samples are
+/// clamped to [-1.0; 1.0].
+pub fn encode(
+    sample_rate: u32,
+    buffer_length: usize,
+    buffers: &[Vec<Vec<f32>>],
+    format: SampleFormat,
+) -> Vec<u8> {
+    let silence = vec![0.0f32; buffer_length];
+    let mut channels = buffers.iter().flatten().collect::<Vec<_>>();
+    if channels.is_empty() {
+        // A file needs at least one channel
+        channels.push(&silence);
+    }
+
+    let num_channels = channels.len() as u16;
+    let block_align = num_channels * format.bytes_per_sample();
+    let data_size = buffer_length as u32 * block_align as u32;
+
+    // Non-PCM formats carry the extension size and a fact chunk
+    let fmt_size: u32 = match format {
+        SampleFormat::Pcm16 => 16,
+        SampleFormat::Float32 => 18,
+    };
+    let fact_size: u32 = match format {
+        SampleFormat::Pcm16 => 0,
+        SampleFormat::Float32 => 8 + 4,
+    };
+    let riff_size = 4 + (8 + fmt_size) + fact_size + (8 + data_size);
+
+    let mut bytes = Vec::with_capacity(8 + riff_size as usize);
+    bytes.extend_from_slice(b"RIFF");
+    bytes.extend_from_slice(&riff_size.to_le_bytes());
+    bytes.extend_from_slice(b"WAVE");
+
+    bytes.extend_from_slice(b"fmt ");
+    bytes.extend_from_slice(&fmt_size.to_le_bytes());
+    bytes.extend_from_slice(&format.format_tag().to_le_bytes());
+    bytes.extend_from_slice(&num_channels.to_le_bytes());
+    bytes.extend_from_slice(&sample_rate.to_le_bytes());
+    bytes.extend_from_slice(&(sample_rate * block_align as u32).to_le_bytes());
+    bytes.extend_from_slice(&block_align.to_le_bytes());
+    bytes.extend_from_slice(&(format.bytes_per_sample() * 8).to_le_bytes());
+
+    if format == SampleFormat::Float32 {
+        bytes.extend_from_slice(&0u16.to_le_bytes());
+
+        bytes.extend_from_slice(b"fact");
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(&(buffer_length as u32).to_le_bytes());
+    }
+
+    bytes.extend_from_slice(b"data");
+    bytes.extend_from_slice(&data_size.to_le_bytes());
+    for sample_index in 0..buffer_length {
+        for channel in &channels {
+            format.write_sample(&mut bytes, channel[sample_index]);
+        }
+    }
+
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn u16_at(bytes: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// Returns the offset and size of the first chunk with the given id
+    fn find_chunk(bytes: &[u8], id: &[u8; 4]) -> (usize, usize) {
+        let mut offset = 12;
+        while offset + 8 <= bytes.len() {
+            let size = u32_at(bytes, offset + 4) as usize;
+            if &bytes[offset..offset + 4] == id {
+                return (offset + 8, size);
+            }
+            offset += 8 + size + size % 2;
+        }
+        panic!("chunk {:?} not found", id);
+    }
+
+    #[test]
+    fn pcm16_header_and_round_trip() {
+        let buffers = vec![
+            vec![vec![0.0, 0.5, -0.5, 1.0]],
+            vec![vec![-1.0, 0.25, 2.0, -3.0]],
+        ];
+        let bytes = encode(44100, 4, &buffers, SampleFormat::Pcm16);
+
+        assert_eq!(&bytes[0..4], b"RIFF");
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(&bytes[8..12], b"WAVE");
+
+        let (fmt, fmt_size) = find_chunk(&bytes, b"fmt ");
+        assert_eq!(fmt_size, 16);
+        assert_eq!(u16_at(&bytes, fmt), 1);
+        assert_eq!(u16_at(&bytes, fmt + 2), 2);
+        assert_eq!(u32_at(&bytes, fmt + 4), 44100);
+        assert_eq!(u32_at(&bytes, fmt + 8), 44100 * 4);
+        assert_eq!(u16_at(&bytes, fmt + 12), 4);
+        assert_eq!(u16_at(&bytes, fmt + 14), 16);
+
+        let (data, data_size) = find_chunk(&bytes, b"data");
+        assert_eq!(data_size, 4 * 2 * 2);
+        assert_eq!(data + data_size, bytes.len());
+
+        let samples = (0..8)
+            .map(|i| u16_at(&bytes, data + i * 2) as i16 as f32 / i16::MAX as f32)
+            .collect::<Vec<_>>();
+        let expected = [0.0, -1.0, 0.5, 0.25, -0.5, 1.0, 1.0, -1.0];
+        for (sample, expected) in samples.iter().zip(expected) {
+            assert!((sample - expected).abs() < 1.0 / i16::MAX as f32);
+        }
+    }
+
+    #[test]
+    fn float32_header_and_round_trip() {
+        let buffers = vec![vec![vec![0.0, 0.125, -0.75]]];
+        let bytes = encode(48000, 3, &buffers, SampleFormat::Float32);
+
+        assert_eq!(u32_at(&bytes, 4) as usize, bytes.len() - 8);
+
+        let (fmt, fmt_size) = find_chunk(&bytes, b"fmt ");
+        assert_eq!(fmt_size, 18);
+        assert_eq!(u16_at(&bytes, fmt), 3);
+        assert_eq!(u16_at(&bytes, fmt + 2), 1);
+        assert_eq!(u32_at(&bytes, fmt + 8), 48000 * 4);
+        assert_eq!(u16_at(&bytes, fmt + 12), 4);
+        assert_eq!(u16_at(&bytes, fmt + 14), 32);
+
+        let (fact, _) = find_chunk(&bytes, b"fact");
+        assert_eq!(u32_at(&bytes, fact), 3);
+
+        let (data, data_size) = find_chunk(&bytes, b"data");
+        assert_eq!(data_size, 3 * 4);
+        let samples = (0..3)
+            .map(|i| f32::from_le_bytes(bytes[data + i * 4..data + i * 4 + 4].try_into().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(samples, vec![0.0, 0.125, -0.75]);
+    }
+
+    #[test]
+    fn empty_buffers_encode_silent_mono() {
+        let bytes = encode(8000, 2, &[], SampleFormat::Pcm16);
+
+        let (fmt, _) = find_chunk(&bytes, b"fmt ");
+        assert_eq!(u16_at(&bytes, fmt + 2), 1);
+
+        let (data, data_size) = find_chunk(&bytes, b"data");
+        assert_eq!(data_size, 4);
+        assert!(bytes[data..].iter().all(|&b| b == 0));
+    }
+}
@@ -1,100 +1,669 @@
 use core::f32;
-use std::{collections::HashMap, time::Duration};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, hash_map::Entry},
+    time::Duration,
+};
 
 use wasm_bindgen::prelude::*;
 use web_sys::js_sys;
 
 use crate::{
-    midi::{ChannelEventKind, MIDIEventKind, MIDIFileData, MetaEvent, Tempo},
-    synth::MidiNote,
+    midi::{
+        ChannelEventKind, Controller, ControllerKind, MIDIEventKind, MIDIFileData, MetaEvent,
+        PitchBend, notes, rpn::ParameterDecoder,
+    },
+    synth::{
+        AFTERTOUCH_SMOOTHING, ChannelFilter, ChannelGain, DEFAULT_PITCH_BEND_RANGE, Damper,
+        Envelope, FilterEnvelope, Marker, MidiMeta, MidiNote, PitchBends, Pressures, SynthSettings,
+        TrackFilter, TrackInfo, UnisonOscillator, Vibrato, Voice, aftertouch_brightness,
+        aftertouch_gain, mix, steal_voices, unison_gain,
+    },
+    synth::{
+        instruments::Timbre,
+        metronome::{self, Click},
+        percussion::{Drum, PERCUSSION_CHANNEL},
+    },
     wave::Wave,
 };
 
 pub struct MidiSynth {
     data: MIDIFileData,
+    meta: MidiMeta,
+    settings: SynthSettings,
+    /// Semitones reached by a full pitch bend, on channels which don't set their own through RPN 0
+    pitch_bend_range: f32,
+    vibrato: Vibrato,
+    envelope: Envelope,
+    /// Sweeps a filter of each note, when set
+    filter_envelope: Option<FilterEnvelope>,
+    channel_filter: ChannelFilter,
+    track_filter: TrackFilter,
+    /// Semitones added to every note, apart from the percussion channel
+    transpose: i8,
+    /// Volume of the clicks played on every beat, if any
+    metronome: Option<f32>,
+}
+
+/// Nodes created by [`MidiSynth::schedule`], kept so the playback can be stopped.
+pub struct ScheduledPlayback {
+    /// All scheduled notes are routed through this node
+    master: web_sys::GainNode,
+    channels: Vec<ScheduledChannel>,
+    notes: Vec<ScheduledNote>,
+    /// Drum hits
+    hits: Vec<ScheduledHit>,
+    metronome: Option<ScheduledClicks>,
+}
+
+/// Clicks of the metronome, or of a count-in, kept so they can be stopped
+pub struct ScheduledClicks {
+    /// Volume of the clicks, between them and their destination
+    gain: web_sys::GainNode,
+    hits: Vec<ScheduledHit>,
+}
+
+/// Drum hit of the percussion channel, or click of the metronome
+struct ScheduledHit {
+    source: web_sys::AudioBufferSourceNode,
+    gain: web_sys::GainNode,
+}
+
+/// Nodes applying the controllers of a channel, between its notes and the master gain
+struct ScheduledChannel {
+    /// Volume and expression
+    gain: web_sys::GainNode,
+    panner: web_sys::StereoPannerNode,
+    /// Low frequency oscillator driving the vibrato of the notes
+    vibrato: web_sys::OscillatorNode,
+    /// Scales the vibrato oscillator to the detune in cents set by the modulation wheel
+    vibrato_depth: web_sys::GainNode,
+}
+
+struct ScheduledNote {
+    /// Oscillators of the unison, or a buffer source for non-periodic waves
+    sources: Vec<web_sys::AudioScheduledSourceNode>,
+    gain: web_sys::GainNode,
+}
+
+/// What the notes of a wave are played from
+#[derive(Clone)]
+pub(super) enum NoteSource {
+    /// Oscillator with the decomposition of the wave, following the pitch of the note
+    Periodic(web_sys::PeriodicWave),
+    /// Looped buffer filled with a wave which has no decomposition, such as noise
+    Buffer(web_sys::AudioBuffer),
+}
+
+impl NoteSource {
+    /// Node playing `note` from the source, yet to be started
+    pub(super) fn create_node(
+        &self,
+        ctx: &web_sys::BaseAudioContext,
+        note: MidiNote,
+    ) -> Result<web_sys::AudioScheduledSourceNode, JsValue> {
+        Ok(match self {
+            NoteSource::Periodic(periodic_wave) => {
+                let oscillator = web_sys::OscillatorNode::new(ctx)?;
+                oscillator.set_periodic_wave(periodic_wave);
+                oscillator.frequency().set_value(note.frequency());
+                oscillator.into()
+            }
+            NoteSource::Buffer(buffer) => {
+                let source = ctx.create_buffer_source()?;
+                source.set_buffer(Some(buffer));
+                source.set_loop(true);
+                source.into()
+            }
+        })
+    }
+}
+
+/// Automate `gain` along the envelope `points` of a note started at `start_time`, as given by
+/// [`Envelope::automation`]
+pub(super) fn automate_envelope(
+    gain: &web_sys::GainNode,
+    points: &[(Duration, f32)],
+    velocity: u8,
+    start_time: Duration,
+) -> Result<(), JsValue> {
+    let velocity_gain = velocity as f32 / 127.0;
+    for (i, (time, level)) in points.iter().enumerate() {
+        let time = (start_time + *time).as_secs_f64();
+        if i == 0 {
+            gain.gain().set_value_at_time(velocity_gain * level, time)?;
+        } else {
+            gain.gain()
+                .linear_ramp_to_value_at_time(velocity_gain * level, time)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Automate `param` to approach the value of each aftertouch pressure, mapped by `value`. The
+/// first pressure is set right away.
+fn automate_pressure(
+    param: &web_sys::AudioParam,
+    pressure: &[(Duration, u8)],
+    value: fn(u8) -> f32,
+) -> Result<(), JsValue> {
+    for (i, (time, pressure)) in pressure.iter().enumerate() {
+        if i == 0 {
+            param.set_value_at_time(value(*pressure), time.as_secs_f64())?;
+        } else {
+            param.set_target_at_time(
+                value(*pressure),
+                time.as_secs_f64(),
+                AFTERTOUCH_SMOOTHING.as_secs_f64(),
+            )?;
+        }
+    }
+
+    Ok(())
+}
+
+impl ScheduledPlayback {
+    /// Stop all notes, including the ones that did not start yet, and disconnect them from the graph.
+    pub fn stop(&self) -> Result<(), JsValue> {
+        for note in &self.notes {
+            for source in &note.sources {
+                source.stop()?;
+                source.disconnect()?;
+            }
+            note.gain.disconnect()?;
+        }
+
+        for hit in &self.hits {
+            hit.stop()?;
+        }
+
+        for channel in &self.channels {
+            channel.gain.disconnect()?;
+            channel.panner.disconnect()?;
+            channel.vibrato.stop()?;
+            channel.vibrato.disconnect()?;
+            channel.vibrato_depth.disconnect()?;
+        }
+
+        if let Some(metronome) = &self.metronome {
+            metronome.stop()?;
+        }
+
+        self.master.disconnect()
+    }
+}
+
+impl ScheduledClicks {
+    /// Schedule `clicks` at `volume`, their times counted from `start` on the context clock
+    pub fn schedule(
+        ctx: &web_sys::BaseAudioContext,
+        destination: &web_sys::AudioNode,
+        volume: f32,
+        clicks: impl IntoIterator<Item = Click>,
+        start: Duration,
+    ) -> Result<Self, JsValue> {
+        let gain = web_sys::GainNode::new(ctx)?;
+        gain.gain().set_value(volume);
+        gain.connect_with_audio_node(destination)?;
+
+        let click_buffer = |accent| {
+            let click = metronome::render_click(accent, ctx.sample_rate() as u32);
+            let buffer = ctx.create_buffer(1, click.len() as u32, ctx.sample_rate())?;
+            buffer.copy_to_channel(&click, 0)?;
+            Ok::<_, JsValue>(buffer)
+        };
+        let accent_buffer = click_buffer(true)?;
+        let beat_buffer = click_buffer(false)?;
+        let hits = clicks
+            .into_iter()
+            .map(|click| {
+                MidiSynth::schedule_hit(
+                    ctx,
+                    &gain,
+                    if click.accent {
+                        &accent_buffer
+                    } else {
+                        &beat_buffer
+                    },
+                    127,
+                    start + click.time,
+                )
+            })
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self { gain, hits })
+    }
+
+    /// Stop the clicks, including the ones that did not start yet, and disconnect them from the
+    /// graph.
+    pub fn stop(&self) -> Result<(), JsValue> {
+        for hit in &self.hits {
+            hit.stop()?;
+        }
+        self.gain.disconnect()
+    }
+}
+
+impl ScheduledHit {
+    fn stop(&self) -> Result<(), JsValue> {
+        AsRef::<web_sys::AudioScheduledSourceNode>::as_ref(&self.source).stop()?;
+        self.source.disconnect()?;
+        self.gain.disconnect()
+    }
 }
 
 impl MidiSynth {
-    pub fn new(data: MIDIFileData) -> Self {
-        Self { data }
+    pub fn new(data: MIDIFileData, settings: SynthSettings) -> Self {
+        Self {
+            meta: MidiMeta::new(&data, settings.max_duration),
+            data,
+            settings,
+            pitch_bend_range: DEFAULT_PITCH_BEND_RANGE,
+            vibrato: Vibrato::default(),
+            envelope: Envelope::default(),
+            filter_envelope: None,
+            channel_filter: ChannelFilter::default(),
+            track_filter: TrackFilter::default(),
+            transpose: 0,
+            metronome: None,
+        }
+    }
+
+    pub fn set_pitch_bend_range(&mut self, semitones: f32) {
+        self.pitch_bend_range = semitones;
+    }
+
+    pub fn set_vibrato(&mut self, vibrato: Vibrato) {
+        self.vibrato = vibrato;
     }
 
+    pub fn set_envelope(&mut self, envelope: Envelope) {
+        self.envelope = envelope;
+    }
+
+    pub fn filter_envelope(&self) -> Option<FilterEnvelope> {
+        self.filter_envelope
+    }
+
+    pub fn settings(&self) -> SynthSettings {
+        self.settings
+    }
+
+    /// Settings of the playback scheduled from now on
+    pub fn set_settings(&mut self, settings: SynthSettings) {
+        if settings.max_duration != self.settings.max_duration {
+            self.meta = MidiMeta::new(&self.data, settings.max_duration);
+        }
+        self.settings = settings;
+    }
+
+    pub fn metronome(&self) -> Option<f32> {
+        self.metronome
+    }
+
+    /// Play a click on every beat at `volume`, or none
+    pub fn set_metronome(&mut self, volume: Option<f32>) {
+        self.metronome = volume;
+    }
+
+    pub fn set_filter_envelope(&mut self, filter_envelope: Option<FilterEnvelope>) {
+        self.filter_envelope = filter_envelope;
+    }
+
+    pub fn set_channel_filter(&mut self, channel_filter: ChannelFilter) {
+        self.channel_filter = channel_filter;
+    }
+
+    pub fn set_track_filter(&mut self, track_filter: TrackFilter) {
+        self.track_filter = track_filter;
+    }
+
+    pub fn set_transpose(&mut self, semitones: i8) {
+        self.transpose = semitones;
+    }
+
+    /// Play the file faster (`rate` above 1) or slower. Notes keep their pitch.
+    pub fn set_playback_rate(&mut self, rate: f32) {
+        self.data.set_playback_rate(rate as f64);
+        self.meta = MidiMeta::new(&self.data, self.settings.max_duration);
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        self.meta.total_duration()
+    }
+
+    /// Time the playback takes, including the release tails of the last notes
+    pub fn playback_duration(&self) -> Duration {
+        self.total_duration() + self.envelope.release
+    }
+
+    pub fn data(&self) -> &MIDIFileData {
+        &self.data
+    }
+
+    /// Give back the file, so it can be played by another synthesizer
+    pub fn into_data(self) -> MIDIFileData {
+        self.data
+    }
+
+    /// MIDI channels used by the file, in ascending order
+    pub fn channels(&self) -> Vec<u8> {
+        self.meta.channels()
+    }
+
+    /// Names of the tracks of the file, in order
+    pub fn track_names(&self) -> Vec<String> {
+        self.meta.track_names()
+    }
+
+    /// Description of the tracks of the file, in order
+    pub fn track_infos(&self) -> Vec<TrackInfo> {
+        self.meta.track_infos()
+    }
+
+    /// Markers and cue points of the file, sorted by time
+    pub fn markers(&self) -> &[Marker] {
+        &self.meta.markers
+    }
+
+    /// Copyright notice of the file, if it has one
+    pub fn copyright(&self) -> Option<&str> {
+        self.meta.copyright.as_deref()
+    }
+
+    /// Schedule all notes starting at `offset` into the file, as if the playback started at `offset`,
+    /// `delay` after the current time of the context.
+    ///
+    /// Notes which are sounding at `offset` are restarted from it. Notes are played with the timbre
+    /// of their channel's program, unless a `wave` is forced for all of them. The context may be an
+    /// offline one, rendering the file into a buffer.
     pub fn schedule(
         &self,
-        ctx: &web_sys::AudioContext,
-        wave: &dyn Wave,
+        ctx: &web_sys::BaseAudioContext,
+        wave: Option<&dyn Wave>,
         destination: &web_sys::AudioNode,
-    ) -> Result<(), JsValue> {
-        let (real, imag) = wave.decompose();
-        let periodic_wave_options = {
-            let options = web_sys::PeriodicWaveOptions::new();
-            options.set_real(&JsValue::from(js_sys::Float32Array::from(real)));
-            options.set_imag(&JsValue::from(js_sys::Float32Array::from(imag)));
-            options
+        offset: Duration,
+        delay: Duration,
+    ) -> Result<ScheduledPlayback, JsValue> {
+        let master = web_sys::GainNode::new(ctx)?;
+        master.connect_with_audio_node(destination)?;
+        let mut notes = vec![];
+        let mut channels = vec![];
+        let mut hits = vec![];
+        // Rendered once per percussion note
+        let mut drum_buffers = HashMap::<u8, web_sys::AudioBuffer>::new();
+
+        let forced_wave = wave
+            .map(|wave| Self::create_note_source(ctx, wave))
+            .transpose()?;
+        // Created when the first note of a timbre is scheduled
+        let timbre_waves = RefCell::new(HashMap::<Timbre, NoteSource>::new());
+        let unison = self.settings.unison.spread(self.settings.unison_spread);
+
+        let playback_start = Duration::from_secs_f64(ctx.current_time()) + delay;
+
+        struct PlayedNote {
+            /// Index of the track among the enabled ones
+            track: usize,
+            channel: u8,
+            /// Key of the note, before the transposition
+            key: u8,
+            note: MidiNote,
+            voice: Voice,
+            on_velocity: u8,
+            off_velocity: u8,
+            timbre: Timbre,
+        }
+
+        // Schedule a note for the time of its voice, unless it was released before `offset`
+        let schedule_played_note = |channel: &ScheduledChannel,
+                                    pitch_bends: Option<&PitchBends>,
+                                    pressures: Option<&Pressures>,
+                                    played_note: PlayedNote|
+         -> Result<Option<ScheduledNote>, JsValue> {
+            let voice = played_note.voice;
+            if voice.release <= offset || voice.end <= voice.start {
+                return Ok(None);
+            }
+
+            let start_time = voice.start.max(offset);
+            let end_time = voice.release;
+            let detune = pitch_bends
+                .map(|pitch_bends| pitch_bends.automation(start_time, end_time))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(time, cents)| (playback_start + (time - offset), cents))
+                .collect::<Vec<_>>();
+            let pressure = pressures
+                .map(|pressures| pressures.automation(played_note.key, start_time, voice.end))
+                .unwrap_or_default()
+                .into_iter()
+                .map(|(time, pressure)| (playback_start + (time - offset), pressure))
+                .collect::<Vec<_>>();
+
+            let note_source = match &forced_wave {
+                Some(forced_wave) => forced_wave.clone(),
+                None => match timbre_waves.borrow_mut().entry(played_note.timbre) {
+                    Entry::Occupied(entry) => entry.get().clone(),
+                    Entry::Vacant(entry) => entry
+                        .insert(Self::create_note_source(ctx, played_note.timbre.wave())?)
+                        .clone(),
+                },
+            };
+
+            Self::schedule_note(
+                ctx,
+                // Stolen notes fade out faster
+                &Envelope {
+                    release: voice.release_length,
+                    ..self.envelope
+                },
+                &channel.gain,
+                &channel.vibrato_depth,
+                self.filter_envelope.as_ref(),
+                &unison,
+                &note_source,
+                played_note.note.transposed(self.transpose),
+                played_note.on_velocity,
+                played_note.off_velocity,
+                playback_start + (start_time - offset),
+                end_time - start_time,
+                &detune,
+                &pressure,
+            )
+            .map(Some)
         };
-        let periodic_wave = web_sys::PeriodicWave::new_with_options(ctx, &periodic_wave_options)?;
 
-        for track in self.data.tracks() {
-            let mut time = Duration::from_secs_f64(ctx.current_time());
-            let mut tick_duration = self.data.time_division().tick_duration(Tempo::default());
+        // Notes of all tracks are scheduled once they are given voices
+        let mut played_notes = vec![];
+        let mut track_channels = vec![];
 
-            struct PlayedNote {
-                start_time: Duration,
-                on_velocity: u8,
+        for track_index in 0..self.data.tracks().len() {
+            if !self.track_filter.is_enabled(track_index) {
+                continue;
             }
 
-            let mut played_notes = HashMap::<(u8, MidiNote), PlayedNote>::new();
+            let track_start = self.meta.track_start(track_index);
 
-            for event in track.events() {
-                time += tick_duration * event.delta_time();
+            // Notes of every channel go through a node applying its volume and expression
+            let mut channel_nodes = HashMap::<u8, (ScheduledChannel, ChannelGain)>::new();
+            let mut pitch_bends = HashMap::<u8, PitchBends>::new();
+            let mut pressures = HashMap::<u8, Pressures>::new();
+            // Pitch bend ranges set by RPN 0, replacing the configured one on their channels
+            let mut pitch_bend_ranges = HashMap::<u8, f32>::new();
+            let mut parameters = ParameterDecoder::default();
+            let mut dampers = HashMap::<u8, Damper>::new();
+            // All sound off events, which end the sounding notes without their release tails
+            let mut sounds_off = HashMap::<u8, Vec<Duration>>::new();
+            // Timbre of each channel from the time of its program changes
+            let mut timbres = HashMap::<u8, Vec<(Duration, Timbre)>>::new();
 
+            let mut walker = self.data.walk_track(track_index);
+            for (time, _, event) in walker.by_ref() {
                 match event.kind() {
+                    MIDIEventKind::Channel(channel_event)
+                        if !self.channel_filter.is_audible(channel_event.channel()) =>
+                    {
+                        // Left silent
+                    }
                     MIDIEventKind::Channel(channel_event) => {
+                        let channel = channel_event.channel();
+                        let (channel_node, channel_gain) = match channel_nodes.entry(channel) {
+                            Entry::Occupied(entry) => entry.into_mut(),
+                            Entry::Vacant(entry) => {
+                                let gain = web_sys::GainNode::new(ctx)?;
+                                let panner = web_sys::StereoPannerNode::new(ctx)?;
+                                gain.connect_with_audio_node(&panner)?;
+                                panner.connect_with_audio_node(&master)?;
+
+                                let vibrato = web_sys::OscillatorNode::new(ctx)?;
+                                let vibrato_depth = web_sys::GainNode::new(ctx)?;
+                                vibrato.frequency().set_value(self.vibrato.rate);
+                                vibrato_depth.gain().set_value(0.0);
+                                vibrato.connect_with_audio_node(&vibrato_depth)?;
+                                vibrato.start_with_when(playback_start.as_secs_f64())?;
+
+                                entry.insert((
+                                    ScheduledChannel {
+                                        gain,
+                                        panner,
+                                        vibrato,
+                                        vibrato_depth,
+                                    },
+                                    ChannelGain::default(),
+                                ))
+                            }
+                        };
+                        // Changes from before the offset apply as soon as the playback starts
+                        let event_time =
+                            (playback_start + time.saturating_sub(offset)).as_secs_f64();
+
                         match channel_event.kind() {
-                            ChannelEventKind::NoteOff {
-                                note,
-                                // TODO: support velocity
-                                velocity: off_velocity,
-                            } => {
-                                let note = MidiNote::new(*note);
-                                if let Some(played_note) =
-                                    played_notes.remove(&(channel_event.channel(), note))
-                                {
-                                    Self::schedule_note(
+                            ChannelEventKind::NoteOn { note, velocity }
+                                if channel == PERCUSSION_CHANNEL && *velocity > 0 =>
+                            {
+                                // Drum hits have a fixed length and ignore note offs
+                                if time >= offset {
+                                    let drum_buffer = match drum_buffers.entry(*note) {
+                                        Entry::Occupied(entry) => entry.into_mut(),
+                                        Entry::Vacant(entry) => {
+                                            let hit = Drum::from_note(*note)
+                                                .render(ctx.sample_rate() as u32);
+                                            let drum_buffer = ctx.create_buffer(
+                                                1,
+                                                hit.len() as u32,
+                                                ctx.sample_rate(),
+                                            )?;
+                                            drum_buffer.copy_to_channel(&hit, 0)?;
+                                            entry.insert(drum_buffer)
+                                        }
+                                    };
+
+                                    hits.push(Self::schedule_hit(
                                         ctx,
-                                        destination,
-                                        &periodic_wave,
-                                        note,
-                                        played_note.on_velocity,
-                                        *off_velocity,
-                                        played_note.start_time,
-                                        time - played_note.start_time,
-                                    )?;
+                                        &channel_node.gain,
+                                        drum_buffer,
+                                        *velocity,
+                                        playback_start + (time - offset),
+                                    )?);
                                 }
                             }
-                            ChannelEventKind::NoteOn { note, velocity } => {
-                                played_notes.insert(
-                                    (channel_event.channel(), MidiNote::new(*note)),
-                                    PlayedNote {
-                                        start_time: time,
-                                        on_velocity: *velocity,
-                                    },
+                            ChannelEventKind::NoteOn { .. } | ChannelEventKind::NoteOff { .. } => {
+                                // Paired into notes below
+                            }
+                            ChannelEventKind::Controller {
+                                controller_number,
+                                controller_value,
+                            } => {
+                                let controller =
+                                    Controller::new(*controller_number, *controller_value);
+                                match controller.kind() {
+                                    ControllerKind::AllNotesOff => {
+                                        dampers.entry(channel).or_default().notes_off.push(time);
+                                    }
+                                    ControllerKind::AllSoundOff => {
+                                        dampers.entry(channel).or_default().notes_off.push(time);
+                                        sounds_off.entry(channel).or_default().push(time);
+                                    }
+                                    ControllerKind::Sustain => {
+                                        dampers
+                                            .entry(channel)
+                                            .or_default()
+                                            .pedal
+                                            .push((time, controller.is_on()));
+                                    }
+                                    ControllerKind::Volume | ControllerKind::Expression => {
+                                        channel_gain.update(controller);
+                                        channel_node
+                                            .gain
+                                            .gain()
+                                            .set_value_at_time(channel_gain.gain(), event_time)?;
+                                    }
+                                    ControllerKind::Modulation => {
+                                        channel_node.vibrato_depth.gain().set_value_at_time(
+                                            self.vibrato.depth(controller.value()),
+                                            event_time,
+                                        )?;
+                                    }
+                                    ControllerKind::Pan => {
+                                        channel_node.panner.pan().set_value_at_time(
+                                            mix::pan_position(controller.value()),
+                                            event_time,
+                                        )?;
+                                    }
+                                    ControllerKind::DataEntry
+                                    | ControllerKind::DataEntryLsb
+                                    | ControllerKind::NrpnLsb
+                                    | ControllerKind::NrpnMsb
+                                    | ControllerKind::RpnLsb
+                                    | ControllerKind::RpnMsb => {
+                                        if let Some(range) = parameters
+                                            .update(channel, controller)
+                                            .and_then(|change| change.pitch_bend_range())
+                                        {
+                                            pitch_bend_ranges.insert(channel, range);
+                                        }
+                                    }
+                                    ControllerKind::Other(_) => {
+                                        log::warn!("Unhandled channel event: {channel_event:?}")
+                                    }
+                                }
+                            }
+                            ChannelEventKind::PitchBend { lsb, msb } => {
+                                let range = pitch_bend_ranges
+                                    .get(&channel)
+                                    .copied()
+                                    .unwrap_or(self.pitch_bend_range);
+                                pitch_bends.entry(channel).or_default().push(
+                                    time,
+                                    PitchBend::from_bytes(*lsb, *msb).semitones(range) * 100.0,
+                                );
+                            }
+                            ChannelEventKind::ProgramChange { program_number } => {
+                                timbres
+                                    .entry(channel)
+                                    .or_default()
+                                    .push((time, Timbre::from_program(*program_number)));
+                            }
+                            ChannelEventKind::NoteAftertouch { note, aftertouch } => {
+                                pressures.entry(channel).or_default().push_key(
+                                    time,
+                                    *note,
+                                    *aftertouch,
                                 );
                             }
-                            ChannelEventKind::NoteAftertouch { .. }
-                            | ChannelEventKind::Controller { .. }
-                            | ChannelEventKind::ProgramChange { .. }
-                            | ChannelEventKind::ChannelAftertouch { .. }
-                            | ChannelEventKind::PitchBend { .. } => {
-                                log::warn!("Unhandled channel event: {channel_event:?}")
+                            ChannelEventKind::ChannelAftertouch { aftertouch } => {
+                                pressures
+                                    .entry(channel)
+                                    .or_default()
+                                    .push_channel(time, *aftertouch);
                             }
                         }
                     }
-                    MIDIEventKind::Meta(MetaEvent::EndOfTrack) => break,
-                    MIDIEventKind::Meta(MetaEvent::SetTempo { tempo }) => {
-                        tick_duration = self.data.time_division().tick_duration(*tempo);
-                    }
-                    MIDIEventKind::Meta(MetaEvent::CopyrightNotice { .. })
+                    MIDIEventKind::Meta(MetaEvent::EndOfTrack)
+                    | MIDIEventKind::Meta(MetaEvent::SetTempo { .. })
+                    | MIDIEventKind::Meta(MetaEvent::CopyrightNotice { .. })
                     | MIDIEventKind::Meta(MetaEvent::SequenceTrackName { .. })
                     | MIDIEventKind::Meta(MetaEvent::InstrumentName { .. })
                     | MIDIEventKind::Meta(MetaEvent::Lyrics { .. })
@@ -105,41 +674,435 @@ impl MidiSynth {
                     MIDIEventKind::Meta(_) => {
                         log::warn!("Unhandled meta in buffer creation event: {event:?}")
                     }
+                    MIDIEventKind::SysEx(_) => {
+                        // Ignored
+                    }
+                }
+            }
+
+            let track_end = walker.time();
+            let track_notes = notes::extract_track_notes(&self.data, track_index);
+            for (note_index, note) in track_notes.iter().enumerate() {
+                // Notes after the song is cut off aren't played
+                if note.channel == PERCUSSION_CHANNEL
+                    || !self.channel_filter.is_audible(note.channel)
+                    || track_start + note.start >= self.total_duration()
+                {
+                    continue;
                 }
+
+                // Notes released while the pedal is down keep sounding until they are struck again
+                let restrike = track_notes[note_index + 1..]
+                    .iter()
+                    .find(|other| other.channel == note.channel && other.key == note.key)
+                    .map(|other| track_start + other.start);
+                let release = dampers
+                    .get(&note.channel)
+                    .map_or(track_start + note.end, |damper| {
+                        damper.sounding_end(track_start + note.end, restrike, track_end)
+                    });
+
+                let start_time = track_start + note.start;
+                // All sound off cuts the note, fading it out no longer than until then
+                let sound_off = sounds_off
+                    .get(&note.channel)
+                    .and_then(|times| times.iter().copied().find(|&time| time > start_time))
+                    .unwrap_or(Duration::MAX);
+                let release = release.min(sound_off);
+                let end = (release + self.envelope.release).min(sound_off);
+                let timbre = timbres
+                    .get(&note.channel)
+                    .and_then(|changes| {
+                        changes
+                            .iter()
+                            .take_while(|(change_time, _)| *change_time <= start_time)
+                            .last()
+                    })
+                    .map(|(_, timbre)| *timbre)
+                    .unwrap_or_default();
+
+                played_notes.push(PlayedNote {
+                    track: track_channels.len(),
+                    channel: note.channel,
+                    key: note.key,
+                    note: MidiNote::new(note.key),
+                    voice: Voice {
+                        start: start_time,
+                        release,
+                        release_length: end - release,
+                        end,
+                    },
+                    on_velocity: note.velocity,
+                    off_velocity: note.release_velocity,
+                    timbre,
+                });
             }
+
+            track_channels.push((channel_nodes, pitch_bends, pressures));
+        }
+
+        let mut voices = played_notes
+            .iter()
+            .map(|played_note| played_note.voice)
+            .collect::<Vec<_>>();
+        steal_voices(&mut voices, self.settings.unison_notes());
+        for (played_note, voice) in played_notes.into_iter().zip(voices) {
+            let (channel_nodes, pitch_bends, pressures) = &track_channels[played_note.track];
+            notes.extend(schedule_played_note(
+                &channel_nodes[&played_note.channel].0,
+                pitch_bends.get(&played_note.channel),
+                pressures.get(&played_note.channel),
+                PlayedNote {
+                    voice,
+                    ..played_note
+                },
+            )?);
         }
 
-        Ok(())
+        channels.extend(
+            track_channels
+                .into_iter()
+                .flat_map(|(channel_nodes, _, _)| channel_nodes.into_values())
+                .map(|(channel_node, _)| channel_node),
+        );
+
+        let metronome = self
+            .metronome
+            .map(|volume| {
+                let clicks = metronome::click_schedule(&self.data, self.total_duration())
+                    .into_iter()
+                    .filter(|click| click.time >= offset)
+                    .map(|click| Click {
+                        time: click.time - offset,
+                        ..click
+                    });
+                ScheduledClicks::schedule(ctx, &master, volume, clicks, playback_start)
+            })
+            .transpose()?;
+
+        Ok(ScheduledPlayback {
+            master,
+            channels,
+            notes,
+            hits,
+            metronome,
+        })
+    }
+
+    pub(super) fn create_note_source(
+        ctx: &web_sys::BaseAudioContext,
+        wave: &dyn Wave,
+    ) -> Result<NoteSource, JsValue> {
+        let Some((real, imag)) = wave.periodic() else {
+            // A second of the wave, looped for as long as the notes last
+            let sample_rate = ctx.sample_rate();
+            let samples = (0..sample_rate as usize)
+                .map(|i| wave.value(1.0, i as f32 / sample_rate))
+                .collect::<Vec<_>>();
+            let buffer = ctx.create_buffer(1, samples.len() as u32, sample_rate)?;
+            buffer.copy_to_channel(&samples, 0)?;
+
+            return Ok(NoteSource::Buffer(buffer));
+        };
+
+        let periodic_wave_options = web_sys::PeriodicWaveOptions::new();
+        periodic_wave_options.set_real(&JsValue::from(js_sys::Float32Array::from(&real[..])));
+        periodic_wave_options.set_imag(&JsValue::from(js_sys::Float32Array::from(&imag[..])));
+
+        web_sys::PeriodicWave::new_with_options(ctx, &periodic_wave_options)
+            .map(NoteSource::Periodic)
     }
 
+    fn schedule_hit(
+        ctx: &web_sys::BaseAudioContext,
+        destination: &web_sys::AudioNode,
+        drum_buffer: &web_sys::AudioBuffer,
+        velocity: u8,
+        start_time: Duration,
+    ) -> Result<ScheduledHit, JsValue> {
+        let source = ctx.create_buffer_source()?;
+        let gain = web_sys::GainNode::new(ctx)?;
+
+        source.set_buffer(Some(drum_buffer));
+        gain.gain().set_value(velocity as f32 / 127.0);
+
+        source.connect_with_audio_node(&gain)?;
+        gain.connect_with_audio_node(destination)?;
+        source.start_with_when(start_time.as_secs_f64())?;
+
+        Ok(ScheduledHit { source, gain })
+    }
+
+    /// The note is released at the end of `duration`, and keeps sounding for the envelope release.
     fn schedule_note(
-        ctx: &web_sys::AudioContext,
+        ctx: &web_sys::BaseAudioContext,
+        envelope: &Envelope,
         destination: &web_sys::AudioNode,
-        periodic_wave: &web_sys::PeriodicWave,
+        // Connected to the detune of the note
+        vibrato: &web_sys::AudioNode,
+        filter_envelope: Option<&FilterEnvelope>,
+        unison: &[UnisonOscillator],
+        note_source: &NoteSource,
         note: MidiNote,
         on_velocity: u8,
         // TODO: support on and off velocity
         #[allow(unused_variables)] off_velocity: u8,
         start_time: Duration,
         duration: Duration,
-    ) -> Result<(), JsValue> {
-        let end_time = start_time + duration;
-        let oscillator = web_sys::OscillatorNode::new(ctx)?;
+        // Detune in cents, starting with its value at `start_time`
+        detune: &[(Duration, f32)],
+        // Aftertouch pressure, starting with its value at `start_time`, empty unless pressed
+        pressure: &[(Duration, u8)],
+    ) -> Result<ScheduledNote, JsValue> {
+        let end_time = start_time + duration + envelope.release;
         let gain = web_sys::GainNode::new(ctx)?;
 
-        oscillator.set_periodic_wave(periodic_wave);
-        oscillator.frequency().set_value(note.frequency());
-        oscillator.start_with_when(start_time.as_secs_f64())?;
-        oscillator.stop_with_when(end_time.as_secs_f64())?;
+        // Pressed notes swell through a gain of their own, leaving the envelope to the other one
+        let output: web_sys::AudioNode = if pressure.is_empty() {
+            gain.clone().into()
+        } else {
+            let pressure_gain = web_sys::GainNode::new(ctx)?;
+            automate_pressure(&pressure_gain.gain(), pressure, aftertouch_gain)?;
+            gain.connect_with_audio_node(&pressure_gain)?;
+            pressure_gain.into()
+        };
 
-        gain.gain()
-            .set_value_at_time(on_velocity as f32 / 127.0, start_time.as_secs_f64())?;
-        gain.gain()
-            .linear_ramp_to_value_at_time(0.0001, end_time.as_secs_f64())?;
+        // The oscillators are filtered together, when there is a filter envelope
+        let note_input: web_sys::AudioNode = match filter_envelope {
+            Some(filter_envelope) => {
+                let filter = ctx.create_biquad_filter()?;
+                filter.q().set_value(filter_envelope.q);
+                let frequency = filter.frequency();
+                let [(_, start_frequency), (attack, cutoff)] =
+                    filter_envelope.automation(ctx.sample_rate() / 2.0);
+                frequency.set_value_at_time(start_frequency, start_time.as_secs_f64())?;
+                frequency.exponential_ramp_to_value_at_time(
+                    cutoff,
+                    (start_time + attack).as_secs_f64(),
+                )?;
+                // Pressure opens the filter on top of its envelope
+                automate_pressure(&filter.detune(), pressure, aftertouch_brightness)?;
 
-        oscillator.connect_with_audio_node(&gain)?;
-        gain.connect_with_audio_node(destination)?;
+                filter.connect_with_audio_node(&gain)?;
+                filter.into()
+            }
+            None => gain.clone().into(),
+        };
+
+        // Buffers don't follow the pitch, so detuning copies of them would only make them louder
+        let unison = match note_source {
+            NoteSource::Periodic(_) => unison,
+            NoteSource::Buffer(_) => &[UnisonOscillator {
+                detune: 0.0,
+                pan: 0.0,
+            }],
+        };
+        let sources = unison
+            .iter()
+            .map(|unison_oscillator| {
+                let source = note_source.create_node(ctx, note)?;
+                if let Some(oscillator) = source.dyn_ref::<web_sys::OscillatorNode>() {
+                    oscillator.detune().set_value(unison_oscillator.detune);
+                    for (time, cents) in detune {
+                        oscillator.detune().set_value_at_time(
+                            cents + unison_oscillator.detune,
+                            time.as_secs_f64(),
+                        )?;
+                    }
+
+                    vibrato.connect_with_audio_param(&oscillator.detune())?;
+                }
+                source.start_with_when(start_time.as_secs_f64())?;
+                source.stop_with_when(end_time.as_secs_f64())?;
+
+                if unison_oscillator.pan == 0.0 {
+                    source.connect_with_audio_node(&note_input)?;
+                } else {
+                    let panner = web_sys::StereoPannerNode::new(ctx)?;
+                    panner.pan().set_value(unison_oscillator.pan);
+                    source.connect_with_audio_node(&panner)?;
+                    panner.connect_with_audio_node(&note_input)?;
+                }
+                Ok(source)
+            })
+            .collect::<Result<Vec<_>, JsValue>>()?;
+
+        // All the oscillators follow the same envelope, quieter as there are more of them
+        let unison_gain = unison_gain(unison.len());
+        let points = envelope
+            .automation(duration)
+            .into_iter()
+            .map(|(time, level)| (time, level * unison_gain))
+            .collect::<Vec<_>>();
+        automate_envelope(&gain, &points, on_velocity, start_time)?;
+        output.connect_with_audio_node(destination)?;
+
+        Ok(ScheduledNote { sources, gain })
+    }
+}
+
+#[cfg(all(test, target_arch = "wasm32"))]
+mod wasm_tests {
+    use wasm_bindgen_futures::JsFuture;
+    use wasm_bindgen_test::wasm_bindgen_test;
+
+    use super::*;
+    use crate::{
+        midi::{
+            MIDIFormat, TimeDivision,
+            builder::{FileBuilder, TrackBuilder},
+        },
+        synth::Unison,
+        wave::{SineWave, WhiteNoise},
+    };
+
+    const SAMPLE_RATE: f32 = 22050.0;
+
+    /// Offline context rendering a second of audio
+    fn offline_context() -> web_sys::OfflineAudioContext {
+        web_sys::OfflineAudioContext::new_with_number_of_channels_and_length_and_sample_rate(
+            1,
+            SAMPLE_RATE as u32,
+            SAMPLE_RATE,
+        )
+        .unwrap()
+    }
+
+    async fn render(ctx: &web_sys::OfflineAudioContext) -> Vec<f32> {
+        let rendered = JsFuture::from(ctx.start_rendering().unwrap())
+            .await
+            .unwrap();
+        web_sys::AudioBuffer::from(rendered)
+            .get_channel_data(0)
+            .unwrap()
+    }
+
+    /// File of a single A4 from `start` until the end of the second, at 96 ticks per half second
+    fn single_note(start: u64) -> MidiSynth {
+        let data = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(TrackBuilder::new().at(start).note(0, 69, 100, 192 - start))
+        .build();
+        MidiSynth::new(data, SynthSettings::default())
+    }
+
+    #[wasm_bindgen_test]
+    async fn stopped_playbacks_are_silent() {
+        let ctx = offline_context();
+        let schedule = |synth: MidiSynth| {
+            synth
+                .schedule(
+                    &ctx,
+                    Some(&SineWave),
+                    &ctx.destination(),
+                    Duration::ZERO,
+                    Duration::ZERO,
+                )
+                .unwrap()
+        };
+
+        // As when a file is loaded while another one plays
+        schedule(single_note(0)).stop().unwrap();
+        schedule(single_note(96));
+        let samples = render(&ctx).await;
+
+        let (first_half, second_half) = samples.split_at(samples.len() / 2);
+        assert!(first_half.iter().all(|sample| *sample == 0.0));
+        assert!(second_half.iter().any(|sample| sample.abs() > 0.01));
+    }
+
+    #[wasm_bindgen_test]
+    async fn all_sound_off_cuts_release_tails() {
+        // A note without a note off, silenced by All Sound Off after half a second
+        let data = FileBuilder::new(
+            MIDIFormat::SingleMultiChannelTrack,
+            TimeDivision::TicksPerBit(96),
+        )
+        .track(
+            TrackBuilder::new()
+                .note_on(0, 69, 100)
+                .at(96)
+                .controller(0, ControllerKind::AllSoundOff.number(), 0)
+                .delta(96),
+        )
+        .build();
+        let mut synth = MidiSynth::new(data, SynthSettings::default());
+        synth.set_envelope(Envelope {
+            release: Duration::from_secs(1),
+            ..Envelope::default()
+        });
+
+        let ctx = offline_context();
+        synth
+            .schedule(
+                &ctx,
+                Some(&SineWave),
+                &ctx.destination(),
+                Duration::ZERO,
+                Duration::ZERO,
+            )
+            .unwrap();
+        let samples = render(&ctx).await;
+
+        // Silent within a millisecond, rather than fading out for a second
+        let cut = samples.len() / 2 + SAMPLE_RATE as usize / 1000;
+        assert!(samples[..cut].iter().any(|sample| sample.abs() > 0.01));
+        assert!(samples[cut..].iter().all(|sample| *sample == 0.0));
+    }
+
+    #[wasm_bindgen_test]
+    fn notes_in_unison_schedule_an_oscillator_per_voice() {
+        // A chord of four notes held for a second
+        let chord = || {
+            let track = (0..4).fold(TrackBuilder::new(), |track, i| {
+                track.note(0, 60 + 4 * i, 100, 192)
+            });
+            FileBuilder::new(
+                MIDIFormat::SingleMultiChannelTrack,
+                TimeDivision::TicksPerBit(96),
+            )
+            .track(track)
+            .build()
+        };
+
+        let ctx = offline_context();
+        let sources = |unison, max_voices, wave: &dyn Wave| {
+            let synth = MidiSynth::new(
+                chord(),
+                SynthSettings {
+                    unison,
+                    max_voices,
+                    ..SynthSettings::default()
+                },
+            );
+            let playback = synth
+                .schedule(
+                    &ctx,
+                    Some(wave),
+                    &ctx.destination(),
+                    Duration::ZERO,
+                    Duration::ZERO,
+                )
+                .unwrap();
+            let sources = playback
+                .notes
+                .iter()
+                .map(|note| note.sources.len())
+                .collect::<Vec<_>>();
+            playback.stop().unwrap();
+            sources
+        };
 
-        Ok(())
+        assert_eq!(sources(Unison::Off, 64, &SineWave), [1; 4]);
+        assert_eq!(sources(Unison::Two, 64, &SineWave), [2; 4]);
+        assert_eq!(sources(Unison::Three, 64, &SineWave), [3; 4]);
+        // The oscillators count against the voice limit, stealing whole notes
+        assert_eq!(sources(Unison::Three, 9, &SineWave), [3; 3]);
+        assert_eq!(sources(Unison::Three, 8, &SineWave), [3; 2]);
+        assert_eq!(sources(Unison::Two, 1, &SineWave), [2]);
+        // Waves without a decomposition are played from a single buffer
+        assert_eq!(sources(Unison::Three, 12, &WhiteNoise::new(1)), [1; 4]);
     }
 }